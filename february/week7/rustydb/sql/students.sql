@@ -0,0 +1,3 @@
+-- The schema RustyDB used to create on every start. Reference it from the
+-- `bootstrap` list in the config file to keep getting it.
+CREATE TABLE Students (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, class TEXT);
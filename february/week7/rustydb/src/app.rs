@@ -0,0 +1,181 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::Ordering,
+    time::Duration,
+};
+
+use crate::{
+    auth::{self, Users},
+    config::Config,
+    dump,
+    log::{self, error, info},
+    replication, server,
+    storage::Database,
+    tls,
+};
+
+const ADMIN_USER: &str = "admin";
+
+/// The file inside the data directory holding the saved database.
+const DATA_FILE: &str = "rustydb.sql";
+
+/// The file next to it holding the user accounts and their grants.
+const USERS_FILE: &str = "users.toml";
+
+/// Run the server described by `config` until it is interrupted, then save
+/// the database if it has somewhere to go. Errors are those that stop it
+/// from starting at all.
+pub fn run(config: Config) -> Result<(), String> {
+    log::set_level(config.log_level);
+    let limits = config.limits.to_limits()?;
+
+    // Pick up where the last run left off, if it saved anything
+    let mut db = Database::new();
+    let saved = config.data_dir.as_ref().map(|dir| dir.join(DATA_FILE));
+    let restored = match &saved {
+        Some(path) if path.exists() => {
+            let script =
+                fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let count = dump::restore(&mut db, &script)
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            info!("Loaded {} statement(s) from {}", count, path.display());
+            true
+        }
+        _ => false,
+    };
+
+    // The accounts are saved apart from the data, password hashes and all
+    let saved_users = config.data_dir.as_ref().map(|dir| dir.join(USERS_FILE));
+    match &saved_users {
+        Some(path) if path.exists() => {
+            let text =
+                fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            db.users = Users::load(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+            info!("Loaded the user accounts from {}", path.display());
+        }
+        // Bootstrap a superuser; without a configured password, make one up
+        _ => {
+            let admin_password = env::var("RUSTYDB_ADMIN_PASSWORD").unwrap_or_else(|_| {
+                let password = auth::random_password();
+                println!(
+                    "Generated password for user '{}': {} (set RUSTYDB_ADMIN_PASSWORD to choose one)",
+                    ADMIN_USER, password
+                );
+                password
+            });
+            db.users
+                .create_user(ADMIN_USER, auth::hash_password(&admin_password), true)
+                .expect("Failed to create the admin user");
+        }
+    }
+
+    let mut srv = server::Server::bind(&config.listen, db)
+        .map_err(|e| format!("Failed to listen on {}: {}", config.listen, e))?;
+    srv = srv.with_limits(limits).with_slow_query_log(
+        (config.slow_query_ms > 0).then(|| Duration::from_millis(config.slow_query_ms)),
+    );
+
+    // Bootstrap scripts set up a fresh database; a restored one already ran
+    // them, and a follower gets its tables from the leader
+    let leader = config.replication.leader.clone();
+    if !restored && leader.is_none() {
+        for script in &config.bootstrap {
+            let count = fs::read_to_string(script)
+                .map_err(|e| e.to_string())
+                .and_then(|text| srv.run_script(&text))
+                .map_err(|e| format!("{}: {}", script.display(), e))?;
+            info!("Ran {} statement(s) from {}", count, script.display());
+        }
+    }
+
+    if let Some(leader) = leader {
+        let password = env::var("RUSTYDB_LEADER_PASSWORD")
+            .map_err(|_| "Set RUSTYDB_LEADER_PASSWORD to follow a leader".to_string())?;
+        let tls = match &config.replication.ca {
+            Some(ca) => Some(
+                tls::client_config(ca)
+                    .map_err(|e| format!("Failed to load the leader's CA: {}", e))?,
+            ),
+            None => None,
+        };
+        info!("Following {} as '{}'", leader, config.replication.user);
+        replication::follow(
+            leader,
+            config.replication.user.clone(),
+            password,
+            tls,
+            srv.database(),
+        );
+    }
+
+    // TLS is opt-in: point both tls.cert and tls.key, or the variables
+    // that override them, at PEM files to enable it
+    let file =
+        |var: &str, key: &Option<PathBuf>| env::var_os(var).map(PathBuf::from).or(key.clone());
+    match (
+        file("RUSTYDB_TLS_CERT", &config.tls.cert),
+        file("RUSTYDB_TLS_KEY", &config.tls.key),
+    ) {
+        (Some(cert), Some(key)) => {
+            let config = tls::server_config(&cert, &key)
+                .map_err(|e| format!("Failed to load TLS certificate: {}", e))?;
+            srv = srv.with_tls(config);
+        }
+        (None, None) => {}
+        _ => {
+            return Err(
+                "Set both tls.cert and tls.key (or RUSTYDB_TLS_CERT and RUSTYDB_TLS_KEY) to enable TLS"
+                    .to_string(),
+            );
+        }
+    }
+
+    // First Ctrl-C: stop accepting and let in-flight statements finish.
+    // Second Ctrl-C: give up waiting.
+    let shutdown = srv.shutdown_handle();
+    ctrlc::set_handler(move || {
+        if shutdown.swap(true, Ordering::SeqCst) {
+            eprintln!("Forced exit.");
+            process::exit(130);
+        }
+        info!("Received interrupt, shutting down (press Ctrl-C again to force)...");
+    })
+    .expect("Failed to install the Ctrl-C handler");
+
+    let db = srv.database();
+    srv.run();
+
+    if let (Some(path), Some(users_path)) = (&saved, &saved_users) {
+        let (script, users) = {
+            let db = db.lock().unwrap();
+            (dump::dump(&db), db.users.save())
+        };
+        match save(path, &script) {
+            Ok(()) => info!("Saved the database to {}", path.display()),
+            Err(e) => error!("Failed to save the database to {}: {}", path.display(), e),
+        }
+        match save(users_path, &users) {
+            Ok(()) => info!("Saved the user accounts to {}", users_path.display()),
+            Err(e) => error!(
+                "Failed to save the user accounts to {}: {}",
+                users_path.display(),
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Write `contents` to `path` without ever leaving a half-written file:
+/// write a sibling first, then rename it into place.
+fn save(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,549 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    sync::LazyLock,
+};
+
+use pbkdf2::pbkdf2_hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::parser::{ConflictAction, CopyEndpoint, OnConflict, Statement};
+
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+const HASH_ROUNDS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+
+/// A per-table permission that can be granted to a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Privilege {
+    Select,
+    Insert,
+    Update,
+    Delete,
+}
+
+impl Privilege {
+    pub const ALL: [Privilege; 4] = [
+        Privilege::Select,
+        Privilege::Insert,
+        Privilege::Update,
+        Privilege::Delete,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "select" => Some(Privilege::Select),
+            "insert" => Some(Privilege::Insert),
+            "update" => Some(Privilege::Update),
+            "delete" => Some(Privilege::Delete),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Privilege {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Privilege::Select => write!(f, "SELECT"),
+            Privilege::Insert => write!(f, "INSERT"),
+            Privilege::Update => write!(f, "UPDATE"),
+            Privilege::Delete => write!(f, "DELETE"),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Password hashing
+// ---------------------------------------------------------------------------
+
+/// Hash `password` with a fresh random salt. The result is self-describing:
+/// `pbkdf2-sha256$<rounds>$<salt hex>$<hash hex>`.
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; SALT_LEN];
+    getrandom::fill(&mut salt).expect("OS random number generator unavailable");
+    hash_with(password, &salt, HASH_ROUNDS)
+}
+
+fn hash_with(password: &str, salt: &[u8], rounds: u32) -> String {
+    let mut hash = [0u8; HASH_LEN];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, rounds, &mut hash);
+    format!(
+        "{}${}${}${}",
+        HASH_SCHEME,
+        rounds,
+        to_hex(salt),
+        to_hex(&hash)
+    )
+}
+
+/// Check `password` against a string produced by `hash_password`.
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let parts: Vec<&str> = stored.split('$').collect();
+    let [scheme, rounds, salt, _] = parts[..] else {
+        return false;
+    };
+    let (Ok(rounds), Some(salt)) = (rounds.parse::<u32>(), from_hex(salt)) else {
+        return false;
+    };
+    if scheme != HASH_SCHEME {
+        return false;
+    }
+    // Compare without short-circuiting so timing doesn't leak the prefix
+    let candidate = hash_with(password, &salt, rounds);
+    candidate.len() == stored.len()
+        && candidate
+            .bytes()
+            .zip(stored.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Burn the same effort as `verify_password` when the user name is unknown,
+/// so response timing doesn't reveal which accounts exist.
+pub fn verify_unknown_user(password: &str) {
+    static DUMMY: LazyLock<String> = LazyLock::new(|| hash_password(""));
+    verify_password(password, &DUMMY);
+}
+
+/// A random password suitable for bootstrapping an account.
+pub fn random_password() -> String {
+    let mut bytes = [0u8; 12];
+    getrandom::fill(&mut bytes).expect("OS random number generator unavailable");
+    to_hex(&bytes)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// ---------------------------------------------------------------------------
+// User catalog
+// ---------------------------------------------------------------------------
+
+pub struct User {
+    password_hash: String,
+    pub superuser: bool,
+    /// Table name → privileges granted on it.
+    grants: HashMap<String, HashSet<Privilege>>,
+}
+
+#[derive(Default)]
+pub struct Users {
+    users: HashMap<String, User>,
+}
+
+impl Users {
+    /// Add a user. `password_hash` must come from `hash_password`, which is
+    /// deliberately slow and so should be called before taking any lock.
+    pub fn create_user(
+        &mut self,
+        name: &str,
+        password_hash: String,
+        superuser: bool,
+    ) -> Result<(), String> {
+        if self.users.contains_key(name) {
+            return Err(format!("User '{}' already exists", name));
+        }
+        self.users.insert(
+            name.to_string(),
+            User {
+                password_hash,
+                superuser,
+                grants: HashMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn drop_user(&mut self, name: &str) -> Result<(), String> {
+        self.users
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| format!("User '{}' not found", name))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    /// The stored hash for `name`, to be checked with `verify_password`
+    /// once the caller has released its lock.
+    pub fn password_hash(&self, name: &str) -> Option<String> {
+        self.users.get(name).map(|u| u.password_hash.clone())
+    }
+
+    pub fn grant(
+        &mut self,
+        name: &str,
+        table: &str,
+        privileges: &[Privilege],
+    ) -> Result<(), String> {
+        let user = self
+            .users
+            .get_mut(name)
+            .ok_or_else(|| format!("User '{}' not found", name))?;
+        user.grants
+            .entry(table.to_string())
+            .or_default()
+            .extend(privileges.iter().copied());
+        Ok(())
+    }
+
+    pub fn revoke(
+        &mut self,
+        name: &str,
+        table: &str,
+        privileges: &[Privilege],
+    ) -> Result<(), String> {
+        let user = self
+            .users
+            .get_mut(name)
+            .ok_or_else(|| format!("User '{}' not found", name))?;
+        if let Some(granted) = user.grants.get_mut(table) {
+            granted.retain(|p| !privileges.contains(p));
+            if granted.is_empty() {
+                user.grants.remove(table);
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop every grant on `table`, so a new table of the same name starts
+    /// out private.
+    pub fn forget_table(&mut self, table: &str) {
+        for user in self.users.values_mut() {
+            user.grants.remove(table);
+        }
+    }
+
+    pub fn has_privilege(&self, name: &str, table: &str, privilege: Privilege) -> bool {
+        match self.users.get(name) {
+            Some(user) => {
+                user.superuser
+                    || user
+                        .grants
+                        .get(table)
+                        .is_some_and(|granted| granted.contains(&privilege))
+            }
+            None => false,
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Saving the catalog
+// ---------------------------------------------------------------------------
+
+/// The user catalog as it is saved: a table per user, e.g.
+///
+/// ```toml
+/// [users.alice]
+/// password_hash = "pbkdf2-sha256$100000$...$..."
+/// superuser = false
+/// grants = { students = ["SELECT", "INSERT"] }
+/// ```
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SavedCatalog {
+    #[serde(default)]
+    users: BTreeMap<String, SavedUser>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SavedUser {
+    password_hash: String,
+    superuser: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    grants: BTreeMap<String, Vec<String>>,
+}
+
+impl Users {
+    /// The accounts, with their password hashes and grants, as TOML that
+    /// [`Users::load`] reads back.
+    pub fn save(&self) -> String {
+        let users = self
+            .users
+            .iter()
+            .map(|(name, user)| {
+                let grants = user
+                    .grants
+                    .iter()
+                    .map(|(table, granted)| {
+                        let privileges = Privilege::ALL
+                            .iter()
+                            .filter(|p| granted.contains(p))
+                            .map(|p| p.to_string())
+                            .collect();
+                        (table.clone(), privileges)
+                    })
+                    .collect();
+                let saved = SavedUser {
+                    password_hash: user.password_hash.clone(),
+                    superuser: user.superuser,
+                    grants,
+                };
+                (name.clone(), saved)
+            })
+            .collect();
+        toml::to_string(&SavedCatalog { users }).expect("the catalog is plain TOML")
+    }
+
+    /// Read back accounts written by [`Users::save`].
+    pub fn load(text: &str) -> Result<Users, String> {
+        let saved: SavedCatalog = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut users = Users::default();
+        for (name, user) in saved.users {
+            users.create_user(&name, user.password_hash, user.superuser)?;
+            for (table, names) in user.grants {
+                let privileges = names
+                    .iter()
+                    .map(|p| {
+                        Privilege::from_name(p)
+                            .ok_or_else(|| format!("User '{}': unknown privilege '{}'", name, p))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                users.grant(&name, &table, &privileges)?;
+            }
+        }
+        Ok(users)
+    }
+}
+
+/// Check that `user` may run `statement`. Table access needs the matching
+/// privilege; schema changes, user management and anything touching the
+/// server's filesystem need a superuser.
+pub fn authorize(users: &Users, user: &str, statement: &Statement) -> Result<(), String> {
+    let needed: Option<Vec<(&str, Privilege)>> = match statement {
+        Statement::Select(query) | Statement::Explain(query) => Some(
+            query
+                .tables()
+                .into_iter()
+                .map(|t| (t.name.as_str(), Privilege::Select))
+                .collect(),
+        ),
+        Statement::Insert {
+            table,
+            on_conflict,
+            returning,
+            ..
+        } => {
+            let mut needed = vec![(table.as_str(), Privilege::Insert)];
+            if let Some(OnConflict {
+                action: ConflictAction::Update(_),
+                ..
+            }) = on_conflict
+            {
+                needed.push((table, Privilege::Update));
+            }
+            if returning.is_some() {
+                needed.push((table, Privilege::Select));
+            }
+            Some(needed)
+        }
+        // What triggers and ON DELETE do to other tables comes with them,
+        // and only a superuser can create a trigger
+        Statement::Update { table, .. } => Some(vec![(table, Privilege::Update)]),
+        Statement::Delete { table, .. } => Some(vec![(table, Privilege::Delete)]),
+        // Settings only affect the session itself
+        Statement::SetStatementTimeout { .. } | Statement::ShowStatementTimeout => Some(vec![]),
+        Statement::CopyFrom {
+            table,
+            source: CopyEndpoint::Client,
+            ..
+        } => Some(vec![(table, Privilege::Insert)]),
+        Statement::CopyTo {
+            table,
+            target: CopyEndpoint::Client,
+            ..
+        } => Some(vec![(table, Privilege::Select)]),
+        _ => None,
+    };
+
+    match needed {
+        Some(needed) => {
+            for (table, privilege) in needed {
+                if !users.has_privilege(user, table, privilege) {
+                    return Err(format!(
+                        "Permission denied: user '{}' lacks {} on table '{}'",
+                        user, privilege, table
+                    ));
+                }
+            }
+            Ok(())
+        }
+        None if users.get(user).is_some_and(|u| u.superuser) => Ok(()),
+        None => Err(format!(
+            "Permission denied: only a superuser may run {}",
+            statement.kind()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `bob`, who may read `t` and add to `u`, and `root`, a superuser.
+    fn users() -> Users {
+        let mut users = Users::default();
+        users
+            .create_user("bob", hash_with("pw", b"salt", 1), false)
+            .unwrap();
+        users.create_user("root", String::new(), true).unwrap();
+        users.grant("bob", "t", &[Privilege::Select]).unwrap();
+        users.grant("bob", "u", &[Privilege::Insert]).unwrap();
+        users
+    }
+
+    fn authorized(users: &Users, user: &str, sql: &str) -> Result<(), String> {
+        authorize(users, user, &Statement::parse(sql).unwrap())
+    }
+
+    // -----------------------------------------------------------------------
+    // Passwords
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn verifies_passwords_against_salted_hashes() {
+        let hash = hash_password("secret");
+        assert!(hash.starts_with("pbkdf2-sha256$100000$"));
+        assert_ne!(hash, hash_password("secret"));
+        assert!(verify_password("secret", &hash));
+        assert!(!verify_password("Secret", &hash));
+        assert!(!verify_password("", &hash));
+    }
+
+    #[test]
+    fn rejects_malformed_hashes() {
+        let hash = hash_with("pw", b"salt", 1);
+        assert!(verify_password("pw", &hash));
+        for stored in [
+            "",
+            "pw",
+            &hash.replace("pbkdf2-sha256", "md5"),
+            &hash.replace("$1$", "$x$"),
+            &hash[..hash.len() - 2],
+            &format!("{}$", hash),
+        ] {
+            assert!(!verify_password("pw", stored), "accepted '{}'", stored);
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // The catalog
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn saves_and_loads_accounts_and_grants() {
+        let mut users = users();
+        users.grant("bob", "t", &[Privilege::Delete]).unwrap();
+        let loaded = Users::load(&users.save()).unwrap();
+        assert_eq!(loaded.save(), users.save());
+        assert!(loaded.get("root").unwrap().superuser);
+        assert!(verify_password("pw", &loaded.password_hash("bob").unwrap()));
+        for (table, privilege, held) in [
+            ("t", Privilege::Select, true),
+            ("t", Privilege::Delete, true),
+            ("t", Privilege::Insert, false),
+            ("u", Privilege::Insert, true),
+        ] {
+            assert_eq!(loaded.has_privilege("bob", table, privilege), held);
+        }
+    }
+
+    #[test]
+    fn refuses_a_catalog_it_did_not_write() {
+        let text =
+            "[users.bob]\npassword_hash = \"x\"\nsuperuser = false\ngrants = { t = [\"DROP\"] }\n";
+        let err = Users::load(text).err().unwrap();
+        assert_eq!(err, "User 'bob': unknown privilege 'DROP'");
+        assert!(Users::load("[users.bob]\nsuperuser = false\n").is_err());
+        assert!(Users::load("[accounts]\n").is_err());
+    }
+
+    #[test]
+    fn revokes_and_forgets_grants() {
+        let mut users = users();
+        users.revoke("bob", "t", &[Privilege::Select]).unwrap();
+        assert!(!users.has_privilege("bob", "t", Privilege::Select));
+        users.forget_table("u");
+        assert!(!users.has_privilege("bob", "u", Privilege::Insert));
+        assert!(users.has_privilege("root", "u", Privilege::Insert));
+        assert!(users.grant("nobody", "t", &[Privilege::Select]).is_err());
+    }
+
+    // -----------------------------------------------------------------------
+    // Authorization
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn needs_the_privilege_each_table_access_takes() {
+        let users = users();
+        for sql in [
+            "SELECT * FROM t",
+            "SELECT * FROM t JOIN t x ON t.a = x.a",
+            "WITH w AS (SELECT a FROM t) SELECT a FROM w",
+            "INSERT INTO u VALUES (1)",
+            "SET statement_timeout = 5",
+        ] {
+            assert_eq!(authorized(&users, "bob", sql), Ok(()), "{}", sql);
+        }
+        for (sql, missing) in [
+            ("SELECT * FROM u", "SELECT on table 'u'"),
+            (
+                "SELECT * FROM t UNION SELECT * FROM u",
+                "SELECT on table 'u'",
+            ),
+            ("INSERT INTO t VALUES (1)", "INSERT on table 't'"),
+            (
+                "INSERT INTO u VALUES (1) ON CONFLICT (a) DO UPDATE SET a = 2",
+                "UPDATE on table 'u'",
+            ),
+            (
+                "INSERT INTO u VALUES (1) RETURNING a",
+                "SELECT on table 'u'",
+            ),
+            ("DELETE FROM t", "DELETE on table 't'"),
+        ] {
+            assert_eq!(
+                authorized(&users, "bob", sql),
+                Err(format!("Permission denied: user 'bob' lacks {}", missing)),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_everything_else_to_superusers() {
+        let users = users();
+        for sql in [
+            "CREATE TABLE v (a INTEGER)",
+            "DROP TABLE t",
+            "GRANT SELECT ON u TO bob",
+            "COPY t TO '/tmp/t.csv'",
+        ] {
+            assert_eq!(authorized(&users, "root", sql), Ok(()), "{}", sql);
+            let err = authorized(&users, "bob", sql).unwrap_err();
+            assert!(
+                err.starts_with("Permission denied: only a superuser may run"),
+                "{}",
+                err
+            );
+        }
+        assert!(authorized(&users, "nobody", "SELECT * FROM t").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! Interactive client for RustyDB.
+//!
+//! Usage: cli [ADDR] [--tls --ca CERT.pem [--server-name NAME]] [--user NAME]
+//!
+//! ADDR defaults to 127.0.0.1:7878. With `--user`, the client logs in for
+//! you, taking the password from RUSTYDB_PASSWORD or asking for it.
+//!
+//! A self-signed certificate for testing must be a leaf (CA:FALSE), e.g.:
+//!
+//!   openssl req -x509 -newkey rsa:2048 -nodes -days 30 \
+//!     -keyout key.pem -out cert.pem -subj "/CN=localhost" \
+//!     -addext "subjectAltName=DNS:localhost,IP:127.0.0.1" \
+//!     -addext "basicConstraints=critical,CA:FALSE"
+//!
+//! Start the server with `cert = "cert.pem"` and `key = "key.pem"` under
+//! `[tls]` in its config (or RUSTYDB_TLS_CERT=cert.pem
+//! RUSTYDB_TLS_KEY=key.pem) and connect with `cli --tls --ca cert.pem`.
+//!
+//! Ctrl-C cancels the running query rather than quitting; use `quit` or
+//! end the input (Ctrl-D) to leave.
+
+use std::{
+    env,
+    io::{self, BufRead, Read, Write},
+    net::TcpStream,
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use rustls::{
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+    pki_types::{CertificateDer, ServerName, pem::PemObject},
+};
+
+/// How long a read waits before letting the input side use the stream.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+struct Options {
+    addr: String,
+    tls: bool,
+    ca: Option<String>,
+    server_name: Option<String>,
+    user: Option<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        addr: "127.0.0.1:7878".to_string(),
+        tls: false,
+        ca: None,
+        server_name: None,
+        user: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match arg.as_str() {
+            "--tls" => options.tls = true,
+            "--ca" => options.ca = Some(value("--ca")?),
+            "--server-name" => options.server_name = Some(value("--server-name")?),
+            "--user" => options.user = Some(value("--user")?),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option: {}", flag)),
+            addr => options.addr = addr.to_string(),
+        }
+    }
+    if options.tls && options.ca.is_none() {
+        return Err("--tls needs --ca with the server's certificate (or its CA)".to_string());
+    }
+    Ok(options)
+}
+
+fn connect(options: &Options) -> Result<Box<dyn Stream>, String> {
+    let mut tcp = TcpStream::connect(&options.addr)
+        .map_err(|e| format!("Could not connect to {}: {}", options.addr, e))?;
+
+    if !options.tls {
+        tcp.set_read_timeout(Some(POLL_INTERVAL))
+            .map_err(|e| e.to_string())?;
+        return Ok(Box::new(tcp));
+    }
+
+    let ca = options.ca.as_deref().unwrap_or_default();
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca).map_err(|e| format!("{}: {}", ca, e))? {
+        let cert = cert.map_err(|e| format!("{}: {}", ca, e))?;
+        roots.add(cert).map_err(|e| format!("{}: {}", ca, e))?;
+    }
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    // Verify against --server-name, or else the host part of the address
+    let host = match &options.server_name {
+        Some(name) => name.clone(),
+        None => options
+            .addr
+            .rsplit_once(':')
+            .map_or(options.addr.as_str(), |(host, _)| host)
+            .to_string(),
+    };
+    let name =
+        ServerName::try_from(host.clone()).map_err(|_| format!("Invalid server name: {}", host))?;
+    let mut conn = ClientConnection::new(Arc::new(config), name).map_err(|e| e.to_string())?;
+
+    // Handshake while the socket still blocks, so a server slow to answer
+    // isn't mistaken for a failure
+    while conn.is_handshaking() {
+        conn.complete_io(&mut tcp)
+            .map_err(|e| format!("TLS handshake with {} failed: {}", options.addr, e))?;
+    }
+    tcp.set_read_timeout(Some(POLL_INTERVAL))
+        .map_err(|e| e.to_string())?;
+    Ok(Box::new(StreamOwned::new(conn, tcp)))
+}
+
+/// Whether a failed read or write only ran into the poll timeout.
+fn timed_out(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+/// Send one line. A TLS session may have to read before it can write, and
+/// such a read can time out; that is retried rather than treated as an error.
+fn send_line(stream: &mut dyn Stream, line: &str) -> io::Result<()> {
+    let line = format!("{}\n", line);
+    let mut rest = line.as_bytes();
+    while !rest.is_empty() {
+        match stream.write(rest) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => rest = &rest[n..],
+            Err(e) if timed_out(&e) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    loop {
+        match stream.flush() {
+            Err(e) if timed_out(&e) => {}
+            result => return result,
+        }
+    }
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let stream = Arc::new(Mutex::new(connect(&options).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    })));
+
+    // Print whatever the server sends, as it arrives
+    let reader = Arc::clone(&stream);
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let result = reader.lock().unwrap().read(&mut buffer);
+            match result {
+                Ok(0) => break,
+                Ok(n) => {
+                    print!("{}", String::from_utf8_lossy(&buffer[..n]));
+                    let _ = io::stdout().flush();
+                }
+                Err(e) if timed_out(&e) => {
+                    // Give the input side a chance at the lock
+                    thread::sleep(Duration::from_millis(5));
+                }
+                // A TLS peer that hangs up without close_notify ends up here
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => {
+                    eprintln!("Connection error: {}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        println!("Server closed the connection");
+        process::exit(0);
+    });
+
+    // Ctrl-C asks the server to stop whatever query is streaming
+    let canceller = Arc::clone(&stream);
+    ctrlc::set_handler(move || {
+        let _ = send_line(&mut **canceller.lock().unwrap(), "CANCEL");
+    })
+    .expect("Failed to install the Ctrl-C handler");
+
+    let send = |line: &str| {
+        if let Err(e) = send_line(&mut **stream.lock().unwrap(), line) {
+            eprintln!("Failed to send: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+
+    if let Some(user) = &options.user {
+        let password = match env::var("RUSTYDB_PASSWORD") {
+            Ok(p) => p,
+            Err(_) => {
+                eprint!("Password for {}: ", user);
+                lines.next().and_then(Result::ok).unwrap_or_default()
+            }
+        };
+        send(&format!("LOGIN {} {}", user, password));
+    }
+
+    for line in lines {
+        let Ok(line) = line else { break };
+        send(&line);
+    }
+
+    // Stdin is exhausted: say goodbye and let the reader print the reply
+    send("quit");
+    loop {
+        thread::park();
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::{exec::QueryLimits, log::Level, server::Limits};
+
+/// Server settings. Every key is optional; see `rustydb.example.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address to accept clients on, e.g. "127.0.0.1:7878".
+    pub listen: String,
+    /// Where the database is saved on shutdown and loaded from at startup.
+    /// Without it the database lives only in memory.
+    pub data_dir: Option<PathBuf>,
+    pub log_level: Level,
+    /// Log statements taking at least this many milliseconds; 0 logs none.
+    pub slow_query_ms: u64,
+    /// SQL scripts run, in order, when starting with an empty database.
+    pub bootstrap: Vec<PathBuf>,
+    pub limits: LimitsConfig,
+    pub replication: ReplicationConfig,
+    pub tls: TlsConfig,
+}
+
+/// Serve clients over TLS with this certificate chain and private key, both
+/// PEM files; RUSTYDB_TLS_CERT and RUSTYDB_TLS_KEY override them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReplicationConfig {
+    /// Follow the leader at this address, serving read-only copies of its
+    /// tables. The password comes from RUSTYDB_LEADER_PASSWORD.
+    pub leader: Option<String>,
+    /// The superuser to log in to the leader as.
+    pub user: String,
+    /// Connect to the leader over TLS, trusting the certificates in this
+    /// PEM file.
+    pub ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub workers: usize,
+    pub max_connections: usize,
+    /// Seconds a client may stay silent; 0 means forever.
+    pub idle_timeout: u64,
+    /// Default milliseconds a query may run; 0 means no limit.
+    pub statement_timeout: u64,
+    /// Rows a query may return; 0 means no limit.
+    pub max_result_rows: usize,
+    /// Megabytes a query may hold at once; 0 means no limit.
+    pub max_query_memory_mb: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: "127.0.0.1:7878".to_string(),
+            data_dir: None,
+            log_level: Level::Info,
+            slow_query_ms: 1000,
+            bootstrap: Vec::new(),
+            limits: LimitsConfig::default(),
+            replication: ReplicationConfig::default(),
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self {
+            leader: None,
+            user: "admin".to_string(),
+            ca: None,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        let limits = Limits::default();
+        Self {
+            workers: limits.workers,
+            max_connections: limits.max_connections,
+            idle_timeout: limits.idle_timeout.map_or(0, |d| d.as_secs()),
+            statement_timeout: limits.query.timeout.map_or(0, |d| d.as_millis() as u64),
+            max_result_rows: limits.query.max_rows.unwrap_or(0),
+            max_query_memory_mb: limits.query.max_memory.map_or(0, |b| b / (1024 * 1024)),
+        }
+    }
+}
+
+impl Config {
+    /// Read a TOML config file. Relative paths inside it are taken relative
+    /// to the file's own directory, not to wherever the server was started.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut config: Config =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        if let Some(dir) = &mut config.data_dir {
+            *dir = base.join(&*dir);
+        }
+        for script in &mut config.bootstrap {
+            *script = base.join(&*script);
+        }
+        let files = [
+            &mut config.replication.ca,
+            &mut config.tls.cert,
+            &mut config.tls.key,
+        ];
+        for path in files.into_iter().flatten() {
+            *path = base.join(&*path);
+        }
+        Ok(config)
+    }
+}
+
+impl LimitsConfig {
+    pub(crate) fn to_limits(&self) -> Result<Limits, String> {
+        if self.workers == 0 {
+            return Err("limits.workers must be at least 1".to_string());
+        }
+        Ok(Limits {
+            workers: self.workers,
+            max_connections: self.max_connections,
+            idle_timeout: (self.idle_timeout > 0).then(|| Duration::from_secs(self.idle_timeout)),
+            query: QueryLimits {
+                timeout: (self.statement_timeout > 0)
+                    .then(|| Duration::from_millis(self.statement_timeout)),
+                max_rows: (self.max_result_rows > 0).then_some(self.max_result_rows),
+                max_memory: (self.max_query_memory_mb > 0)
+                    .then(|| self.max_query_memory_mb.saturating_mul(1024 * 1024)),
+            },
+        })
+    }
+}
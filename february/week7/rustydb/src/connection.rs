@@ -0,0 +1,380 @@
+use std::{
+    slice,
+    sync::{Arc, Mutex},
+    vec,
+};
+
+use crate::{
+    dump,
+    engine::{self, Outcome, Settings},
+    lexer,
+    parser::Statement,
+    replication,
+    storage::{self, Value},
+};
+
+/// An in-process database. Cloning the handle shares the same data, so it
+/// can be handed to as many threads as need it; each opens its own
+/// `Connection`.
+#[derive(Clone)]
+pub struct Database {
+    inner: Arc<Mutex<storage::Database>>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Database {
+    /// A new, empty database.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(storage::Database::new())),
+        }
+    }
+
+    /// A database rebuilt from a script written by [`Database::dump`] (or
+    /// the server's DUMP).
+    pub fn restore(script: &str) -> Result<Self, String> {
+        let mut db = storage::Database::new();
+        dump::restore(&mut db, script)?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(db)),
+        })
+    }
+
+    /// Every table and view as a SQL script, e.g. to save to a file.
+    pub fn dump(&self) -> String {
+        dump::dump(&self.inner.lock().unwrap())
+    }
+
+    pub fn connect(&self) -> Connection {
+        Connection {
+            db: Arc::clone(&self.inner),
+            settings: Settings::default(),
+        }
+    }
+}
+
+/// A session on a `Database`. There are no accounts in-process: a
+/// connection may do anything a server superuser can, except read from or
+/// write to a client (`STDIN`, `STDOUT`, SOURCE). Its queries have no time
+/// limit unless it sets `statement_timeout`.
+pub struct Connection {
+    db: Arc<Mutex<storage::Database>>,
+    settings: Settings,
+}
+
+impl Connection {
+    /// Run one statement. Returns the number of rows it added or changed
+    /// (INSERT, COPY) or returned (SELECT), otherwise 0.
+    pub fn execute(&mut self, sql: &str) -> Result<usize, String> {
+        let statement = Statement::parse(sql)?;
+        let outcome = engine::execute(&mut self.db.lock().unwrap(), statement, &mut self.settings)?;
+        row_count(outcome)
+    }
+
+    /// Run a script of `;`-separated statements, stopping at the first
+    /// that fails.
+    pub fn execute_batch(&mut self, sql: &str) -> Result<(), String> {
+        for (i, text) in lexer::split_statements(sql).iter().enumerate() {
+            self.execute(text)
+                .map_err(|e| format!("statement {}: {}", i + 1, e))?;
+        }
+        Ok(())
+    }
+
+    /// Run a SELECT (or EXPLAIN, SHOW or INSERT ... RETURNING) and read all
+    /// of its rows.
+    pub fn query(&mut self, sql: &str) -> Result<Rows, String> {
+        let statement = parse_query(sql)?;
+        let outcome = engine::execute(&mut self.db.lock().unwrap(), statement, &mut self.settings)?;
+        rows(outcome)
+    }
+
+    /// Start a transaction. Its statements see their own changes, but
+    /// nobody else does until it commits; dropping it without committing
+    /// rolls it back.
+    pub fn transaction(&mut self) -> Transaction<'_> {
+        let shared = self.db.lock().unwrap();
+        let db = shared.fork();
+        let base_lsn = shared.replication.lsn();
+        drop(shared);
+        Transaction {
+            settings: self.settings,
+            conn: self,
+            db,
+            base_lsn,
+        }
+    }
+}
+
+/// A group of statements that take effect together or not at all.
+///
+/// A transaction works on its own copy of the tables, so it never blocks
+/// other connections. The price is that it fails to commit if anyone else
+/// changed any table after it began; start it again to retry.
+pub struct Transaction<'a> {
+    conn: &'a mut Connection,
+    /// The transaction's own copy of the tables and views.
+    db: storage::Database,
+    /// How many changes the database had committed when the copy was made.
+    base_lsn: u64,
+    /// The connection's settings, as SET inside the transaction leaves them.
+    settings: Settings,
+}
+
+impl Transaction<'_> {
+    /// As [`Connection::execute`], inside the transaction.
+    pub fn execute(&mut self, sql: &str) -> Result<usize, String> {
+        let outcome = self.run(Statement::parse(sql)?)?;
+        row_count(outcome)
+    }
+
+    /// As [`Connection::query`], inside the transaction.
+    pub fn query(&mut self, sql: &str) -> Result<Rows, String> {
+        let outcome = self.run(parse_query(sql)?)?;
+        rows(outcome)
+    }
+
+    /// Make the transaction's changes visible to everyone.
+    pub fn commit(self) -> Result<(), String> {
+        let mut db = self.db;
+        let changes = db.replication.take_journal();
+        if changes.is_empty() {
+            self.conn.settings = self.settings;
+            return Ok(());
+        }
+        let mut shared = self.conn.db.lock().unwrap();
+        if shared.replication.lsn() != self.base_lsn {
+            return Err(
+                "Another connection changed the database during this transaction; nothing was committed."
+                    .to_string(),
+            );
+        }
+        shared.replace_data(db);
+        // Followers get the whole transaction as one change
+        replication::publish(&mut shared, |_| changes.join("\n"));
+        drop(shared);
+        self.conn.settings = self.settings;
+        Ok(())
+    }
+
+    /// Throw the transaction's changes away. Dropping it does the same.
+    pub fn rollback(self) {}
+
+    fn run(&mut self, statement: Statement) -> Result<Outcome, String> {
+        // Accounts live outside the tables a transaction copies
+        if let Statement::CreateUser { .. }
+        | Statement::DropUser { .. }
+        | Statement::Grant { .. }
+        | Statement::Revoke { .. }
+        | Statement::ShowReplication = statement
+        {
+            return Err(format!(
+                "{} can't be used inside a transaction.",
+                statement.kind()
+            ));
+        }
+        engine::execute(&mut self.db, statement, &mut self.settings)
+    }
+}
+
+/// Parse `sql`, which must be a statement that returns rows.
+fn parse_query(sql: &str) -> Result<Statement, String> {
+    match Statement::parse(sql)? {
+        statement @ (Statement::Select(_)
+        | Statement::Explain(_)
+        | Statement::ShowReplication
+        | Statement::ShowMetrics
+        | Statement::ShowStatementTimeout
+        | Statement::Insert {
+            returning: Some(_), ..
+        }) => Ok(statement),
+        statement => Err(format!(
+            "{} returns no rows; use execute instead.",
+            statement.kind()
+        )),
+    }
+}
+
+fn row_count(outcome: Outcome) -> Result<usize, String> {
+    match outcome {
+        Outcome::Done { rows, .. } => Ok(rows),
+        Outcome::Query(mut query) => query.rows.try_fold(0, |count, row| row.map(|_| count + 1)),
+    }
+}
+
+fn rows(outcome: Outcome) -> Result<Rows, String> {
+    let Outcome::Query(query) = outcome else {
+        unreachable!("parse_query only lets through statements that return rows");
+    };
+    let columns: Arc<[String]> = query.headers.into();
+    let rows = query
+        .rows
+        .map(|values| {
+            Ok(Row {
+                columns: Arc::clone(&columns),
+                values: values?,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+    Ok(Rows { columns, rows })
+}
+
+// ---------------------------------------------------------------------------
+// Results
+// ---------------------------------------------------------------------------
+
+/// The result of a query: its column names and all of its rows.
+pub struct Rows {
+    columns: Arc<[String]>,
+    rows: Vec<Row>,
+}
+
+impl Rows {
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Row> {
+        self.rows.iter()
+    }
+}
+
+impl IntoIterator for Rows {
+    type Item = Row;
+    type IntoIter = vec::IntoIter<Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Rows {
+    type Item = &'a Row;
+    type IntoIter = slice::Iter<'a, Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.iter()
+    }
+}
+
+/// One row of a query result.
+pub struct Row {
+    columns: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    /// The value of a column, by position or by name, converted to `T`:
+    /// `row.get::<i64>("id")`, `row.get::<Option<String>>(1)`.
+    pub fn get<T: FromValue>(&self, column: impl ColumnIndex) -> Result<T, String> {
+        let i = column.index(&self.columns)?;
+        T::from_value(&self.values[i]).map_err(|e| format!("Column '{}': {}", self.columns[i], e))
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+}
+
+/// Something that picks out a column of a `Row`: its position or its name.
+pub trait ColumnIndex {
+    fn index(&self, columns: &[String]) -> Result<usize, String>;
+}
+
+impl ColumnIndex for usize {
+    fn index(&self, columns: &[String]) -> Result<usize, String> {
+        if *self < columns.len() {
+            Ok(*self)
+        } else {
+            Err(format!(
+                "Column index {} is out of range; the row has {} columns.",
+                self,
+                columns.len()
+            ))
+        }
+    }
+}
+
+impl ColumnIndex for &str {
+    fn index(&self, columns: &[String]) -> Result<usize, String> {
+        columns
+            .iter()
+            .position(|c| c == self)
+            .ok_or_else(|| format!("No column named '{}' in the result.", self))
+    }
+}
+
+/// A Rust type a column value can be read as.
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, String>;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Integer(i) => Ok(*i),
+            other => Err(mismatch("INTEGER", other)),
+        }
+    }
+}
+
+/// Reads REAL values, and INTEGER ones too.
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Real(r) => Ok(*r),
+            Value::Integer(i) => Ok(*i as f64),
+            other => Err(mismatch("REAL", other)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Text(s) => Ok(s.clone()),
+            other => Err(mismatch("TEXT", other)),
+        }
+    }
+}
+
+/// NULL reads as `None`; anything else as `T`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            other => T::from_value(other).map(Some),
+        }
+    }
+}
+
+fn mismatch(expected: &str, found: &Value) -> String {
+    match found {
+        Value::Null => format!("expected {}, found NULL", expected),
+        Value::Text(s) => format!(
+            "expected {}, found TEXT {}",
+            expected,
+            lexer::quote_literal(s)
+        ),
+        other => format!("expected {}, found {}", expected, other),
+    }
+}
@@ -0,0 +1,224 @@
+use crate::storage::{Row, Table, Value};
+
+/// Format options accepted by COPY, e.g. `WITH (HEADER, DELIMITER ';')`.
+pub struct CsvOptions {
+    /// Whether the first record holds column names.
+    pub header: bool,
+    pub delimiter: char,
+    /// Unquoted fields equal to this string are read and written as NULL.
+    pub null: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            header: false,
+            delimiter: ',',
+            null: String::new(),
+        }
+    }
+}
+
+/// One parsed record, with the line it started on for error messages.
+struct Record {
+    line: usize,
+    fields: Vec<Option<String>>, // None means NULL
+}
+
+// ---------------------------------------------------------------------------
+// Reading
+// ---------------------------------------------------------------------------
+
+/// Split CSV text into records, following RFC 4180 quoting: fields may be
+/// wrapped in double quotes, which allows delimiters and newlines inside
+/// them, and `""` inside a quoted field stands for one quote. Blank lines
+/// are skipped.
+fn parse_records(data: &str, options: &CsvOptions) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    let mut chars = data.chars().peekable();
+    let mut line = 1;
+
+    while chars.peek().is_some() {
+        let start_line = line;
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut at_line_end = false;
+
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if field.is_empty() && !quoted => {
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some('"') if chars.peek() == Some(&'"') => {
+                                chars.next();
+                                field.push('"');
+                            }
+                            Some('"') => break,
+                            Some(c) => {
+                                if c == '\n' {
+                                    line += 1;
+                                }
+                                field.push(c);
+                            }
+                            None => {
+                                return Err(format!(
+                                    "line {}: unterminated quoted field",
+                                    start_line
+                                ));
+                            }
+                        }
+                    }
+                }
+                c if c == options.delimiter => {
+                    fields.push(finish_field(field, quoted, options));
+                    field = String::new();
+                    quoted = false;
+                }
+                '\r' if chars.peek() == Some(&'\n') => {}
+                '\n' => {
+                    line += 1;
+                    at_line_end = true;
+                    break;
+                }
+                c if quoted => {
+                    return Err(format!(
+                        "line {}: unexpected '{}' after closing quote",
+                        line, c
+                    ));
+                }
+                c => field.push(c),
+            }
+        }
+
+        if fields.is_empty() && field.is_empty() && !quoted {
+            continue; // blank line
+        }
+        fields.push(finish_field(field, quoted, options));
+        records.push(Record {
+            line: start_line,
+            fields,
+        });
+        if !at_line_end {
+            break;
+        }
+    }
+    Ok(records)
+}
+
+fn finish_field(field: String, quoted: bool, options: &CsvOptions) -> Option<String> {
+    if !quoted && field == options.null {
+        None
+    } else {
+        Some(field)
+    }
+}
+
+/// Parse CSV `data` into rows for `table`, ready to be inserted.
+///
+/// Values are matched to `columns` (or, failing that, the header record when
+/// `options.header` is set, or else the full schema in order). Columns not
+/// listed are filled with NULL. Every field is coerced to its column's type;
+/// if any record fails, there are no rows.
+pub fn import(
+    table: &Table,
+    columns: Option<&[String]>,
+    data: &str,
+    options: &CsvOptions,
+) -> Result<Vec<Row>, String> {
+    let mut records = parse_records(data, options)?.into_iter();
+
+    let header = if options.header {
+        match records.next() {
+            Some(r) => Some(
+                r.fields
+                    .into_iter()
+                    .map(Option::unwrap_or_default)
+                    .collect(),
+            ),
+            None => return Ok(Vec::new()),
+        }
+    } else {
+        None
+    };
+
+    let targets: Vec<String> = match (columns, header) {
+        (Some(cols), _) => cols.to_vec(),
+        (None, Some(names)) => names,
+        (None, None) => table.column_names(),
+    };
+    let positions = table.column_positions(&targets)?;
+
+    let mut rows = Vec::new();
+    for record in records {
+        if record.fields.len() != positions.len() {
+            return Err(format!(
+                "line {}: expected {} fields, got {}",
+                record.line,
+                positions.len(),
+                record.fields.len()
+            ));
+        }
+        let mut values = vec![Value::Null; table.columns.len()];
+        for (&pos, field) in positions.iter().zip(record.fields) {
+            values[pos] = field.map(Value::Text).unwrap_or(Value::Null);
+        }
+        let row = table
+            .build_row(values)
+            .map_err(|e| format!("line {}: {}", record.line, e))?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// ---------------------------------------------------------------------------
+// Writing
+// ---------------------------------------------------------------------------
+
+/// Render `table` (or just `columns` of it) as CSV text, one record per line.
+pub fn export(
+    table: &Table,
+    columns: Option<&[String]>,
+    options: &CsvOptions,
+) -> Result<String, String> {
+    let names = match columns {
+        Some(cols) => cols.to_vec(),
+        None => table.column_names(),
+    };
+    let positions = table.column_positions(&names)?;
+
+    let mut out = String::new();
+    if options.header {
+        let fields: Vec<String> = names.iter().map(|n| quote_field(n, options)).collect();
+        out.push_str(&fields.join(&options.delimiter.to_string()));
+        out.push('\n');
+    }
+    for row in table.select_all() {
+        let values = row.get_inner_vec();
+        let fields: Vec<String> = positions
+            .iter()
+            .map(|&i| match &values[i] {
+                Value::Null => options.null.clone(),
+                v => quote_field(&v.to_string(), options),
+            })
+            .collect();
+        out.push_str(&fields.join(&options.delimiter.to_string()));
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Quote a non-NULL field if it could otherwise be misread: it contains the
+/// delimiter, a quote or a line break, or it looks like the NULL marker.
+fn quote_field(field: &str, options: &CsvOptions) -> String {
+    let needs_quotes = field == options.null
+        || field
+            .chars()
+            .any(|c| c == options.delimiter || c == '"' || c == '\n' || c == '\r');
+    if needs_quotes {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
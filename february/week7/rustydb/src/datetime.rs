@@ -0,0 +1,1235 @@
+//! DATE, TIME, TIMESTAMP and INTERVAL: reading them from ISO-8601 text,
+//! writing them back out, and the arithmetic and functions over them.
+//!
+//! A DATE counts days from 1970-01-01 and a TIMESTAMP microseconds from
+//! its midnight, on the Gregorian calendar carried back before 1582 and
+//! without a time zone; both run from year 1 to year 9999. A TIME counts
+//! microseconds from midnight. The rules for mixing them follow
+//! PostgreSQL's: a date plus an interval is a timestamp, the difference of
+//! two timestamps an interval, and so on.
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    parser::BinaryOp,
+    storage::{DataType, Value},
+};
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+/// How many days a month counts as wherever intervals are compared or
+/// fractions of a month spill over into days.
+const DAYS_PER_MONTH: i64 = 30;
+
+const MIN_DATE: i64 = days_from_civil(1, 1, 1);
+const MAX_DATE: i64 = days_from_civil(9999, 12, 31);
+
+/// The fields EXTRACT can take out of a value.
+const EXTRACT_FIELDS: &[&str] = &[
+    "year", "quarter", "month", "week", "day", "dow", "isodow", "doy", "hour", "minute", "second",
+    "epoch",
+];
+
+/// The fields DATE_TRUNC can cut a value down to.
+const TRUNC_FIELDS: &[&str] = &[
+    "year", "quarter", "month", "week", "day", "hour", "minute", "second",
+];
+
+/// An INTERVAL: months, days and microseconds, kept apart because months
+/// differ in length. `1 mon` added to January 31st gives February's last
+/// day, where `30 days` would run into March.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Interval {
+    pub months: i32,
+    pub days: i32,
+    pub micros: i64,
+}
+
+impl Interval {
+    /// The length of the interval with a month counted as 30 days, which
+    /// is how intervals compare: `1 mon` equals `30 days`.
+    fn span(self) -> i128 {
+        (i128::from(self.months) * i128::from(DAYS_PER_MONTH) + i128::from(self.days))
+            * i128::from(MICROS_PER_DAY)
+            + i128::from(self.micros)
+    }
+
+    pub fn compare(&self, other: &Interval) -> Ordering {
+        self.span().cmp(&other.span())
+    }
+
+    /// The key two intervals share when they compare equal.
+    pub fn key(&self) -> i128 {
+        self.span()
+    }
+
+    fn checked_add(self, other: Interval) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_add(other.months)?,
+            days: self.days.checked_add(other.days)?,
+            micros: self.micros.checked_add(other.micros)?,
+        })
+    }
+
+    fn checked_neg(self) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_neg()?,
+            days: self.days.checked_neg()?,
+            micros: self.micros.checked_neg()?,
+        })
+    }
+
+    /// An interval of `months`, `days` and `micros`, any of them
+    /// fractional: what a month leaves over becomes days, and what a day
+    /// leaves over becomes time, the way PostgreSQL scales intervals.
+    fn from_parts(months: f64, days: f64, micros: f64) -> Option<Interval> {
+        let whole_months = months.trunc();
+        let days = days + (months - whole_months) * DAYS_PER_MONTH as f64;
+        let mut whole_days = days.trunc();
+        let mut spilt = ((days - whole_days) * MICROS_PER_DAY as f64).round();
+        // A third of 14 months is 4 months and 20 days and 24 hours, as
+        // floating point has it: 21 days
+        if spilt.abs() >= MICROS_PER_DAY as f64 {
+            whole_days += spilt.signum();
+            spilt -= spilt.signum() * MICROS_PER_DAY as f64;
+        }
+        let micros = (micros + spilt).round();
+        let fits = |x: f64, limit: f64| x.is_finite() && x.abs() <= limit;
+        if !fits(whole_months, i32::MAX as f64)
+            || !fits(whole_days, i32::MAX as f64)
+            || !fits(micros, 9.2e18)
+        {
+            return None;
+        }
+        Some(Interval {
+            months: whole_months as i32,
+            days: whole_days as i32,
+            micros: micros as i64,
+        })
+    }
+
+    fn scale(self, factor: f64) -> Option<Interval> {
+        Interval::from_parts(
+            f64::from(self.months) * factor,
+            f64::from(self.days) * factor,
+            self.micros as f64 * factor,
+        )
+    }
+}
+
+/// Written the way PostgreSQL writes intervals: `1 year 2 mons 3 days
+/// 04:05:06`, leaving out what is zero. A part after a negative one gets
+/// a `+` if it is positive, as in `-1 days +02:00:00`.
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts: Vec<String> = Vec::new();
+        let mut negative = false;
+        let mut sign = |n: i64| {
+            let sign = if n > 0 && negative { "+" } else { "" };
+            negative |= n < 0;
+            sign
+        };
+        let units = [
+            (i64::from(self.months / 12), "year"),
+            (i64::from(self.months % 12), "mon"),
+            (i64::from(self.days), "day"),
+        ];
+        for (n, unit) in units {
+            if n != 0 {
+                let plural = if n == 1 { "" } else { "s" };
+                parts.push(format!("{}{} {}{}", sign(n), n, unit, plural));
+            }
+        }
+        if self.micros != 0 || parts.is_empty() {
+            let sign = match sign(self.micros) {
+                _ if self.micros < 0 => "-",
+                sign => sign,
+            };
+            let micros = self.micros.unsigned_abs();
+            let hours = micros / MICROS_PER_HOUR as u64;
+            parts.push(format!(
+                "{}{:02}:{}",
+                sign,
+                hours,
+                clock(micros % MICROS_PER_HOUR as u64)
+            ));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Whether values of `data_type` are dates, times or intervals.
+pub fn is_temporal(data_type: DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Date | DataType::Time | DataType::Timestamp | DataType::Interval
+    )
+}
+
+/// The timestamp a date's day starts with.
+pub fn midnight(date: i32) -> i64 {
+    i64::from(date) * MICROS_PER_DAY
+}
+
+/// The current time, as a TIMESTAMP in UTC.
+pub fn now() -> Value {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Value::Timestamp(since_epoch.as_micros() as i64)
+}
+
+// ---------------------------------------------------------------------------
+// The calendar
+// ---------------------------------------------------------------------------
+
+/// Days from 1970-01-01 to the given day, counting back for days before.
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Counted in years that start in March, so February's leap day comes last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// `date` moved on by `months`, kept within the month it lands in:
+/// a month after January 31st is February's last day.
+fn add_months(date: i64, months: i32) -> Option<i64> {
+    let (year, month, day) = civil_from_days(date);
+    let total = year * 12 + month - 1 + i64::from(months);
+    let (year, month) = (total.div_euclid(12), total.rem_euclid(12) + 1);
+    if !(1..=9999).contains(&year) {
+        return None;
+    }
+    Some(days_from_civil(
+        year,
+        month,
+        day.min(days_in_month(year, month)),
+    ))
+}
+
+fn check_date(days: i64) -> Result<i32, String> {
+    if (MIN_DATE..=MAX_DATE).contains(&days) {
+        Ok(days as i32)
+    } else {
+        Err("Date out of range".to_string())
+    }
+}
+
+fn check_timestamp(micros: i64) -> Result<i64, String> {
+    if (MIN_DATE..=MAX_DATE).contains(&micros.div_euclid(MICROS_PER_DAY)) {
+        Ok(micros)
+    } else {
+        Err("Timestamp out of range".to_string())
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Writing values out
+// ---------------------------------------------------------------------------
+
+/// `YYYY-MM-DD`.
+pub fn format_date(date: i32) -> String {
+    let (year, month, day) = civil_from_days(i64::from(date));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// `HH:MM:SS`, with the fraction of a second only if there is one.
+pub fn format_time(micros: i64) -> String {
+    let micros = micros as u64;
+    format!(
+        "{:02}:{}",
+        micros / MICROS_PER_HOUR as u64,
+        clock(micros % MICROS_PER_HOUR as u64)
+    )
+}
+
+/// `YYYY-MM-DD HH:MM:SS`, with the fraction of a second only if there is one.
+pub fn format_timestamp(micros: i64) -> String {
+    let date = micros.div_euclid(MICROS_PER_DAY) as i32;
+    format!(
+        "{} {}",
+        format_date(date),
+        format_time(micros.rem_euclid(MICROS_PER_DAY))
+    )
+}
+
+/// `MM:SS[.ffffff]` for less than an hour's worth of microseconds.
+fn clock(micros: u64) -> String {
+    let (minutes, micros) = (
+        micros / MICROS_PER_MINUTE as u64,
+        micros % MICROS_PER_MINUTE as u64,
+    );
+    let (seconds, fraction) = (
+        micros / MICROS_PER_SECOND as u64,
+        micros % MICROS_PER_SECOND as u64,
+    );
+    if fraction == 0 {
+        format!("{:02}:{:02}", minutes, seconds)
+    } else {
+        let fraction = format!("{:06}", fraction);
+        format!(
+            "{:02}:{:02}.{}",
+            minutes,
+            seconds,
+            fraction.trim_end_matches('0')
+        )
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Reading values in
+// ---------------------------------------------------------------------------
+
+/// Read `text` as a value of `data_type`, one of the temporal types:
+/// `2024-01-31` for a DATE, `13:45:00.5` for a TIME, `2024-01-31 13:45`
+/// or `2024-01-31T13:45:00+02:00` for a TIMESTAMP, and `1 day 02:00:00` or
+/// ISO-8601's `P1DT2H` for an INTERVAL. `None` if it isn't one.
+pub fn parse(data_type: DataType, text: &str) -> Option<Value> {
+    let text = text.trim();
+    match data_type {
+        DataType::Date => parse_date(text).map(|d| Value::Date(d as i32)),
+        DataType::Time => parse_time(text).map(Value::Time),
+        DataType::Timestamp => parse_timestamp(text).map(Value::Timestamp),
+        DataType::Interval => parse_interval(text).map(Value::Interval),
+        _ => None,
+    }
+}
+
+/// Digits only, at least `min` and at most `max` of them.
+fn number(text: &str, min: usize, max: usize) -> Option<i64> {
+    let fits = (min..=max).contains(&text.len()) && text.bytes().all(|b| b.is_ascii_digit());
+    fits.then(|| text.parse().ok()).flatten()
+}
+
+fn parse_date(text: &str) -> Option<i64> {
+    let mut parts = text.split('-');
+    let year = number(parts.next()?, 4, 4)?;
+    let month = number(parts.next()?, 1, 2)?;
+    let day = number(parts.next()?, 1, 2)?;
+    let valid = parts.next().is_none()
+        && year >= 1
+        && (1..=12).contains(&month)
+        && (1..=days_in_month(year, month)).contains(&day);
+    valid.then(|| days_from_civil(year, month, day))
+}
+
+/// `HH:MM[:SS[.ffffff]]`; digits past the microseconds are dropped.
+fn parse_time(text: &str) -> Option<i64> {
+    let micros = parse_clock(text, 2)?;
+    (micros < MICROS_PER_DAY).then_some(micros)
+}
+
+/// `H:MM[:SS[.ffffff]]` with up to `hour_digits` digits of hours, as
+/// microseconds; minutes and seconds must be under 60.
+fn parse_clock(text: &str, hour_digits: usize) -> Option<i64> {
+    let mut parts = text.split(':');
+    let hours = number(parts.next()?, 1, hour_digits)?;
+    let minutes = number(parts.next()?, 2, 2)?;
+    let seconds = match parts.next() {
+        Some(seconds) => {
+            let (whole, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+            let whole = number(whole, 2, 2)?;
+            let fraction = match fraction {
+                "" if seconds.ends_with('.') => return None,
+                "" => 0,
+                _ => {
+                    let digits = &fraction[..fraction.len().min(6)];
+                    number(fraction, 1, usize::MAX)?;
+                    number(digits, 1, 6)? * 10i64.pow(6 - digits.len() as u32)
+                }
+            };
+            (whole < 60).then_some(whole * MICROS_PER_SECOND + fraction)?
+        }
+        None => 0,
+    };
+    if parts.next().is_some() || minutes >= 60 {
+        return None;
+    }
+    hours
+        .checked_mul(MICROS_PER_HOUR)?
+        .checked_add(minutes * MICROS_PER_MINUTE + seconds)
+}
+
+/// A date, then optionally a time after a space or `T`, then optionally
+/// an offset from UTC: `Z`, `+02`, `+02:00` or `-0530`. There are no time
+/// zones, so a timestamp with an offset is kept as the UTC time it is.
+fn parse_timestamp(text: &str) -> Option<i64> {
+    let (date, time) = match text.find([' ', 'T']) {
+        Some(at) => (&text[..at], Some(text[at + 1..].trim_start())),
+        None => (text, None),
+    };
+    let date = parse_date(date)?;
+    let (time, offset) = match time {
+        Some(time) => {
+            let (clock, offset) = split_offset(time)?;
+            (parse_time(clock)?, offset)
+        }
+        None => (0, 0),
+    };
+    check_timestamp(date * MICROS_PER_DAY + time - offset).ok()
+}
+
+/// A time of day and its offset from UTC in microseconds, 0 if it has
+/// none.
+fn split_offset(time: &str) -> Option<(&str, i64)> {
+    if let Some(clock) = time.strip_suffix('Z') {
+        return Some((clock, 0));
+    }
+    let Some(at) = time.rfind(['+', '-']) else {
+        return Some((time, 0));
+    };
+    let (clock, offset) = (time[..at].trim_end(), &time[at + 1..]);
+    let (hours, minutes) = match offset.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if offset.len() == 4 => (offset.get(..2)?, offset.get(2..)?),
+        None => (offset, "00"),
+    };
+    let (hours, minutes) = (number(hours, 2, 2)?, number(minutes, 2, 2)?);
+    if hours >= 24 || minutes >= 60 {
+        return None;
+    }
+    let offset = hours * MICROS_PER_HOUR + minutes * MICROS_PER_MINUTE;
+    Some((
+        clock,
+        if time[at..].starts_with('-') {
+            -offset
+        } else {
+            offset
+        },
+    ))
+}
+
+fn parse_interval(text: &str) -> Option<Interval> {
+    if let Some(iso) = text.strip_prefix(['P', 'p']) {
+        return parse_iso_interval(iso);
+    }
+    let (mut months, mut days, mut micros) = (0.0, 0.0, 0.0);
+    let mut words = text.split_whitespace().peekable();
+    words.peek()?;
+    while let Some(word) = words.next() {
+        if word.contains(':') {
+            let (negative, clock) = match word.strip_prefix('-') {
+                Some(clock) => (true, clock),
+                None => (false, word.strip_prefix('+').unwrap_or(word)),
+            };
+            let time = parse_clock(clock, 9)? as f64;
+            micros += if negative { -time } else { time };
+            continue;
+        }
+        let n = signed_number(word)?;
+        let unit = words.next()?.to_lowercase();
+        let singular = unit.strip_suffix('s').unwrap_or(&unit);
+        match singular {
+            "year" | "yr" => months += n * 12.0,
+            "mon" | "month" => months += n,
+            "week" => days += n * 7.0,
+            "day" => days += n,
+            "hour" | "hr" => micros += n * MICROS_PER_HOUR as f64,
+            "minute" | "min" => micros += n * MICROS_PER_MINUTE as f64,
+            "second" | "sec" => micros += n * MICROS_PER_SECOND as f64,
+            "millisecond" => micros += n * 1000.0,
+            "microsecond" => micros += n,
+            _ => return None,
+        }
+    }
+    Interval::from_parts(months, days, micros)
+}
+
+/// A decimal number with an optional sign, like `-1.5`.
+fn signed_number(word: &str) -> Option<f64> {
+    let digits = word.strip_prefix(['+', '-']).unwrap_or(word);
+    let plain = !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+        && digits.bytes().any(|b| b.is_ascii_digit());
+    plain.then(|| word.parse().ok()).flatten()
+}
+
+/// ISO-8601's `PnYnMnWnDTnHnMnS`, after the `P`, where any number may be
+/// left out with its letter and the last may have a fraction.
+fn parse_iso_interval(text: &str) -> Option<Interval> {
+    let (date, time) = match text.split_once(['T', 't']) {
+        Some((date, time)) if !time.is_empty() => (date, time),
+        Some(_) => return None,
+        None => (text, ""),
+    };
+    let (mut months, mut days, mut micros) = (0.0, 0.0, 0.0);
+    let mut read = |text: &str, units: &str| -> Option<()> {
+        let mut rest = text;
+        let mut seen = 0;
+        while !rest.is_empty() {
+            let at = rest.find(|c: char| c.is_ascii_alphabetic())?;
+            let n = signed_number(&rest[..at])?;
+            let letter = rest[at..].chars().next()?.to_ascii_uppercase();
+            // Each letter at most once, in order
+            let position = units[seen..].find(letter)? + seen;
+            seen = position + 1;
+            match (units, letter) {
+                ("YMWD", 'Y') => months += n * 12.0,
+                ("YMWD", 'M') => months += n,
+                ("YMWD", 'W') => days += n * 7.0,
+                ("YMWD", _) => days += n,
+                (_, 'H') => micros += n * MICROS_PER_HOUR as f64,
+                (_, 'M') => micros += n * MICROS_PER_MINUTE as f64,
+                _ => micros += n * MICROS_PER_SECOND as f64,
+            }
+            rest = &rest[at + 1..];
+        }
+        Some(())
+    };
+    if date.is_empty() && time.is_empty() {
+        return None;
+    }
+    read(date, "YMWD")?;
+    read(time, "HMS")?;
+    Interval::from_parts(months, days, micros)
+}
+
+// ---------------------------------------------------------------------------
+// Conversions and arithmetic
+// ---------------------------------------------------------------------------
+
+/// Convert `value` to `to`, one of the temporal types: text is read as
+/// [`parse`] reads it, a date is its midnight, and a timestamp gives its
+/// date or its time of day. A `strict` conversion, the one used to store
+/// a value, refuses to drop the time of day from a timestamp.
+pub fn convert(to: DataType, value: &Value, strict: bool) -> Option<Value> {
+    match (to, value) {
+        (_, Value::Text(s)) => parse(to, s),
+        (DataType::Date, Value::Date(_))
+        | (DataType::Time, Value::Time(_))
+        | (DataType::Timestamp, Value::Timestamp(_))
+        | (DataType::Interval, Value::Interval(_)) => Some(value.clone()),
+        (DataType::Timestamp, Value::Date(d)) => Some(Value::Timestamp(midnight(*d))),
+        (DataType::Date, Value::Timestamp(t)) if !strict || t % MICROS_PER_DAY == 0 => {
+            Some(Value::Date(t.div_euclid(MICROS_PER_DAY) as i32))
+        }
+        (DataType::Time, Value::Timestamp(t)) if !strict => {
+            Some(Value::Time(t.rem_euclid(MICROS_PER_DAY)))
+        }
+        _ => None,
+    }
+}
+
+/// Make `l` and `r` comparable: text compared with a date, time,
+/// timestamp or interval is read as one of the same type, the way
+/// PostgreSQL reads a quoted literal.
+pub fn comparable(l: Value, r: Value) -> Result<(Value, Value), String> {
+    let read = |text: &str, like: &Value| {
+        let data_type = like.data_type().expect("not NULL");
+        parse(data_type, text)
+            .ok_or_else(|| format!("Cannot compare {} with '{}'", data_type, text))
+    };
+    match (&l, &r) {
+        (Value::Text(s), v) if v.data_type().is_some_and(is_temporal) => Ok((read(s, v)?, r)),
+        (v, Value::Text(s)) if v.data_type().is_some_and(is_temporal) => {
+            let r = read(s, v)?;
+            Ok((l, r))
+        }
+        _ => Ok((l, r)),
+    }
+}
+
+/// The type `l op r` gives for operands of types `l` and `r`, at least one
+/// of them temporal; `None` where the operator doesn't apply. Worked out
+/// by applying it to a value of each type, so it can't disagree with
+/// [`arithmetic`].
+pub fn result_type(op: BinaryOp, l: DataType, r: DataType) -> Option<DataType> {
+    let sample = |t: DataType| match t {
+        DataType::Integer => Value::Integer(1),
+        DataType::Real => Value::Real(1.0),
+        DataType::Text => Value::Text(String::new()),
+        DataType::Date => Value::Date(0),
+        DataType::Time => Value::Time(0),
+        DataType::Timestamp => Value::Timestamp(0),
+        DataType::Interval => Value::Interval(Interval::default()),
+    };
+    arithmetic(op, &sample(l), &sample(r))?
+        .ok()
+        .and_then(|v| v.data_type())
+}
+
+/// `l op r` for `+`, `-`, `*` and `/` where either side is a date, time,
+/// timestamp or interval; `None` if neither is, leaving it to ordinary
+/// arithmetic.
+pub fn arithmetic(op: BinaryOp, l: &Value, r: &Value) -> Option<Result<Value, String>> {
+    use BinaryOp::{Add, Divide, Multiply, Subtract};
+    use Value::{Date, Integer, Interval as Span, Real, Time, Timestamp};
+
+    let temporal = |v: &Value| v.data_type().is_some_and(is_temporal);
+    if !temporal(l) && !temporal(r) {
+        return None;
+    }
+    let range = |what: &str| format!("{} out of range", what);
+    let result = match (op, l, r) {
+        (Add, Date(d), Integer(n)) | (Add, Integer(n), Date(d)) => i64::from(*d)
+            .checked_add(*n)
+            .ok_or_else(|| range("Date"))
+            .and_then(check_date)
+            .map(Date),
+        (Subtract, Date(d), Integer(n)) => i64::from(*d)
+            .checked_sub(*n)
+            .ok_or_else(|| range("Date"))
+            .and_then(check_date)
+            .map(Date),
+        (Subtract, Date(a), Date(b)) => Ok(Integer(i64::from(*a) - i64::from(*b))),
+        (Add, Date(d), Time(t)) | (Add, Time(t), Date(d)) => Ok(Timestamp(midnight(*d) + t)),
+
+        (Add, Date(d), Span(i)) | (Add, Span(i), Date(d)) => {
+            add_interval(midnight(*d), *i).map(Timestamp)
+        }
+        (Add, Timestamp(t), Span(i)) | (Add, Span(i), Timestamp(t)) => {
+            add_interval(*t, *i).map(Timestamp)
+        }
+        (Subtract, Date(d), Span(i)) => negate(*i)
+            .and_then(|i| add_interval(midnight(*d), i))
+            .map(Timestamp),
+        (Subtract, Timestamp(t), Span(i)) => {
+            negate(*i).and_then(|i| add_interval(*t, i)).map(Timestamp)
+        }
+        (Subtract, Timestamp(_) | Date(_), Timestamp(_) | Date(_)) => {
+            let at = |v: &Value| match v {
+                Date(d) => midnight(*d),
+                Timestamp(t) => *t,
+                _ => unreachable!("matched above"),
+            };
+            // Whole days are counted as days, as PostgreSQL does
+            let difference = at(l) - at(r);
+            Ok(Span(Interval {
+                months: 0,
+                days: (difference / MICROS_PER_DAY) as i32,
+                micros: difference % MICROS_PER_DAY,
+            }))
+        }
+
+        // A time of day wraps around midnight, and ignores whole days
+        (Add, Time(t), Span(i)) | (Add, Span(i), Time(t)) => Ok(Time(
+            (t + i.micros % MICROS_PER_DAY).rem_euclid(MICROS_PER_DAY),
+        )),
+        (Subtract, Time(t), Span(i)) => Ok(Time(
+            (t - i.micros % MICROS_PER_DAY).rem_euclid(MICROS_PER_DAY),
+        )),
+        (Subtract, Time(a), Time(b)) => Ok(Span(Interval {
+            micros: a - b,
+            ..Interval::default()
+        })),
+
+        (Add, Span(a), Span(b)) => a.checked_add(*b).ok_or_else(|| range("Interval")).map(Span),
+        (Subtract, Span(a), Span(b)) => negate(*b)
+            .and_then(|b| a.checked_add(b).ok_or_else(|| range("Interval")))
+            .map(Span),
+        (Multiply, Span(i), n @ (Integer(_) | Real(_)))
+        | (Multiply, n @ (Integer(_) | Real(_)), Span(i)) => scale(*i, as_f64(n)).map(Span),
+        (Divide, Span(_), Integer(0)) => Err("Division by zero".to_string()),
+        (Divide, Span(_), Real(r)) if *r == 0.0 => Err("Division by zero".to_string()),
+        (Divide, Span(i), n @ (Integer(_) | Real(_))) => scale(*i, 1.0 / as_f64(n)).map(Span),
+        _ => Err(format!(
+            "Cannot apply '{}' to {} and {}",
+            op.symbol(),
+            type_name(l),
+            type_name(r)
+        )),
+    };
+    Some(result)
+}
+
+fn as_f64(v: &Value) -> f64 {
+    match v {
+        Value::Integer(i) => *i as f64,
+        Value::Real(r) => *r,
+        _ => 0.0,
+    }
+}
+
+fn type_name(v: &Value) -> String {
+    v.data_type().map_or("NULL".to_string(), |t| t.to_string())
+}
+
+fn negate(interval: Interval) -> Result<Interval, String> {
+    interval
+        .checked_neg()
+        .ok_or_else(|| "Interval out of range".to_string())
+}
+
+/// Negate an interval, for unary minus.
+pub fn negate_interval(interval: Interval) -> Result<Value, String> {
+    negate(interval).map(Value::Interval)
+}
+
+fn scale(interval: Interval, factor: f64) -> Result<Interval, String> {
+    interval
+        .scale(factor)
+        .ok_or_else(|| "Interval out of range".to_string())
+}
+
+/// `timestamp + interval`: first the months, then the days, then the time.
+fn add_interval(timestamp: i64, interval: Interval) -> Result<i64, String> {
+    let (date, time) = (
+        timestamp.div_euclid(MICROS_PER_DAY),
+        timestamp.rem_euclid(MICROS_PER_DAY),
+    );
+    add_months(date, interval.months)
+        .map(|date| date + i64::from(interval.days))
+        .and_then(|date| (date * MICROS_PER_DAY + time).checked_add(interval.micros))
+        .ok_or_else(|| "Timestamp out of range".to_string())
+        .and_then(check_timestamp)
+}
+
+// ---------------------------------------------------------------------------
+// Functions
+// ---------------------------------------------------------------------------
+
+/// Check that EXTRACT knows `field`.
+pub fn check_extract_field(field: &str) -> Result<(), String> {
+    if EXTRACT_FIELDS.contains(&field) {
+        Ok(())
+    } else {
+        Err(format!(
+            "EXTRACT can't take '{}'; it takes one of: {}",
+            field,
+            EXTRACT_FIELDS.join(", ")
+        ))
+    }
+}
+
+/// The type EXTRACT gives for `field`: seconds and the epoch may have a
+/// fraction, the rest are whole numbers.
+pub fn extract_type(field: &str) -> DataType {
+    match field {
+        "second" | "epoch" => DataType::Real,
+        _ => DataType::Integer,
+    }
+}
+
+/// Text given to EXTRACT or DATE_TRUNC, read as a timestamp.
+fn timestamp_arg(function: &str, v: &Value) -> Result<Value, String> {
+    match v {
+        Value::Text(s) => parse(DataType::Timestamp, s)
+            .ok_or_else(|| format!("{}: '{}' is not a timestamp", function, s)),
+        v => Ok(v.clone()),
+    }
+}
+
+/// `EXTRACT(field FROM value)`: a part of a date, time, timestamp or
+/// interval as a number. `dow` counts from Sunday as 0, `isodow` from
+/// Monday as 1, and `week` is the ISO-8601 week of the year.
+pub fn extract(field: &str, value: &Value) -> Result<Value, String> {
+    let value = timestamp_arg("EXTRACT", value)?;
+    let integer = |n: i64| Ok(Value::Integer(n));
+    let seconds = |micros: i64| Ok(Value::Real(micros as f64 / MICROS_PER_SECOND as f64));
+    let missing = |what: &str| Err(format!("EXTRACT: a {} has no {}", what, field));
+    let time_field = |micros: i64| match field {
+        "hour" => integer(micros / MICROS_PER_HOUR),
+        "minute" => integer(micros / MICROS_PER_MINUTE % 60),
+        "second" => seconds(micros % MICROS_PER_MINUTE),
+        _ => unreachable!("a time field"),
+    };
+    match value {
+        Value::Date(_) | Value::Timestamp(_) => {
+            let at = match value {
+                Value::Date(d) => midnight(d),
+                Value::Timestamp(t) => t,
+                _ => unreachable!("matched above"),
+            };
+            let (date, time) = (at.div_euclid(MICROS_PER_DAY), at.rem_euclid(MICROS_PER_DAY));
+            let (year, month, day) = civil_from_days(date);
+            // 1970-01-01 was a Thursday
+            let weekday = (date + 4).rem_euclid(7);
+            match field {
+                "year" => integer(year),
+                "quarter" => integer((month - 1) / 3 + 1),
+                "month" => integer(month),
+                "day" => integer(day),
+                "dow" => integer(weekday),
+                "isodow" => integer(if weekday == 0 { 7 } else { weekday }),
+                "doy" => integer(date - days_from_civil(year, 1, 1) + 1),
+                "week" => {
+                    // The week belongs to the year its Thursday falls in
+                    let thursday = date - (weekday + 6) % 7 + 3;
+                    let (year, _, _) = civil_from_days(thursday);
+                    integer((thursday - days_from_civil(year, 1, 1)) / 7 + 1)
+                }
+                "epoch" => seconds(at),
+                _ if matches!(value, Value::Date(_)) => missing("DATE"),
+                _ => time_field(time),
+            }
+        }
+        Value::Time(t) => match field {
+            "hour" | "minute" | "second" => time_field(t),
+            "epoch" => seconds(t),
+            _ => missing("TIME"),
+        },
+        Value::Interval(i) => match field {
+            "year" => integer(i64::from(i.months / 12)),
+            "quarter" => integer(i64::from(i.months % 12 / 3 + 1)),
+            "month" => integer(i64::from(i.months % 12)),
+            "day" => integer(i64::from(i.days)),
+            "hour" => integer(i.micros / MICROS_PER_HOUR),
+            "minute" => integer(i.micros / MICROS_PER_MINUTE % 60),
+            "second" => seconds(i.micros % MICROS_PER_MINUTE),
+            // A year is 365.25 days here, and the other months 30
+            "epoch" => {
+                let days = f64::from(i.months / 12) * 365.25
+                    + f64::from(i.months % 12 * 30)
+                    + f64::from(i.days);
+                Ok(Value::Real(
+                    days * 86400.0 + i.micros as f64 / MICROS_PER_SECOND as f64,
+                ))
+            }
+            _ => missing("INTERVAL"),
+        },
+        v => Err(format!(
+            "EXTRACT expects a DATE, TIME, TIMESTAMP or INTERVAL, got '{}'",
+            v
+        )),
+    }
+}
+
+/// `DATE_TRUNC(field, value)`: a timestamp (or a date, taken as one) cut
+/// back to the start of its year, quarter, month, week (from Monday),
+/// day, hour, minute or second; or an interval with what is smaller than
+/// `field` dropped.
+pub fn date_trunc(field: &Value, value: &Value) -> Result<Value, String> {
+    let field = field.to_string().to_lowercase();
+    if !TRUNC_FIELDS.contains(&field.as_str()) {
+        return Err(format!(
+            "DATE_TRUNC can't truncate to '{}'; it takes one of: {}",
+            field,
+            TRUNC_FIELDS.join(", ")
+        ));
+    }
+    let step = match field.as_str() {
+        "hour" => Some(MICROS_PER_HOUR),
+        "minute" => Some(MICROS_PER_MINUTE),
+        "second" => Some(MICROS_PER_SECOND),
+        _ => None,
+    };
+    match timestamp_arg("DATE_TRUNC", value)? {
+        Value::Date(d) => date_trunc(&Value::Text(field), &Value::Timestamp(midnight(d))),
+        Value::Timestamp(t) => {
+            if let Some(step) = step {
+                return Ok(Value::Timestamp(t - t.rem_euclid(step)));
+            }
+            let date = t.div_euclid(MICROS_PER_DAY);
+            let (year, month, _) = civil_from_days(date);
+            let date = match field.as_str() {
+                "year" => days_from_civil(year, 1, 1),
+                "quarter" => days_from_civil(year, month - (month - 1) % 3, 1),
+                "month" => days_from_civil(year, month, 1),
+                // Back to Monday; 1970-01-01 was a Thursday
+                "week" => date - (date + 3).rem_euclid(7),
+                _ => date,
+            };
+            check_date(date).map(|date| Value::Timestamp(midnight(date)))
+        }
+        Value::Interval(i) => {
+            let (months, days, micros) = match field.as_str() {
+                "year" => (i.months - i.months % 12, 0, 0),
+                "quarter" => (i.months - i.months % 3, 0, 0),
+                "month" => (i.months, 0, 0),
+                "week" => return Err("DATE_TRUNC can't truncate an INTERVAL to weeks".to_string()),
+                "day" => (i.months, i.days, 0),
+                _ => (
+                    i.months,
+                    i.days,
+                    i.micros - i.micros % step.expect("a time unit"),
+                ),
+            };
+            Ok(Value::Interval(Interval {
+                months,
+                days,
+                micros,
+            }))
+        }
+        v => Err(format!(
+            "DATE_TRUNC expects a DATE, TIMESTAMP or INTERVAL, got '{}'",
+            v
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(data_type: DataType, text: &str) -> Value {
+        parse(data_type, text).unwrap_or_else(|| panic!("not a {}: '{}'", data_type, text))
+    }
+
+    fn date(text: &str) -> Value {
+        value(DataType::Date, text)
+    }
+
+    fn time(text: &str) -> Value {
+        value(DataType::Time, text)
+    }
+
+    fn timestamp(text: &str) -> Value {
+        value(DataType::Timestamp, text)
+    }
+
+    fn interval(text: &str) -> Value {
+        value(DataType::Interval, text)
+    }
+
+    fn span(text: &str) -> Interval {
+        match interval(text) {
+            Value::Interval(i) => i,
+            _ => unreachable!("read as an INTERVAL"),
+        }
+    }
+
+    /// `l op r`, written out, or the error it gives.
+    fn apply(op: BinaryOp, l: &Value, r: &Value) -> Result<String, String> {
+        arithmetic(op, l, r)
+            .expect("a temporal operand")
+            .map(|v| v.to_string())
+    }
+
+    fn trunc(field: &str, value: &Value) -> String {
+        date_trunc(&Value::Text(field.to_string()), value)
+            .expect("truncates")
+            .to_string()
+    }
+
+    // -----------------------------------------------------------------------
+    // Reading and writing
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn reads_intervals() {
+        let cases = [
+            (
+                "1 year 2 mons 3 days 04:05:06",
+                "1 year 2 mons 3 days 04:05:06",
+            ),
+            ("1.5 years", "1 year 6 mons"),
+            ("2 weeks 90 minutes", "14 days 01:30:00"),
+            ("-1 days +02:00:00", "-1 days +02:00:00"),
+            ("0.5 day", "12:00:00"),
+            ("250 milliseconds", "00:00:00.25"),
+            ("P2W", "14 days"),
+            ("P1Y2M3DT4H5M6S", "1 year 2 mons 3 days 04:05:06"),
+            ("PT1.5H", "01:30:00"),
+            ("0 days", "00:00:00"),
+        ];
+        for (text, written) in cases {
+            assert_eq!(interval(text).to_string(), written, "reading '{}'", text);
+        }
+        for text in ["", "1", "1 fortnight", "P", "P1DT", "P1H", "1 day 25:61"] {
+            assert_eq!(parse(DataType::Interval, text), None, "reading '{}'", text);
+        }
+    }
+
+    #[test]
+    fn reads_dates_and_times() {
+        assert_eq!(date("2024-02-29").to_string(), "2024-02-29");
+        assert_eq!(date("0001-01-01"), Value::Date(MIN_DATE as i32));
+        assert_eq!(time("13:45:00.5").to_string(), "13:45:00.5");
+        assert_eq!(
+            timestamp("2024-01-31T13:45:00Z").to_string(),
+            "2024-01-31 13:45:00"
+        );
+        for text in ["2023-02-29", "2024-13-01", "0000-01-01", "2024-1-1x"] {
+            assert_eq!(parse(DataType::Date, text), None, "reading '{}'", text);
+        }
+        assert_eq!(parse(DataType::Time, "24:00"), None);
+    }
+
+    #[test]
+    fn reads_offsets_from_utc() {
+        let cases = [
+            ("2024-01-31T13:45:00Z", "2024-01-31 13:45:00"),
+            ("2024-01-31T13:45:00+02:00", "2024-01-31 11:45:00"),
+            ("2024-01-31 13:45 -0530", "2024-01-31 19:15:00"),
+            ("2024-01-31 01:00:00.5+02", "2024-01-30 23:00:00.5"),
+            ("2024-12-31T23:30:00-01:00", "2025-01-01 00:30:00"),
+        ];
+        for (text, utc) in cases {
+            assert_eq!(timestamp(text).to_string(), utc, "reading '{}'", text);
+        }
+        for text in [
+            "2024-01-31 13:45+2",
+            "2024-01-31 13:45+24:00",
+            "2024-01-31 13:45+02:60",
+            "2024-01-31 13:45 Europe/Paris",
+            "2024-01-31Z",
+            "0001-01-01 00:00+01:00",
+        ] {
+            assert_eq!(parse(DataType::Timestamp, text), None, "reading '{}'", text);
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Arithmetic
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn adds_months_to_month_ends() {
+        use BinaryOp::{Add, Subtract};
+        let cases = [
+            ("2024-01-31", "1 mon", "2024-02-29 00:00:00"),
+            ("2023-01-31", "1 mon", "2023-02-28 00:00:00"),
+            ("2024-03-31", "-1 mons", "2024-02-29 00:00:00"),
+            ("2024-02-29", "1 year", "2025-02-28 00:00:00"),
+            ("2024-01-31", "1 mon 1 day", "2024-03-01 00:00:00"),
+        ];
+        for (day, span, result) in cases {
+            assert_eq!(
+                apply(Add, &date(day), &interval(span)),
+                Ok(result.to_string())
+            );
+        }
+        assert_eq!(
+            apply(Subtract, &date("2024-03-31"), &interval("1 mon")),
+            Ok("2024-02-29 00:00:00".to_string())
+        );
+    }
+
+    #[test]
+    fn adds_to_timestamps() {
+        use BinaryOp::{Add, Subtract};
+        let at = timestamp("2024-01-31 22:30:00");
+        let result = |op, l: &Value, r: &Value| apply(op, l, r).expect("applies");
+        assert_eq!(
+            result(Add, &at, &interval("2 hours")),
+            "2024-02-01 00:30:00"
+        );
+        assert_eq!(
+            result(Add, &interval("1 mon 01:00:00"), &at),
+            "2024-02-29 23:30:00"
+        );
+        assert_eq!(
+            result(Subtract, &at, &interval("31 days")),
+            "2023-12-31 22:30:00"
+        );
+        assert_eq!(
+            result(Subtract, &at, &timestamp("2024-01-01 23:00:00")),
+            "29 days 23:30:00"
+        );
+        assert_eq!(
+            result(Subtract, &date("2024-01-01"), &at),
+            "-30 days -22:30:00"
+        );
+        assert_eq!(
+            result(Add, &date("2024-01-31"), &Value::Integer(1)),
+            "2024-02-01"
+        );
+        assert_eq!(
+            result(Subtract, &date("2024-03-01"), &date("2024-02-01")),
+            "29"
+        );
+        assert_eq!(
+            result(Add, &date("2024-01-31"), &time("12:00")),
+            "2024-01-31 12:00:00"
+        );
+    }
+
+    #[test]
+    fn wraps_times_around_midnight() {
+        use BinaryOp::{Add, Subtract};
+        let result = |op, l: &Value, r: &Value| apply(op, l, r).expect("applies");
+        assert_eq!(result(Add, &time("23:30"), &interval("1 hour")), "00:30:00");
+        assert_eq!(result(Add, &time("10:00"), &interval("3 days")), "10:00:00");
+        assert_eq!(
+            result(Subtract, &time("00:15"), &interval("30 minutes")),
+            "23:45:00"
+        );
+        assert_eq!(
+            result(Subtract, &time("08:00"), &time("09:30")),
+            "-01:30:00"
+        );
+    }
+
+    #[test]
+    fn scales_intervals() {
+        use BinaryOp::{Add, Divide, Multiply, Subtract};
+        let long = interval("1 year 2 mons 3 days 04:05:06");
+        let result = |op, l: &Value, r: &Value| apply(op, l, r).expect("applies");
+        assert_eq!(
+            result(Divide, &long, &Value::Integer(3)),
+            "4 mons 21 days 01:21:42"
+        );
+        assert_eq!(
+            result(Multiply, &long, &Value::Real(0.5)),
+            "7 mons 1 day 14:02:33"
+        );
+        assert_eq!(
+            result(Multiply, &Value::Integer(2), &interval("1 day")),
+            "2 days"
+        );
+        assert_eq!(
+            result(Add, &interval("1 day"), &interval("-02:00:00")),
+            "1 day -02:00:00"
+        );
+        assert_eq!(
+            result(Subtract, &interval("1 mon"), &interval("1 day")),
+            "1 mon -1 days"
+        );
+        assert_eq!(
+            negate_interval(span("-1 days +02:00:00")).map(|v| v.to_string()),
+            Ok("1 day -02:00:00".to_string())
+        );
+        assert_eq!(
+            apply(Divide, &long, &Value::Integer(0)),
+            Err("Division by zero".to_string())
+        );
+    }
+
+    #[test]
+    fn compares_months_as_thirty_days() {
+        assert_eq!(span("1 mon").compare(&span("30 days")), Ordering::Equal);
+        assert_eq!(span("1 day").compare(&span("23:59:59")), Ordering::Greater);
+        assert_eq!(span("1 mon").key(), span("720:00:00").key());
+    }
+
+    #[test]
+    fn reports_results_out_of_range() {
+        use BinaryOp::{Add, Multiply, Subtract};
+        let error = |op, l: &Value, r: &Value| apply(op, l, r).expect_err("out of range");
+        assert_eq!(
+            error(Add, &date("9999-12-31"), &Value::Integer(1)),
+            "Date out of range"
+        );
+        assert_eq!(
+            error(Subtract, &date("0001-01-01"), &Value::Integer(1)),
+            "Date out of range"
+        );
+        assert_eq!(
+            error(Add, &timestamp("9999-12-31 23:00"), &interval("1 hour")),
+            "Timestamp out of range"
+        );
+        assert_eq!(
+            error(Add, &date("2024-01-01"), &interval("10000 years")),
+            "Timestamp out of range"
+        );
+        assert_eq!(
+            error(Multiply, &interval("1000000000 days"), &Value::Integer(3)),
+            "Interval out of range"
+        );
+        assert_eq!(
+            error(Add, &date("2024-01-01"), &Value::Real(1.5)),
+            "Cannot apply '+' to DATE and REAL"
+        );
+        assert_eq!(
+            negate_interval(Interval {
+                months: i32::MIN,
+                ..Interval::default()
+            }),
+            Err("Interval out of range".to_string())
+        );
+    }
+
+    // -----------------------------------------------------------------------
+    // Functions
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn extracts_fields() {
+        let day = date("2024-01-31");
+        let cases = [
+            ("year", &day, Value::Integer(2024)),
+            ("quarter", &day, Value::Integer(1)),
+            ("dow", &day, Value::Integer(3)),
+            ("dow", &date("2024-02-04"), Value::Integer(0)),
+            ("isodow", &date("2024-02-04"), Value::Integer(7)),
+            ("doy", &date("2024-12-31"), Value::Integer(366)),
+            ("week", &date("2023-12-25"), Value::Integer(52)),
+            ("week", &date("2021-01-03"), Value::Integer(53)),
+            ("epoch", &day, Value::Real(1706659200.0)),
+            ("epoch", &date("1969-12-31"), Value::Real(-86400.0)),
+            (
+                "second",
+                &timestamp("2024-01-31 10:11:12.5"),
+                Value::Real(12.5),
+            ),
+            ("hour", &time("23:59"), Value::Integer(23)),
+        ];
+        for (field, value, expected) in cases {
+            assert_eq!(
+                extract(field, value),
+                Ok(expected),
+                "EXTRACT({} FROM {})",
+                field,
+                value
+            );
+        }
+        let span = interval("1 year 2 mons 3 days 04:05:06");
+        assert_eq!(extract("epoch", &span), Ok(Value::Real(37015506.0)));
+        assert_eq!(extract("month", &span), Ok(Value::Integer(2)));
+        assert_eq!(
+            extract("dow", &Value::Text("2024-01-31 08:00".to_string())),
+            Ok(Value::Integer(3))
+        );
+        assert_eq!(
+            extract("hour", &day),
+            Err("EXTRACT: a DATE has no hour".to_string())
+        );
+        assert_eq!(
+            extract("dow", &span),
+            Err("EXTRACT: a INTERVAL has no dow".to_string())
+        );
+    }
+
+    #[test]
+    fn truncates_to_fields() {
+        let at = timestamp("2024-05-17 10:11:12.5");
+        assert_eq!(trunc("year", &at), "2024-01-01 00:00:00");
+        assert_eq!(trunc("quarter", &at), "2024-04-01 00:00:00");
+        assert_eq!(trunc("month", &at), "2024-05-01 00:00:00");
+        assert_eq!(trunc("week", &at), "2024-05-13 00:00:00");
+        assert_eq!(trunc("week", &date("2024-01-31")), "2024-01-29 00:00:00");
+        // A Monday stays where it is, and a Sunday goes back six days
+        assert_eq!(trunc("week", &date("2024-01-29")), "2024-01-29 00:00:00");
+        assert_eq!(trunc("week", &date("2024-02-04")), "2024-01-29 00:00:00");
+        assert_eq!(trunc("WEEK", &date("1970-01-01")), "1969-12-29 00:00:00");
+        assert_eq!(trunc("quarter", &date("2024-12-31")), "2024-10-01 00:00:00");
+        assert_eq!(trunc("minute", &at), "2024-05-17 10:11:00");
+        assert_eq!(trunc("second", &at), "2024-05-17 10:11:12");
+
+        let span = interval("1 year 5 mons 3 days 04:05:06");
+        assert_eq!(trunc("quarter", &span), "1 year 3 mons");
+        assert_eq!(trunc("day", &span), "1 year 5 mons 3 days");
+        assert_eq!(trunc("hour", &span), "1 year 5 mons 3 days 04:00:00");
+        assert!(date_trunc(&Value::Text("week".to_string()), &span).is_err());
+        // The calendar carried back makes the first day a Monday
+        assert_eq!(trunc("week", &date("0001-01-03")), "0001-01-01 00:00:00");
+    }
+}
@@ -0,0 +1,654 @@
+use std::{fs, sync::Arc, time::Duration};
+
+use crate::{
+    auth::{self, Privilege},
+    csv::{self, CsvOptions},
+    datetime, dump,
+    eval::{self, Field},
+    exec::{self, Query, QueryLimits},
+    lexer::quote_identifier,
+    metrics::Metrics,
+    parser::{CopyEndpoint, SelectItem, Statement},
+    replication, stats,
+    storage::{Database, Inserted, Table, Trigger, Value},
+};
+
+/// What a successfully executed statement produced.
+pub enum Outcome {
+    /// A SELECT or SHOW: its rows, to be read after the lock is released.
+    Query(Query),
+    /// Anything else: a line describing what was done, and how many rows
+    /// were added or changed.
+    Done { message: String, rows: usize },
+}
+
+impl Outcome {
+    fn done(message: String) -> Self {
+        Outcome::Done { message, rows: 0 }
+    }
+}
+
+/// A session's settings, changed with SET.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Settings {
+    /// What the session's queries may do.
+    pub limits: QueryLimits,
+    /// The server's limits, which `SET ... = DEFAULT` goes back to.
+    pub defaults: QueryLimits,
+}
+
+impl Settings {
+    pub fn new(defaults: QueryLimits) -> Self {
+        Self {
+            limits: defaults,
+            defaults,
+        }
+    }
+}
+
+/// Execute a parsed statement against `db` for a session with `settings`.
+/// Errors come back without the "ERROR: " prefix the server puts in front
+/// of them. Statements that read from or write to a client connection
+/// (`STDIN`, `STDOUT`, SOURCE) are left to the server; the only thing here
+/// that stands in for one is the text returned by DUMP and COPY TO STDOUT.
+pub fn execute(
+    db: &mut Database,
+    statement: Statement,
+    settings: &mut Settings,
+) -> Result<Outcome, String> {
+    match statement {
+        Statement::Insert {
+            table,
+            columns,
+            values,
+            on_conflict,
+            returning,
+        } => {
+            // RETURNING is checked before the row goes in
+            let fields = match (&returning, db.get_table(&table)) {
+                (Some(items), Some(table_meta)) => {
+                    Some(returning_fields(&table, table_meta, items)?)
+                }
+                _ => None,
+            };
+            let (inserted, sequences) = db
+                .insert_into_table(&table, columns.as_deref(), values, on_conflict.as_ref())
+                .map_err(|e| format!("Insert into '{}' failed: {}.", table, e))?;
+            let (stored, message) = match &inserted {
+                Inserted::Added { row } => {
+                    (Some(row), format!("OK: Inserted 1 row into '{}'.", table))
+                }
+                Inserted::Updated { new, .. } => {
+                    (Some(new), format!("OK: Updated 1 row in '{}'.", table))
+                }
+                Inserted::Skipped => (
+                    None,
+                    format!(
+                        "OK: Inserted 0 rows into '{}'; the row was already there.",
+                        table
+                    ),
+                ),
+            };
+            if let Some(stored) = stored {
+                Metrics::add(&db.metrics.rows_written, 1);
+                replication::publish(db, |db| {
+                    let mut sql = match &inserted {
+                        Inserted::Updated { old, new } => {
+                            let target = on_conflict
+                                .as_ref()
+                                .and_then(|c| c.target.as_ref())
+                                .expect("DO UPDATE has a target");
+                            let table_meta = db.get_table(&table).expect("just inserted");
+                            dump::upsert_sql(&table, table_meta, target, old, new)
+                        }
+                        _ => dump::insert_sql(&table, stored),
+                    };
+                    // Followers' sequences move on as ours did
+                    for name in &sequences {
+                        let sequence = db.get_sequence(name).expect("just advanced");
+                        sql.push('\n');
+                        sql.push_str(&dump::restart_sequence_sql(name, sequence));
+                    }
+                    sql
+                });
+            }
+            match (returning, fields) {
+                (Some(items), Some(fields)) => {
+                    let row = stored.map(|row| row.get_inner_vec().as_slice());
+                    returning_rows(&items, &fields, row).map(Outcome::Query)
+                }
+                _ => Ok(Outcome::Done {
+                    message,
+                    rows: usize::from(stored.is_some()),
+                }),
+            }
+        }
+
+        Statement::Update {
+            table,
+            mut assignments,
+            mut condition,
+        } => {
+            // One time for every row, and for followers replaying it
+            let now = datetime::now();
+            for (_, expr) in &mut assignments {
+                expr.bind_now(&now);
+            }
+            if let Some(condition) = &mut condition {
+                condition.bind_now(&now);
+            }
+            let count = db
+                .update_where(&table, &assignments, condition.as_ref())
+                .map_err(|e| format!("Update of '{}' failed: {}.", table, e))?;
+            Metrics::add(&db.metrics.rows_written, count);
+            // Replaying the statement changes the same rows the same way
+            replication::publish(db, |_| {
+                dump::update_sql(&table, &assignments, condition.as_ref())
+            });
+            Ok(Outcome::Done {
+                message: format!(
+                    "OK: Updated {} row{} in '{}'.",
+                    count,
+                    if count == 1 { "" } else { "s" },
+                    table
+                ),
+                rows: count,
+            })
+        }
+
+        Statement::Delete {
+            table,
+            mut condition,
+        } => {
+            // As for UPDATE, so that followers delete the same rows
+            if let Some(condition) = &mut condition {
+                condition.bind_now(&datetime::now());
+            }
+            let count = db
+                .delete_where(&table, condition.as_ref())
+                .map_err(|e| format!("Delete from '{}' failed: {}.", table, e))?;
+            Metrics::add(&db.metrics.rows_written, count);
+            replication::publish(db, |_| dump::delete_sql(&table, condition.as_ref()));
+            Ok(Outcome::Done {
+                message: format!(
+                    "OK: Deleted {} row{} from '{}'.",
+                    count,
+                    if count == 1 { "" } else { "s" },
+                    table
+                ),
+                rows: count,
+            })
+        }
+
+        Statement::AlterTable {
+            table,
+            column,
+            restart,
+        } => {
+            db.restart_auto_increment(&table, &column, restart)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |db| {
+                dump::restart_auto_increment_sql(
+                    &table,
+                    db.get_table(&table).expect("just altered"),
+                )
+            });
+            Ok(Outcome::done(format!(
+                "OK: Restarted column '{}' of '{}'.",
+                column, table
+            )))
+        }
+
+        Statement::CreateSequence {
+            name,
+            start,
+            increment,
+        } => {
+            db.create_sequence(&name, start, increment)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |db| {
+                dump::create_sequence_sql(&name, db.get_sequence(&name).expect("just created"))
+            });
+            Ok(Outcome::done(format!("OK: Created sequence '{}'.", name)))
+        }
+
+        Statement::DropSequence { name, if_exists } => {
+            db.drop_sequence(&name, if_exists)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |_| {
+                format!("DROP SEQUENCE IF EXISTS {};", quote_identifier(&name))
+            });
+            Ok(Outcome::done(format!("OK: Dropped sequence '{}'.", name)))
+        }
+
+        Statement::AlterSequence { name, restart } => {
+            db.restart_sequence(&name, restart)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |db| {
+                dump::restart_sequence_sql(&name, db.get_sequence(&name).expect("just restarted"))
+            });
+            Ok(Outcome::done(format!("OK: Restarted sequence '{}'.", name)))
+        }
+
+        Statement::CreateTable {
+            table,
+            columns,
+            checks,
+        } => {
+            db.create_table(&table, columns, checks)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |db| {
+                dump::create_table_sql(&table, db.get_table(&table).expect("just created"))
+            });
+            Ok(Outcome::done(format!("OK: Created table '{}'.", table)))
+        }
+
+        Statement::DropTable { table, if_exists } => {
+            db.drop_table(&table, if_exists)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |_| {
+                format!("DROP TABLE IF EXISTS {};", quote_identifier(&table))
+            });
+            Ok(Outcome::done(format!("OK: Dropped table '{}'.", table)))
+        }
+
+        Statement::CreateTrigger {
+            name,
+            table,
+            timing,
+            event,
+            body,
+        } => {
+            let trigger = Trigger {
+                name: name.clone(),
+                timing,
+                event,
+                body,
+            };
+            let sql = dump::create_trigger_sql(&table, &trigger);
+            db.create_trigger(&table, trigger)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |_| sql);
+            Ok(Outcome::done(format!("OK: Created trigger '{}'.", name)))
+        }
+
+        Statement::DropTrigger { name, if_exists } => {
+            db.drop_trigger(&name, if_exists)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |_| {
+                format!("DROP TRIGGER IF EXISTS {};", quote_identifier(&name))
+            });
+            Ok(Outcome::done(format!("OK: Dropped trigger '{}'.", name)))
+        }
+
+        Statement::CreateView { name, query } => {
+            // Reading a view must not move sequences on
+            if query.calls("NEXTVAL") {
+                return Err("A view can't call nextval().".to_string());
+            }
+            // Planning checks that the tables and columns exist
+            let headers = exec::select(db, &query, QueryLimits::default())?.headers;
+            for (i, header) in headers.iter().enumerate() {
+                if headers[..i].contains(header) {
+                    return Err(format!(
+                        "View column '{}' appears more than once; rename one with AS.",
+                        header
+                    ));
+                }
+            }
+            db.create_view(&name, query)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |db| {
+                dump::create_view_sql(&name, db.get_view(&name).expect("just created"))
+            });
+            Ok(Outcome::done(format!("OK: Created view '{}'.", name)))
+        }
+
+        Statement::DropView { name, if_exists } => {
+            db.drop_view(&name, if_exists)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |_| {
+                format!("DROP VIEW IF EXISTS {};", quote_identifier(&name))
+            });
+            Ok(Outcome::done(format!("OK: Dropped view '{}'.", name)))
+        }
+
+        Statement::CreateIndex {
+            name,
+            table,
+            column,
+        } => {
+            db.create_index(&name, &table, &column)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |db| {
+                let table_meta = db.get_table(&table).expect("just indexed");
+                dump::create_index_sql(&table, table_meta, &name)
+            });
+            Ok(Outcome::done(format!(
+                "OK: Created index '{}' on '{}'.",
+                name, table
+            )))
+        }
+
+        Statement::DropIndex { name, if_exists } => {
+            db.drop_index(&name, if_exists)
+                .map_err(|e| format!("{}.", e))?;
+            replication::publish(db, |_| {
+                format!("DROP INDEX IF EXISTS {};", quote_identifier(&name))
+            });
+            Ok(Outcome::done(format!("OK: Dropped index '{}'.", name)))
+        }
+
+        // Statistics are this server's own; followers gather theirs
+        Statement::Analyze { table } => {
+            let names = match table {
+                Some(name) if db.get_table(&name).is_some() => vec![name],
+                Some(name) => return Err(format!("Table '{}' not found.", name)),
+                None => db.table_names(),
+            };
+            for name in &names {
+                let table = db.get_table_mut(name).expect("listed above");
+                table.stats = Some(stats::analyze(table));
+            }
+            Ok(Outcome::done(format!(
+                "OK: Analyzed {} table{}.",
+                names.len(),
+                if names.len() == 1 { "" } else { "s" }
+            )))
+        }
+
+        Statement::Explain(query) => exec::explain(db, &query).map(Outcome::Query),
+
+        Statement::Dump { target } => {
+            let script = dump::dump(db);
+            match target {
+                CopyEndpoint::Client => Ok(Outcome::done(format!("{}\\.", script))),
+                CopyEndpoint::File(path) => fs::write(&path, script)
+                    .map(|()| Outcome::done(format!("OK: Dumped database to '{}'.", path)))
+                    .map_err(|e| format!("Could not write '{}': {}", path, e)),
+            }
+        }
+
+        Statement::Restore { source } => match source {
+            CopyEndpoint::File(path) => {
+                let script = fs::read_to_string(&path)
+                    .map_err(|e| format!("Could not read '{}': {}", path, e))?;
+                restore(db, &script)
+            }
+            CopyEndpoint::Client => {
+                Err("RESTORE FROM STDIN is only available over a connection.".to_string())
+            }
+        },
+
+        Statement::ShowReplication => Ok(Outcome::Query(db.replication.status())),
+        Statement::ShowMetrics => Ok(Outcome::Query(db.metrics.report())),
+
+        Statement::SetStatementTimeout { timeout } => {
+            settings.limits.timeout = match timeout {
+                None => settings.defaults.timeout,
+                Some(Duration::ZERO) => None,
+                Some(timeout) => Some(timeout),
+            };
+            Ok(Outcome::done(format!(
+                "OK: statement_timeout is {}.",
+                show_timeout(settings.limits.timeout)
+            )))
+        }
+
+        Statement::ShowStatementTimeout => Ok(Outcome::Query(Query {
+            headers: vec!["statement_timeout".to_string()],
+            rows: Box::new(std::iter::once(Ok(vec![Value::Text(show_timeout(
+                settings.limits.timeout,
+            ))]))),
+            cancel: Arc::default(),
+        })),
+
+        Statement::Source { .. } => Err("SOURCE is only available over a connection.".to_string()),
+
+        Statement::CreateUser {
+            name,
+            password,
+            superuser,
+        } => create_user(db, &name, auth::hash_password(&password), superuser),
+
+        Statement::DropUser { name } => {
+            db.users.drop_user(&name).map_err(|e| format!("{}.", e))?;
+            Ok(Outcome::done(format!("OK: Dropped user '{}'.", name)))
+        }
+
+        Statement::Grant {
+            privileges,
+            table,
+            user,
+        } => {
+            if db.get_table(&table).is_none() && db.get_view(&table).is_none() {
+                return Err(format!("Table '{}' not found.", table));
+            }
+            db.users
+                .grant(&user, &table, &privileges)
+                .map_err(|e| format!("{}.", e))?;
+            Ok(Outcome::done(format!(
+                "OK: Granted {} on '{}' to '{}'.",
+                privilege_list(&privileges),
+                table,
+                user
+            )))
+        }
+
+        Statement::Revoke {
+            privileges,
+            table,
+            user,
+        } => {
+            db.users
+                .revoke(&user, &table, &privileges)
+                .map_err(|e| format!("{}.", e))?;
+            Ok(Outcome::done(format!(
+                "OK: Revoked {} on '{}' from '{}'.",
+                privilege_list(&privileges),
+                table,
+                user
+            )))
+        }
+
+        Statement::CopyFrom {
+            table,
+            columns,
+            source,
+            options,
+        } => {
+            let data = match source {
+                CopyEndpoint::File(path) => fs::read_to_string(&path)
+                    .map_err(|e| format!("Could not read '{}': {}", path, e))?,
+                CopyEndpoint::Client => {
+                    return Err("COPY FROM STDIN is only available over a connection.".to_string());
+                }
+            };
+            copy_from(db, &table, columns.as_deref(), &data, &options)
+        }
+
+        Statement::CopyTo {
+            table,
+            columns,
+            target,
+            options,
+        } => {
+            let table_meta = db
+                .get_table(&table)
+                .ok_or_else(|| format!("Table '{}' not found.", table))?;
+            let csv = csv::export(table_meta, columns.as_deref(), &options)
+                .map_err(|e| format!("COPY {} failed: {}.", table, e))?;
+            let count = table_meta.select_all().len();
+            match target {
+                // Stream the data back, terminated like COPY FROM STDIN input
+                CopyEndpoint::Client => Ok(Outcome::done(format!("{}\\.", csv))),
+                CopyEndpoint::File(path) => fs::write(&path, csv)
+                    .map(|()| {
+                        Outcome::done(format!(
+                            "OK: Copied {} row{} from '{}' to '{}'.",
+                            count,
+                            if count == 1 { "" } else { "s" },
+                            table,
+                            path
+                        ))
+                    })
+                    .map_err(|e| format!("Could not write '{}': {}", path, e)),
+            }
+        }
+
+        // nextval() moves sequences on: the rows are all made while the
+        // database is at hand, and followers' sequences moved on as ours
+        Statement::Select(mut query) if query.calls("NEXTVAL") => {
+            query.bind_now(&datetime::now());
+            let (result, sequences) = exec::select_advancing(db, &query, settings.limits)?;
+            if !sequences.is_empty() {
+                replication::publish(db, |db| {
+                    let statements: Vec<String> = sequences
+                        .iter()
+                        .map(|name| {
+                            let sequence = db.get_sequence(name).expect("just advanced");
+                            dump::restart_sequence_sql(name, sequence)
+                        })
+                        .collect();
+                    statements.join("\n")
+                });
+            }
+            Ok(Outcome::Query(result))
+        }
+        // One time for every row, as for UPDATE
+        Statement::Select(mut query) => {
+            query.bind_now(&datetime::now());
+            exec::select(db, &query, settings.limits).map(Outcome::Query)
+        }
+    }
+}
+
+/// Add an account whose password was already hashed, which is slow enough
+/// that the server does it before taking the database lock.
+pub fn create_user(
+    db: &mut Database,
+    name: &str,
+    hash: String,
+    superuser: bool,
+) -> Result<Outcome, String> {
+    db.users
+        .create_user(name, hash, superuser)
+        .map_err(|e| format!("{}.", e))?;
+    Ok(Outcome::done(format!("OK: Created user '{}'.", name)))
+}
+
+/// Replay a dump script, all or nothing.
+pub fn restore(db: &mut Database, script: &str) -> Result<Outcome, String> {
+    // Into a copy, so that a statement failing part-way through leaves
+    // none of those before it applied, here or on the followers
+    let mut restored = db.fork();
+    let count =
+        dump::restore(&mut restored, script).map_err(|e| format!("Restore failed at {}.", e))?;
+    db.replace_data(restored);
+    // Followers replay the same script
+    replication::publish(db, |_| script.to_string());
+    Ok(Outcome::done(format!(
+        "OK: Restored {} statement{}.",
+        count,
+        if count == 1 { "" } else { "s" }
+    )))
+}
+
+/// Load CSV `data` into `table`.
+pub fn copy_from(
+    db: &mut Database,
+    table: &str,
+    columns: Option<&[String]>,
+    data: &str,
+    options: &CsvOptions,
+) -> Result<Outcome, String> {
+    let table_meta = db
+        .get_table(table)
+        .ok_or_else(|| format!("Table '{}' not found.", table))?;
+    let rows = csv::import(table_meta, columns, data, options)
+        .map_err(|e| format!("COPY {} failed: {}.", table, e))?;
+    let rows = db
+        .insert_rows(table, rows)
+        .map_err(|e| format!("COPY {} failed: {}.", table, e))?;
+    let count = rows.len();
+    Metrics::add(&db.metrics.rows_written, count);
+    // Followers get the new rows one INSERT each, and run any triggers
+    // for them as we did
+    replication::publish(db, |_| {
+        let inserts: Vec<String> = rows
+            .iter()
+            .map(|row| dump::insert_sql(table, row))
+            .collect();
+        inserts.join("\n")
+    });
+    Ok(Outcome::Done {
+        message: format!(
+            "OK: Copied {} row{} into '{}'.",
+            count,
+            if count == 1 { "" } else { "s" },
+            table
+        ),
+        rows: count,
+    })
+}
+
+/// The columns RETURNING may name, checking that `items` only name those.
+fn returning_fields(name: &str, table: &Table, items: &[SelectItem]) -> Result<Vec<Field>, String> {
+    let fields = table.fields(name);
+    for item in items {
+        if let SelectItem::Expr { expr, .. } = item {
+            if expr.has_window() {
+                return Err("Window functions aren't allowed in RETURNING.".to_string());
+            }
+            eval::check_columns(expr, &fields)?;
+        }
+    }
+    Ok(fields)
+}
+
+/// RETURNING's result: one row about the row just inserted or updated, or
+/// none if ON CONFLICT DO NOTHING dropped it.
+fn returning_rows(
+    items: &[SelectItem],
+    fields: &[Field],
+    row: Option<&[Value]>,
+) -> Result<Query, String> {
+    let mut headers = Vec::new();
+    let mut values = Vec::new();
+    for item in items {
+        match item {
+            SelectItem::Wildcard => {
+                headers.extend(fields.iter().map(|f| f.name.clone()));
+                if let Some(row) = row {
+                    values.extend(row.iter().cloned());
+                }
+            }
+            SelectItem::Expr { expr, .. } => {
+                headers.push(item.header());
+                if let Some(row) = row {
+                    values.push(eval::eval(expr, fields, row)?);
+                }
+            }
+        }
+    }
+    let rows = row.map(|_| Ok(values));
+    Ok(Query {
+        headers,
+        rows: Box::new(rows.into_iter()),
+        cancel: Arc::default(),
+    })
+}
+
+/// A statement timeout as SHOW reports it: `5s`, `250ms`, or `0` for none.
+fn show_timeout(timeout: Option<Duration>) -> String {
+    match timeout.map(|t| t.as_millis()) {
+        None | Some(0) => "0".to_string(),
+        Some(ms) if ms % 1000 == 0 => format!("{}s", ms / 1000),
+        Some(ms) => format!("{}ms", ms),
+    }
+}
+
+fn privilege_list(privileges: &[Privilege]) -> String {
+    let names: Vec<String> = privileges.iter().map(|p| p.to_string()).collect();
+    names.join(", ")
+}
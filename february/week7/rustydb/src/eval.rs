@@ -0,0 +1,480 @@
+use crate::{
+    datetime,
+    parser::{BinaryOp, ColumnRef, Expr},
+    storage::{DataType, Value},
+    window,
+};
+
+/// A column of the rows an expression is evaluated against: its name, the
+/// table (or alias) it comes from, and its type if that is known (a view's
+/// columns may hold anything).
+#[derive(Clone)]
+pub struct Field {
+    pub table: String,
+    pub name: String,
+    pub data_type: Option<DataType>,
+}
+
+/// Built-in scalar functions: name, minimum and maximum argument count.
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("UPPER", 1, 1),
+    ("LOWER", 1, 1),
+    ("LENGTH", 1, 1),
+    ("SUBSTR", 2, 3),
+    ("ABS", 1, 1),
+    ("ROUND", 1, 2),
+    ("COALESCE", 1, usize::MAX),
+    ("NOW", 0, 0),
+    ("DATE_TRUNC", 2, 2),
+    ("NEXTVAL", 1, 1),
+];
+
+/// Check that `name` is a known function and accepts `argc` arguments.
+pub fn check_function(name: &str, argc: usize) -> Result<(), String> {
+    check_arity(FUNCTIONS, name, argc)
+        .unwrap_or_else(|| Err(format!("Unknown function: '{}'", name)))
+}
+
+/// Check `argc` against the entry for `name` in a table of functions like
+/// [`FUNCTIONS`]; `None` if the table has no such function.
+pub fn check_arity(
+    functions: &[(&str, usize, usize)],
+    name: &str,
+    argc: usize,
+) -> Option<Result<(), String>> {
+    let &(_, min, max) = functions.iter().find(|(n, _, _)| *n == name)?;
+    if argc < min || argc > max {
+        let expected = if min == max {
+            min.to_string()
+        } else if max == usize::MAX {
+            format!("at least {}", min)
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Some(Err(format!(
+            "Function {} expects {} argument{}, got {}",
+            name,
+            expected,
+            if min == 1 && (max == 1 || max == usize::MAX) {
+                ""
+            } else {
+                "s"
+            },
+            argc
+        )));
+    }
+    Some(Ok(()))
+}
+
+/// Where `column` is among `fields`. An unqualified name must belong to
+/// just one of the tables.
+pub fn resolve(column: &ColumnRef, fields: &[Field]) -> Result<usize, String> {
+    let mut matches = fields.iter().enumerate().filter(|(_, f)| {
+        f.name == column.name && column.table.as_ref().is_none_or(|t| *t == f.table)
+    });
+    match (matches.next(), matches.next()) {
+        (Some((i, _)), None) => Ok(i),
+        (Some(_), Some(_)) => Err(format!(
+            "Column '{}' is ambiguous; qualify it with its table name.",
+            column.name
+        )),
+        (None, _) => match &column.table {
+            Some(table) if !fields.iter().any(|f| f.table == *table) => Err(format!(
+                "Table or alias '{}' is not part of the query.",
+                table
+            )),
+            Some(table) => Err(format!(
+                "Column '{}' not found in table '{}'.",
+                column.name, table
+            )),
+            None => match fields.first() {
+                Some(first) if fields.iter().all(|f| f.table == first.table) => Err(format!(
+                    "Column '{}' not found in table '{}'.",
+                    column.name, first.table
+                )),
+                _ => Err(format!("Column '{}' not found.", column.name)),
+            },
+        },
+    }
+}
+
+/// The type `expr` produces over `fields`, where that can be told without
+/// running it; `None` for NULL and wherever it depends on the values.
+pub fn data_type(expr: &Expr, fields: &[Field]) -> Option<DataType> {
+    let numeric = |t: Option<DataType>| t.filter(|t| t.is_numeric());
+    match expr {
+        Expr::Column(column) => resolve(column, fields)
+            .ok()
+            .and_then(|i| fields[i].data_type),
+        Expr::Literal(v) => v.data_type(),
+        Expr::Negate(inner) => match data_type(inner, fields) {
+            Some(DataType::Interval) => Some(DataType::Interval),
+            t => numeric(t),
+        },
+        Expr::Not(_) | Expr::IsNull { .. } => Some(DataType::Integer),
+        Expr::Binary {
+            op: BinaryOp::Concat,
+            ..
+        } => Some(DataType::Text),
+        Expr::Binary { op, .. } if op.is_comparison() || is_logic(*op) => Some(DataType::Integer),
+        Expr::Binary { op, left, right } => {
+            let (l, r) = (data_type(left, fields)?, data_type(right, fields)?);
+            if datetime::is_temporal(l) || datetime::is_temporal(r) {
+                return datetime::result_type(*op, l, r);
+            }
+            match (numeric(Some(l)), numeric(Some(r))) {
+                (Some(DataType::Integer), Some(DataType::Integer)) => Some(DataType::Integer),
+                (Some(_), Some(_)) => Some(DataType::Real),
+                _ => None,
+            }
+        }
+        Expr::Function { name, args } => match name.as_str() {
+            "NOW" => Some(DataType::Timestamp),
+            "NEXTVAL" => Some(DataType::Integer),
+            "DATE_TRUNC" => match data_type(&args[1], fields)? {
+                DataType::Interval => Some(DataType::Interval),
+                _ => Some(DataType::Timestamp),
+            },
+            "EXTRACT" => match &args[0] {
+                Expr::Literal(Value::Text(field)) => Some(datetime::extract_type(field)),
+                _ => None,
+            },
+            "UPPER" | "LOWER" | "SUBSTR" => Some(DataType::Text),
+            "LENGTH" => Some(DataType::Integer),
+            "ROUND" => Some(DataType::Real),
+            "ABS" => numeric(data_type(&args[0], fields)),
+            "COALESCE" => {
+                let types: Option<Vec<DataType>> =
+                    args.iter().map(|a| data_type(a, fields)).collect();
+                let types = types?;
+                if types.iter().all(|t| *t == types[0]) {
+                    Some(types[0])
+                } else if types.iter().all(|t| t.is_numeric()) {
+                    Some(DataType::Real)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        },
+        Expr::Cast { to, .. } => Some(*to),
+        Expr::Window { name, args, .. } => {
+            let types: Vec<_> = args.iter().map(|a| data_type(a, fields)).collect();
+            window::data_type(name, &types)
+        }
+    }
+}
+
+/// Check that every column referenced by `expr` is one of `fields`.
+pub fn check_columns(expr: &Expr, fields: &[Field]) -> Result<(), String> {
+    let mut result = Ok(());
+    expr.for_each_column(&mut |column| {
+        if result.is_ok() {
+            result = resolve(column, fields).map(|_| ());
+        }
+    });
+    result
+}
+
+/// Evaluate `expr` against one row whose values line up with `fields`.
+pub fn eval(expr: &Expr, fields: &[Field], row: &[Value]) -> Result<Value, String> {
+    match expr {
+        Expr::Column(column) => resolve(column, fields).map(|i| row[i].clone()),
+
+        Expr::Literal(v) => Ok(v.clone()),
+
+        Expr::Negate(inner) => match eval(inner, fields, row)? {
+            Value::Null => Ok(Value::Null),
+            Value::Interval(i) => datetime::negate_interval(i),
+            v => match v.to_number() {
+                Some(Value::Integer(i)) => i
+                    .checked_neg()
+                    .map(Value::Integer)
+                    .ok_or_else(|| "Integer overflow".to_string()),
+                Some(Value::Real(r)) => Ok(Value::Real(-r)),
+                _ => Err(format!("Cannot negate non-numeric value '{}'", v)),
+            },
+        },
+
+        Expr::Not(inner) => {
+            let v = eval(inner, fields, row)?;
+            Ok(match truth("NOT", &v)? {
+                Some(b) => boolean(!b),
+                None => Value::Null,
+            })
+        }
+
+        Expr::IsNull { expr, negated } => {
+            let v = eval(expr, fields, row)?;
+            Ok(boolean(v.is_null() != *negated))
+        }
+
+        Expr::Binary { op, left, right } if is_logic(*op) => {
+            let symbol = op.symbol();
+            let l = truth(symbol, &eval(left, fields, row)?)?;
+            // FALSE AND x, and TRUE OR x, are settled without x
+            let settled = *op == BinaryOp::Or;
+            if l == Some(settled) {
+                return Ok(boolean(settled));
+            }
+            let r = truth(symbol, &eval(right, fields, row)?)?;
+            Ok(match (l, r) {
+                (_, Some(b)) if b == settled => boolean(settled),
+                (Some(_), Some(_)) => boolean(!settled),
+                _ => Value::Null,
+            })
+        }
+
+        Expr::Binary { op, left, right } => {
+            let l = eval(left, fields, row)?;
+            let r = eval(right, fields, row)?;
+            binary(*op, l, r)
+        }
+
+        Expr::Function { name, args } => {
+            let args = args
+                .iter()
+                .map(|a| eval(a, fields, row))
+                .collect::<Result<Vec<_>, _>>()?;
+            call_function(name, args)
+        }
+
+        Expr::Cast { expr, to } => cast(eval(expr, fields, row)?, *to),
+
+        // Computed beforehand over all the rows, see `window::compute`
+        Expr::Window { name, .. } => Err(format!(
+            "Window function {} can only be used in the SELECT list",
+            name
+        )),
+    }
+}
+
+fn binary(op: BinaryOp, l: Value, r: Value) -> Result<Value, String> {
+    if l.is_null() || r.is_null() {
+        return Ok(Value::Null);
+    }
+    if op == BinaryOp::Concat {
+        return Ok(Value::Text(format!("{}{}", l, r)));
+    }
+    if op.is_comparison() {
+        let (l, r) = datetime::comparable(l, r)?;
+        let ordering = l.compare(&r);
+        return Ok(boolean(match op {
+            BinaryOp::Eq => ordering.is_eq(),
+            BinaryOp::NotEq => ordering.is_ne(),
+            BinaryOp::Lt => ordering.is_lt(),
+            BinaryOp::LtEq => ordering.is_le(),
+            BinaryOp::Gt => ordering.is_gt(),
+            _ => ordering.is_ge(),
+        }));
+    }
+    if let Some(result) = datetime::arithmetic(op, &l, &r) {
+        return result;
+    }
+
+    let symbol = op.symbol();
+    let numeric = |v: &Value| {
+        v.to_number()
+            .ok_or_else(|| format!("Cannot apply '{}' to non-numeric value '{}'", symbol, v))
+    };
+    let (l, r) = (numeric(&l)?, numeric(&r)?);
+
+    match (l, r) {
+        (Value::Integer(a), Value::Integer(b)) => {
+            let result = match op {
+                BinaryOp::Add => a.checked_add(b),
+                BinaryOp::Subtract => a.checked_sub(b),
+                BinaryOp::Multiply => a.checked_mul(b),
+                BinaryOp::Divide | BinaryOp::Modulo if b == 0 => {
+                    return Err("Division by zero".to_string());
+                }
+                BinaryOp::Divide => a.checked_div(b),
+                BinaryOp::Modulo => a.checked_rem(b),
+                _ => unreachable!("not arithmetic"),
+            };
+            result
+                .map(Value::Integer)
+                .ok_or_else(|| "Integer overflow".to_string())
+        }
+        (a, b) => {
+            let (a, b) = (as_f64(&a), as_f64(&b));
+            let result = match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Subtract => a - b,
+                BinaryOp::Multiply => a * b,
+                BinaryOp::Divide | BinaryOp::Modulo if b == 0.0 => {
+                    return Err("Division by zero".to_string());
+                }
+                BinaryOp::Divide => a / b,
+                BinaryOp::Modulo => a % b,
+                _ => unreachable!("not arithmetic"),
+            };
+            Ok(Value::Real(result))
+        }
+    }
+}
+
+fn is_logic(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::And | BinaryOp::Or)
+}
+
+/// A truth value as comparisons give it: 1 or 0.
+fn boolean(b: bool) -> Value {
+    Value::Integer(i64::from(b))
+}
+
+/// Whether `v` counts as true where a condition is expected: any number
+/// but zero does. NULL is neither true nor false.
+pub fn truth(symbol: &str, v: &Value) -> Result<Option<bool>, String> {
+    match v.to_number() {
+        _ if v.is_null() => Ok(None),
+        Some(Value::Integer(i)) => Ok(Some(i != 0)),
+        Some(Value::Real(r)) => Ok(Some(r != 0.0)),
+        _ => Err(format!(
+            "{} expects a number as a condition, got '{}'",
+            symbol, v
+        )),
+    }
+}
+
+fn as_f64(v: &Value) -> f64 {
+    match v {
+        Value::Integer(i) => *i as f64,
+        Value::Real(r) => *r,
+        _ => 0.0,
+    }
+}
+
+/// Coerce a function argument to an integer, truncating reals.
+fn integer_arg(name: &str, v: &Value) -> Result<i64, String> {
+    match v.to_number() {
+        Some(Value::Integer(i)) => Ok(i),
+        Some(Value::Real(r)) => Ok(r as i64),
+        _ => Err(format!("{}: expected an integer, got '{}'", name, v)),
+    }
+}
+
+/// Whether `x` lies exactly halfway between two numbers of `digits`
+/// decimal places, like 0.125 does for 2.
+fn is_half(x: f64, digits: u32) -> bool {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i64;
+    let fraction = bits & ((1 << 52) - 1);
+    // x = ±mantissa * 2^(exponent - 1075)
+    let mantissa = if exponent == 0 {
+        fraction << 1
+    } else {
+        fraction | (1 << 52)
+    };
+    // x * 10^(digits + 1) is then an odd multiple of 5 just when the
+    // mantissa has as many trailing zero bits as the power of two divides out
+    mantissa != 0 && i64::from(mantissa.trailing_zeros()) == 1075 - exponent - i64::from(digits) - 1
+}
+
+fn call_function(name: &str, args: Vec<Value>) -> Result<Value, String> {
+    // Every function except COALESCE returns NULL when any argument is NULL
+    if name != "COALESCE" && args.iter().any(Value::is_null) {
+        return Ok(Value::Null);
+    }
+
+    match name {
+        "UPPER" => Ok(Value::Text(args[0].to_string().to_uppercase())),
+        "LOWER" => Ok(Value::Text(args[0].to_string().to_lowercase())),
+        "LENGTH" => Ok(Value::Integer(args[0].to_string().chars().count() as i64)),
+
+        // SUBSTR(text, start [, length]) — `start` is 1-based; positions
+        // before the first character are counted but produce nothing.
+        "SUBSTR" => {
+            let text = args[0].to_string();
+            let start = integer_arg(name, &args[1])?;
+            let end = match args.get(2) {
+                Some(len) => {
+                    let len = integer_arg(name, len)?;
+                    if len < 0 {
+                        return Err("SUBSTR: negative length not allowed".to_string());
+                    }
+                    start.saturating_add(len)
+                }
+                None => i64::MAX,
+            };
+            let skip = (start.max(1) - 1) as usize;
+            let take = (end.max(1) - start.max(1)) as usize;
+            Ok(Value::Text(text.chars().skip(skip).take(take).collect()))
+        }
+
+        "ABS" => match args[0].to_number() {
+            Some(Value::Integer(i)) => i
+                .checked_abs()
+                .map(Value::Integer)
+                .ok_or_else(|| "Integer overflow".to_string()),
+            Some(Value::Real(r)) => Ok(Value::Real(r.abs())),
+            _ => Err(format!("ABS: expected a number, got '{}'", args[0])),
+        },
+
+        // ROUND(number [, digits]) — always yields a REAL. Halves are
+        // rounded away from zero, going by the decimal digits of the value
+        // actually stored: 1.65 is really 1.6499... and rounds to 1.6.
+        "ROUND" => {
+            let x = match args[0].to_number() {
+                Some(n) => as_f64(&n),
+                None => return Err(format!("ROUND: expected a number, got '{}'", args[0])),
+            };
+            let digits = match args.get(1) {
+                Some(d) => integer_arg(name, d)?,
+                None => 0,
+            };
+            if digits > 0 && x.is_finite() {
+                let digits = digits.min(30) as u32;
+                // Formatting rounds exact halves to even; one step further
+                // from zero, they round away from it
+                let x = if is_half(x, digits) {
+                    f64::from_bits(x.to_bits() + 1)
+                } else {
+                    x
+                };
+                let text = format!("{:.*}", digits as usize, x);
+                return Ok(Value::Real(text.parse().unwrap_or(x)));
+            }
+            let factor = 10f64.powi(digits.clamp(-308, 308) as i32);
+            Ok(Value::Real((x * factor).round() / factor))
+        }
+
+        "COALESCE" => Ok(args
+            .into_iter()
+            .find(|v| !v.is_null())
+            .unwrap_or(Value::Null)),
+
+        // Statements fix it beforehand; only a view reads the clock itself
+        "NOW" => Ok(datetime::now()),
+        "DATE_TRUNC" => datetime::date_trunc(&args[0], &args[1]),
+        // EXTRACT(field FROM value), with the field passed as text
+        "EXTRACT" => datetime::extract(&args[0].to_string(), &args[1]),
+        // A query takes these out to be worked out as its rows come; there
+        // is nothing here to move a sequence on elsewhere
+        "NEXTVAL" => {
+            Err("nextval() can only be called in a select list or INSERT values".to_string())
+        }
+
+        other => Err(format!("Unknown function: '{}'", other)),
+    }
+}
+
+fn cast(v: Value, to: DataType) -> Result<Value, String> {
+    if v.is_null() {
+        return Ok(Value::Null);
+    }
+    let fail = |v: &Value| format!("Cannot cast '{}' to {}", v, to);
+    match to {
+        DataType::Integer => match v.to_number() {
+            Some(Value::Integer(i)) => Ok(Value::Integer(i)),
+            Some(Value::Real(r)) if r.is_finite() => Ok(Value::Integer(r.trunc() as i64)),
+            _ => Err(fail(&v)),
+        },
+        DataType::Real => match v.to_number() {
+            Some(n) => Ok(Value::Real(as_f64(&n))),
+            None => Err(fail(&v)),
+        },
+        DataType::Text => Ok(Value::Text(v.to_string())),
+        _ => datetime::convert(to, &v, false).ok_or_else(|| fail(&v)),
+    }
+}
@@ -0,0 +1,2212 @@
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    eval::{self, Field},
+    metrics::Metrics,
+    parser::{BinaryOp, ColumnRef, Condition, Expr, Select, SelectItem, SetOp, WithQuery},
+    stats::DEFAULT_SELECTIVITY,
+    storage::{DataType, Database, Key, Row, Sequence, Table, Value},
+    window,
+};
+
+/// One result row, or the error that ended the query.
+pub type RowResult = Result<Vec<Value>, String>;
+
+/// A planned SELECT: its column headers and a lazy stream of result rows.
+///
+/// Rows are pulled one at a time through a chain of operators (scans and
+/// index lookups, filters, joins, window functions, then the projection,
+/// repeated for each view in between) over snapshots of the tables taken
+/// at planning time, so the database lock is only needed to build the
+/// plan, not while the rows are consumed.
+pub struct Query {
+    pub headers: Vec<String>,
+    pub rows: Rows,
+    /// Set to stop the query from another thread: the rows end with an
+    /// error within a few dozen more, even while none are coming out.
+    pub cancel: Arc<AtomicBool>,
+}
+
+pub type Rows = Box<dyn Iterator<Item = RowResult> + Send>;
+
+/// Views may be defined in terms of other views, but a chain this long
+/// almost certainly loops (possible once a table a view reads from has been
+/// replaced by a view of the same name).
+const MAX_VIEW_DEPTH: usize = 32;
+
+/// Rounds a recursive WITH query may go through. One that gets this far
+/// is almost certainly going round a cycle with UNION ALL, which keeps
+/// rows it has already produced; UNION would leave them out and stop.
+const MAX_RECURSION: usize = 10_000;
+
+/// Cost of fetching one row through an index, relative to reading the
+/// next row of a scan.
+const INDEX_FETCH_COST: f64 = 4.0;
+
+/// Rows between checks of a query's deadline, per operator.
+const DEADLINE_CHECK_INTERVAL: usize = 64;
+
+/// Bounds on what one query may do before it is stopped with an error.
+/// `None` means no bound.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryLimits {
+    /// Wall-clock time from planning to the last row.
+    pub timeout: Option<Duration>,
+    /// Rows in the result.
+    pub max_rows: Option<usize>,
+    /// Bytes of rows the query holds at once, e.g. in a hash join's table.
+    pub max_memory: Option<usize>,
+}
+
+/// Plan `query` against `db`, to be run within `limits`.
+pub fn select(db: &Database, query: &Select, limits: QueryLimits) -> Result<Query, String> {
+    start(db, query, limits).map(|(query, _)| query)
+}
+
+/// Run `query`, which calls `nextval()`, to its end and move the sequences
+/// on in `db` as its rows did. Unlike [`select`]'s, these rows can't be
+/// left to come once the database is let go. Returns them with the names
+/// of the sequences moved on; if the query fails, none are.
+pub fn select_advancing(
+    db: &mut Database,
+    query: &Select,
+    limits: QueryLimits,
+) -> Result<(Query, Vec<String>), String> {
+    let (query, budget) = start(db, query, limits)?;
+    let rows = query.rows.collect::<Result<Vec<_>, _>>()?;
+    let advanced = mem::take(&mut *budget.sequences.lock().unwrap());
+    let mut names = Vec::with_capacity(advanced.len());
+    for (name, sequence) in advanced {
+        db.restart_sequence(&name, Some(sequence.next))?;
+        names.push(name);
+    }
+    let query = Query {
+        rows: Box::new(rows.into_iter().map(Ok)),
+        ..query
+    };
+    Ok((query, names))
+}
+
+/// Plan `query` and start it, returning its rows with the budget they
+/// keep to.
+fn start(
+    db: &Database,
+    query: &Select,
+    limits: QueryLimits,
+) -> Result<(Query, Arc<Budget>), String> {
+    let Planned { headers, plan, .. } = plan(db, query, &[], 0)?;
+    let cancel = Arc::new(AtomicBool::new(false));
+    let budget = Arc::new(Budget {
+        limits,
+        deadline: limits.timeout.map(|t| Instant::now() + t),
+        cancelled: Arc::clone(&cancel),
+        memory: AtomicUsize::new(0),
+        sequences: Mutex::default(),
+        metrics: Arc::clone(&db.metrics),
+    });
+    let rows = plan.open(&budget);
+    let rows: Rows = match limits.max_rows {
+        Some(max) => Box::new(RowCap {
+            input: rows,
+            max,
+            count: 0,
+        }),
+        None => rows,
+    };
+    let metrics = Arc::clone(&db.metrics);
+    let rows = rows.inspect(move |row| {
+        if row.is_ok() {
+            Metrics::add(&metrics.rows_returned, 1);
+        }
+    });
+    let query = Query {
+        headers,
+        rows: Box::new(rows),
+        cancel,
+    };
+    Ok((query, budget))
+}
+
+/// `EXPLAIN`: the plan `select` would run, one operator per row, each
+/// indented under the one that reads from it.
+pub fn explain(db: &Database, query: &Select) -> Result<Query, String> {
+    let Planned { plan, .. } = plan(db, query, &[], 0)?;
+    let mut lines = Vec::new();
+    plan.explain(0, &mut lines);
+    Ok(Query {
+        headers: vec!["plan".to_string()],
+        rows: Box::new(lines.into_iter().map(|line| Ok(vec![Value::Text(line)]))),
+        cancel: Arc::default(),
+    })
+}
+
+/// The positions of the rows of `table` that WHERE `condition` keeps, as a
+/// query would find them; every row's without a condition.
+pub fn matching_rows(
+    table_name: &str,
+    table: &Table,
+    condition: Option<&Expr>,
+) -> Result<Vec<usize>, String> {
+    let rows = table.select_all();
+    let Some(condition) = condition else {
+        return Ok((0..rows.len()).collect());
+    };
+    let fields = table.fields(table_name);
+    eval::check_columns(condition, &fields)?;
+    let (equalities, rest) = split_where(condition);
+    let matchers = equalities
+        .iter()
+        .map(|c| Ok((eval::resolve(&c.column, &fields)?, Matcher::new(&c.value))))
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut positions = Vec::new();
+    for (position, row) in rows.iter().enumerate() {
+        let values = row.get_inner_vec();
+        if matchers.iter().all(|(c, m)| m.matches(&values[*c]))
+            && rest
+                .as_ref()
+                .map_or(Ok(true), |rest| holds(rest, &fields, values))?
+        {
+            positions.push(position);
+        }
+    }
+    Ok(positions)
+}
+
+/// Split a WHERE condition into the `column = value` parts ANDed together
+/// in it, which are compared as [`Matcher`] does, and whatever else is
+/// left, which is evaluated like any other expression.
+fn split_where(condition: &Expr) -> (Vec<Condition>, Option<Expr>) {
+    let mut equalities = Vec::new();
+    let mut rest: Option<Expr> = None;
+    for part in condition.conjuncts() {
+        if let Some(equality) = Condition::from_expr(part) {
+            equalities.push(equality);
+            continue;
+        }
+        rest = Some(match rest {
+            None => part.clone(),
+            Some(left) => Expr::Binary {
+                op: BinaryOp::And,
+                left: Box::new(left),
+                right: Box::new(part.clone()),
+            },
+        });
+    }
+    (equalities, rest)
+}
+
+/// Whether a WHERE condition holds for `row`. NULL, like false, doesn't.
+fn holds(condition: &Expr, fields: &[Field], row: &[Value]) -> Result<bool, String> {
+    Ok(eval::truth("WHERE", &eval::eval(condition, fields, row)?)? == Some(true))
+}
+
+// ---------------------------------------------------------------------------
+// Planning
+// ---------------------------------------------------------------------------
+
+/// A table or view of the FROM clause, before it is joined to the others.
+struct Relation<'a> {
+    fields: Vec<Field>,
+    /// How many distinct values each field is expected to hold.
+    distinct: Vec<f64>,
+    /// The cheapest way to read it on its own, WHERE condition included.
+    access: Plan,
+    /// What reading it that way costs, in scanned-row units.
+    cost: f64,
+    /// For a table, what an index join into it needs: the table, and the
+    /// WHERE condition's `column = value` parts on it.
+    table: Option<(&'a Table, Vec<(usize, Matcher)>)>,
+}
+
+/// The columns two sides of a join must agree on: (column of the rows
+/// joined so far, field of the relation being added).
+type JoinKeys = Vec<(usize, usize)>;
+
+/// How a result is sorted: (column, whether it's descending) for each
+/// ORDER BY term, most significant first.
+type SortKeys = Vec<(usize, bool)>;
+
+/// `a = b` in a JOIN's ON: (relation, field) on each side.
+struct Edge {
+    sides: [(usize, usize); 2],
+}
+
+/// A planned query and the columns it produces.
+struct Planned {
+    headers: Vec<String>,
+    /// Each column's type, where every row's value in it has the same one.
+    types: Vec<Option<DataType>>,
+    plan: Plan,
+}
+
+/// A name defined by WITH, as the queries that may read it see it.
+#[derive(Clone)]
+enum Cte<'q> {
+    /// Planned afresh wherever it is read, like a view.
+    Query {
+        definition: &'q WithQuery,
+        recursive: bool,
+    },
+    /// A recursive query's own name inside its recursive part: the rows the
+    /// previous round added.
+    Working {
+        name: &'q str,
+        headers: Vec<String>,
+        types: Vec<Option<DataType>>,
+        rows: f64,
+        slot: WorkingSlot,
+    },
+}
+
+impl Cte<'_> {
+    fn name(&self) -> &str {
+        match self {
+            Cte::Query { definition, .. } => &definition.name,
+            Cte::Working { name, .. } => name,
+        }
+    }
+}
+
+/// `scope` with the names `query`'s WITH defines added after it.
+fn with_scope<'q>(query: &'q Select, scope: &[Cte<'q>]) -> Vec<Cte<'q>> {
+    let mut scope = scope.to_vec();
+    if let Some(with) = &query.with {
+        for definition in &with.queries {
+            scope.push(Cte::Query {
+                definition,
+                recursive: with.recursive && definition.reads_itself(),
+            });
+        }
+    }
+    scope
+}
+
+/// Plan each SELECT of a compound query, combine them left to right, then
+/// sort the result by its ORDER BY. `scope` holds the WITH names the query
+/// may read besides its own.
+fn plan<'q>(
+    db: &Database,
+    query: &'q Select,
+    scope: &[Cte<'q>],
+    depth: usize,
+) -> Result<Planned, String> {
+    let scope = with_scope(query, scope);
+    let mut planned = plan_block(db, query, &scope, depth)?;
+    if query.order_by.is_empty() {
+        return combine(db, query, &scope, depth, planned);
+    }
+
+    // ORDER BY terms that aren't result columns are worked out as extra
+    // ones, dropped again once the rows are sorted
+    let columns = planned.headers.len();
+    let (keys, extra) = order_keys(query, &planned.headers)?;
+    if extra.is_empty() {
+        planned = combine(db, query, &scope, depth, planned)?;
+    } else {
+        let mut block = query.clone();
+        block.projection.extend(
+            extra
+                .into_iter()
+                .map(|expr| SelectItem::Expr { expr, alias: None }),
+        );
+        planned = plan_block(db, &block, &scope, depth)?;
+        planned.headers.truncate(columns);
+        planned.types.truncate(columns);
+    }
+    let terms: Vec<String> = query.order_by.iter().map(|o| o.to_string()).collect();
+    planned.plan = Plan {
+        rows: planned.plan.rows,
+        op: Op::Sort {
+            input: Box::new(planned.plan),
+            label: terms.join(", "),
+            keys,
+            columns,
+        },
+    };
+    Ok(planned)
+}
+
+/// Where each ORDER BY term of `query` finds its value in the rows of a
+/// result with columns `headers`, and whether it sorts them descending. A
+/// number or the name of a result column picks that column; any other
+/// expression is returned to be worked out as an extra column past them.
+fn order_keys(query: &Select, headers: &[String]) -> Result<(SortKeys, Vec<Expr>), String> {
+    let mut keys = Vec::with_capacity(query.order_by.len());
+    let mut extra = Vec::new();
+    for term in &query.order_by {
+        let position = match &term.expr {
+            Expr::Literal(Value::Integer(n)) => {
+                if *n < 1 || *n as usize > headers.len() {
+                    return Err(format!(
+                        "ORDER BY {}: the result has columns 1 to {}.",
+                        n,
+                        headers.len()
+                    ));
+                }
+                Some(*n as usize - 1)
+            }
+            Expr::Column(ColumnRef { table: None, name }) => {
+                let mut named = headers.iter().enumerate().filter(|(_, h)| *h == name);
+                match (named.next(), named.next()) {
+                    (Some(_), Some(_)) => {
+                        return Err(format!(
+                            "ORDER BY {} is ambiguous: more than one result column has that name.",
+                            term.expr
+                        ));
+                    }
+                    (found, _) => found.map(|(i, _)| i),
+                }
+            }
+            _ => None,
+        };
+        let position = match position {
+            Some(position) => position,
+            None if !query.compound.is_empty() => {
+                return Err(format!(
+                    "ORDER BY {} must name a result column, or give its number, to sort the rows of {}.",
+                    term.expr, query.compound[0].0
+                ));
+            }
+            None if query.distinct => {
+                return Err(format!(
+                    "ORDER BY {} must name a result column, or give its number, to sort the rows of SELECT DISTINCT.",
+                    term.expr
+                ));
+            }
+            None => {
+                extra.push(term.expr.clone());
+                headers.len() + extra.len() - 1
+            }
+        };
+        keys.push((position, term.descending));
+    }
+    Ok((keys, extra))
+}
+
+/// Add the blocks combined with `query`'s first, already `planned`, to
+/// its plan.
+fn combine<'q>(
+    db: &Database,
+    query: &'q Select,
+    scope: &[Cte<'q>],
+    depth: usize,
+    mut planned: Planned,
+) -> Result<Planned, String> {
+    for (op, block) in &query.compound {
+        let right = plan_block(db, block, scope, depth)?;
+        combine_types(*op, &mut planned, &right)?;
+        let rows = match op {
+            SetOp::Union | SetOp::UnionAll => planned.plan.rows + right.plan.rows,
+            SetOp::Intersect => planned.plan.rows.min(right.plan.rows),
+            SetOp::Except => planned.plan.rows,
+        };
+        planned.plan = Plan {
+            rows,
+            op: Op::Compound {
+                op: *op,
+                left: Box::new(planned.plan),
+                right: Box::new(right.plan),
+            },
+        };
+    }
+    Ok(planned)
+}
+
+/// Check that `right` can be combined with `left` by `op`, and work out the
+/// column types of the result.
+fn combine_types(op: SetOp, left: &mut Planned, right: &Planned) -> Result<(), String> {
+    if right.headers.len() != left.headers.len() {
+        return Err(format!(
+            "Each side of {} must return the same number of columns: {} on the left, {} on the right.",
+            op,
+            left.headers.len(),
+            right.headers.len()
+        ));
+    }
+    for (c, (ours, theirs)) in left.types.iter_mut().zip(&right.types).enumerate() {
+        *ours = match (*ours, *theirs) {
+            (None, t) | (t, None) => t,
+            (Some(a), Some(b)) if a == b => Some(a),
+            (Some(a), Some(b)) if a.is_numeric() && b.is_numeric() => Some(DataType::Real),
+            (Some(a), Some(b)) => {
+                return Err(format!(
+                    "{} can't combine {} with {} in column {} ('{}').",
+                    op,
+                    a,
+                    b,
+                    c + 1,
+                    left.headers[c]
+                ));
+            }
+        };
+    }
+    Ok(())
+}
+
+/// Plan a WITH query where it is read, `scope` being the names defined
+/// before it.
+fn plan_with_query<'q>(
+    db: &Database,
+    definition: &'q WithQuery,
+    recursive: bool,
+    scope: &[Cte<'q>],
+    depth: usize,
+) -> Result<Planned, String> {
+    if !recursive {
+        let mut planned = plan(db, &definition.query, scope, depth)?;
+        rename_columns(definition, &mut planned)?;
+        return Ok(planned);
+    }
+
+    // The SELECTs before the first that reads the query's own name start
+    // it off; the rest are run again on each round's new rows until there
+    // are none
+    let name = definition.name.as_str();
+    let query = &definition.query;
+    if !query.order_by.is_empty() {
+        return Err(format!(
+            "It can't have ORDER BY, as '{}' is read a round at a time; sort where it is read.",
+            name
+        ));
+    }
+    let scope = with_scope(query, scope);
+    let reads = |block: &Select| block.block_tables().filter(|t| t.name == name).count();
+    let blocks: Vec<&Select> = query.blocks().collect();
+    let first = blocks
+        .iter()
+        .position(|block| reads(block) > 0)
+        .expect("a recursive query reads itself");
+    if first == 0 {
+        return Err(format!(
+            "Its first SELECT can't read '{}'; the recursion starts from that SELECT's rows.",
+            name
+        ));
+    }
+    let op = query.compound[first - 1].0;
+    if !matches!(op, SetOp::Union | SetOp::UnionAll)
+        || query.compound[first..].iter().any(|(o, _)| *o != op)
+    {
+        return Err(
+            "Its recursive SELECTs must all be added with UNION, or all with UNION ALL."
+                .to_string(),
+        );
+    }
+    if blocks[first..].iter().any(|block| reads(block) != 1) {
+        return Err(format!(
+            "Each of its recursive SELECTs must read '{}' exactly once.",
+            name
+        ));
+    }
+
+    let start = Select {
+        with: None,
+        compound: query.compound[..first - 1].to_vec(),
+        ..query.clone()
+    };
+    let mut planned = plan(db, &start, &scope, depth)?;
+    rename_columns(definition, &mut planned)?;
+    let slot = WorkingSlot::default();
+    let mut step_scope = scope.clone();
+    step_scope.push(Cte::Working {
+        name,
+        headers: planned.headers.clone(),
+        types: planned.types.clone(),
+        rows: planned.plan.rows,
+        slot: slot.clone(),
+    });
+    let mut step: Option<Plan> = None;
+    for block in &blocks[first..] {
+        let right = plan_block(db, block, &step_scope, depth)?;
+        combine_types(op, &mut planned, &right)?;
+        step = Some(match step {
+            None => right.plan,
+            Some(left) => Plan {
+                rows: left.rows + right.plan.rows,
+                op: Op::Compound {
+                    op: SetOp::UnionAll,
+                    left: Box::new(left),
+                    right: Box::new(right.plan),
+                },
+            },
+        });
+    }
+    let step = step.expect("a recursive query has a recursive SELECT");
+    planned.plan = Plan {
+        rows: planned.plan.rows + step.rows,
+        op: Op::Recursive {
+            name: name.to_string(),
+            start: Box::new(planned.plan),
+            step: Box::new(step),
+            slot,
+            union_all: op == SetOp::UnionAll,
+        },
+    };
+    Ok(planned)
+}
+
+/// Give a WITH query's result the column names listed after its name.
+fn rename_columns(definition: &WithQuery, planned: &mut Planned) -> Result<(), String> {
+    if definition.columns.is_empty() {
+        return Ok(());
+    }
+    if definition.columns.len() != planned.headers.len() {
+        return Err(format!(
+            "It names {} columns but its query returns {}.",
+            definition.columns.len(),
+            planned.headers.len()
+        ));
+    }
+    planned.headers = definition.columns.clone();
+    Ok(())
+}
+
+/// Plan one SELECT, ignoring any blocks combined with it.
+fn plan_block<'q>(
+    db: &Database,
+    query: &'q Select,
+    scope: &[Cte<'q>],
+    depth: usize,
+) -> Result<Planned, String> {
+    let tables: Vec<_> = query.block_tables().collect();
+    for (i, table) in tables.iter().enumerate() {
+        if tables[..i].iter().any(|t| t.binding() == table.binding()) {
+            return Err(format!(
+                "'{}' appears more than once in the query; give one an alias.",
+                table.binding()
+            ));
+        }
+    }
+
+    // Every field of every relation, in FROM order, and where each is from
+    let mut sources = Vec::new();
+    for table in &tables {
+        sources.push(source(db, &table.name, table.binding(), scope, depth)?);
+    }
+    let all_fields: Vec<Field> = sources.iter().flat_map(|s| s.fields.clone()).collect();
+    let origins: Vec<(usize, usize)> = sources
+        .iter()
+        .enumerate()
+        .flat_map(|(r, s)| (0..s.fields.len()).map(move |f| (r, f)))
+        .collect();
+
+    // Expand `*` and make sure every referenced column exists
+    let mut headers: Vec<String> = Vec::new();
+    let mut types = Vec::new();
+    let mut exprs: Vec<Expr> = Vec::new();
+    for item in &query.projection {
+        match item {
+            SelectItem::Wildcard => {
+                for field in &all_fields {
+                    headers.push(field.name.clone());
+                    types.push(field.data_type);
+                    exprs.push(Expr::Column(ColumnRef {
+                        table: Some(field.table.clone()),
+                        name: field.name.clone(),
+                    }));
+                }
+            }
+            SelectItem::Expr { expr, .. } => {
+                eval::check_columns(expr, &all_fields)?;
+                headers.push(item.header());
+                types.push(eval::data_type(expr, &all_fields));
+                exprs.push(expr.clone());
+            }
+        }
+    }
+
+    // Likewise the values of nextval(), one per call, and window functions,
+    // which are computed over all the joined rows at once: operators of
+    // their own add them to the rows, for the projection to read.
+    let mut sequences = Vec::new();
+    for expr in &mut exprs {
+        take_nextvals(expr, &mut sequences)?;
+    }
+    let sequences = sequences
+        .into_iter()
+        .map(|name| match db.get_sequence(&name) {
+            Some(sequence) => Ok((name, sequence.clone())),
+            None => Err(format!("Sequence '{}' not found.", name)),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let mut calls = Vec::new();
+    for expr in &mut exprs {
+        take_windows(expr, &mut calls);
+    }
+
+    // The `column = value` parts of WHERE go to their tables, where an
+    // index may find the rows; the rest filters the joined rows
+    let (equalities, rest) = match &query.condition {
+        Some(condition) => {
+            eval::check_columns(condition, &all_fields)?;
+            split_where(condition)
+        }
+        None => (Vec::new(), None),
+    };
+    let mut filters: Vec<Vec<(usize, &Condition)>> = sources.iter().map(|_| Vec::new()).collect();
+    for condition in &equalities {
+        let (r, field) = origins[eval::resolve(&condition.column, &all_fields)?];
+        filters[r].push((field, condition));
+    }
+
+    let mut edges = Vec::new();
+    for (j, join) in query.joins.iter().enumerate() {
+        for (left, right) in &join.on {
+            let left_origin = origins[eval::resolve(left, &all_fields)?];
+            let right_origin = origins[eval::resolve(right, &all_fields)?];
+            let (mut a, mut b) = (left_origin.0, right_origin.0);
+            if a > b {
+                (a, b) = (b, a);
+            }
+            if b != j + 1 || a == b {
+                return Err(format!(
+                    "JOIN condition {} = {} must compare a column of '{}' with one of a table before it.",
+                    left,
+                    right,
+                    join.table.binding()
+                ));
+            }
+            edges.push(Edge {
+                sides: [left_origin, right_origin],
+            });
+        }
+    }
+
+    let relations = sources
+        .into_iter()
+        .zip(filters)
+        .map(|(source, filters)| relation(source, filters))
+        .collect();
+    let (mut joined, mut fields) = join_order(relations, &edges);
+    if let Some(condition) = rest {
+        joined = Plan {
+            rows: joined.rows * DEFAULT_SELECTIVITY,
+            op: Op::Where {
+                input: Box::new(joined),
+                fields: fields.clone(),
+                condition,
+            },
+        };
+    }
+    if !sequences.is_empty() {
+        fields.extend((0..sequences.len()).map(|i| Field {
+            table: String::new(),
+            name: nextval_column(i),
+            data_type: Some(DataType::Integer),
+        }));
+        joined = Plan {
+            rows: joined.rows,
+            op: Op::NextVal {
+                input: Box::new(joined),
+                sequences,
+            },
+        };
+    }
+    if !calls.is_empty() {
+        let results: Vec<Field> = calls
+            .iter()
+            .enumerate()
+            .map(|(i, call)| Field {
+                table: String::new(),
+                name: window_column(i),
+                data_type: eval::data_type(call, &fields),
+            })
+            .collect();
+        joined = Plan {
+            rows: joined.rows,
+            op: Op::Window {
+                input: Box::new(joined),
+                fields: fields.clone(),
+                calls,
+            },
+        };
+        fields.extend(results);
+    }
+
+    let mut plan = Plan {
+        rows: joined.rows,
+        op: Op::Project {
+            input: Box::new(joined),
+            fields,
+            exprs,
+        },
+    };
+    if query.distinct {
+        plan = Plan {
+            rows: plan.rows,
+            op: Op::Distinct {
+                input: Box::new(plan),
+            },
+        };
+    }
+    Ok(Planned {
+        headers,
+        types,
+        plan,
+    })
+}
+
+/// Move the window function calls in `expr` to `calls`, each distinct call
+/// once, leaving references to the columns their results will be in.
+fn take_windows(expr: &mut Expr, calls: &mut Vec<Expr>) {
+    match expr {
+        Expr::Column(_) | Expr::Literal(_) => {}
+        Expr::Negate(inner)
+        | Expr::Not(inner)
+        | Expr::IsNull { expr: inner, .. }
+        | Expr::Cast { expr: inner, .. } => take_windows(inner, calls),
+        Expr::Binary { left, right, .. } => {
+            take_windows(left, calls);
+            take_windows(right, calls);
+        }
+        Expr::Function { args, .. } => args.iter_mut().for_each(|a| take_windows(a, calls)),
+        Expr::Window { .. } => {
+            let i = match calls.iter().position(|c| c == expr) {
+                Some(i) => i,
+                None => {
+                    calls.push(expr.clone());
+                    calls.len() - 1
+                }
+            };
+            *expr = Expr::Column(ColumnRef {
+                table: Some(String::new()),
+                name: window_column(i),
+            });
+        }
+    }
+}
+
+/// The name of the column the `i`th window function call's results are
+/// added to the rows as; not one a query can refer to by accident.
+fn window_column(i: usize) -> String {
+    format!("#window{}", i)
+}
+
+/// Move the sequences the `nextval('name')` calls in `expr` name to
+/// `sequences`, once for each call, since each moves its sequence on;
+/// leaving references to the columns their values will be in.
+fn take_nextvals(expr: &mut Expr, sequences: &mut Vec<String>) -> Result<(), String> {
+    match expr {
+        Expr::Column(_) | Expr::Literal(_) => Ok(()),
+        Expr::Negate(inner)
+        | Expr::Not(inner)
+        | Expr::IsNull { expr: inner, .. }
+        | Expr::Cast { expr: inner, .. } => take_nextvals(inner, sequences),
+        Expr::Binary { left, right, .. } => {
+            take_nextvals(left, sequences)?;
+            take_nextvals(right, sequences)
+        }
+        Expr::Function { name, args } if name == "NEXTVAL" => {
+            let [Expr::Literal(Value::Text(sequence))] = args.as_slice() else {
+                return Err("nextval() takes the name of a sequence, in quotes".to_string());
+            };
+            sequences.push(sequence.clone());
+            *expr = Expr::Column(ColumnRef {
+                table: Some(String::new()),
+                name: nextval_column(sequences.len() - 1),
+            });
+            Ok(())
+        }
+        Expr::Function { args, .. } | Expr::Window { args, .. } => args
+            .iter_mut()
+            .try_for_each(|a| take_nextvals(a, sequences)),
+    }
+}
+
+/// As [`window_column`], for the `i`th `nextval()` call.
+fn nextval_column(i: usize) -> String {
+    format!("#nextval{}", i)
+}
+
+/// What a name in FROM refers to, not yet filtered.
+struct Source<'a> {
+    name: String,
+    binding: String,
+    fields: Vec<Field>,
+    kind: SourceKind<'a>,
+}
+
+enum SourceKind<'a> {
+    Table(&'a Table),
+    /// A view's or WITH query's planned query, run in its place.
+    View {
+        plan: Box<Plan>,
+        cte: bool,
+    },
+}
+
+fn source<'a, 'q>(
+    db: &'a Database,
+    name: &str,
+    binding: &str,
+    scope: &[Cte<'q>],
+    depth: usize,
+) -> Result<Source<'a>, String> {
+    let fields = |columns: Vec<(String, Option<DataType>)>| {
+        columns
+            .into_iter()
+            .map(|(name, data_type)| Field {
+                table: binding.to_string(),
+                name,
+                data_type,
+            })
+            .collect()
+    };
+    // WITH names hide tables and views, the latest definition the others
+    if let Some(i) = scope.iter().rposition(|cte| cte.name() == name) {
+        let planned = match &scope[i] {
+            Cte::Query {
+                definition,
+                recursive,
+            } => {
+                if depth >= MAX_VIEW_DEPTH {
+                    return Err(format!("WITH query '{}' nests too deeply.", name));
+                }
+                let visible = if *recursive {
+                    &scope[..=i]
+                } else {
+                    &scope[..i]
+                };
+                plan_with_query(db, definition, *recursive, visible, depth + 1)
+                    .map_err(|e| format!("In WITH query '{}': {}", name, e))?
+            }
+            Cte::Working {
+                headers,
+                types,
+                rows,
+                slot,
+                ..
+            } => Planned {
+                headers: headers.clone(),
+                types: types.clone(),
+                plan: Plan {
+                    rows: *rows,
+                    op: Op::WorkingTable {
+                        name: name.to_string(),
+                        slot: slot.clone(),
+                    },
+                },
+            },
+        };
+        return Ok(Source {
+            name: name.to_string(),
+            binding: binding.to_string(),
+            fields: fields(planned.headers.into_iter().zip(planned.types).collect()),
+            kind: SourceKind::View {
+                plan: Box::new(planned.plan),
+                cte: true,
+            },
+        });
+    }
+    if let Some(table) = db.get_table(name) {
+        return Ok(Source {
+            name: name.to_string(),
+            binding: binding.to_string(),
+            fields: fields(
+                table
+                    .columns
+                    .iter()
+                    .map(|c| (c.name.clone(), Some(c.data_type)))
+                    .collect(),
+            ),
+            kind: SourceKind::Table(table),
+        });
+    }
+    let Some(view) = db.get_view(name) else {
+        return Err(format!("Table '{}' not found.", name));
+    };
+    if depth >= MAX_VIEW_DEPTH {
+        return Err(format!(
+            "View '{}' nests too deeply; is it defined in terms of itself?",
+            name
+        ));
+    }
+    let Planned {
+        headers,
+        types,
+        plan,
+    } = plan(db, view, &[], depth + 1).map_err(|e| format!("In view '{}': {}", name, e))?;
+    Ok(Source {
+        name: name.to_string(),
+        binding: binding.to_string(),
+        fields: fields(headers.into_iter().zip(types).collect()),
+        kind: SourceKind::View {
+            plan: Box::new(plan),
+            cte: false,
+        },
+    })
+}
+
+/// Work out how to read `source` on its own, applying `filters` (the
+/// `column = value` parts of the WHERE condition on its fields, by
+/// position) if any belong here.
+fn relation<'a>(source: Source<'a>, filters: Vec<(usize, &Condition)>) -> Relation<'a> {
+    let described = |field: usize, value: &Value| {
+        Condition {
+            column: ColumnRef {
+                table: Some(source.binding.clone()),
+                name: source.fields[field].name.clone(),
+            },
+            value: value.clone(),
+        }
+        .to_string()
+    };
+    let label = if source.name == source.binding {
+        source.name.clone()
+    } else {
+        format!("{} AS {}", source.name, source.binding)
+    };
+
+    match source.kind {
+        SourceKind::View { plan: input, cte } => {
+            let label = format!("{} {}", if cte { "CTE" } else { "View" }, label);
+            let rows = input.rows;
+            let mut access = Plan {
+                rows,
+                op: Op::View { label, input },
+            };
+            for (field, cond) in filters {
+                access = Plan {
+                    rows: access.rows * DEFAULT_SELECTIVITY,
+                    op: Op::Filter {
+                        condition: described(field, &cond.value),
+                        input: Box::new(access),
+                        position: field,
+                        matcher: Matcher::new(&cond.value),
+                    },
+                };
+            }
+            Relation {
+                distinct: vec![access.rows; source.fields.len()],
+                fields: source.fields,
+                cost: rows,
+                access,
+                table: None,
+            }
+        }
+
+        SourceKind::Table(table) => {
+            let total = table.select_all().len() as f64;
+            let mut distinct: Vec<f64> = (0..table.columns.len())
+                .map(|c| match (table.index_on(c), &table.stats) {
+                    (Some(index), _) => index.distinct() as f64,
+                    (None, Some(stats)) => stats.columns[c].distinct,
+                    (None, None) => total,
+                })
+                .collect();
+            let scan = Plan {
+                rows: total,
+                op: Op::Scan {
+                    label: label.clone(),
+                    rows: table.snapshot(),
+                },
+            };
+            if filters.is_empty() {
+                return Relation {
+                    fields: source.fields,
+                    distinct,
+                    access: scan,
+                    cost: total,
+                    table: Some((table, Vec::new())),
+                };
+            }
+
+            // The first condition on an indexed column may be looked up;
+            // the others filter what that finds
+            let mut filters = filters;
+            let lead = filters
+                .iter()
+                .position(|(field, _)| table.index_on(*field).is_some())
+                .unwrap_or(0);
+            filters.swap(0, lead);
+
+            let mut access = scan;
+            let mut cost = total;
+            let mut matchers = Vec::with_capacity(filters.len());
+            for (i, (field, cond)) in filters.into_iter().enumerate() {
+                // The literal as stored in this column; one that can't be
+                // stored there matches nothing
+                let key = table.columns[field]
+                    .data_type
+                    .coerce(cond.value.clone())
+                    .ok()
+                    .filter(|v| !v.is_null());
+                let matcher = Matcher::new(&cond.value);
+                let condition = described(field, key.as_ref().unwrap_or(&cond.value));
+                let index = table.index_on(field).filter(|_| i == 0);
+                let matches = match (&key, index, &table.stats) {
+                    (None, _, _) => 0.0,
+                    (Some(key), Some(index), _) => {
+                        Key::from_value(key).map_or(0, |k| index.lookup(&k).len()) as f64
+                    }
+                    (Some(key), None, Some(stats)) => {
+                        access.rows * stats.equality_selectivity(field, key)
+                    }
+                    (Some(_), None, None) => access.rows * DEFAULT_SELECTIVITY,
+                };
+                for d in &mut distinct {
+                    *d = d.min(matches);
+                }
+                distinct[field] = matches.min(1.0);
+
+                access = match index {
+                    Some(index) if matches * INDEX_FETCH_COST < total => {
+                        let positions = key
+                            .as_ref()
+                            .and_then(Key::from_value)
+                            .map_or_else(Vec::new, |k| index.lookup(&k).to_vec());
+                        cost = matches * INDEX_FETCH_COST;
+                        Plan {
+                            rows: matches,
+                            op: Op::IndexLookup {
+                                label: label.clone(),
+                                index: index.name.clone(),
+                                condition,
+                                rows: table.snapshot(),
+                                positions,
+                            },
+                        }
+                    }
+                    _ => Plan {
+                        rows: matches,
+                        op: Op::Filter {
+                            condition,
+                            input: Box::new(access),
+                            position: field,
+                            matcher: matcher.clone(),
+                        },
+                    },
+                };
+                matchers.push((field, matcher));
+            }
+            Relation {
+                fields: source.fields,
+                distinct,
+                access,
+                cost,
+                table: Some((table, matchers)),
+            }
+        }
+    }
+}
+
+/// Join `relations` greedily: start from the one expected to be smallest,
+/// then keep adding whichever connected relation gives the smallest result,
+/// by hash join or, where an index makes it cheaper, index join. Returns
+/// the plan and the fields of the rows it produces.
+fn join_order(relations: Vec<Relation>, edges: &[Edge]) -> (Plan, Vec<Field>) {
+    let mut relations: Vec<Option<Relation>> = relations.into_iter().map(Some).collect();
+    let first = (0..relations.len())
+        .min_by(|&a, &b| {
+            let rows = |r: usize| relations[r].as_ref().unwrap().access.rows;
+            rows(a).total_cmp(&rows(b))
+        })
+        .expect("a query reads at least one table");
+    let relation = relations[first].take().unwrap();
+    // Which (relation, field) each column of the joined rows is
+    let mut order: Vec<(usize, usize)> = (0..relation.fields.len()).map(|f| (first, f)).collect();
+    let mut fields = relation.fields;
+    let mut distinct = relation.distinct;
+    let mut plan = relation.access;
+
+    while relations.iter().any(Option::is_some) {
+        // (relation, estimated rows, keys)
+        let mut best: Option<(usize, f64, JoinKeys)> = None;
+        for (r, candidate) in relations.iter().enumerate() {
+            let Some(candidate) = candidate else { continue };
+            let keys: JoinKeys = edges
+                .iter()
+                .filter_map(|edge| {
+                    let [a, b] = edge.sides;
+                    let (ours, theirs) = if b.0 == r { (a, b) } else { (b, a) };
+                    let column = order.iter().position(|&o| o == ours)?;
+                    (theirs.0 == r).then_some((column, theirs.1))
+                })
+                .collect();
+            if keys.is_empty() {
+                continue;
+            }
+            let mut rows = plan.rows * candidate.access.rows;
+            for &(column, field) in &keys {
+                let ours = distinct[column].min(plan.rows);
+                let theirs = candidate.distinct[field].min(candidate.access.rows);
+                rows /= ours.max(theirs).max(1.0);
+            }
+            if best
+                .as_ref()
+                .is_none_or(|(_, best_rows, _)| rows < *best_rows)
+            {
+                best = Some((r, rows, keys));
+            }
+        }
+        let (r, rows, keys) = best.expect("every JOIN is connected to an earlier table");
+        let relation = relations[r].take().unwrap();
+
+        let condition = keys
+            .iter()
+            .map(|&(column, field)| {
+                format!(
+                    "{} = {}",
+                    qualified(&fields[column]),
+                    qualified(&relation.fields[field])
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ");
+
+        // An index join probes an index once per outer row; a hash join
+        // reads the whole of the inner side once
+        let hash_cost = relation.cost + plan.rows;
+        let mut index_join = None;
+        if let Some((table, filter)) = &relation.table {
+            let total = table.select_all().len() as f64;
+            for (k, &(_, field)) in keys.iter().enumerate() {
+                let Some(index) = table.index_on(field) else {
+                    continue;
+                };
+                let per_probe = total / (index.distinct() as f64).max(1.0);
+                let cost = plan.rows * (1.0 + INDEX_FETCH_COST * per_probe);
+                if cost < hash_cost && index_join.as_ref().is_none_or(|(_, c, _, _)| cost < *c) {
+                    index_join = Some((k, cost, index, filter.clone()));
+                }
+            }
+        }
+
+        let outer = Box::new(plan);
+        let op = match index_join {
+            Some((k, _, index, filter)) => {
+                let (outer_key, _) = keys[k];
+                let mut checks = keys;
+                checks.remove(k);
+                let (table, _) = relation.table.expect("index joins are into tables");
+                Op::IndexJoin {
+                    outer,
+                    label: relation.access.label(),
+                    index: index.name.clone(),
+                    condition,
+                    rows: table.snapshot(),
+                    entries: index.snapshot(),
+                    outer_key,
+                    checks,
+                    filter,
+                }
+            }
+            None => Op::HashJoin {
+                outer,
+                inner: Box::new(relation.access),
+                condition,
+                keys,
+            },
+        };
+        plan = Plan { rows, op };
+
+        order.extend((0..relation.fields.len()).map(|f| (r, f)));
+        fields.extend(relation.fields);
+        distinct = distinct
+            .into_iter()
+            .chain(relation.distinct)
+            .map(|d| d.min(rows))
+            .collect();
+    }
+    (plan, fields)
+}
+
+fn qualified(field: &Field) -> ColumnRef {
+    ColumnRef {
+        table: Some(field.table.clone()),
+        name: field.name.clone(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Plans
+// ---------------------------------------------------------------------------
+
+/// One operator of a plan, with those it reads from, and how many rows the
+/// planner expects it to produce. Cloning one gives a plan that can be run
+/// again, as a recursive query's is on each round.
+#[derive(Clone)]
+struct Plan {
+    rows: f64,
+    op: Op,
+}
+
+#[derive(Clone)]
+enum Op {
+    /// Every row of a table. `label` names it as the query does.
+    Scan { label: String, rows: Arc<Vec<Row>> },
+    /// The rows of a table an index found for the WHERE condition.
+    IndexLookup {
+        label: String,
+        index: String,
+        condition: String,
+        rows: Arc<Vec<Row>>,
+        positions: Vec<usize>,
+    },
+    /// The rows of a view's or WITH query's own plan. `label` says which
+    /// and names it as the query does, e.g. `View v AS x`.
+    View { label: String, input: Box<Plan> },
+    Filter {
+        input: Box<Plan>,
+        condition: String,
+        position: usize,
+        matcher: Matcher,
+    },
+    /// The input's rows the rest of the WHERE condition holds for, once
+    /// its tables are joined.
+    Where {
+        input: Box<Plan>,
+        fields: Vec<Field>,
+        condition: Expr,
+    },
+    /// Joins each outer row to the inner rows with equal `keys` (outer
+    /// column, inner column), through a hash table of the inner side.
+    HashJoin {
+        outer: Box<Plan>,
+        inner: Box<Plan>,
+        condition: String,
+        keys: Vec<(usize, usize)>,
+    },
+    /// Joins each outer row to the rows of a table its index finds for the
+    /// outer column `outer_key`.
+    IndexJoin {
+        outer: Box<Plan>,
+        label: String,
+        index: String,
+        condition: String,
+        rows: Arc<Vec<Row>>,
+        entries: Arc<HashMap<Key, Vec<usize>>>,
+        outer_key: usize,
+        /// Further join columns, compared once a row is found.
+        checks: Vec<(usize, usize)>,
+        /// The `column = value` parts of the WHERE condition on this table.
+        filter: Vec<(usize, Matcher)>,
+    },
+    /// The input's rows, each with the next value of the sequence of
+    /// every `nextval()` call added on the end. The sequences are as they
+    /// stood when the query was planned.
+    NextVal {
+        input: Box<Plan>,
+        sequences: Vec<(String, Sequence)>,
+    },
+    /// The input's rows, each with the result of every window function
+    /// call added on the end.
+    Window {
+        input: Box<Plan>,
+        fields: Vec<Field>,
+        calls: Vec<Expr>,
+    },
+    Project {
+        input: Box<Plan>,
+        fields: Vec<Field>,
+        exprs: Vec<Expr>,
+    },
+    /// The input's rows with duplicates left out.
+    Distinct { input: Box<Plan> },
+    /// The input's rows in order of the columns `keys` gives (position,
+    /// descending), cut down to the first `columns` once sorted. `label`
+    /// is the ORDER BY as written.
+    Sort {
+        input: Box<Plan>,
+        label: String,
+        keys: SortKeys,
+        columns: usize,
+    },
+    /// Two SELECTs combined by UNION, INTERSECT or EXCEPT.
+    Compound {
+        op: SetOp,
+        left: Box<Plan>,
+        right: Box<Plan>,
+    },
+    /// A recursive WITH query: the rows of `start`, then those of `step`
+    /// run on the rows the round before added, until a round adds none.
+    /// With UNION rather than UNION ALL, rows already produced are left out.
+    Recursive {
+        name: String,
+        start: Box<Plan>,
+        step: Box<Plan>,
+        slot: WorkingSlot,
+        union_all: bool,
+    },
+    /// Inside `step`: the rows the last round of a recursive query added.
+    WorkingTable { name: String, slot: WorkingSlot },
+}
+
+/// Where a recursive query leaves each round's rows for the next to read.
+type WorkingSlot = Arc<Mutex<Arc<Vec<Vec<Value>>>>>;
+
+impl Plan {
+    /// What the table an access path reads is called in the query.
+    fn label(&self) -> String {
+        match &self.op {
+            Op::Scan { label, .. } | Op::IndexLookup { label, .. } => label.clone(),
+            Op::Filter { input, .. } => input.label(),
+            _ => unreachable!("only table access paths have a table"),
+        }
+    }
+
+    fn explain(&self, depth: usize, lines: &mut Vec<String>) {
+        let line = match &self.op {
+            Op::Scan { label, .. } => format!("Scan {}", label),
+            Op::IndexLookup {
+                label,
+                index,
+                condition,
+                ..
+            } => format!("Index Lookup {} using {} ({})", label, index, condition),
+            Op::View { label, .. } => label.clone(),
+            Op::Filter { condition, .. } => format!("Filter ({})", condition),
+            Op::Where { condition, .. } => format!("Filter ({})", condition),
+            Op::HashJoin { condition, .. } => format!("Hash Join ({})", condition),
+            Op::IndexJoin {
+                label,
+                index,
+                condition,
+                ..
+            } => format!("Index Join {} using {} ({})", label, index, condition),
+            Op::NextVal { sequences, .. } => {
+                let names: Vec<&str> = sequences.iter().map(|(n, _)| n.as_str()).collect();
+                format!("Next Value ({})", names.join(", "))
+            }
+            Op::Window { calls, .. } => {
+                let calls: Vec<String> = calls.iter().map(|c| c.to_string()).collect();
+                format!("Window ({})", calls.join(", "))
+            }
+            Op::Project { .. } => "Project".to_string(),
+            Op::Distinct { .. } => "Distinct".to_string(),
+            Op::Sort { label, .. } => format!("Sort ({})", label),
+            Op::Compound { op, .. } => match op {
+                SetOp::Union => "Union",
+                SetOp::UnionAll => "Union All",
+                SetOp::Intersect => "Intersect",
+                SetOp::Except => "Except",
+            }
+            .to_string(),
+            Op::Recursive {
+                name, union_all, ..
+            } => format!(
+                "Recursive {} {}",
+                if *union_all { "Union All" } else { "Union" },
+                name
+            ),
+            Op::WorkingTable { name, .. } => format!("Working Table {}", name),
+        };
+        lines.push(format!(
+            "{}{}  (rows={:.0})",
+            "  ".repeat(depth),
+            line,
+            self.rows
+        ));
+        match &self.op {
+            Op::Scan { .. } | Op::IndexLookup { .. } | Op::WorkingTable { .. } => {}
+            Op::View { input, .. }
+            | Op::Filter { input, .. }
+            | Op::Where { input, .. }
+            | Op::NextVal { input, .. }
+            | Op::Window { input, .. }
+            | Op::Project { input, .. }
+            | Op::Distinct { input }
+            | Op::Sort { input, .. } => input.explain(depth + 1, lines),
+            Op::HashJoin { outer, inner, .. } => {
+                outer.explain(depth + 1, lines);
+                inner.explain(depth + 1, lines);
+            }
+            Op::Compound { left, right, .. } => {
+                left.explain(depth + 1, lines);
+                right.explain(depth + 1, lines);
+            }
+            Op::Recursive { start, step, .. } => {
+                start.explain(depth + 1, lines);
+                step.explain(depth + 1, lines);
+            }
+            Op::IndexJoin { outer, .. } => outer.explain(depth + 1, lines),
+        }
+    }
+
+    /// Start running the plan, with every operator keeping to `budget`.
+    fn open(self, budget: &Arc<Budget>) -> Rows {
+        let rows: Rows = match self.op {
+            Op::Scan { rows, .. } => Box::new(Scan {
+                rows,
+                next: 0,
+                budget: Arc::clone(budget),
+            }),
+            Op::IndexLookup {
+                rows, positions, ..
+            } => {
+                let budget = Arc::clone(budget);
+                Box::new(positions.into_iter().map(move |p| {
+                    Metrics::add(&budget.metrics.rows_scanned, 1);
+                    Ok(rows[p].get_inner_vec().clone())
+                }))
+            }
+            Op::View { input, .. } => input.open(budget),
+            Op::Filter {
+                input,
+                position,
+                matcher,
+                ..
+            } => Box::new(Filter {
+                input: input.open(budget),
+                position,
+                matcher,
+            }),
+            Op::Where {
+                input,
+                fields,
+                condition,
+            } => Box::new(Where {
+                input: input.open(budget),
+                fields,
+                condition,
+            }),
+            Op::HashJoin {
+                outer, inner, keys, ..
+            } => Box::new(HashJoin {
+                outer: outer.open(budget),
+                inner: Some(inner.open(budget)),
+                table: HashMap::new(),
+                keys,
+                budget: Arc::clone(budget),
+                current: None,
+            }),
+            Op::IndexJoin {
+                outer,
+                rows,
+                entries,
+                outer_key,
+                checks,
+                filter,
+                ..
+            } => Box::new(IndexJoin {
+                outer: outer.open(budget),
+                rows,
+                entries,
+                outer_key,
+                checks,
+                filter,
+                budget: Arc::clone(budget),
+                current: None,
+            }),
+            Op::NextVal { input, sequences } => Box::new(NextVal {
+                input: input.open(budget),
+                sequences,
+                budget: Arc::clone(budget),
+            }),
+            Op::Window {
+                input,
+                fields,
+                calls,
+            } => Box::new(Window {
+                input: Some(input.open(budget)),
+                fields,
+                calls,
+                budget: Arc::clone(budget),
+                output: Vec::new().into_iter(),
+            }),
+            Op::Project {
+                input,
+                fields,
+                exprs,
+            } => Box::new(Project {
+                input: input.open(budget),
+                fields,
+                exprs,
+            }),
+            Op::Distinct { input } => Box::new(Distinct::new(input.open(budget), budget)),
+            Op::Sort {
+                input,
+                keys,
+                columns,
+                ..
+            } => Box::new(Sort {
+                input: Some(input.open(budget)),
+                keys,
+                columns,
+                budget: Arc::clone(budget),
+                output: Vec::new().into_iter(),
+            }),
+            Op::Compound { op, left, right } => {
+                let (left, right) = (left.open(budget), right.open(budget));
+                match op {
+                    SetOp::UnionAll => Box::new(left.chain(right)),
+                    SetOp::Union => Box::new(Distinct::new(Box::new(left.chain(right)), budget)),
+                    SetOp::Intersect | SetOp::Except => Box::new(SetFilter {
+                        input: Distinct::new(left, budget),
+                        other: Some(right),
+                        found: HashSet::new(),
+                        keep_found: op == SetOp::Intersect,
+                        budget: Arc::clone(budget),
+                    }),
+                }
+            }
+            Op::Recursive {
+                name,
+                start,
+                step,
+                slot,
+                union_all,
+            } => Box::new(Recursive {
+                name,
+                current: start.open(budget),
+                step: *step,
+                slot,
+                added: Vec::new(),
+                seen: (!union_all).then(HashSet::new),
+                rounds: 0,
+                budget: Arc::clone(budget),
+            }),
+            Op::WorkingTable { slot, .. } => {
+                let rows = Arc::clone(&slot.lock().unwrap());
+                Box::new((0..rows.len()).map(move |i| Ok(rows[i].clone())))
+            }
+        };
+        Box::new(Guard {
+            input: rows,
+            budget: Arc::clone(budget),
+            produced: 0,
+        })
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Limits
+// ---------------------------------------------------------------------------
+
+/// A running query's limits and what it has used of them so far, shared by
+/// all of its operators.
+struct Budget {
+    limits: QueryLimits,
+    deadline: Option<Instant>,
+    /// Set through [`Query::cancel`].
+    cancelled: Arc<AtomicBool>,
+    /// Bytes held by operators that keep rows, such as hash joins.
+    memory: AtomicUsize,
+    /// The sequences `nextval()` has moved on, as they now stand.
+    sequences: Mutex<Vec<(String, Sequence)>>,
+    /// Where rows read from tables are counted.
+    metrics: Arc<Metrics>,
+}
+
+impl Budget {
+    fn check_deadline(&self) -> Result<(), String> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err("Query cancelled.".to_string());
+        }
+        match (self.deadline, self.limits.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() >= deadline => Err(format!(
+                "Query cancelled: it ran longer than statement_timeout ({} ms).",
+                timeout.as_millis()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Account for `bytes` more held by an operator.
+    fn reserve(&self, bytes: usize) -> Result<(), String> {
+        let used = self.memory.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        match self.limits.max_memory {
+            Some(max) if used > max => Err(format!(
+                "Query cancelled: it needed more than {} MB of memory.",
+                max / (1024 * 1024)
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Roughly how many bytes `row` takes up.
+fn row_size(row: &[Value]) -> usize {
+    let text: usize = row
+        .iter()
+        .map(|v| match v {
+            Value::Text(s) => s.len(),
+            _ => 0,
+        })
+        .sum();
+    mem::size_of::<Vec<Value>>() + mem::size_of_val(row) + text
+}
+
+/// Passes on an operator's rows until the query runs out of time or is
+/// cancelled. Every
+/// operator is wrapped in one, so a query that produces nothing for a long
+/// time (a filter that rejects everything, a hash table being built) is
+/// caught as surely as one producing too much.
+struct Guard {
+    input: Rows,
+    budget: Arc<Budget>,
+    produced: usize,
+}
+
+impl Iterator for Guard {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        self.produced += 1;
+        if self.produced.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+            && let Err(e) = self.budget.check_deadline()
+        {
+            return Some(Err(e));
+        }
+        self.input.next()
+    }
+}
+
+/// Ends a query with an error once it returns more than `max` rows.
+struct RowCap {
+    input: Rows,
+    max: usize,
+    count: usize,
+}
+
+impl Iterator for RowCap {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        let row = self.input.next()?;
+        self.count += 1;
+        if self.count > self.max {
+            return Some(Err(format!(
+                "Query cancelled: it returned more than {} rows.",
+                self.max
+            )));
+        }
+        Some(row)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Operators
+// ---------------------------------------------------------------------------
+
+/// Every row of a table snapshot, in insertion order.
+struct Scan {
+    rows: Arc<Vec<Row>>,
+    next: usize,
+    budget: Arc<Budget>,
+}
+
+impl Iterator for Scan {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        let row = self.rows.get(self.next)?;
+        self.next += 1;
+        Metrics::add(&self.budget.metrics.rows_scanned, 1);
+        Some(Ok(row.get_inner_vec().clone()))
+    }
+}
+
+/// Whether a value equals a literal. As when storing, the literal is
+/// converted to the type of the value it is compared with; one it can't be
+/// converted to never matches, and neither does NULL.
+#[derive(Clone)]
+struct Matcher {
+    /// The literal as each type, where possible.
+    as_integer: Option<Value>,
+    as_real: Option<Value>,
+    as_text: Option<Value>,
+    as_date: Option<Value>,
+    as_time: Option<Value>,
+    as_timestamp: Option<Value>,
+    as_interval: Option<Value>,
+}
+
+impl Matcher {
+    fn new(literal: &Value) -> Self {
+        let convert = |to: DataType| {
+            to.coerce(literal.clone())
+                .ok()
+                .filter(|v: &Value| !v.is_null())
+        };
+        Self {
+            as_integer: convert(DataType::Integer),
+            as_real: convert(DataType::Real),
+            as_text: convert(DataType::Text),
+            as_date: convert(DataType::Date),
+            as_time: convert(DataType::Time),
+            as_timestamp: convert(DataType::Timestamp),
+            as_interval: convert(DataType::Interval),
+        }
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        let target = match value {
+            Value::Null => return false,
+            Value::Integer(_) => &self.as_integer,
+            Value::Real(_) => &self.as_real,
+            Value::Text(_) => &self.as_text,
+            Value::Date(_) => &self.as_date,
+            Value::Time(_) => &self.as_time,
+            Value::Timestamp(_) => &self.as_timestamp,
+            Value::Interval(_) => &self.as_interval,
+        };
+        // `1 mon` is as long as `30 days`, though not written the same
+        target.as_ref().is_some_and(|t| t.compare(value).is_eq())
+    }
+}
+
+/// Rows whose value at `position` matches a literal.
+struct Filter {
+    input: Rows,
+    position: usize,
+    matcher: Matcher,
+}
+
+impl Iterator for Filter {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        loop {
+            match self.input.next()? {
+                Ok(values)
+                    if !values
+                        .get(self.position)
+                        .is_some_and(|v| self.matcher.matches(v)) => {}
+                row => return Some(row), // matching rows, and errors
+            }
+        }
+    }
+}
+
+/// Rows a condition holds for.
+struct Where {
+    input: Rows,
+    fields: Vec<Field>,
+    condition: Expr,
+}
+
+impl Iterator for Where {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        loop {
+            let row = match self.input.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            match holds(&self.condition, &self.fields, &row) {
+                Ok(true) => return Some(Ok(row)),
+                Ok(false) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// The join key of `row` at `columns`; `None` if any part of it is NULL,
+/// as NULL never equals anything.
+fn join_key(row: &[Value], columns: impl Iterator<Item = usize>) -> Option<Vec<Key>> {
+    columns.map(|c| Key::from_value(&row[c])).collect()
+}
+
+/// Hash join. The inner side is read into a hash table the first time a
+/// row is asked for; the outer side is then streamed past it.
+struct HashJoin {
+    outer: Rows,
+    /// Until the hash table is built.
+    inner: Option<Rows>,
+    table: HashMap<Vec<Key>, Vec<Vec<Value>>>,
+    keys: Vec<(usize, usize)>,
+    budget: Arc<Budget>,
+    /// The outer row being joined, its key, and how many of its matches
+    /// have been returned.
+    current: Option<(Vec<Value>, Vec<Key>, usize)>,
+}
+
+impl Iterator for HashJoin {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        if let Some(inner) = self.inner.take() {
+            for row in inner {
+                let row = match row {
+                    Ok(row) => row,
+                    Err(e) => return Some(Err(e)),
+                };
+                if let Some(key) = join_key(&row, self.keys.iter().map(|k| k.1)) {
+                    if let Err(e) = self.budget.reserve(row_size(&row)) {
+                        return Some(Err(e));
+                    }
+                    self.table.entry(key).or_default().push(row);
+                }
+            }
+        }
+        loop {
+            if let Some((outer, key, returned)) = &mut self.current {
+                if let Some(inner) = self.table[key].get(*returned) {
+                    *returned += 1;
+                    return Some(Ok(outer.iter().chain(inner).cloned().collect()));
+                }
+                self.current = None;
+            }
+            let outer = match self.outer.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(key) = join_key(&outer, self.keys.iter().map(|k| k.0))
+                && self.table.contains_key(&key)
+            {
+                self.current = Some((outer, key, 0));
+            }
+        }
+    }
+}
+
+/// Index nested-loop join into a table snapshot.
+struct IndexJoin {
+    outer: Rows,
+    rows: Arc<Vec<Row>>,
+    entries: Arc<HashMap<Key, Vec<usize>>>,
+    outer_key: usize,
+    checks: Vec<(usize, usize)>,
+    filter: Vec<(usize, Matcher)>,
+    budget: Arc<Budget>,
+    /// The outer row being joined, its key, and how many of the positions
+    /// the index gave for it have been looked at.
+    current: Option<(Vec<Value>, Key, usize)>,
+}
+
+impl IndexJoin {
+    /// Whether the inner row meets the rest of the join condition and the
+    /// WHERE condition.
+    fn accepts(&self, outer: &[Value], inner: &[Value]) -> bool {
+        self.filter
+            .iter()
+            .all(|(position, matcher)| matcher.matches(&inner[*position]))
+            && self.checks.iter().all(|&(o, i)| {
+                Key::from_value(&outer[o]).is_some_and(|k| Key::from_value(&inner[i]) == Some(k))
+            })
+    }
+}
+
+impl Iterator for IndexJoin {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        loop {
+            if let Some((outer, key, looked_at)) = self.current.take() {
+                let positions = &self.entries[&key];
+                let found = positions[looked_at..]
+                    .iter()
+                    .position(|&p| self.accepts(&outer, self.rows[p].get_inner_vec()));
+                let read = found.map_or(positions.len() - looked_at, |f| f + 1);
+                Metrics::add(&self.budget.metrics.rows_scanned, read);
+                if let Some(found) = found {
+                    let inner = self.rows[positions[looked_at + found]].get_inner_vec();
+                    let joined = outer.iter().chain(inner).cloned().collect();
+                    self.current = Some((outer, key, looked_at + found + 1));
+                    return Some(Ok(joined));
+                }
+            }
+            let outer = match self.outer.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(key) = Key::from_value(&outer[self.outer_key])
+                && self.entries.contains_key(&key)
+            {
+                self.current = Some((outer, key, 0));
+            }
+        }
+    }
+}
+
+/// Evaluates the select list against each input row.
+struct Project {
+    input: Rows,
+    fields: Vec<Field>,
+    exprs: Vec<Expr>,
+}
+
+impl Iterator for Project {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        let row = match self.input.next()? {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(
+            self.exprs
+                .iter()
+                .map(|expr| eval::eval(expr, &self.fields, &row))
+                .collect(),
+        )
+    }
+}
+
+/// Adds the next value of a sequence to each row for every `nextval()`
+/// call, moving the sequences on in the query's budget.
+struct NextVal {
+    input: Rows,
+    /// Where each sequence started if the budget has yet to move it on.
+    sequences: Vec<(String, Sequence)>,
+    budget: Arc<Budget>,
+}
+
+impl Iterator for NextVal {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        let mut row = match self.input.next()? {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut advanced = self.budget.sequences.lock().unwrap();
+        for (name, sequence) in &self.sequences {
+            let i = match advanced.iter().position(|(n, _)| n == name) {
+                Some(i) => i,
+                None => {
+                    advanced.push((name.clone(), sequence.clone()));
+                    advanced.len() - 1
+                }
+            };
+            match advanced[i].1.advance(name) {
+                Ok(value) => row.push(Value::Integer(value)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok(row))
+    }
+}
+
+/// Computes window functions: reads every input row first, since a row's
+/// window may take in any of the others, then returns them in the same
+/// order with each call's result added.
+struct Window {
+    /// Until the rows have been read.
+    input: Option<Rows>,
+    fields: Vec<Field>,
+    calls: Vec<Expr>,
+    budget: Arc<Budget>,
+    output: std::vec::IntoIter<Vec<Value>>,
+}
+
+impl Window {
+    fn compute(&mut self, input: Rows) -> Result<(), String> {
+        let mut rows = Vec::new();
+        for row in input {
+            let row = row?;
+            self.budget.reserve(row_size(&row))?;
+            rows.push(row);
+        }
+        let mut results = Vec::with_capacity(self.calls.len());
+        for call in &self.calls {
+            results.push(window::compute(call, &self.fields, &rows)?.into_iter());
+        }
+        for row in &mut rows {
+            row.extend(results.iter_mut().map(|r| r.next().unwrap()));
+        }
+        self.output = rows.into_iter();
+        Ok(())
+    }
+}
+
+impl Iterator for Window {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        if let Some(input) = self.input.take()
+            && let Err(e) = self.compute(input)
+        {
+            return Some(Err(e));
+        }
+        self.output.next().map(Ok)
+    }
+}
+
+/// A row's values as compared by DISTINCT and the set operations, which,
+/// unlike `=`, treat NULLs as equal to each other.
+fn distinct_key(row: &[Value]) -> Vec<Option<Key>> {
+    row.iter().map(Key::from_value).collect()
+}
+
+/// Each row the first time it comes up. The rows seen so far are kept to
+/// recognise repeats.
+struct Distinct {
+    input: Rows,
+    seen: HashSet<Vec<Option<Key>>>,
+    budget: Arc<Budget>,
+}
+
+impl Distinct {
+    fn new(input: Rows, budget: &Arc<Budget>) -> Self {
+        Self {
+            input,
+            seen: HashSet::new(),
+            budget: Arc::clone(budget),
+        }
+    }
+}
+
+impl Iterator for Distinct {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        loop {
+            let row = match self.input.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            if self.seen.insert(distinct_key(&row)) {
+                if let Err(e) = self.budget.reserve(row_size(&row)) {
+                    return Some(Err(e));
+                }
+                return Some(Ok(row));
+            }
+        }
+    }
+}
+
+/// ORDER BY. Every row is read and sorted the first time one is asked for;
+/// rows that sort equal keep the order they came in.
+struct Sort {
+    /// Until the rows are sorted.
+    input: Option<Rows>,
+    keys: SortKeys,
+    columns: usize,
+    budget: Arc<Budget>,
+    output: std::vec::IntoIter<Vec<Value>>,
+}
+
+impl Iterator for Sort {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        if let Some(input) = self.input.take() {
+            let mut rows = Vec::new();
+            for row in input {
+                let row = match row {
+                    Ok(row) => row,
+                    Err(e) => return Some(Err(e)),
+                };
+                if let Err(e) = self.budget.reserve(row_size(&row)) {
+                    return Some(Err(e));
+                }
+                rows.push(row);
+            }
+            // NULLs first, as if smaller than anything, as in a window
+            rows.sort_by(|a, b| {
+                self.keys
+                    .iter()
+                    .map(|&(k, descending)| {
+                        let ordering = a[k].compare(&b[k]);
+                        if descending {
+                            ordering.reverse()
+                        } else {
+                            ordering
+                        }
+                    })
+                    .find(|o| o.is_ne())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+            for row in &mut rows {
+                row.truncate(self.columns);
+            }
+            self.output = rows.into_iter();
+        }
+        self.output.next().map(Ok)
+    }
+}
+
+/// INTERSECT or EXCEPT: the distinct rows of the left side that are (or
+/// are not) among those of the right. The right side is read into a hash
+/// set the first time a row is asked for.
+struct SetFilter {
+    input: Distinct,
+    /// Until the hash set is built.
+    other: Option<Rows>,
+    found: HashSet<Vec<Option<Key>>>,
+    /// Whether to return the rows that are in `found` (INTERSECT) or those
+    /// that are not (EXCEPT).
+    keep_found: bool,
+    budget: Arc<Budget>,
+}
+
+impl Iterator for SetFilter {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        if let Some(other) = self.other.take() {
+            for row in other {
+                let row = match row {
+                    Ok(row) => row,
+                    Err(e) => return Some(Err(e)),
+                };
+                if self.found.insert(distinct_key(&row))
+                    && let Err(e) = self.budget.reserve(row_size(&row))
+                {
+                    return Some(Err(e));
+                }
+            }
+        }
+        loop {
+            match self.input.next()? {
+                Ok(row) if self.found.contains(&distinct_key(&row)) != self.keep_found => {}
+                row => return Some(row), // rows to keep, and errors
+            }
+        }
+    }
+}
+
+/// Runs a recursive WITH query: streams each round's rows while keeping
+/// them as the input of the next.
+struct Recursive {
+    name: String,
+    /// The round being read.
+    current: Rows,
+    step: Plan,
+    slot: WorkingSlot,
+    /// Rows the current round has added so far.
+    added: Vec<Vec<Value>>,
+    /// For UNION, every row produced so far.
+    seen: Option<HashSet<Vec<Option<Key>>>>,
+    rounds: usize,
+    budget: Arc<Budget>,
+}
+
+impl Iterator for Recursive {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<RowResult> {
+        loop {
+            if let Some(row) = self.current.next() {
+                let row = match row {
+                    Ok(row) => row,
+                    Err(e) => return Some(Err(e)),
+                };
+                if let Some(seen) = &mut self.seen
+                    && !seen.insert(distinct_key(&row))
+                {
+                    continue;
+                }
+                if let Err(e) = self.budget.reserve(row_size(&row)) {
+                    return Some(Err(e));
+                }
+                self.added.push(row.clone());
+                return Some(Ok(row));
+            }
+            if self.added.is_empty() {
+                return None;
+            }
+            self.rounds += 1;
+            if self.rounds > MAX_RECURSION {
+                self.added.clear();
+                return Some(Err(format!(
+                    "Query cancelled: '{}' was still adding rows after {} rounds; does it go round a cycle?",
+                    self.name, MAX_RECURSION
+                )));
+            }
+            *self.slot.lock().unwrap() = Arc::new(mem::take(&mut self.added));
+            self.current = self.step.clone().open(&self.budget);
+        }
+    }
+}
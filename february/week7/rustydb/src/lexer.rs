@@ -0,0 +1,515 @@
+use std::fmt;
+
+/// Reserved words. They always lex as keywords, so using one as a name
+/// takes double quotes: `SELECT "from" FROM t`.
+const KEYWORDS: &[&str] = &[
+    "AS",
+    "CAST",
+    "DISTINCT",
+    "EXCEPT",
+    "FROM",
+    "INNER",
+    "INSERT",
+    "INTERSECT",
+    "INTO",
+    "JOIN",
+    "NULL",
+    "ON",
+    "SELECT",
+    "UNION",
+    "VALUES",
+    "WHERE",
+];
+
+/// Operators and punctuation, longest first so `<=` wins over `<`.
+const SYMBOLS: &[&str] = &[
+    "||", "<=", ">=", "<>", "!=", "(", ")", ",", ";", ".", "+", "-", "*", "/", "%", "=", "<", ">",
+];
+
+/// Where a token starts in the input; both counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// A reserved word, upper-cased.
+    Keyword(&'static str),
+    /// An unquoted name, as written. Words that are only special in some
+    /// places (TABLE, HEADER, STDIN, ...) come through as identifiers too.
+    Identifier(String),
+    /// A `"double-quoted"` name, with `""` unescaped.
+    QuotedIdentifier(String),
+    /// A `'single-quoted'` string, with `''` unescaped.
+    String(String),
+    /// A numeric literal, as written.
+    Number(String),
+    Symbol(&'static str),
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The token exactly as it appeared in the input.
+    pub text: String,
+    pub span: Span,
+}
+
+/// Characters of the input with a running line/column position.
+struct Cursor {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    column: usize,
+}
+
+impl Cursor {
+    fn new(input: &str) -> Self {
+        Self {
+            chars: input.chars().collect(),
+            pos: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn text_from(&self, start: usize) -> String {
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// Skip whitespace and comments. Fails on an unterminated `/* */`.
+    fn skip_trivia(&mut self) -> Result<(), String> {
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some(c), _) if c.is_whitespace() => {
+                    self.bump();
+                }
+                (Some('-'), Some('-')) => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                }
+                (Some('/'), Some('*')) => {
+                    let start = self.span();
+                    self.bump();
+                    self.bump();
+                    loop {
+                        match (self.peek(), self.peek_at(1)) {
+                            (Some('*'), Some('/')) => {
+                                self.bump();
+                                self.bump();
+                                break;
+                            }
+                            (Some(_), _) => {
+                                self.bump();
+                            }
+                            (None, _) => return Err(syntax_error(start, "unterminated comment")),
+                        }
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Read a literal closed by `quote`, where a doubled quote stands for
+    /// one. The opening quote has already been consumed.
+    fn quoted(&mut self, quote: char, start: Span, what: &str) -> Result<String, String> {
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => {
+                    if self.peek() == Some(quote) {
+                        self.bump();
+                        s.push(quote);
+                    } else {
+                        return Ok(s);
+                    }
+                }
+                Some(c) => s.push(c),
+                None => return Err(syntax_error(start, &format!("unterminated {}", what))),
+            }
+        }
+    }
+
+    fn number(&mut self, start: Span) -> Result<(), String> {
+        let digits = |cur: &mut Cursor| {
+            while cur.peek().is_some_and(|c| c.is_ascii_digit()) {
+                cur.bump();
+            }
+        };
+        let begin = self.pos;
+        digits(self);
+        if self.peek() == Some('.') {
+            self.bump();
+            digits(self);
+        }
+        if matches!(self.peek(), Some('e' | 'E'))
+            && (self.peek_at(1).is_some_and(|c| c.is_ascii_digit())
+                || (matches!(self.peek_at(1), Some('+' | '-'))
+                    && self.peek_at(2).is_some_and(|c| c.is_ascii_digit())))
+        {
+            self.bump();
+            self.bump();
+            digits(self);
+        }
+        // `12abc` is a typo, not a number followed by a name
+        if self.peek().is_some_and(is_word_char) {
+            while self.peek().is_some_and(is_word_char) {
+                self.bump();
+            }
+            return Err(syntax_error(
+                start,
+                &format!("invalid number '{}'", self.text_from(begin)),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn is_word_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+pub fn syntax_error(span: Span, message: &str) -> String {
+    format!("Syntax error at {}: {}", span, message)
+}
+
+/// Split `input` into tokens, dropping whitespace and comments (`-- ...`
+/// to the end of the line, and `/* ... */`).
+pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut cur = Cursor::new(input);
+    let mut tokens = Vec::new();
+
+    loop {
+        cur.skip_trivia()?;
+        let span = cur.span();
+        let begin = cur.pos;
+        let Some(c) = cur.peek() else { break };
+
+        let kind = if c == '\'' {
+            cur.bump();
+            TokenKind::String(cur.quoted('\'', span, "string literal")?)
+        } else if c == '"' {
+            cur.bump();
+            let name = cur.quoted('"', span, "quoted identifier")?;
+            if name.is_empty() {
+                return Err(syntax_error(span, "empty quoted identifier"));
+            }
+            TokenKind::QuotedIdentifier(name)
+        } else if c.is_ascii_digit()
+            || (c == '.' && cur.peek_at(1).is_some_and(|d| d.is_ascii_digit()))
+        {
+            cur.number(span)?;
+            TokenKind::Number(cur.text_from(begin))
+        } else if is_word_start(c) {
+            while cur.peek().is_some_and(is_word_char) {
+                cur.bump();
+            }
+            let word = cur.text_from(begin);
+            match keyword(&word) {
+                Some(kw) => TokenKind::Keyword(kw),
+                None => TokenKind::Identifier(word),
+            }
+        } else {
+            let symbol = SYMBOLS.iter().find(|s| {
+                s.chars()
+                    .enumerate()
+                    .all(|(i, sc)| cur.peek_at(i) == Some(sc))
+            });
+            match symbol {
+                Some(s) => {
+                    for _ in 0..s.chars().count() {
+                        cur.bump();
+                    }
+                    TokenKind::Symbol(s)
+                }
+                None => {
+                    return Err(syntax_error(span, &format!("unexpected character '{}'", c)));
+                }
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            text: cur.text_from(begin),
+            span,
+        });
+    }
+    Ok(tokens)
+}
+
+/// Write `tokens` back out as text that lexes to the same tokens, spaced
+/// the way they usually are: not inside parentheses or around a `.`, nor
+/// before a `,` or `;`.
+pub fn join_tokens(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    let mut prev: Option<&TokenKind> = None;
+    for token in tokens {
+        let tight = matches!(prev, None | Some(TokenKind::Symbol("(" | ".")))
+            || matches!(token.kind, TokenKind::Symbol(")" | "." | "," | ";"));
+        if !tight {
+            text.push(' ');
+        }
+        text.push_str(&token.text);
+        prev = Some(&token.kind);
+    }
+    text
+}
+
+/// The position just past the end of `input`, for "found end of input".
+pub fn end_span(input: &str) -> Span {
+    let mut cur = Cursor::new(input);
+    while cur.bump().is_some() {}
+    cur.span()
+}
+
+fn keyword(word: &str) -> Option<&'static str> {
+    KEYWORDS
+        .iter()
+        .find(|kw| kw.eq_ignore_ascii_case(word))
+        .copied()
+}
+
+// ---------------------------------------------------------------------------
+// Quoting
+// ---------------------------------------------------------------------------
+
+/// Render `s` as a SQL string literal, doubling any embedded quotes.
+pub fn quote_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Render a table or column name so it lexes back as the same name:
+/// as is when it is a plain word, double-quoted otherwise.
+pub fn quote_identifier(name: &str) -> String {
+    let plain = name.starts_with(is_word_start)
+        && name.chars().all(is_word_char)
+        && keyword(name).is_none();
+    if plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Scripts
+// ---------------------------------------------------------------------------
+
+/// Follows the words of a statement, outside literals and comments, to
+/// tell whether it is a CREATE TRIGGER part-way through its BEGIN ... END
+/// body, where a `;` ends one of the body's statements rather than its own.
+#[derive(Default)]
+struct TriggerBody {
+    words: usize,
+    word: String,
+    trigger: bool,
+    open: bool,
+}
+
+impl TriggerBody {
+    fn push(&mut self, c: char) {
+        if is_word_char(c) {
+            self.word.push(c);
+            return;
+        }
+        if self.word.is_empty() {
+            return;
+        }
+        let word = std::mem::take(&mut self.word);
+        match self.words {
+            0 => self.trigger = word.eq_ignore_ascii_case("create"),
+            1 => self.trigger &= word.eq_ignore_ascii_case("trigger"),
+            _ if self.trigger && word.eq_ignore_ascii_case("begin") => self.open = true,
+            _ if self.trigger && word.eq_ignore_ascii_case("end") => self.open = false,
+            _ => {}
+        }
+        self.words += 1;
+    }
+
+    /// Whether a `;` here leaves the statement going.
+    fn is_open(&mut self) -> bool {
+        self.push(' ');
+        self.open
+    }
+}
+
+/// Split a script into individual statements on `;`, ignoring semicolons
+/// inside literals, quoted names and comments, and dropping the comments.
+/// The semicolons between a trigger's BEGIN and END stay in its statement.
+/// Statements that are empty after trimming are skipped.
+pub fn split_statements(script: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut body = TriggerBody::default();
+    let mut chars = script.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        body.push(c);
+        match c {
+            '\'' | '"' => {
+                current.push(c);
+                // Copy the literal verbatim; a doubled quote is just two
+                // quotes in a row
+                for inner in chars.by_ref() {
+                    current.push(inner);
+                    if inner == c {
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        current.push('\n');
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+                current.push(' ');
+            }
+            ';' if body.is_open() => current.push(c),
+            ';' => {
+                let stmt = current.trim();
+                if !stmt.is_empty() {
+                    statements.push(stmt.to_string());
+                }
+                current.clear();
+                body = TriggerBody::default();
+            }
+            c => current.push(c),
+        }
+    }
+    let stmt = current.trim();
+    if !stmt.is_empty() {
+        statements.push(stmt.to_string());
+    }
+    statements
+}
+
+/// Where a request's text stops, as far as a connection waiting for the
+/// rest of it is concerned.
+struct Stop {
+    /// Inside a literal, a quoted name or a `/* */` comment, with a
+    /// parenthesis left open, or before the END of a trigger's body.
+    incomplete: bool,
+    /// Right after a `;` that ends a statement, give or take whitespace
+    /// and comments.
+    terminated: bool,
+}
+
+fn scan(text: &str) -> Stop {
+    let cut = Stop {
+        incomplete: true,
+        terminated: false,
+    };
+    let mut depth = 0i32;
+    let mut body = TriggerBody::default();
+    let mut terminated = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        body.push(c);
+        match c {
+            '\'' | '"' => {
+                // A doubled quote closes and immediately reopens, which
+                // comes out the same
+                let closed = chars.by_ref().any(|inner| inner == c);
+                if !closed {
+                    return cut;
+                }
+                terminated = false;
+            }
+            // A line comment hides any quote or parenthesis in it
+            '-' if chars.peek() == Some(&'-') => {
+                let more_lines = chars.by_ref().any(|c| c == '\n');
+                if !more_lines {
+                    break;
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                let closed = chars.by_ref().any(|c| {
+                    let end = prev == '*' && c == '/';
+                    prev = c;
+                    end
+                });
+                if !closed {
+                    return cut;
+                }
+            }
+            ';' if depth <= 0 && !body.is_open() => {
+                body = TriggerBody::default();
+                terminated = true;
+            }
+            c => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                terminated &= c.is_whitespace();
+            }
+        }
+    }
+    Stop {
+        incomplete: depth > 0 || body.is_open(),
+        terminated,
+    }
+}
+
+/// Whether `text` ends with the `;` of its last statement, with nothing
+/// after it but whitespace and comments.
+pub fn is_terminated(text: &str) -> bool {
+    let stop = scan(text);
+    stop.terminated && !stop.incomplete
+}
@@ -0,0 +1,54 @@
+//! RustyDB: a small SQL database that can run in-process, like SQLite, or
+//! as a server (the `rustydb` binary, a thin wrapper over [`app::run`]).
+//!
+//! ```
+//! use rustydb::Database;
+//!
+//! let db = Database::new();
+//! let mut conn = db.connect();
+//! conn.execute_batch(
+//!     "CREATE TABLE users (id INTEGER, name TEXT);
+//!      INSERT INTO users VALUES (1, 'Ada');",
+//! )?;
+//!
+//! let mut tx = conn.transaction();
+//! tx.execute("INSERT INTO users VALUES (2, 'Grace')")?;
+//! tx.commit()?;
+//!
+//! for row in &conn.query("SELECT id, UPPER(name) AS name FROM users")? {
+//!     let id: i64 = row.get("id")?;
+//!     let name: String = row.get(1)?;
+//!     println!("{} {}", id, name);
+//! }
+//! # Ok::<(), String>(())
+//! ```
+//!
+//! Errors are plain messages, the same ones a server client would see.
+
+pub mod app;
+mod auth;
+pub mod config;
+mod connection;
+mod csv;
+mod datetime;
+mod dump;
+mod engine;
+mod eval;
+mod exec;
+mod lexer;
+pub mod log;
+mod metrics;
+mod parser;
+mod pool;
+mod replication;
+mod server;
+mod stats;
+mod storage;
+mod tls;
+mod trigger;
+mod window;
+
+pub use connection::{ColumnIndex, Connection, Database, FromValue, Row, Rows, Transaction};
+pub use datetime::Interval;
+pub use lexer::{quote_identifier, quote_literal};
+pub use storage::Value;
@@ -1,18 +1,74 @@
-use storage::Database;
+//! RustyDB server.
+//!
+//! Usage: rustydb [--config FILE] [--listen ADDR] [--data-dir DIR] [--follow ADDR]
+//!
+//! Flags override the config file; see `rustydb.example.toml` for its keys.
 
-mod parser;
-mod server;
-mod storage;
+use std::{env, path::PathBuf, process};
 
-const STUDENT_TABLE: &str = "Students";
+use rustydb::config::Config;
+
+const USAGE: &str =
+    "Usage: rustydb [--config FILE] [--listen ADDR] [--data-dir DIR] [--follow ADDR]";
+
+struct Options {
+    config: Option<PathBuf>,
+    listen: Option<String>,
+    data_dir: Option<PathBuf>,
+    follow: Option<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        config: None,
+        listen: None,
+        data_dir: None,
+        follow: None,
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match arg.as_str() {
+            "--config" => options.config = Some(value("--config")?.into()),
+            "--listen" => options.listen = Some(value("--listen")?),
+            "--data-dir" => options.data_dir = Some(value("--data-dir")?.into()),
+            "--follow" => options.follow = Some(value("--follow")?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            other => return Err(format!("Unknown argument: {}\n{}", other, USAGE)),
+        }
+    }
+    Ok(options)
+}
 
 fn main() {
-    let mut db = Database::new();
-    db.create_table(
-        STUDENT_TABLE,
-        vec!["id".to_string(), "name".to_string(), "class".to_string()],
-    );
-
-    let srv = server::Server::bind("127.0.0.1:7878", db).expect("Failed to bind TCP listener");
-    srv.run();
+    let options = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let mut config = match &options.config {
+        Some(path) => Config::load(path).unwrap_or_else(|e| fail(&e)),
+        None => Config::default(),
+    };
+    if let Some(listen) = options.listen {
+        config.listen = listen;
+    }
+    if let Some(dir) = options.data_dir {
+        config.data_dir = Some(dir);
+    }
+    if let Some(leader) = options.follow {
+        config.replication.leader = Some(leader);
+    }
+
+    if let Err(e) = rustydb::app::run(config) {
+        fail(&e);
+    }
+}
+
+/// Report a startup error and exit.
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
 }
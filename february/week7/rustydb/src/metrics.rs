@@ -0,0 +1,178 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{exec::Query, storage::Value};
+
+/// Upper bounds, in milliseconds, of the buckets of each statement kind's
+/// latency histogram. Anything slower lands in one more, unbounded bucket.
+const LATENCY_BUCKETS_MS: [u64; 12] = [1, 2, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 10000];
+
+/// What the server has done since it started, read with SHOW METRICS.
+/// Counters are updated without taking the database lock; each only ever
+/// grows, except `connections_active`.
+pub struct Metrics {
+    started: Instant,
+    pub connections: AtomicU64,
+    pub connections_active: AtomicU64,
+    /// Turned away for being over the connection limit.
+    pub connections_rejected: AtomicU64,
+    pub failed_logins: AtomicU64,
+    /// Read from tables by queries, whether or not they made it into the
+    /// result.
+    pub rows_scanned: AtomicU64,
+    /// Sent back as query results.
+    pub rows_returned: AtomicU64,
+    /// Added by INSERT, COPY and RESTORE.
+    pub rows_written: AtomicU64,
+    /// Statements that take at least this many milliseconds are counted
+    /// and logged as slow; 0 turns the slow-query log off.
+    slow_query_ms: AtomicU64,
+    slow_queries: AtomicU64,
+    statements: Mutex<BTreeMap<&'static str, StatementStats>>,
+}
+
+/// How often one kind of statement ran and how long it took.
+#[derive(Default)]
+struct StatementStats {
+    count: u64,
+    errors: u64,
+    buckets: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    total: Duration,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            connections_active: AtomicU64::new(0),
+            connections_rejected: AtomicU64::new(0),
+            failed_logins: AtomicU64::new(0),
+            rows_scanned: AtomicU64::new(0),
+            rows_returned: AtomicU64::new(0),
+            rows_written: AtomicU64::new(0),
+            slow_query_ms: AtomicU64::new(0),
+            slow_queries: AtomicU64::new(0),
+            statements: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add `n` to one of the counters.
+    pub fn add(counter: &AtomicU64, n: usize) {
+        counter.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Log statements slower than `threshold`, or none.
+    pub fn set_slow_query_threshold(&self, threshold: Option<Duration>) {
+        let ms = threshold.map_or(0, |t| (t.as_millis() as u64).max(1));
+        self.slow_query_ms.store(ms, Ordering::Relaxed);
+    }
+
+    /// Count a statement of `kind` that took `elapsed`, from receiving it
+    /// to sending the last of its result. Returns whether it was slow
+    /// enough for the slow-query log.
+    pub fn record(&self, kind: &'static str, elapsed: Duration, failed: bool) -> bool {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| elapsed <= Duration::from_millis(bound))
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        {
+            let mut statements = self.statements.lock().unwrap();
+            let stats = statements.entry(kind).or_default();
+            stats.count += 1;
+            stats.errors += u64::from(failed);
+            stats.buckets[bucket] += 1;
+            stats.total += elapsed;
+        }
+        let threshold = self.slow_query_ms.load(Ordering::Relaxed);
+        let slow = threshold > 0 && elapsed.as_millis() as u64 >= threshold;
+        if slow {
+            self.slow_queries.fetch_add(1, Ordering::Relaxed);
+        }
+        slow
+    }
+
+    /// `SHOW METRICS`: one row per counter, named the way Prometheus would.
+    /// Each statement kind gets a count, an error count and a cumulative
+    /// latency histogram (`le` is the bucket's bound in milliseconds).
+    pub fn report(&self) -> Query {
+        let count = |counter: &AtomicU64| Value::Integer(counter.load(Ordering::Relaxed) as i64);
+        let mut rows = vec![
+            (
+                "uptime_seconds".to_string(),
+                Value::Real(self.started.elapsed().as_millis() as f64 / 1000.0),
+            ),
+            ("connections_total".to_string(), count(&self.connections)),
+            (
+                "connections_active".to_string(),
+                count(&self.connections_active),
+            ),
+            (
+                "connections_rejected_total".to_string(),
+                count(&self.connections_rejected),
+            ),
+            (
+                "failed_logins_total".to_string(),
+                count(&self.failed_logins),
+            ),
+            ("rows_scanned_total".to_string(), count(&self.rows_scanned)),
+            (
+                "rows_returned_total".to_string(),
+                count(&self.rows_returned),
+            ),
+            ("rows_written_total".to_string(), count(&self.rows_written)),
+            ("slow_queries_total".to_string(), count(&self.slow_queries)),
+        ];
+
+        let statements = self.statements.lock().unwrap();
+        let errors: u64 = statements.values().map(|s| s.errors).sum();
+        rows.push(("errors_total".to_string(), Value::Integer(errors as i64)));
+        for (kind, stats) in statements.iter() {
+            let label = format!("kind=\"{}\"", kind);
+            rows.push((
+                format!("statements_total{{{}}}", label),
+                Value::Integer(stats.count as i64),
+            ));
+            rows.push((
+                format!("statement_errors_total{{{}}}", label),
+                Value::Integer(stats.errors as i64),
+            ));
+            let mut cumulative = 0;
+            for (i, n) in stats.buckets.iter().enumerate() {
+                cumulative += n;
+                let bound = LATENCY_BUCKETS_MS
+                    .get(i)
+                    .map_or("+Inf".to_string(), |b| b.to_string());
+                rows.push((
+                    format!("statement_latency_ms_bucket{{{},le=\"{}\"}}", label, bound),
+                    Value::Integer(cumulative as i64),
+                ));
+            }
+            rows.push((
+                format!("statement_latency_ms_sum{{{}}}", label),
+                Value::Real(stats.total.as_micros() as f64 / 1000.0),
+            ));
+        }
+
+        Query {
+            headers: vec!["metric".to_string(), "value".to_string()],
+            rows: Box::new(
+                rows.into_iter()
+                    .map(|(name, value)| Ok(vec![Value::Text(name), value])),
+            ),
+            cancel: Arc::default(),
+        }
+    }
+}
@@ -1,250 +1,2523 @@
-/// A condition used in WHERE clauses: `column = value`
+use std::{fmt, time::Duration};
+
+use crate::{
+    auth::Privilege,
+    csv::CsvOptions,
+    datetime, dump, eval,
+    lexer::{self, Span, Token, TokenKind, quote_identifier, quote_literal, syntax_error},
+    storage::{
+        Check, Column, DataType, ForeignKey, KeyKind, OnDelete, TriggerEvent, TriggerTiming, Value,
+    },
+    trigger, window,
+};
+
+/// A column as written in a query, optionally qualified by the table (or
+/// alias) it belongs to: `name` or `t.name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnRef {
+    pub table: Option<String>,
+    pub name: String,
+}
+
+impl fmt::Display for ColumnRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(table) = &self.table {
+            write!(f, "{}.", quote_identifier(table))?;
+        }
+        write!(f, "{}", quote_identifier(&self.name))
+    }
+}
+
+/// `column = value`: a part of a WHERE condition the planner can hand to
+/// the table the column is in, for an index to look up.
+#[derive(Clone)]
 pub struct Condition {
-    pub column: String,
-    pub value: String,
+    pub column: ColumnRef,
+    pub value: Value,
+}
+
+impl Condition {
+    /// `expr` as a `column = value` condition, if that is what it is, or
+    /// `value = column`.
+    pub fn from_expr(expr: &Expr) -> Option<Condition> {
+        let Expr::Binary {
+            op: BinaryOp::Eq,
+            left,
+            right,
+        } = expr
+        else {
+            return None;
+        };
+        match (&**left, &**right) {
+            (Expr::Column(column), Expr::Literal(value))
+            | (Expr::Literal(value), Expr::Column(column)) => Some(Condition {
+                column: column.clone(),
+                value: value.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.column, dump::sql_literal(&self.value))
+    }
+}
+
+/// Binary operators usable in expressions, from `+` to string concatenation,
+/// comparisons and AND / OR. Comparisons and logic give 1 for true and 0
+/// for false.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+}
+
+/// How tightly NOT and IS [NOT] NULL bind, between AND and the comparisons.
+const NOT_PRECEDENCE: u8 = 3;
+const IS_PRECEDENCE: u8 = 4;
+
+impl BinaryOp {
+    fn from_token(tok: &TokenKind) -> Option<Self> {
+        match tok {
+            TokenKind::Symbol("+") => Some(BinaryOp::Add),
+            TokenKind::Symbol("-") => Some(BinaryOp::Subtract),
+            TokenKind::Symbol("*") => Some(BinaryOp::Multiply),
+            TokenKind::Symbol("/") => Some(BinaryOp::Divide),
+            TokenKind::Symbol("%") => Some(BinaryOp::Modulo),
+            TokenKind::Symbol("||") => Some(BinaryOp::Concat),
+            TokenKind::Symbol("=") => Some(BinaryOp::Eq),
+            TokenKind::Symbol("<>" | "!=") => Some(BinaryOp::NotEq),
+            TokenKind::Symbol("<") => Some(BinaryOp::Lt),
+            TokenKind::Symbol("<=") => Some(BinaryOp::LtEq),
+            TokenKind::Symbol(">") => Some(BinaryOp::Gt),
+            TokenKind::Symbol(">=") => Some(BinaryOp::GtEq),
+            TokenKind::Identifier(word) if word.eq_ignore_ascii_case("and") => Some(BinaryOp::And),
+            TokenKind::Identifier(word) if word.eq_ignore_ascii_case("or") => Some(BinaryOp::Or),
+            _ => None,
+        }
+    }
+
+    /// Binding strength; higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::NotEq
+            | BinaryOp::Lt
+            | BinaryOp::LtEq
+            | BinaryOp::Gt
+            | BinaryOp::GtEq => 5,
+            BinaryOp::Concat => 6,
+            BinaryOp::Add | BinaryOp::Subtract => 7,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Modulo => 8,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulo => "%",
+            BinaryOp::Concat => "||",
+            BinaryOp::Eq => "=",
+            BinaryOp::NotEq => "<>",
+            BinaryOp::Lt => "<",
+            BinaryOp::LtEq => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::GtEq => ">=",
+            BinaryOp::And => "AND",
+            BinaryOp::Or => "OR",
+        }
+    }
+
+    /// Whether the operator compares its operands.
+    pub fn is_comparison(self) -> bool {
+        self.precedence() == 5
+    }
+}
+
+/// A scalar expression, as used in the projection of a SELECT.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Column(ColumnRef),
+    Literal(Value),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    /// `expr IS NULL`, or `expr IS NOT NULL` if `negated`.
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Function {
+        name: String, // upper-cased
+        args: Vec<Expr>,
+    },
+    Cast {
+        expr: Box<Expr>,
+        to: DataType,
+    },
+    /// `NAME(args) OVER (...)`: computed from the rows of the window around
+    /// each row rather than from the row alone. `COUNT(*)` has no args.
+    Window {
+        name: String, // upper-cased
+        args: Vec<Expr>,
+        over: Box<WindowSpec>,
+    },
 }
 
-/// The parsed SQL statement variants we support.
-pub enum Statement {
-    Select {
-        table: String,
-        columns: Vec<String>, // ["*"] means all columns
-        condition: Option<Condition>,
-    },
-    Insert {
-        table: String,
-        #[allow(dead_code)]
-        columns: Vec<String>,
-        values: Vec<String>,
-    },
-}
+impl Expr {
+    /// Give every column reference without a table name `table`'s.
+    pub fn qualify(&mut self, table: &str) {
+        match self {
+            Expr::Column(column) => {
+                column.table.get_or_insert_with(|| table.to_string());
+            }
+            Expr::Literal(_) => {}
+            Expr::Negate(inner)
+            | Expr::Not(inner)
+            | Expr::IsNull { expr: inner, .. }
+            | Expr::Cast { expr: inner, .. } => inner.qualify(table),
+            Expr::Binary { left, right, .. } => {
+                left.qualify(table);
+                right.qualify(table);
+            }
+            Expr::Function { args, .. } => args.iter_mut().for_each(|a| a.qualify(table)),
+            Expr::Window { args, over, .. } => {
+                args.iter_mut().for_each(|a| a.qualify(table));
+                over.partition_by.iter_mut().for_each(|e| e.qualify(table));
+                over.order_by.iter_mut().for_each(|o| o.expr.qualify(table));
+            }
+        }
+    }
+
+    /// Call `f` on every column the expression references.
+    pub fn for_each_column<'a>(&'a self, f: &mut impl FnMut(&'a ColumnRef)) {
+        match self {
+            Expr::Column(column) => f(column),
+            Expr::Literal(_) => {}
+            Expr::Negate(inner)
+            | Expr::Not(inner)
+            | Expr::IsNull { expr: inner, .. }
+            | Expr::Cast { expr: inner, .. } => inner.for_each_column(f),
+            Expr::Binary { left, right, .. } => {
+                left.for_each_column(f);
+                right.for_each_column(f);
+            }
+            Expr::Function { args, .. } => args.iter().for_each(|a| a.for_each_column(f)),
+            Expr::Window { args, over, .. } => {
+                args.iter().for_each(|a| a.for_each_column(f));
+                over.partition_by.iter().for_each(|e| e.for_each_column(f));
+                over.order_by.iter().for_each(|o| o.expr.for_each_column(f));
+            }
+        }
+    }
+
+    /// Whether the expression calls a window function anywhere.
+    pub fn has_window(&self) -> bool {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) => false,
+            Expr::Negate(inner)
+            | Expr::Not(inner)
+            | Expr::IsNull { expr: inner, .. }
+            | Expr::Cast { expr: inner, .. } => inner.has_window(),
+            Expr::Binary { left, right, .. } => left.has_window() || right.has_window(),
+            Expr::Function { args, .. } => args.iter().any(Expr::has_window),
+            Expr::Window { .. } => true,
+        }
+    }
+
+    /// Whether the expression calls the scalar function `name` anywhere.
+    pub fn calls(&self, name: &str) -> bool {
+        match self {
+            Expr::Column(_) | Expr::Literal(_) => false,
+            Expr::Negate(inner)
+            | Expr::Not(inner)
+            | Expr::IsNull { expr: inner, .. }
+            | Expr::Cast { expr: inner, .. } => inner.calls(name),
+            Expr::Binary { left, right, .. } => left.calls(name) || right.calls(name),
+            Expr::Function { name: called, args } => {
+                called == name || args.iter().any(|a| a.calls(name))
+            }
+            Expr::Window { args, over, .. } => args
+                .iter()
+                .chain(&over.partition_by)
+                .chain(over.order_by.iter().map(|o| &o.expr))
+                .any(|e| e.calls(name)),
+        }
+    }
+
+    /// The conditions ANDed together to make this one, or just itself.
+    pub fn conjuncts(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Binary {
+                op: BinaryOp::And,
+                left,
+                right,
+            } => {
+                let mut parts = left.conjuncts();
+                parts.extend(right.conjuncts());
+                parts
+            }
+            _ => vec![self],
+        }
+    }
+
+    /// Replace every `NOW()` with the timestamp `now`, so that the
+    /// expression gives the same wherever and whenever it is evaluated.
+    pub fn bind_now(&mut self, now: &Value) {
+        match self {
+            Expr::Function { name, .. } if name == "NOW" => *self = Expr::Literal(now.clone()),
+            Expr::Column(_) | Expr::Literal(_) => {}
+            Expr::Negate(inner)
+            | Expr::Not(inner)
+            | Expr::IsNull { expr: inner, .. }
+            | Expr::Cast { expr: inner, .. } => inner.bind_now(now),
+            Expr::Binary { left, right, .. } => {
+                left.bind_now(now);
+                right.bind_now(now);
+            }
+            Expr::Function { args, .. } => args.iter_mut().for_each(|a| a.bind_now(now)),
+            Expr::Window { args, over, .. } => {
+                args.iter_mut().for_each(|a| a.bind_now(now));
+                over.partition_by.iter_mut().for_each(|e| e.bind_now(now));
+                over.order_by.iter_mut().for_each(|o| o.expr.bind_now(now));
+            }
+        }
+    }
+
+    /// How tightly the expression holds together when written out, as
+    /// [`BinaryOp::precedence`] counts it.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary { op, .. } => op.precedence(),
+            Expr::Not(_) => NOT_PRECEDENCE,
+            Expr::IsNull { .. } => IS_PRECEDENCE,
+            _ => u8::MAX,
+        }
+    }
+}
+
+/// The `OVER (...)` of a window function: which rows share a window, in
+/// what order, and which of them each row's frame holds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowSpec {
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderBy>,
+    /// `ROWS BETWEEN start AND end`. Without it, the frame runs from the
+    /// start of the partition to the last row that sorts equal to the
+    /// current one, or to the end if there is no ORDER BY.
+    pub frame: Option<(FrameBound, FrameBound)>,
+}
+
+/// `expr [ASC | DESC]`. NULLs sort first, as if smaller than anything.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+    pub expr: Expr,
+    pub descending: bool,
+}
+
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.descending { " DESC" } else { "" };
+        write!(f, "{}{}", self.expr, direction)
+    }
+}
+
+/// One end of a window frame, counted in rows from the current one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(usize),
+    CurrentRow,
+    Following(usize),
+    UnboundedFollowing,
+}
+
+impl FrameBound {
+    /// Where the bound is relative to the current row, ignoring how many
+    /// rows away: a frame's start can't come after its end.
+    fn rank(self) -> u8 {
+        match self {
+            FrameBound::UnboundedPreceding => 0,
+            FrameBound::Preceding(_) => 1,
+            FrameBound::CurrentRow => 2,
+            FrameBound::Following(_) => 3,
+            FrameBound::UnboundedFollowing => 4,
+        }
+    }
+}
+
+impl fmt::Display for FrameBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameBound::UnboundedPreceding => write!(f, "UNBOUNDED PRECEDING"),
+            FrameBound::Preceding(n) => write!(f, "{} PRECEDING", n),
+            FrameBound::CurrentRow => write!(f, "CURRENT ROW"),
+            FrameBound::Following(n) => write!(f, "{} FOLLOWING", n),
+            FrameBound::UnboundedFollowing => write!(f, "UNBOUNDED FOLLOWING"),
+        }
+    }
+}
+
+impl fmt::Display for WindowSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut clauses = Vec::new();
+        if !self.partition_by.is_empty() {
+            let exprs: Vec<String> = self.partition_by.iter().map(|e| e.to_string()).collect();
+            clauses.push(format!("PARTITION BY {}", exprs.join(", ")));
+        }
+        if !self.order_by.is_empty() {
+            let items: Vec<String> = self.order_by.iter().map(|o| o.to_string()).collect();
+            clauses.push(format!("ORDER BY {}", items.join(", ")));
+        }
+        if let Some((start, end)) = &self.frame {
+            clauses.push(format!("ROWS BETWEEN {} AND {}", start, end));
+        }
+        write!(f, "({})", clauses.join(" "))
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Column(column) => write!(f, "{}", column),
+            Expr::Literal(v) => write!(f, "{}", dump::sql_literal(v)),
+            Expr::Negate(inner) => match inner.as_ref() {
+                _ if inner.precedence() < u8::MAX => write!(f, "-({})", inner),
+                // `--` would start a comment
+                _ if inner.to_string().starts_with('-') => write!(f, "-({})", inner),
+                _ => write!(f, "-{}", inner),
+            },
+            Expr::Not(inner) if inner.precedence() < IS_PRECEDENCE => write!(f, "NOT ({})", inner),
+            Expr::Not(inner) => write!(f, "NOT {}", inner),
+            Expr::IsNull { expr, negated } => {
+                if expr.precedence() < IS_PRECEDENCE {
+                    write!(f, "({})", expr)?;
+                } else {
+                    write!(f, "{}", expr)?;
+                }
+                write!(f, " IS {}NULL", if *negated { "NOT " } else { "" })
+            }
+            Expr::Binary { op, left, right } => {
+                // Parenthesise children that bind more loosely than we do
+                let wrap = |e: &Expr, strict: bool| {
+                    e.precedence() < op.precedence()
+                        || (strict && e.precedence() == op.precedence())
+                };
+                if wrap(left, false) {
+                    write!(f, "({})", left)?;
+                } else {
+                    write!(f, "{}", left)?;
+                }
+                write!(f, " {} ", op.symbol())?;
+                if wrap(right, true) {
+                    write!(f, "({})", right)
+                } else {
+                    write!(f, "{}", right)
+                }
+            }
+            Expr::Function { name, args } if name == "EXTRACT" => match args.as_slice() {
+                [Expr::Literal(Value::Text(field)), from] => {
+                    write!(f, "EXTRACT({} FROM {})", field.to_uppercase(), from)
+                }
+                _ => unreachable!("EXTRACT is parsed with a field and a value"),
+            },
+            Expr::Function { name, args } => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Expr::Cast { expr, to } => write!(f, "CAST({} AS {})", expr, to),
+            Expr::Window { name, args, over } if args.is_empty() && name == "COUNT" => {
+                write!(f, "COUNT(*) OVER {}", over)
+            }
+            Expr::Window { name, args, over } => {
+                let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "{}({}) OVER {}", name, args.join(", "), over)
+            }
+        }
+    }
+}
+
+/// One entry in a SELECT projection list.
+#[derive(Clone)]
+pub enum SelectItem {
+    /// `*` — every column of the table, in schema order.
+    Wildcard,
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+    },
+}
+
+impl SelectItem {
+    /// The column header shown for this item in the result set.
+    pub fn header(&self) -> String {
+        match self {
+            SelectItem::Wildcard => "*".to_string(),
+            SelectItem::Expr {
+                alias: Some(alias), ..
+            } => alias.clone(),
+            SelectItem::Expr {
+                expr: Expr::Column(column),
+                alias: None,
+            } => column.name.clone(),
+            SelectItem::Expr { expr, alias: None } => expr.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SelectItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectItem::Wildcard => write!(f, "*"),
+            SelectItem::Expr {
+                expr,
+                alias: Some(alias),
+            } => write!(f, "{} AS {}", expr, quote_identifier(alias)),
+            SelectItem::Expr { expr, alias: None } => write!(f, "{}", expr),
+        }
+    }
+}
+
+/// A table (or view) named in FROM or JOIN, possibly under an alias.
+#[derive(Clone)]
+pub struct TableRef {
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+impl TableRef {
+    /// The name its columns are qualified with: the alias if there is one.
+    pub fn binding(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl fmt::Display for TableRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", quote_identifier(&self.name))?;
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {}", quote_identifier(alias))?;
+        }
+        Ok(())
+    }
+}
+
+/// `JOIN table ON a = b [AND c = d ...]`: an inner join on column equality.
+#[derive(Clone)]
+pub struct Join {
+    pub table: TableRef,
+    pub on: Vec<(ColumnRef, ColumnRef)>,
+}
+
+/// How UNION, INTERSECT or EXCEPT combines the rows of two queries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOp {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+impl fmt::Display for SetOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SetOp::Union => write!(f, "UNION"),
+            SetOp::UnionAll => write!(f, "UNION ALL"),
+            SetOp::Intersect => write!(f, "INTERSECT"),
+            SetOp::Except => write!(f, "EXCEPT"),
+        }
+    }
+}
+
+/// `WITH [RECURSIVE] name [(columns)] AS (query), ...` ahead of a query.
+/// Each name can be read like a view by the queries after it and, with
+/// RECURSIVE, by its own.
+#[derive(Clone)]
+pub struct With {
+    pub recursive: bool,
+    pub queries: Vec<WithQuery>,
+}
+
+/// One named query of a WITH clause.
+#[derive(Clone)]
+pub struct WithQuery {
+    pub name: String,
+    /// Names for its columns in place of the query's headers; empty to
+    /// keep those.
+    pub columns: Vec<String>,
+    pub query: Select,
+}
+
+impl WithQuery {
+    /// Whether the query reads its own name, making it recursive if the
+    /// WITH says RECURSIVE.
+    pub fn reads_itself(&self) -> bool {
+        self.query
+            .blocks()
+            .any(|block| block.block_tables().any(|t| t.name == self.name))
+    }
+}
+
+/// A query: `SELECT [DISTINCT] projection FROM table [JOIN ...] [WHERE
+/// condition]`, possibly followed by more such blocks to combine it with.
+/// Each table may also be a view, or a name defined by WITH.
+#[derive(Clone)]
+pub struct Select {
+    /// Only on the first block of a query.
+    pub with: Option<With>,
+    pub distinct: bool,
+    pub from: TableRef,
+    pub joins: Vec<Join>,
+    pub projection: Vec<SelectItem>,
+    pub condition: Option<Expr>,
+    /// `UNION`, `INTERSECT` or `EXCEPT` blocks, applied left to right with
+    /// equal precedence, as SQLite does. Their own `compound` is empty.
+    pub compound: Vec<(SetOp, Select)>,
+    /// `ORDER BY`, for the whole query: only on its first block. Each term
+    /// is the number of a result column, from 1, the name of one, or, for
+    /// a query of one block without DISTINCT, any expression of its FROM.
+    pub order_by: Vec<OrderBy>,
+}
+
+impl Select {
+    /// Every table or view the query reads, in the order they appear,
+    /// including those of the blocks combined with it and of its WITH
+    /// queries, but not the names WITH defines.
+    pub fn tables(&self) -> Vec<&TableRef> {
+        let mut tables = Vec::new();
+        self.collect_tables(&mut Vec::new(), &mut tables);
+        tables
+    }
+
+    fn collect_tables<'a>(&'a self, defined: &mut Vec<&'a str>, tables: &mut Vec<&'a TableRef>) {
+        let outer = defined.len();
+        if let Some(with) = &self.with {
+            for query in &with.queries {
+                if with.recursive {
+                    defined.push(&query.name);
+                    query.query.collect_tables(defined, tables);
+                } else {
+                    query.query.collect_tables(defined, tables);
+                    defined.push(&query.name);
+                }
+            }
+        }
+        for block in self.blocks() {
+            tables.extend(
+                block
+                    .block_tables()
+                    .filter(|t| !defined.contains(&t.name.as_str())),
+            );
+        }
+        defined.truncate(outer);
+    }
+
+    /// This block and those combined with it.
+    pub fn blocks(&self) -> impl Iterator<Item = &Select> {
+        std::iter::once(self).chain(self.compound.iter().map(|(_, block)| block))
+    }
+
+    /// The tables and views of this block's own FROM and JOINs.
+    pub fn block_tables(&self) -> impl Iterator<Item = &TableRef> {
+        std::iter::once(&self.from).chain(self.joins.iter().map(|j| &j.table))
+    }
+
+    /// Whether function `name` is called anywhere in the query, including
+    /// the blocks combined with it and its WITH queries.
+    pub fn calls(&self, name: &str) -> bool {
+        let mut with = self.with.iter().flat_map(|w| &w.queries);
+        with.any(|q| q.query.calls(name))
+            || self.order_by.iter().any(|o| o.expr.calls(name))
+            || self.blocks().any(|block| {
+                block.condition.as_ref().is_some_and(|c| c.calls(name))
+                    || block.projection.iter().any(|item| match item {
+                        SelectItem::Wildcard => false,
+                        SelectItem::Expr { expr, .. } => expr.calls(name),
+                    })
+            })
+    }
+
+    /// As [`Expr::bind_now`], for every expression of the query, its
+    /// combined blocks and its WITH queries. The views it reads are left
+    /// as they are.
+    pub fn bind_now(&mut self, now: &Value) {
+        if let Some(with) = &mut self.with {
+            for query in &mut with.queries {
+                query.query.bind_now(now);
+            }
+        }
+        for order in &mut self.order_by {
+            order.expr.bind_now(now);
+        }
+        if let Some(condition) = &mut self.condition {
+            condition.bind_now(now);
+        }
+        for item in &mut self.projection {
+            if let SelectItem::Expr { expr, .. } = item {
+                expr.bind_now(now);
+            }
+        }
+        for (_, block) in &mut self.compound {
+            block.bind_now(now);
+        }
+    }
+}
+
+/// Renders the query back as SQL that parses to the same thing.
+impl fmt::Display for Select {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(with) = &self.with {
+            let recursive = if with.recursive { "RECURSIVE " } else { "" };
+            write!(f, "WITH {}", recursive)?;
+            for (i, query) in with.queries.iter().enumerate() {
+                let separator = if i == 0 { "" } else { ", " };
+                write!(f, "{}{}", separator, quote_identifier(&query.name))?;
+                if !query.columns.is_empty() {
+                    let columns: Vec<String> =
+                        query.columns.iter().map(|c| quote_identifier(c)).collect();
+                    write!(f, " ({})", columns.join(", "))?;
+                }
+                write!(f, " AS ({})", query.query)?;
+            }
+            write!(f, " ")?;
+        }
+        let items: Vec<String> = self.projection.iter().map(|i| i.to_string()).collect();
+        let distinct = if self.distinct { "DISTINCT " } else { "" };
+        write!(
+            f,
+            "SELECT {}{} FROM {}",
+            distinct,
+            items.join(", "),
+            self.from
+        )?;
+        for join in &self.joins {
+            let on: Vec<String> = join
+                .on
+                .iter()
+                .map(|(left, right)| format!("{} = {}", left, right))
+                .collect();
+            write!(f, " JOIN {} ON {}", join.table, on.join(" AND "))?;
+        }
+        if let Some(condition) = &self.condition {
+            write!(f, " WHERE {}", condition)?;
+        }
+        for (op, block) in &self.compound {
+            write!(f, " {} {}", op, block)?;
+        }
+        if !self.order_by.is_empty() {
+            let items: Vec<String> = self.order_by.iter().map(|o| o.to_string()).collect();
+            write!(f, " ORDER BY {}", items.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Where COPY reads its data from or writes it to.
+pub enum CopyEndpoint {
+    /// A file on the server's filesystem.
+    File(String),
+    /// The client connection itself (`STDIN` / `STDOUT`).
+    Client,
+}
+
+/// A value given to INSERT for one column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InsertValue {
+    Value(Value),
+    /// DEFAULT: NULL, or the next number for an AUTOINCREMENT column.
+    Default,
+    /// `nextval('sequence')`, taken as the row goes in.
+    NextVal(String),
+    /// `NOW()`, the time the row goes in.
+    Now,
+}
+
+/// `ON CONFLICT [(column)] DO ...` of an INSERT: what to do instead when
+/// the row would duplicate a key.
+#[derive(Clone)]
+pub struct OnConflict {
+    /// The key column whose duplicates are handled, or any key's if `None`
+    /// (DO NOTHING only).
+    pub target: Option<String>,
+    pub action: ConflictAction,
+}
+
+#[derive(Clone)]
+pub enum ConflictAction {
+    /// Leave the row already there alone.
+    Nothing,
+    /// `SET column = expr, ...` on the row already there. Unqualified
+    /// columns are its own; `excluded.column` is the row that wasn't added.
+    Update(Vec<(String, Expr)>),
+}
+
+/// The parsed SQL statement variants we support.
+pub enum Statement {
+    CreateTable {
+        table: String,
+        columns: Vec<Column>,
+        checks: Vec<Check>,
+    },
+    DropTable {
+        table: String,
+        if_exists: bool,
+    },
+    Select(Select),
+    /// A named, stored query usable wherever a table name is.
+    CreateView {
+        name: String,
+        query: Select,
+    },
+    DropView {
+        name: String,
+        if_exists: bool,
+    },
+    /// A hash index on one column, used for `=` lookups and joins.
+    CreateIndex {
+        name: String,
+        table: String,
+        column: String,
+    },
+    DropIndex {
+        name: String,
+        if_exists: bool,
+    },
+    /// Gather the statistics the planner uses for one table, or all of them.
+    Analyze {
+        table: Option<String>,
+    },
+    /// Show how a query would be run, without running it.
+    Explain(Select),
+    /// One row. Columns left out of `columns` get their DEFAULT.
+    Insert {
+        table: String,
+        columns: Option<Vec<String>>,
+        values: Vec<InsertValue>,
+        on_conflict: Option<OnConflict>,
+        /// `RETURNING ...`: what to send back about the row as stored.
+        returning: Option<Vec<SelectItem>>,
+    },
+    /// `UPDATE table SET col = expr, ... [WHERE condition]`.
+    Update {
+        table: String,
+        assignments: Vec<(String, Expr)>,
+        condition: Option<Expr>,
+    },
+    /// `DELETE FROM table [WHERE condition]`.
+    Delete {
+        table: String,
+        condition: Option<Expr>,
+    },
+    /// `ALTER TABLE table ALTER col RESTART [WITH n]`: where the numbers of
+    /// an AUTOINCREMENT column go on from; `None` goes back to 1.
+    AlterTable {
+        table: String,
+        column: String,
+        restart: Option<i64>,
+    },
+    /// Statements run for each row an INSERT, UPDATE or DELETE of `table`
+    /// changes, kept as the text of each.
+    CreateTrigger {
+        name: String,
+        table: String,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+        body: Vec<String>,
+    },
+    DropTrigger {
+        name: String,
+        if_exists: bool,
+    },
+    /// A counter handing out integers through `nextval('name')`.
+    CreateSequence {
+        name: String,
+        start: i64,
+        increment: i64,
+    },
+    DropSequence {
+        name: String,
+        if_exists: bool,
+    },
+    /// `ALTER SEQUENCE name RESTART [WITH n]`: `None` goes back to the start.
+    AlterSequence {
+        name: String,
+        restart: Option<i64>,
+    },
+    CopyFrom {
+        table: String,
+        columns: Option<Vec<String>>,
+        source: CopyEndpoint,
+        options: CsvOptions,
+    },
+    CopyTo {
+        table: String,
+        columns: Option<Vec<String>>,
+        target: CopyEndpoint,
+        options: CsvOptions,
+    },
+    /// Write every table as a replayable SQL script.
+    Dump {
+        target: CopyEndpoint,
+    },
+    /// Replay a script produced by DUMP.
+    Restore {
+        source: CopyEndpoint,
+    },
+    /// Run the statements in a server-side SQL file, reporting each result.
+    Source {
+        path: String,
+    },
+    /// Report this server's followers, or its leader and how far behind it is.
+    ShowReplication,
+    /// Report the server's counters and statement latencies.
+    ShowMetrics,
+    /// Limit how long each of this session's queries may run: `None` goes
+    /// back to the server's default, zero means no limit.
+    SetStatementTimeout {
+        timeout: Option<Duration>,
+    },
+    ShowStatementTimeout,
+    CreateUser {
+        name: String,
+        password: String,
+        superuser: bool,
+    },
+    DropUser {
+        name: String,
+    },
+    Grant {
+        privileges: Vec<Privilege>,
+        table: String,
+        user: String,
+    },
+    Revoke {
+        privileges: Vec<Privilege>,
+        table: String,
+        user: String,
+    },
+}
+
+impl Statement {
+    /// A short name for the kind of statement, e.g. "CREATE TABLE".
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Statement::CreateTable { .. } => "CREATE TABLE",
+            Statement::DropTable { .. } => "DROP TABLE",
+            Statement::Select(_) => "SELECT",
+            Statement::CreateView { .. } => "CREATE VIEW",
+            Statement::DropView { .. } => "DROP VIEW",
+            Statement::CreateIndex { .. } => "CREATE INDEX",
+            Statement::DropIndex { .. } => "DROP INDEX",
+            Statement::Analyze { .. } => "ANALYZE",
+            Statement::Explain(_) => "EXPLAIN",
+            Statement::Insert { .. } => "INSERT",
+            Statement::Update { .. } => "UPDATE",
+            Statement::Delete { .. } => "DELETE",
+            Statement::AlterTable { .. } => "ALTER TABLE",
+            Statement::CreateTrigger { .. } => "CREATE TRIGGER",
+            Statement::DropTrigger { .. } => "DROP TRIGGER",
+            Statement::CreateSequence { .. } => "CREATE SEQUENCE",
+            Statement::DropSequence { .. } => "DROP SEQUENCE",
+            Statement::AlterSequence { .. } => "ALTER SEQUENCE",
+            Statement::CopyFrom { .. } | Statement::CopyTo { .. } => "COPY",
+            Statement::Dump { .. } => "DUMP",
+            Statement::Restore { .. } => "RESTORE",
+            Statement::Source { .. } => "SOURCE",
+            Statement::ShowReplication => "SHOW REPLICATION",
+            Statement::ShowMetrics => "SHOW METRICS",
+            Statement::SetStatementTimeout { .. } => "SET",
+            Statement::ShowStatementTimeout => "SHOW",
+            Statement::CreateUser { .. } => "CREATE USER",
+            Statement::DropUser { .. } => "DROP USER",
+            Statement::Grant { .. } => "GRANT",
+            Statement::Revoke { .. } => "REVOKE",
+        }
+    }
+
+    /// Whether the statement changes tables, views or sequences, which only
+    /// a leader may do; its followers get the change from it. A SELECT
+    /// that calls `nextval()` moves sequences on.
+    pub fn is_write(&self) -> bool {
+        if let Statement::Select(query) = self {
+            return query.calls("NEXTVAL");
+        }
+        matches!(
+            self,
+            Statement::CreateTable { .. }
+                | Statement::DropTable { .. }
+                | Statement::CreateView { .. }
+                | Statement::DropView { .. }
+                | Statement::CreateIndex { .. }
+                | Statement::DropIndex { .. }
+                | Statement::Insert { .. }
+                | Statement::Update { .. }
+                | Statement::Delete { .. }
+                | Statement::AlterTable { .. }
+                | Statement::CreateTrigger { .. }
+                | Statement::DropTrigger { .. }
+                | Statement::CreateSequence { .. }
+                | Statement::DropSequence { .. }
+                | Statement::AlterSequence { .. }
+                | Statement::CopyFrom { .. }
+                | Statement::Restore { .. }
+        )
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parser helpers
+// ---------------------------------------------------------------------------
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Where the input ends, for "found end of input" errors.
+    end: Span,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Token>, end: Span) -> Self {
+        Self {
+            tokens,
+            pos: 0,
+            end,
+        }
+    }
+
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|t| &t.kind)
+    }
+
+    fn advance(&mut self) {
+        self.pos += 1;
+    }
+
+    /// A syntax error at the next token: "expected X, found Y".
+    fn unexpected(&self, expected: &str) -> String {
+        match self.tokens.get(self.pos) {
+            Some(tok) => syntax_error(
+                tok.span,
+                &format!("expected {}, found '{}'", expected, tok.text),
+            ),
+            None => syntax_error(
+                self.end,
+                &format!("expected {}, found end of input", expected),
+            ),
+        }
+    }
+
+    /// Whether the next token is `word`: the keyword itself or, for words
+    /// that aren't reserved, an identifier spelled that way in any case.
+    fn peek_word(&self, word: &str) -> bool {
+        match self.peek() {
+            Some(TokenKind::Keyword(kw)) => kw.eq_ignore_ascii_case(word),
+            Some(TokenKind::Identifier(name)) => name.eq_ignore_ascii_case(word),
+            _ => false,
+        }
+    }
+
+    /// Consume the next token if it is `word`.
+    fn accept_word(&mut self, word: &str) -> bool {
+        let found = self.peek_word(word);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    /// Consume the next token and assert (case-insensitive) it is `word`.
+    fn expect_word(&mut self, word: &str) -> Result<(), String> {
+        if self.accept_word(word) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&word.to_uppercase()))
+        }
+    }
+
+    fn peek_symbol(&self, symbol: &str) -> bool {
+        self.peek() == Some(&TokenKind::Symbol(symbol_str(symbol)))
+    }
+
+    fn accept_symbol(&mut self, symbol: &str) -> bool {
+        let found = self.peek_symbol(symbol);
+        if found {
+            self.advance();
+        }
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), String> {
+        if self.accept_symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("'{}'", symbol)))
+        }
+    }
+
+    /// Consume a table, column or user name: a plain or quoted identifier.
+    fn expect_name(&mut self, role: &str) -> Result<String, String> {
+        match self.peek() {
+            Some(TokenKind::Identifier(name) | TokenKind::QuotedIdentifier(name)) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.unexpected(role)),
+        }
+    }
+
+    /// Consume any bare word, reserved or not, e.g. a type or privilege
+    /// name, and return it as written.
+    fn expect_any_word(&mut self, role: &str) -> Result<String, String> {
+        match self.peek() {
+            Some(TokenKind::Keyword(kw)) => {
+                let word = kw.to_string();
+                self.advance();
+                Ok(word)
+            }
+            Some(TokenKind::Identifier(word)) => {
+                let word = word.clone();
+                self.advance();
+                Ok(word)
+            }
+            _ => Err(self.unexpected(role)),
+        }
+    }
+
+    /// Consume a 'quoted' string literal and return its contents.
+    fn expect_string(&mut self, role: &str) -> Result<String, String> {
+        match self.peek() {
+            Some(TokenKind::String(s)) => {
+                let s = s.clone();
+                self.advance();
+                Ok(s)
+            }
+            _ => Err(self.unexpected(role)),
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // Parse a comma-separated list of names enclosed in parentheses
+    // e.g.  ( col1 , col2 , col3 )
+    // -----------------------------------------------------------------------
+    fn parse_paren_list(&mut self) -> Result<Vec<String>, String> {
+        self.expect_symbol("(")?;
+        let mut items = vec![self.expect_name("column name")?];
+        while self.accept_symbol(",") {
+            items.push(self.expect_name("column name")?);
+        }
+        self.expect_symbol(")")?;
+        Ok(items)
+    }
+
+    // -----------------------------------------------------------------------
+    // Parse a literal value as written in VALUES (...):
+    // a quoted string, NULL, a typed literal like DATE '2024-01-31', or a
+    // number with an optional '-'. Numbers stay text here; the table
+    // schema decides their real type.
+    // -----------------------------------------------------------------------
+    fn parse_value(&mut self) -> Result<Value, String> {
+        if let Some(value) = self.parse_typed_literal()? {
+            return Ok(value);
+        }
+        let value = match self.peek() {
+            Some(TokenKind::String(s)) => Value::Text(s.clone()),
+            Some(TokenKind::Keyword("NULL")) => Value::Null,
+            Some(TokenKind::Number(n)) => Value::Text(n.clone()),
+            // Text has to be quoted: a bare word is more likely a mistake
+            Some(TokenKind::Identifier(name)) => {
+                return Err(syntax_error(
+                    self.tokens[self.pos].span,
+                    &format!("expected a value, found identifier '{}'", name),
+                ));
+            }
+            Some(TokenKind::Symbol("-")) => {
+                self.advance();
+                match self.peek() {
+                    Some(TokenKind::Number(n)) => Value::Text(format!("-{}", n)),
+                    _ => return Err(self.unexpected("number")),
+                }
+            }
+            _ => return Err(self.unexpected("value")),
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    /// `DATE '...'`, `TIME '...'`, `TIMESTAMP '...'` or `INTERVAL '...'`,
+    /// if that is what comes next.
+    fn parse_typed_literal(&mut self) -> Result<Option<Value>, String> {
+        let data_type = match self.peek() {
+            Some(TokenKind::Identifier(word)) => DataType::from_name(word),
+            _ => None,
+        };
+        let text = match self.tokens.get(self.pos + 1).map(|t| &t.kind) {
+            Some(TokenKind::String(text)) => text.clone(),
+            _ => return Ok(None),
+        };
+        let Some(data_type) = data_type.filter(|t| datetime::is_temporal(*t)) else {
+            return Ok(None);
+        };
+        self.advance();
+        self.advance();
+        datetime::parse(data_type, &text)
+            .map(Some)
+            .ok_or_else(|| format!("Invalid {} literal: {}", data_type, quote_literal(&text)))
+    }
+
+    // -----------------------------------------------------------------------
+    // Parse a parenthesised, comma-separated list of INSERT values
+    // e.g.  ( DEFAULT , 'Alice' , -3 , nextval('ids') )
+    // -----------------------------------------------------------------------
+    fn parse_value_list(&mut self) -> Result<Vec<InsertValue>, String> {
+        self.expect_symbol("(")?;
+        let mut values = vec![self.parse_insert_value()?];
+        while self.accept_symbol(",") {
+            values.push(self.parse_insert_value()?);
+        }
+        self.expect_symbol(")")?;
+        Ok(values)
+    }
+
+    fn parse_insert_value(&mut self) -> Result<InsertValue, String> {
+        if self.accept_word("default") {
+            return Ok(InsertValue::Default);
+        }
+        if self.peek_word("nextval") && self.next_is_symbol("(") {
+            self.advance();
+            self.advance();
+            let name = self.expect_string("quoted sequence name")?;
+            self.expect_symbol(")")?;
+            return Ok(InsertValue::NextVal(name));
+        }
+        if self.peek_word("now") && self.next_is_symbol("(") {
+            self.advance();
+            self.advance();
+            self.expect_symbol(")")?;
+            return Ok(InsertValue::Now);
+        }
+        self.parse_value().map(InsertValue::Value)
+    }
+
+    /// An integer, with an optional minus sign.
+    fn expect_integer(&mut self, role: &str) -> Result<i64, String> {
+        let negative = self.accept_symbol("-");
+        let value = match self.peek() {
+            Some(TokenKind::Number(n)) if negative => format!("-{}", n).parse().ok(),
+            Some(TokenKind::Number(n)) => n.parse().ok(),
+            _ => None,
+        };
+        let Some(value) = value else {
+            return Err(self.unexpected(role));
+        };
+        self.advance();
+        Ok(value)
+    }
+
+    // -----------------------------------------------------------------------
+    // Parse a comma-separated projection list (no parens)
+    // e.g.  *  |  id, name || ' ' || class AS label, UPPER(name)
+    // Stops at the first token that does not continue the list (FROM / end)
+    // -----------------------------------------------------------------------
+    fn parse_projection(&mut self) -> Result<Vec<SelectItem>, String> {
+        let mut items = Vec::new();
+        loop {
+            if self.accept_symbol("*") {
+                items.push(SelectItem::Wildcard);
+            } else {
+                let expr = self.parse_expr()?;
+                let alias = if self.accept_word("as") {
+                    Some(self.expect_name("alias")?)
+                } else if let Some(TokenKind::Identifier(_) | TokenKind::QuotedIdentifier(_)) =
+                    self.peek()
+                {
+                    // Implicit alias: `SELECT id * 2 doubled FROM ...`
+                    Some(self.expect_name("alias")?)
+                } else {
+                    None
+                };
+                items.push(SelectItem::Expr { expr, alias });
+            }
+            if !self.accept_symbol(",") {
+                break;
+            }
+        }
+        Ok(items)
+    }
+
+    // -----------------------------------------------------------------------
+    // Expressions — precedence climbing over the binary operators, lowest
+    // first: OR, AND, NOT, IS [NOT] NULL, the comparisons, `||`, then
+    // `+ -`, then `* / %`, then unary minus.
+    // -----------------------------------------------------------------------
+    fn parse_expr(&mut self) -> Result<Expr, String> {
+        self.parse_binary(1)
+    }
+
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        loop {
+            if IS_PRECEDENCE >= min_prec && self.accept_word("is") {
+                let negated = self.accept_word("not");
+                self.expect_word("null")?;
+                left = Expr::IsNull {
+                    expr: Box::new(left),
+                    negated,
+                };
+                continue;
+            }
+            let Some(op) = self.peek().and_then(BinaryOp::from_token) else {
+                break;
+            };
+            if op.precedence() < min_prec {
+                break;
+            }
+            self.advance();
+            let right = self.parse_binary(op.precedence() + 1)?;
+            left = Expr::Binary {
+                op,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.accept_word("not") {
+            let inner = self.parse_binary(IS_PRECEDENCE)?;
+            return Ok(Expr::Not(Box::new(inner)));
+        }
+        if self.accept_symbol("-") {
+            // Read with its digits, so that -9223372036854775808 fits an
+            // INTEGER though 9223372036854775808 doesn't
+            if let Some(TokenKind::Number(n)) = self.peek() {
+                let value =
+                    number_value(&format!("-{}", n)).ok_or_else(|| self.unexpected("number"))?;
+                self.advance();
+                return Ok(Expr::Literal(value));
+            }
+            let inner = self.parse_unary()?;
+            return Ok(match inner {
+                Expr::Literal(Value::Integer(i)) if i != i64::MIN => {
+                    Expr::Literal(Value::Integer(-i))
+                }
+                Expr::Literal(Value::Real(r)) => Expr::Literal(Value::Real(-r)),
+                other => Expr::Negate(Box::new(other)),
+            });
+        }
+        if self.accept_symbol("+") {
+            return self.parse_unary();
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        let Some(kind) = self.peek().cloned() else {
+            return Err(self.unexpected("expression"));
+        };
+        match kind {
+            TokenKind::Symbol("(") => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                Ok(expr)
+            }
+            TokenKind::String(s) => {
+                self.advance();
+                Ok(Expr::Literal(Value::Text(s)))
+            }
+            TokenKind::Number(n) => {
+                let value = number_value(&n).ok_or_else(|| self.unexpected("number"))?;
+                self.advance();
+                Ok(Expr::Literal(value))
+            }
+            TokenKind::Keyword("NULL") => {
+                self.advance();
+                Ok(Expr::Literal(Value::Null))
+            }
+            TokenKind::Identifier(_) if let Some(value) = self.parse_typed_literal()? => {
+                Ok(Expr::Literal(value))
+            }
+            // EXTRACT(field FROM expr), the field a word or a string
+            TokenKind::Identifier(name)
+                if name.eq_ignore_ascii_case("extract") && self.next_is_symbol("(") =>
+            {
+                self.advance();
+                self.advance();
+                let field = match self.peek() {
+                    Some(TokenKind::String(s)) => s.to_lowercase(),
+                    Some(TokenKind::Identifier(word)) => word.to_lowercase(),
+                    _ => return Err(self.unexpected("field name")),
+                };
+                self.advance();
+                datetime::check_extract_field(&field)?;
+                self.expect_word("from")?;
+                let expr = self.parse_expr()?;
+                self.expect_symbol(")")?;
+                Ok(Expr::Function {
+                    name: "EXTRACT".to_string(),
+                    args: vec![Expr::Literal(Value::Text(field)), expr],
+                })
+            }
+            TokenKind::Keyword("CAST") => {
+                self.advance();
+                self.expect_symbol("(")?;
+                let expr = self.parse_expr()?;
+                self.expect_word("as")?;
+                let type_name = self.expect_any_word("type name")?;
+                let to = DataType::from_name(&type_name)
+                    .ok_or_else(|| format!("Unknown type in CAST: '{}'", type_name))?;
+                self.expect_symbol(")")?;
+                Ok(Expr::Cast {
+                    expr: Box::new(expr),
+                    to,
+                })
+            }
+            // Function call: NAME ( [arg, ...] ) [OVER (...)]
+            TokenKind::Identifier(name) if self.next_is_symbol("(") => {
+                self.advance();
+                self.advance();
+                let name = name.to_uppercase();
+                let mut args = Vec::new();
+                let star = name == "COUNT" && self.accept_symbol("*");
+                if star {
+                    self.expect_symbol(")")?;
+                } else if !self.accept_symbol(")") {
+                    loop {
+                        args.push(self.parse_expr()?);
+                        if self.accept_symbol(")") {
+                            break;
+                        }
+                        if !self.accept_symbol(",") {
+                            return Err(self.unexpected("',' or ')'"));
+                        }
+                    }
+                }
+                if self.peek_word("over") && self.next_is_symbol("(") {
+                    self.advance();
+                    window::check_function(&name, args.len() + usize::from(star))?;
+                    let over = self.parse_window_spec()?;
+                    let nested = args
+                        .iter()
+                        .chain(&over.partition_by)
+                        .chain(over.order_by.iter().map(|o| &o.expr))
+                        .any(Expr::has_window);
+                    if nested {
+                        return Err("Window functions can't be nested".to_string());
+                    }
+                    return Ok(Expr::Window {
+                        name,
+                        args,
+                        over: Box::new(over),
+                    });
+                }
+                if window::is_function(&name) {
+                    return Err(format!(
+                        "{} is only supported as a window function, with OVER (...)",
+                        name
+                    ));
+                }
+                eval::check_function(&name, args.len())?;
+                Ok(Expr::Function { name, args })
+            }
+            TokenKind::Identifier(_) | TokenKind::QuotedIdentifier(_) => {
+                Ok(Expr::Column(self.parse_column_ref()?))
+            }
+            _ => Err(self.unexpected("expression")),
+        }
+    }
+
+    /// `( [PARTITION BY expr, ...] [ORDER BY expr [ASC|DESC], ...]
+    /// [ROWS BETWEEN bound AND bound | ROWS bound] )`, after OVER.
+    fn parse_window_spec(&mut self) -> Result<WindowSpec, String> {
+        self.expect_symbol("(")?;
+        let mut partition_by = Vec::new();
+        if self.accept_word("partition") {
+            self.expect_word("by")?;
+            loop {
+                partition_by.push(self.parse_expr()?);
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+        }
+        let order_by = self.parse_order_by()?;
+        let frame = if self.accept_word("rows") {
+            let frame = if self.accept_word("between") {
+                let start = self.parse_frame_bound()?;
+                self.expect_word("and")?;
+                (start, self.parse_frame_bound()?)
+            } else {
+                (self.parse_frame_bound()?, FrameBound::CurrentRow)
+            };
+            match frame {
+                (FrameBound::UnboundedFollowing, _) => {
+                    return Err("A window frame can't start at UNBOUNDED FOLLOWING".to_string());
+                }
+                (_, FrameBound::UnboundedPreceding) => {
+                    return Err("A window frame can't end at UNBOUNDED PRECEDING".to_string());
+                }
+                (start, end) if start.rank() > end.rank() => {
+                    return Err(format!(
+                        "A window frame can't start at {} and end at {}",
+                        start, end
+                    ));
+                }
+                _ => Some(frame),
+            }
+        } else if self.peek_word("range") || self.peek_word("groups") {
+            return Err("Only ROWS window frames are supported".to_string());
+        } else {
+            None
+        };
+        self.expect_symbol(")")?;
+        Ok(WindowSpec {
+            partition_by,
+            order_by,
+            frame,
+        })
+    }
+
+    /// `UNBOUNDED PRECEDING`, `n PRECEDING`, `CURRENT ROW`, `n FOLLOWING`
+    /// or `UNBOUNDED FOLLOWING`.
+    fn parse_frame_bound(&mut self) -> Result<FrameBound, String> {
+        if self.accept_word("unbounded") {
+            if self.accept_word("preceding") {
+                return Ok(FrameBound::UnboundedPreceding);
+            }
+            self.expect_word("following")?;
+            return Ok(FrameBound::UnboundedFollowing);
+        }
+        if self.accept_word("current") {
+            self.expect_word("row")?;
+            return Ok(FrameBound::CurrentRow);
+        }
+        let rows = match self.peek() {
+            Some(TokenKind::Number(n)) => n.parse::<usize>().ok(),
+            _ => None,
+        };
+        let Some(rows) = rows else {
+            return Err(self.unexpected("frame bound"));
+        };
+        self.advance();
+        if self.accept_word("preceding") {
+            return Ok(FrameBound::Preceding(rows));
+        }
+        self.expect_word("following")?;
+        Ok(FrameBound::Following(rows))
+    }
+
+    /// Whether the token after the next one is `symbol`.
+    fn next_is_symbol(&self, symbol: &str) -> bool {
+        self.tokens.get(self.pos + 1).map(|t| &t.kind)
+            == Some(&TokenKind::Symbol(symbol_str(symbol)))
+    }
+
+    /// A column name, optionally qualified: `name` or `table.name`.
+    fn parse_column_ref(&mut self) -> Result<ColumnRef, String> {
+        let name = self.expect_name("column name")?;
+        if !self.accept_symbol(".") {
+            return Ok(ColumnRef { table: None, name });
+        }
+        Ok(ColumnRef {
+            table: Some(name),
+            name: self.expect_name("column name")?,
+        })
+    }
+
+    // -----------------------------------------------------------------------
+    // Parse optional WHERE clause → Expr
+    // Syntax: WHERE condition, e.g. a > 0 AND (b = 1 OR c IS NULL)
+    // -----------------------------------------------------------------------
+    fn parse_where(&mut self) -> Result<Option<Expr>, String> {
+        if !self.accept_word("where") {
+            return Ok(None);
+        }
+        let condition = self.parse_expr()?;
+        if condition.has_window() {
+            return Err("Window functions aren't allowed in WHERE".to_string());
+        }
+        Ok(Some(condition))
+    }
+
+    // -----------------------------------------------------------------------
+    // SELECT col1, col2 FROM table [WHERE condition]
+    // SELECT * FROM table [WHERE condition]
+    // SELECT expr [AS alias], ... FROM table [WHERE condition]
+    // SELECT ... FROM t [AS] a [INNER] JOIN u [AS] b ON a.x = b.y [AND ...]
+    // SELECT DISTINCT ...
+    // SELECT ... UNION [ALL] | INTERSECT | EXCEPT SELECT ...
+    // SELECT ... ORDER BY expr [ASC | DESC], ...
+    // WITH [RECURSIVE] name [(col, ...)] AS (SELECT ...), ... SELECT ...
+    // -----------------------------------------------------------------------
+
+    /// A whole query, from its WITH or SELECT on.
+    fn parse_query(&mut self) -> Result<Select, String> {
+        if self.accept_word("with") {
+            self.parse_with()
+        } else {
+            self.expect_word("select")?;
+            self.parse_select()
+        }
+    }
+
+    /// A query after the word WITH.
+    fn parse_with(&mut self) -> Result<Select, String> {
+        let recursive = self.accept_word("recursive");
+        let mut queries = Vec::new();
+        loop {
+            let name = self.expect_name("WITH query name")?;
+            if queries.iter().any(|q: &WithQuery| q.name == name) {
+                return Err(format!("WITH query '{}' is defined more than once", name));
+            }
+            let columns = if self.peek_symbol("(") {
+                self.parse_paren_list()?
+            } else {
+                Vec::new()
+            };
+            self.expect_word("as")?;
+            self.expect_symbol("(")?;
+            let query = self.parse_query()?;
+            self.expect_symbol(")")?;
+            queries.push(WithQuery {
+                name,
+                columns,
+                query,
+            });
+            if !self.accept_symbol(",") {
+                break;
+            }
+        }
+        self.expect_word("select")?;
+        let mut query = self.parse_select()?;
+        query.with = Some(With { recursive, queries });
+        Ok(query)
+    }
+
+    /// `ORDER BY expr [ASC | DESC], ...`, if that is what comes next.
+    fn parse_order_by(&mut self) -> Result<Vec<OrderBy>, String> {
+        let mut order_by = Vec::new();
+        if !self.accept_word("order") {
+            return Ok(order_by);
+        }
+        self.expect_word("by")?;
+        loop {
+            let expr = self.parse_expr()?;
+            let descending = if self.accept_word("desc") {
+                true
+            } else {
+                self.accept_word("asc");
+                false
+            };
+            order_by.push(OrderBy { expr, descending });
+            if !self.accept_symbol(",") {
+                break;
+            }
+        }
+        Ok(order_by)
+    }
+
+    /// A query after the word SELECT.
+    fn parse_select(&mut self) -> Result<Select, String> {
+        let mut query = self.parse_select_block()?;
+        loop {
+            let op = if self.accept_word("union") {
+                if self.accept_word("all") {
+                    SetOp::UnionAll
+                } else {
+                    SetOp::Union
+                }
+            } else if self.accept_word("intersect") {
+                SetOp::Intersect
+            } else if self.accept_word("except") {
+                SetOp::Except
+            } else {
+                break;
+            };
+            self.expect_word("select")?;
+            query.compound.push((op, self.parse_select_block()?));
+        }
+        query.order_by = self.parse_order_by()?;
+        Ok(query)
+    }
 
-// ---------------------------------------------------------------------------
-// Tokenizer
-// ---------------------------------------------------------------------------
+    /// One SELECT, after the word SELECT, up to any UNION, INTERSECT or
+    /// EXCEPT.
+    fn parse_select_block(&mut self) -> Result<Select, String> {
+        let distinct = self.accept_word("distinct");
+        // Projection — * and/or comma-separated expressions
+        let projection = self.parse_projection()?;
 
-/// Split the raw input into tokens, handling:
-///  - Quoted strings (single-quoted) as one token
-///  - Parentheses / commas as individual tokens
-///  - Stripping trailing semicolons
-fn tokenize(input: &str) -> Vec<String> {
-    let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
-
-    while let Some(&ch) = chars.peek() {
-        match ch {
-            // Skip whitespace
-            ' ' | '\t' | '\n' | '\r' => {
-                chars.next();
-            }
-            // Single-quoted string → one token (without the quotes)
-            '\'' => {
-                chars.next(); // consume opening quote
-                let mut s = String::new();
-                for c in chars.by_ref() {
-                    if c == '\'' {
-                        break;
-                    }
-                    s.push(c);
-                }
-                tokens.push(s);
-            }
-            // Punctuation that is its own token
-            '(' | ')' | ',' | ';' => {
-                chars.next();
-                // Silently drop semicolons — they are just statement terminators
-                if ch != ';' {
-                    tokens.push(ch.to_string());
-                }
-            }
-            // Regular word token
-            _ => {
-                let mut s = String::new();
-                while let Some(&c) = chars.peek() {
-                    if c == ' '
-                        || c == '\t'
-                        || c == '\n'
-                        || c == '\r'
-                        || c == '('
-                        || c == ')'
-                        || c == ','
-                        || c == ';'
-                    {
-                        break;
-                    }
-                    s.push(c);
-                    chars.next();
+        self.expect_word("from")?;
+        let from = self.parse_table_ref()?;
+
+        let mut joins = Vec::new();
+        loop {
+            if self.accept_word("inner") {
+                self.expect_word("join")?;
+            } else if !self.accept_word("join") {
+                break;
+            }
+            let table = self.parse_table_ref()?;
+            self.expect_word("on")?;
+            let mut on = Vec::new();
+            loop {
+                let left = self.parse_column_ref()?;
+                self.expect_symbol("=")?;
+                let right = self.parse_column_ref()?;
+                on.push((left, right));
+                if !self.accept_word("and") {
+                    break;
                 }
-                tokens.push(s);
             }
+            joins.push(Join { table, on });
         }
+
+        let condition = self.parse_where()?;
+
+        Ok(Select {
+            with: None,
+            distinct,
+            from,
+            joins,
+            projection,
+            condition,
+            compound: Vec::new(),
+            order_by: Vec::new(),
+        })
     }
-    tokens
-}
 
-// ---------------------------------------------------------------------------
-// Parser helpers
-// ---------------------------------------------------------------------------
+    /// `table [[AS] alias]`
+    fn parse_table_ref(&mut self) -> Result<TableRef, String> {
+        let name = self.expect_name("table name")?;
+        let alias = if self.accept_word("as") {
+            Some(self.expect_name("alias")?)
+        } else if let Some(TokenKind::Identifier(_) | TokenKind::QuotedIdentifier(_)) = self.peek()
+            && !self.peek_word("order")
+        {
+            Some(self.expect_name("alias")?)
+        } else {
+            None
+        };
+        Ok(TableRef { name, alias })
+    }
 
-struct Parser {
-    tokens: Vec<String>,
-    pos: usize,
-}
+    // -----------------------------------------------------------------------
+    // INSERT INTO table [(col1, col2)] VALUES (val1, val2) [RETURNING ...]
+    // -----------------------------------------------------------------------
+    fn parse_insert(&mut self) -> Result<Statement, String> {
+        self.expect_word("into")?;
+        let table = self.expect_name("table name")?;
 
-impl Parser {
-    fn new(tokens: Vec<String>) -> Self {
-        Self { tokens, pos: 0 }
+        // Optional explicit column list
+        let columns = if self.peek_symbol("(") {
+            Some(self.parse_paren_list()?)
+        } else {
+            None
+        };
+
+        self.expect_word("values")?;
+        let values = self.parse_value_list()?;
+
+        if let Some(columns) = &columns
+            && columns.len() != values.len()
+        {
+            return Err(format!(
+                "Column count ({}) does not match value count ({})",
+                columns.len(),
+                values.len()
+            ));
+        }
+
+        let on_conflict = if self.accept_word("on") {
+            Some(self.parse_on_conflict()?)
+        } else {
+            None
+        };
+
+        let returning = if self.accept_word("returning") {
+            Some(self.parse_projection()?)
+        } else {
+            None
+        };
+
+        Ok(Statement::Insert {
+            table,
+            columns,
+            values,
+            on_conflict,
+            returning,
+        })
     }
 
-    fn peek(&self) -> Option<&str> {
-        self.tokens.get(self.pos).map(|s| s.as_str())
+    // -----------------------------------------------------------------------
+    // UPDATE table SET col1 = expr, col2 = expr [WHERE condition]
+    // DELETE FROM table [WHERE condition]
+    // -----------------------------------------------------------------------
+    fn parse_update(&mut self) -> Result<Statement, String> {
+        let table = self.expect_name("table name")?;
+        self.expect_word("set")?;
+        let assignments = self.parse_assignments()?;
+        let condition = self.parse_where()?;
+        Ok(Statement::Update {
+            table,
+            assignments,
+            condition,
+        })
     }
 
-    fn next_token(&mut self) -> Option<&str> {
-        let tok = self.tokens.get(self.pos).map(|s| s.as_str());
-        self.pos += 1;
-        tok
+    fn parse_delete(&mut self) -> Result<Statement, String> {
+        self.expect_word("from")?;
+        let table = self.expect_name("table name")?;
+        let condition = self.parse_where()?;
+        Ok(Statement::Delete { table, condition })
     }
 
-    /// Consume the next token and assert (case-insensitive) it equals `expected`.
-    fn expect_keyword(&mut self, expected: &str) -> Result<(), String> {
-        match self.next_token() {
-            Some(tok) if tok.to_lowercase() == expected.to_lowercase() => Ok(()),
-            Some(tok) => Err(format!(
-                "Syntax error: expected '{}', found '{}'",
-                expected, tok
-            )),
-            None => Err(format!(
-                "Syntax error: expected '{}', found end of input",
-                expected
-            )),
+    /// `col1 = expr, col2 = expr`, after SET.
+    fn parse_assignments(&mut self) -> Result<Vec<(String, Expr)>, String> {
+        let mut assignments: Vec<(String, Expr)> = Vec::new();
+        loop {
+            let column = self.expect_name("column name")?;
+            if assignments.iter().any(|(c, _)| *c == column) {
+                return Err(format!("Column '{}' is set more than once", column));
+            }
+            self.expect_symbol("=")?;
+            assignments.push((column, self.parse_expr()?));
+            if !self.accept_symbol(",") {
+                return Ok(assignments);
+            }
         }
     }
 
-    /// Consume the next token and return it (error if missing).
-    fn expect_any(&mut self, role: &str) -> Result<String, String> {
-        match self.next_token() {
-            Some(tok) => Ok(tok.to_string()),
-            None => Err(format!(
-                "Syntax error: expected {}, found end of input",
-                role
-            )),
+    // -----------------------------------------------------------------------
+    // ON CONFLICT [(column)] DO NOTHING
+    // ON CONFLICT (column) DO UPDATE SET col1 = expr, col2 = excluded.col2
+    // -----------------------------------------------------------------------
+    fn parse_on_conflict(&mut self) -> Result<OnConflict, String> {
+        self.expect_word("conflict")?;
+        let target = if self.accept_symbol("(") {
+            let column = self.expect_name("column name")?;
+            self.expect_symbol(")")?;
+            Some(column)
+        } else {
+            None
+        };
+        self.expect_word("do")?;
+        if self.accept_word("nothing") {
+            return Ok(OnConflict {
+                target,
+                action: ConflictAction::Nothing,
+            });
+        }
+        self.expect_word("update")?;
+        if target.is_none() {
+            return Err(
+                "ON CONFLICT DO UPDATE needs the key column to watch, e.g. ON CONFLICT (id)"
+                    .to_string(),
+            );
         }
+        self.expect_word("set")?;
+        let assignments = self.parse_assignments()?;
+        Ok(OnConflict {
+            target,
+            action: ConflictAction::Update(assignments),
+        })
     }
 
     // -----------------------------------------------------------------------
-    // Parse a comma-separated list of identifiers enclosed in parentheses
-    // e.g.  ( col1 , col2 , col3 )
+    // CREATE TABLE table (col1 TYPE [PRIMARY KEY | UNIQUE] [AUTOINCREMENT]
+    //                     [REFERENCES other (col) [ON DELETE action]]
+    //                     [[CONSTRAINT name] CHECK (expr)], ...,
+    //                     [[CONSTRAINT name] CHECK (expr)], ...)
+    // CREATE TRIGGER name BEFORE | AFTER INSERT | UPDATE | DELETE ON table
+    //     [FOR EACH ROW] BEGIN statement; ... END
+    // CREATE SEQUENCE name [START [WITH] n] [INCREMENT [BY] n]
+    // CREATE USER name WITH PASSWORD 'secret' [SUPERUSER]
+    // CREATE VIEW name AS SELECT ...
+    // CREATE INDEX name ON table (column)
     // -----------------------------------------------------------------------
-    fn parse_paren_list(&mut self) -> Result<Vec<String>, String> {
-        self.expect_keyword("(")?;
-        let mut items = Vec::new();
+    fn parse_create(&mut self) -> Result<Statement, String> {
+        if self.accept_word("user") {
+            return self.parse_create_user();
+        }
+        if self.accept_word("view") {
+            return self.parse_create_view();
+        }
+        if self.accept_word("index") {
+            return self.parse_create_index();
+        }
+        if self.accept_word("sequence") {
+            return self.parse_create_sequence();
+        }
+        if self.accept_word("trigger") {
+            return self.parse_create_trigger();
+        }
+        self.expect_word("table")?;
+        let table = self.expect_name("table name")?;
+        self.expect_symbol("(")?;
+
+        let mut columns: Vec<Column> = Vec::new();
+        let mut checks: Vec<Check> = Vec::new();
         loop {
-            match self.peek() {
-                Some(")") => {
-                    self.next_token();
+            if self.peek_word("constraint") || self.peek_word("check") {
+                self.parse_check(&mut checks, &format!("{}_check", table))?;
+                if self.accept_symbol(")") {
+                    break;
+                }
+                if !self.accept_symbol(",") {
+                    return Err(self.unexpected("',' or ')'"));
+                }
+                continue;
+            }
+            let name = self.expect_name("column name")?;
+            if columns.iter().any(|c| c.name == name) {
+                return Err(format!("Column '{}' specified more than once", name));
+            }
+            let type_name = self.expect_any_word("column type")?;
+            // SERIAL is short for INTEGER AUTOINCREMENT
+            let serial = type_name.eq_ignore_ascii_case("serial");
+            let data_type = match DataType::from_name(&type_name) {
+                Some(data_type) => data_type,
+                None if serial => DataType::Integer,
+                None => return Err(format!("Unknown column type: '{}'", type_name)),
+            };
+            let mut column = Column::new(&name, data_type);
+            column.auto_increment = serial;
+            loop {
+                if self.accept_word("primary") {
+                    self.expect_word("key")?;
+                    if columns.iter().any(|c| c.key == Some(KeyKind::Primary)) {
+                        return Err("A table can have only one PRIMARY KEY".to_string());
+                    }
+                    column.key = Some(KeyKind::Primary);
+                } else if self.accept_word("unique") {
+                    column.key = column.key.or(Some(KeyKind::Unique));
+                } else if self.accept_word("autoincrement") {
+                    column.auto_increment = true;
+                } else if column.references.is_none() && self.accept_word("references") {
+                    column.references = Some(self.parse_references()?);
+                } else if self.peek_word("constraint") || self.peek_word("check") {
+                    self.parse_check(&mut checks, &format!("{}_{}_check", table, name))?;
+                } else {
                     break;
                 }
-                Some(",") => {
-                    self.next_token();
+            }
+            if column.auto_increment {
+                if data_type != DataType::Integer {
+                    return Err(format!(
+                        "AUTOINCREMENT column '{}' must be an INTEGER",
+                        name
+                    ));
                 }
-                Some(_) => {
-                    let item = self.expect_any("identifier")?;
-                    items.push(item);
+                if columns.iter().any(|c| c.auto_increment) {
+                    return Err("A table can have only one AUTOINCREMENT column".to_string());
                 }
-                None => return Err("Syntax error: unclosed parenthesis".to_string()),
+            }
+            columns.push(column);
+
+            if self.accept_symbol(")") {
+                break;
+            }
+            if !self.accept_symbol(",") {
+                return Err(self.unexpected("',' or ')'"));
             }
         }
-        Ok(items)
+
+        if columns.is_empty() {
+            return Err("A table needs at least one column".to_string());
+        }
+        Ok(Statement::CreateTable {
+            table,
+            columns,
+            checks,
+        })
     }
 
-    // -----------------------------------------------------------------------
-    // Parse a comma-separated list of column names (no parens)
-    // Stops at a keyword that is NOT a comma, e.g. FROM / WHERE / end
-    // -----------------------------------------------------------------------
-    fn parse_column_list(&mut self) -> Result<Vec<String>, String> {
-        let mut cols = Vec::new();
+    /// `[CONSTRAINT name] CHECK (expr)`, added to `checks`. Without a name
+    /// it gets `default`, numbered if that is taken, as in PostgreSQL.
+    fn parse_check(&mut self, checks: &mut Vec<Check>, default: &str) -> Result<(), String> {
+        let name = if self.accept_word("constraint") {
+            Some(self.expect_name("constraint name")?)
+        } else {
+            None
+        };
+        self.expect_word("check")?;
+        self.expect_symbol("(")?;
+        let expr = self.parse_expr()?;
+        self.expect_symbol(")")?;
+        let taken = |name: &str| checks.iter().any(|c| c.name == name);
+        let name = match name {
+            Some(name) if taken(&name) => {
+                return Err(format!("Constraint '{}' specified more than once", name));
+            }
+            Some(name) => name,
+            None => (0..)
+                .map(|i| match i {
+                    0 => default.to_string(),
+                    i => format!("{}{}", default, i),
+                })
+                .find(|name| !taken(name))
+                .expect("some number is free"),
+        };
+        checks.push(Check { name, expr });
+        Ok(())
+    }
+
+    fn parse_create_trigger(&mut self) -> Result<Statement, String> {
+        let name = self.expect_name("trigger name")?;
+        let timing = if self.accept_word("before") {
+            TriggerTiming::Before
+        } else if self.accept_word("after") {
+            TriggerTiming::After
+        } else {
+            return Err(self.unexpected("BEFORE or AFTER"));
+        };
+        let event = if self.accept_word("insert") {
+            TriggerEvent::Insert
+        } else if self.accept_word("update") {
+            TriggerEvent::Update
+        } else if self.accept_word("delete") {
+            TriggerEvent::Delete
+        } else {
+            return Err(self.unexpected("INSERT, UPDATE or DELETE"));
+        };
+        self.expect_word("on")?;
+        let table = self.expect_name("table name")?;
+        // Statement-level triggers aren't supported, so this is all there is
+        if self.accept_word("for") {
+            self.expect_word("each")?;
+            self.expect_word("row")?;
+        }
+        self.expect_word("begin")?;
+
+        let mut body = Vec::new();
+        let mut start = self.pos;
         loop {
-            let col = self.expect_any("column name")?;
-            cols.push(col);
             match self.peek() {
-                Some(",") => {
-                    self.next_token(); // consume comma, continue
+                None if self.pos > start => return Err(self.unexpected("';'")),
+                None => return Err(self.unexpected("END")),
+                Some(TokenKind::Symbol(";")) => {
+                    if self.pos > start {
+                        body.push(lexer::join_tokens(&self.tokens[start..self.pos]));
+                    }
+                    self.advance();
+                    start = self.pos;
                 }
-                _ => break,
+                _ if self.pos == start && self.peek_word("end") => {
+                    self.advance();
+                    break;
+                }
+                _ => self.advance(),
             }
         }
-        Ok(cols)
+        if body.is_empty() {
+            return Err(format!(
+                "Trigger '{}' needs at least one statement between BEGIN and END",
+                name
+            ));
+        }
+        // NEW and OLD are checked against the table when it is created
+        for text in &body {
+            trigger::parse(text, |_, _| Ok("NULL".to_string()))
+                .map_err(|e| format!("In the body of trigger '{}': {}", name, e))?;
+        }
+        Ok(Statement::CreateTrigger {
+            name,
+            table,
+            timing,
+            event,
+            body,
+        })
+    }
+
+    /// `other (col) [ON DELETE CASCADE | SET NULL | RESTRICT | NO ACTION]`,
+    /// after REFERENCES.
+    fn parse_references(&mut self) -> Result<ForeignKey, String> {
+        let table = self.expect_name("table name")?;
+        self.expect_symbol("(")?;
+        let column = self.expect_name("column name")?;
+        self.expect_symbol(")")?;
+        let mut on_delete = OnDelete::Restrict;
+        if self.accept_word("on") {
+            self.expect_word("delete")?;
+            on_delete = if self.accept_word("cascade") {
+                OnDelete::Cascade
+            } else if self.accept_word("set") {
+                self.expect_word("null")?;
+                OnDelete::SetNull
+            } else if self.accept_word("restrict") {
+                OnDelete::Restrict
+            } else if self.accept_word("no") {
+                // Without deferred checks, NO ACTION is RESTRICT
+                self.expect_word("action")?;
+                OnDelete::Restrict
+            } else {
+                return Err(self.unexpected("CASCADE, SET NULL, RESTRICT or NO ACTION"));
+            };
+        }
+        Ok(ForeignKey {
+            table,
+            column,
+            on_delete,
+        })
     }
 
-    // -----------------------------------------------------------------------
-    // Parse optional WHERE clause → Condition
-    // Syntax: WHERE column = value
-    // -----------------------------------------------------------------------
-    fn parse_where(&mut self) -> Result<Option<Condition>, String> {
-        match self.peek() {
-            Some(kw) if kw.to_lowercase() == "where" => {
-                self.next_token(); // consume WHERE
-                let column = self.expect_any("column name")?;
-                self.expect_keyword("=")?;
-                let value = self.expect_any("value")?;
-                Ok(Some(Condition { column, value }))
+    fn parse_create_sequence(&mut self) -> Result<Statement, String> {
+        let name = self.expect_name("sequence name")?;
+        let (mut start, mut increment) = (None, None);
+        loop {
+            if start.is_none() && self.accept_word("start") {
+                self.accept_word("with");
+                start = Some(self.expect_integer("start value")?);
+            } else if increment.is_none() && self.accept_word("increment") {
+                self.accept_word("by");
+                increment = Some(self.expect_integer("increment")?);
+            } else {
+                break;
             }
-            _ => Ok(None),
         }
+        // Counting down starts from -1, as in PostgreSQL
+        let increment = increment.unwrap_or(1);
+        let start = start.unwrap_or(if increment < 0 { -1 } else { 1 });
+        Ok(Statement::CreateSequence {
+            name,
+            start,
+            increment,
+        })
     }
 
     // -----------------------------------------------------------------------
-    // SELECT col1, col2 FROM table [WHERE col = val]
-    // SELECT * FROM table [WHERE col = val]
+    // ALTER SEQUENCE name RESTART [[WITH] n]
+    // ALTER TABLE table ALTER [COLUMN] col RESTART [[WITH] n]
     // -----------------------------------------------------------------------
-    fn parse_select(&mut self) -> Result<Statement, String> {
-        // Column list — could be * or comma-separated names
-        let columns = self.parse_column_list()?;
+    fn parse_alter(&mut self) -> Result<Statement, String> {
+        if self.accept_word("table") {
+            let table = self.expect_name("table name")?;
+            self.expect_word("alter")?;
+            self.accept_word("column");
+            let column = self.expect_name("column name")?;
+            let restart = self.parse_restart()?;
+            return Ok(Statement::AlterTable {
+                table,
+                column,
+                restart,
+            });
+        }
+        self.expect_word("sequence")?;
+        let name = self.expect_name("sequence name")?;
+        let restart = self.parse_restart()?;
+        Ok(Statement::AlterSequence { name, restart })
+    }
 
-        self.expect_keyword("from")?;
-        let table = self.expect_any("table name")?;
+    /// `RESTART [[WITH] n]`.
+    fn parse_restart(&mut self) -> Result<Option<i64>, String> {
+        self.expect_word("restart")?;
+        if self.accept_word("with")
+            || self.peek_symbol("-")
+            || matches!(self.peek(), Some(TokenKind::Number(_)))
+        {
+            Ok(Some(self.expect_integer("restart value")?))
+        } else {
+            Ok(None)
+        }
+    }
 
-        let condition = self.parse_where()?;
+    fn parse_create_user(&mut self) -> Result<Statement, String> {
+        let name = self.expect_name("user name")?;
+        self.expect_word("with")?;
+        self.expect_word("password")?;
+        let password = self.expect_string("quoted password")?;
+        let superuser = self.accept_word("superuser");
+        Ok(Statement::CreateUser {
+            name,
+            password,
+            superuser,
+        })
+    }
+
+    fn parse_create_view(&mut self) -> Result<Statement, String> {
+        let name = self.expect_name("view name")?;
+        self.expect_word("as")?;
+        let query = self.parse_query()?;
+        Ok(Statement::CreateView { name, query })
+    }
 
-        Ok(Statement::Select {
+    fn parse_create_index(&mut self) -> Result<Statement, String> {
+        let name = self.expect_name("index name")?;
+        self.expect_word("on")?;
+        let table = self.expect_name("table name")?;
+        self.expect_symbol("(")?;
+        let column = self.expect_name("column name")?;
+        if self.peek_symbol(",") {
+            return Err("Indexes cover a single column".to_string());
+        }
+        self.expect_symbol(")")?;
+        Ok(Statement::CreateIndex {
+            name,
             table,
-            columns,
-            condition,
+            column,
         })
     }
 
     // -----------------------------------------------------------------------
-    // INSERT INTO table (col1, col2) VALUES (val1, val2)
+    // GRANT priv [, priv ...] | ALL [PRIVILEGES] ON [TABLE] table TO user
+    // REVOKE priv [, priv ...] | ALL [PRIVILEGES] ON [TABLE] table FROM user
     // -----------------------------------------------------------------------
-    fn parse_insert(&mut self) -> Result<Statement, String> {
-        self.expect_keyword("into")?;
-        let table = self.expect_any("table name")?;
+    fn parse_grant(&mut self, revoke: bool) -> Result<Statement, String> {
+        let mut privileges = Vec::new();
+        if self.accept_word("all") {
+            self.accept_word("privileges");
+            privileges.extend(Privilege::ALL);
+        } else {
+            loop {
+                let name = self.expect_any_word("privilege")?;
+                let privilege = Privilege::from_name(&name)
+                    .ok_or_else(|| format!("Unknown privilege: '{}'", name))?;
+                if !privileges.contains(&privilege) {
+                    privileges.push(privilege);
+                }
+                if !self.accept_symbol(",") {
+                    break;
+                }
+            }
+        }
 
-        // Optional explicit column list
-        let columns = if self.peek() == Some("(") {
-            self.parse_paren_list()?
+        self.expect_word("on")?;
+        self.accept_word("table");
+        let table = self.expect_name("table name")?;
+        self.expect_word(if revoke { "from" } else { "to" })?;
+        let user = self.expect_name("user name")?;
+
+        Ok(if revoke {
+            Statement::Revoke {
+                privileges,
+                table,
+                user,
+            }
         } else {
-            vec!["*".to_string()]
+            Statement::Grant {
+                privileges,
+                table,
+                user,
+            }
+        })
+    }
+
+    // -----------------------------------------------------------------------
+    // DROP TABLE [IF EXISTS] table
+    // DROP VIEW [IF EXISTS] view
+    // DROP INDEX [IF EXISTS] index
+    // DROP SEQUENCE [IF EXISTS] sequence
+    // DROP TRIGGER [IF EXISTS] trigger
+    // DROP USER name
+    // -----------------------------------------------------------------------
+    fn parse_drop(&mut self) -> Result<Statement, String> {
+        if self.accept_word("user") {
+            let name = self.expect_name("user name")?;
+            return Ok(Statement::DropUser { name });
+        }
+        let view = self.accept_word("view");
+        let index = !view && self.accept_word("index");
+        let sequence = !view && !index && self.accept_word("sequence");
+        let trigger = !view && !index && !sequence && self.accept_word("trigger");
+        if !view && !index && !sequence && !trigger {
+            self.expect_word("table")?;
+        }
+        let if_exists = self.accept_word("if");
+        if if_exists {
+            self.expect_word("exists")?;
+        }
+        if view {
+            let name = self.expect_name("view name")?;
+            return Ok(Statement::DropView { name, if_exists });
+        }
+        if index {
+            let name = self.expect_name("index name")?;
+            return Ok(Statement::DropIndex { name, if_exists });
+        }
+        if sequence {
+            let name = self.expect_name("sequence name")?;
+            return Ok(Statement::DropSequence { name, if_exists });
+        }
+        if trigger {
+            let name = self.expect_name("trigger name")?;
+            return Ok(Statement::DropTrigger { name, if_exists });
+        }
+        let table = self.expect_name("table name")?;
+        Ok(Statement::DropTable { table, if_exists })
+    }
+
+    // -----------------------------------------------------------------------
+    // DUMP [TO 'file' | TO STDOUT]
+    // RESTORE FROM 'file' | STDIN
+    // -----------------------------------------------------------------------
+    fn parse_dump(&mut self) -> Result<Statement, String> {
+        if self.peek().is_none() {
+            return Ok(Statement::Dump {
+                target: CopyEndpoint::Client,
+            });
+        }
+        self.expect_word("to")?;
+        let target = self.parse_endpoint("STDOUT")?;
+        Ok(Statement::Dump { target })
+    }
+
+    fn parse_restore(&mut self) -> Result<Statement, String> {
+        self.expect_word("from")?;
+        let source = self.parse_endpoint("STDIN")?;
+        Ok(Statement::Restore { source })
+    }
+
+    // -----------------------------------------------------------------------
+    // SOURCE 'file'
+    // -----------------------------------------------------------------------
+    fn parse_source(&mut self) -> Result<Statement, String> {
+        let path = self.expect_string("quoted file name")?;
+        Ok(Statement::Source { path })
+    }
+
+    // -----------------------------------------------------------------------
+    // ANALYZE [table]
+    // EXPLAIN SELECT ...
+    // -----------------------------------------------------------------------
+    fn parse_analyze(&mut self) -> Result<Statement, String> {
+        let table = match self.peek() {
+            None => None,
+            Some(_) => Some(self.expect_name("table name")?),
         };
+        Ok(Statement::Analyze { table })
+    }
 
-        self.expect_keyword("values")?;
-        let values = self.parse_paren_list()?;
+    fn parse_explain(&mut self) -> Result<Statement, String> {
+        self.parse_query().map(Statement::Explain)
+    }
 
-        if columns[0] != "*" && columns.len() != values.len() {
-            return Err(format!(
-                "Column count ({}) does not match value count ({})",
-                columns.len(),
-                values.len()
-            ));
+    // -----------------------------------------------------------------------
+    // SHOW REPLICATION
+    // SHOW statement_timeout
+    // SHOW METRICS
+    // -----------------------------------------------------------------------
+    fn parse_show(&mut self) -> Result<Statement, String> {
+        if self.accept_word("statement_timeout") {
+            return Ok(Statement::ShowStatementTimeout);
         }
+        if self.accept_word("metrics") {
+            return Ok(Statement::ShowMetrics);
+        }
+        self.expect_word("replication")?;
+        Ok(Statement::ShowReplication)
+    }
 
-        Ok(Statement::Insert {
-            table,
-            columns,
-            values,
+    // -----------------------------------------------------------------------
+    // SET statement_timeout = ms | 'duration' | DEFAULT   (or TO)
+    // -----------------------------------------------------------------------
+    fn parse_set(&mut self) -> Result<Statement, String> {
+        let name = self.expect_name("setting name")?;
+        if !name.eq_ignore_ascii_case("statement_timeout") {
+            return Err(format!("Unknown setting: '{}'", name));
+        }
+        if !self.accept_symbol("=") {
+            self.expect_word("to")?;
+        }
+        if self.accept_word("default") {
+            return Ok(Statement::SetStatementTimeout { timeout: None });
+        }
+        let text = match self.peek() {
+            Some(TokenKind::Number(n) | TokenKind::String(n)) => n.clone(),
+            _ => return Err(self.unexpected("milliseconds, a quoted duration or DEFAULT")),
+        };
+        let timeout = parse_duration(&text).ok_or_else(|| {
+            format!(
+                "Invalid statement_timeout: '{}'; use milliseconds or a duration like '5s'",
+                text
+            )
+        })?;
+        self.advance();
+        Ok(Statement::SetStatementTimeout {
+            timeout: Some(timeout),
         })
     }
+
+    /// A quoted server-side file name, or the keyword `client_keyword`
+    /// (STDIN / STDOUT) meaning the connection itself.
+    fn parse_endpoint(&mut self, client_keyword: &str) -> Result<CopyEndpoint, String> {
+        if let Some(TokenKind::String(path)) = self.peek() {
+            let path = path.clone();
+            self.advance();
+            return Ok(CopyEndpoint::File(path));
+        }
+        if self.accept_word(client_keyword) {
+            return Ok(CopyEndpoint::Client);
+        }
+        Err(self.unexpected(&format!("quoted file name or {}", client_keyword)))
+    }
+
+    // -----------------------------------------------------------------------
+    // COPY table [(col, ...)] FROM 'file' | STDIN [WITH] [options]
+    // COPY table [(col, ...)] TO 'file' | STDOUT [WITH] [options]
+    // -----------------------------------------------------------------------
+    fn parse_copy(&mut self) -> Result<Statement, String> {
+        let table = self.expect_name("table name")?;
+        let columns = if self.peek_symbol("(") {
+            Some(self.parse_paren_list()?)
+        } else {
+            None
+        };
+
+        if self.accept_word("from") {
+            let source = self.parse_endpoint("STDIN")?;
+            let options = self.parse_copy_options()?;
+            Ok(Statement::CopyFrom {
+                table,
+                columns,
+                source,
+                options,
+            })
+        } else if self.accept_word("to") {
+            let target = self.parse_endpoint("STDOUT")?;
+            let options = self.parse_copy_options()?;
+            Ok(Statement::CopyTo {
+                table,
+                columns,
+                target,
+                options,
+            })
+        } else {
+            Err(self.unexpected("FROM or TO"))
+        }
+    }
+
+    // -----------------------------------------------------------------------
+    // COPY options, either bare or parenthesised:
+    //   [WITH] [CSV] [HEADER] [DELIMITER 'c'] [NULL 'str']
+    //   [WITH] (FORMAT csv, HEADER [true|false], DELIMITER 'c', NULL 'str')
+    // -----------------------------------------------------------------------
+    fn parse_copy_options(&mut self) -> Result<CsvOptions, String> {
+        let mut options = CsvOptions::default();
+
+        self.accept_word("with");
+        let parenthesised = self.accept_symbol("(");
+
+        loop {
+            if self.peek().is_none() {
+                if parenthesised {
+                    return Err(self.unexpected("')'"));
+                }
+                break;
+            }
+            if parenthesised {
+                if self.accept_symbol(")") {
+                    break;
+                }
+                if self.accept_symbol(",") {
+                    continue;
+                }
+            }
+            let option = self.expect_any_word("COPY option")?.to_lowercase();
+            match option.as_str() {
+                "csv" => {}
+                "format" => {
+                    let format = self.expect_any_word("format name")?;
+                    if !format.eq_ignore_ascii_case("csv") {
+                        return Err(format!("Unsupported COPY format: '{}'", format));
+                    }
+                }
+                "header" => {
+                    options.header = true;
+                    if self.accept_word("true") || self.accept_word("on") {
+                        // explicit default
+                    } else if self.accept_word("false") || self.accept_word("off") {
+                        options.header = false;
+                    }
+                }
+                "delimiter" => {
+                    let delimiter = self.expect_string("quoted delimiter")?;
+                    let mut chars = delimiter.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if c != '"' && c != '\n' && c != '\r' => {
+                            options.delimiter = c
+                        }
+                        _ => {
+                            return Err(format!(
+                                "COPY delimiter must be a single character, found '{}'",
+                                delimiter
+                            ));
+                        }
+                    }
+                }
+                "null" => options.null = self.expect_string("quoted NULL marker")?,
+                other => return Err(format!("Unknown COPY option: '{}'", other)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// A numeric literal: an INTEGER when it fits an i64, otherwise a REAL.
+fn number_value(text: &str) -> Option<Value> {
+    if let Ok(i) = text.parse::<i64>() {
+        Some(Value::Integer(i))
+    } else {
+        text.parse::<f64>().ok().map(Value::Real)
+    }
+}
+
+/// A duration as written in SET: plain milliseconds, or a number with one of
+/// the units `ms`, `s`, `min` or `h`.
+fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let millis = match unit.trim().to_lowercase().as_str() {
+        "" | "ms" => 1,
+        "s" => 1_000,
+        "min" => 60_000,
+        "h" => 3_600_000,
+        _ => return None,
+    };
+    Some(Duration::from_millis(number.checked_mul(millis)?))
+}
+
+/// The interned form of a symbol, for comparing against `TokenKind::Symbol`.
+fn symbol_str(symbol: &str) -> &'static str {
+    match symbol {
+        "(" => "(",
+        ")" => ")",
+        "," => ",",
+        "." => ".",
+        "*" => "*",
+        "+" => "+",
+        "-" => "-",
+        "=" => "=",
+        other => unreachable!("symbol '{}' is not used by the parser", other),
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -255,24 +2528,97 @@ impl Statement {
     /// Parse a SQL string into a `Statement`.
     ///
     /// Supported syntax:
-    /// ```
-    /// INSERT INTO table_name (col1, col2) VALUES (val1, val2);
+    /// ```text
+    /// INSERT INTO table_name [(col1, col2)] VALUES (val1, DEFAULT) [RETURNING col1];
+    /// INSERT INTO table_name VALUES (...) ON CONFLICT [(col)] DO NOTHING;
+    /// INSERT INTO table_name VALUES (...) ON CONFLICT (col) DO UPDATE SET col2 = excluded.col2;
+    /// UPDATE table_name SET col1 = col1 + 1, col2 = 'x' [WHERE condition];
+    /// DELETE FROM table_name [WHERE condition];
     /// SELECT * FROM table_name;
-    /// SELECT col1, col2 FROM table_name WHERE col = val;
+    /// SELECT col1, col2 FROM table_name WHERE col > 0 AND other = 'x';
+    /// SELECT UPPER(name) AS upper_name, id * 2 FROM table_name;
+    /// SELECT o.id, c.name FROM orders o JOIN customers c ON o.customer = c.id;
+    /// SELECT DISTINCT class FROM students;
+    /// SELECT name FROM a UNION [ALL] | INTERSECT | EXCEPT SELECT name FROM b;
+    /// SELECT name, score FROM students ORDER BY score DESC, 1;
+    /// WITH r AS (SELECT ...) SELECT ... FROM r;
+    /// WITH RECURSIVE r (col) AS (SELECT ... UNION SELECT ... FROM t JOIN r ON ...) SELECT ...;
+    /// SELECT name, RANK() OVER (PARTITION BY class ORDER BY score DESC) FROM students;
+    /// SELECT SUM(x) OVER (ORDER BY id ROWS BETWEEN 2 PRECEDING AND CURRENT ROW) FROM t;
+    /// EXPLAIN SELECT ...;
+    /// COPY table_name FROM 'data.csv' WITH (HEADER);
+    /// COPY table_name (col1, col2) TO STDOUT CSV HEADER;
+    /// CREATE TABLE table_name (col1 INTEGER, col2 TEXT);
+    /// CREATE TABLE table_name (id INTEGER PRIMARY KEY AUTOINCREMENT, code TEXT UNIQUE);
+    /// CREATE TABLE table_name (owner INTEGER REFERENCES other (id) ON DELETE CASCADE);
+    /// ALTER TABLE table_name ALTER id RESTART [WITH 1];
+    /// CREATE SEQUENCE name [START WITH 1] [INCREMENT BY 1];
+    /// INSERT INTO table_name VALUES (nextval('name'), ...);
+    /// ALTER SEQUENCE name RESTART [WITH 1];
+    /// DROP SEQUENCE [IF EXISTS] name;
+    /// DROP TABLE [IF EXISTS] table_name;
+    /// CREATE VIEW view_name AS SELECT ...;
+    /// DROP VIEW [IF EXISTS] view_name;
+    /// CREATE INDEX index_name ON table_name (col);
+    /// DROP INDEX [IF EXISTS] index_name;
+    /// ANALYZE [table_name];
+    /// DUMP [TO 'backup.sql'];
+    /// RESTORE FROM 'backup.sql';
+    /// SOURCE 'script.sql';
+    /// SHOW REPLICATION;
+    /// SET statement_timeout = 5000;   -- or '5s', or DEFAULT
+    /// SHOW statement_timeout;
+    /// SHOW METRICS;
+    /// CREATE USER name WITH PASSWORD 'secret' [SUPERUSER];
+    /// DROP USER name;
+    /// GRANT SELECT, INSERT ON table_name TO name;
+    /// REVOKE ALL ON table_name FROM name;
     /// ```
+    ///
+    /// Names are case-sensitive and may be double-quoted (`"from"`);
+    /// keywords are not. `--` and `/* */` comments are ignored. Syntax
+    /// errors give the line and column of the offending token.
     pub fn parse(input: &str) -> Result<Self, String> {
-        let tokens = tokenize(input);
+        let mut tokens = lexer::tokenize(input)?;
+        // A trailing semicolon just ends the statement
+        while tokens
+            .last()
+            .is_some_and(|t| t.kind == TokenKind::Symbol(";"))
+        {
+            tokens.pop();
+        }
         if tokens.is_empty() {
             return Err("Empty statement".to_string());
         }
 
-        let mut parser = Parser::new(tokens);
-        let keyword = parser.expect_any("statement keyword")?;
+        let mut parser = Parser::new(tokens, lexer::end_span(input));
+        let keyword = parser.expect_any_word("statement keyword")?;
 
-        match keyword.to_lowercase().as_str() {
-            "select" => parser.parse_select(),
+        let statement = match keyword.to_lowercase().as_str() {
+            "select" => parser.parse_select().map(Statement::Select),
+            "with" => parser.parse_with().map(Statement::Select),
             "insert" => parser.parse_insert(),
+            "update" => parser.parse_update(),
+            "delete" => parser.parse_delete(),
+            "copy" => parser.parse_copy(),
+            "create" => parser.parse_create(),
+            "drop" => parser.parse_drop(),
+            "alter" => parser.parse_alter(),
+            "dump" => parser.parse_dump(),
+            "restore" => parser.parse_restore(),
+            "source" => parser.parse_source(),
+            "show" => parser.parse_show(),
+            "set" => parser.parse_set(),
+            "analyze" => parser.parse_analyze(),
+            "explain" => parser.parse_explain(),
+            "grant" => parser.parse_grant(false),
+            "revoke" => parser.parse_grant(true),
             other => Err(format!("Unknown statement: '{}'", other)),
+        }?;
+
+        if parser.peek().is_some() {
+            return Err(parser.unexpected("end of statement"));
         }
+        Ok(statement)
     }
 }
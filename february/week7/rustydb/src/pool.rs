@@ -0,0 +1,67 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed set of worker threads pulling jobs off a shared queue.
+pub struct ThreadPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Job>>,
+}
+
+impl ThreadPool {
+    /// Start `size` workers. Panics if `size` is zero.
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "a thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                thread::Builder::new()
+                    .name(format!("rustydb-worker-{}", id))
+                    .spawn(move || {
+                        loop {
+                            // Hold the lock only while waiting, not while working
+                            let job = receiver.lock().unwrap().recv();
+                            match job {
+                                // A panicking client must not take its worker with it
+                                Ok(job) => {
+                                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                                }
+                                Err(_) => break, // pool dropped
+                            }
+                        }
+                    })
+                    .expect("Failed to spawn worker thread")
+            })
+            .collect();
+
+        Self {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    /// Queue `job` to run on the next free worker.
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Box::new(job));
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Let queued jobs drain, then wait for every worker to exit.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    sync::{
+        Arc, Mutex,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rustls::{ClientConfig, ClientConnection, StreamOwned, pki_types::ServerName};
+
+use crate::{
+    dump,
+    exec::Query,
+    log::{info, warning},
+    storage::{Database, Value},
+};
+
+/// How often a leader with nothing to send tells its followers so.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A follower that hears nothing from its leader for this long assumes the
+/// connection is dead and starts over.
+const LEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait between attempts to reach the leader.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The connection to a leader: a plain socket or a TLS session.
+trait LeaderStream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> LeaderStream for T {}
+
+/// One committed write, as a script that replays it.
+pub struct Change {
+    pub lsn: u64,
+    pub script: String,
+}
+
+/// Replication state of a database: how many changes it has committed, who
+/// is following them and, on a follower, how far behind its leader it is.
+///
+/// It lives inside `Database` so that changes are published under the same
+/// lock that made them, and a new follower's snapshot lines up exactly with
+/// the first change it is sent.
+#[derive(Default)]
+pub struct Replication {
+    /// Log sequence number: how many changes have been committed.
+    lsn: u64,
+    next_id: usize,
+    followers: Vec<Follower>,
+    /// Set on a follower, which then refuses writes of its own.
+    leader: Option<LeaderStatus>,
+    /// Every change published, kept for a transaction to commit in one go.
+    journal: Option<Vec<String>>,
+}
+
+/// A follower streaming from this server, as the leader sees it.
+struct Follower {
+    id: usize,
+    peer: String,
+    changes: Sender<Change>,
+    /// The last change the follower reported applying.
+    acked: u64,
+    last_ack: Instant,
+}
+
+/// This server's view of the leader it follows.
+struct LeaderStatus {
+    addr: String,
+    /// Why the last attempt to stream failed; `None` while streaming.
+    error: Option<String>,
+    /// The last change applied here.
+    applied: u64,
+    /// The leader's latest change, as of its last message.
+    leader_lsn: u64,
+    last_contact: Option<Instant>,
+}
+
+impl Replication {
+    /// State for a transaction's copy of the database, which records the
+    /// changes made to it rather than sending them anywhere.
+    pub fn journaled() -> Self {
+        Self {
+            journal: Some(Vec::new()),
+            ..Self::default()
+        }
+    }
+
+    /// How many changes have been committed.
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    /// The changes recorded so far, if journaled; see `journaled`.
+    pub fn take_journal(&mut self) -> Vec<String> {
+        self.journal
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Register a follower. Returns its id, the change its snapshot must
+    /// reflect, and the stream of changes after that one.
+    pub fn subscribe(&mut self, peer: &str) -> (usize, u64, Receiver<Change>) {
+        let (sender, receiver) = mpsc::channel();
+        self.next_id += 1;
+        self.followers.push(Follower {
+            id: self.next_id,
+            peer: peer.to_string(),
+            changes: sender,
+            acked: self.lsn,
+            last_ack: Instant::now(),
+        });
+        (self.next_id, self.lsn, receiver)
+    }
+
+    pub fn unsubscribe(&mut self, id: usize) {
+        self.followers.retain(|f| f.id != id);
+    }
+
+    /// Record that follower `id` has applied every change up to `lsn`.
+    pub fn ack(&mut self, id: usize, lsn: u64) {
+        if let Some(follower) = self.followers.iter_mut().find(|f| f.id == id) {
+            follower.acked = lsn;
+            follower.last_ack = Instant::now();
+        }
+    }
+
+    /// The address of the leader, if this server is a follower.
+    pub fn leader(&self) -> Option<&str> {
+        self.leader.as_ref().map(|l| l.addr.as_str())
+    }
+
+    /// `SHOW REPLICATION`: one row per follower on a leader, or a single
+    /// row describing the leader on a follower. `lag` counts changes not
+    /// yet applied; `last_contact` is in seconds.
+    pub fn status(&self) -> Query {
+        let headers = [
+            "role",
+            "peer",
+            "state",
+            "lsn",
+            "peer_lsn",
+            "lag",
+            "last_contact",
+        ];
+        let seconds = |at: Instant| Value::Real((at.elapsed().as_millis() as f64) / 1000.0);
+        let count = |n: u64| Value::Integer(n as i64);
+
+        let rows: Vec<Vec<Value>> = match &self.leader {
+            Some(leader) => vec![vec![
+                Value::Text("follower".to_string()),
+                Value::Text(leader.addr.clone()),
+                Value::Text(match &leader.error {
+                    None => "streaming".to_string(),
+                    Some(e) => format!("reconnecting: {}", e),
+                }),
+                count(leader.applied),
+                count(leader.leader_lsn),
+                count(leader.leader_lsn.saturating_sub(leader.applied)),
+                leader.last_contact.map_or(Value::Null, seconds),
+            ]],
+            None if self.followers.is_empty() => vec![vec![
+                Value::Text("leader".to_string()),
+                Value::Null,
+                Value::Text("no followers".to_string()),
+                count(self.lsn),
+                Value::Null,
+                Value::Null,
+                Value::Null,
+            ]],
+            None => self
+                .followers
+                .iter()
+                .map(|f| {
+                    vec![
+                        Value::Text("leader".to_string()),
+                        Value::Text(f.peer.clone()),
+                        Value::Text("streaming".to_string()),
+                        count(self.lsn),
+                        count(f.acked),
+                        count(self.lsn.saturating_sub(f.acked)),
+                        seconds(f.last_ack),
+                    ]
+                })
+                .collect(),
+        };
+        Query {
+            headers: headers.iter().map(|h| h.to_string()).collect(),
+            rows: Box::new(rows.into_iter().map(Ok)),
+            cancel: Arc::default(),
+        }
+    }
+}
+
+/// Record a committed write. `change` renders it as SQL for any followers
+/// or journal; it is only called if there are some.
+pub fn publish(db: &mut Database, change: impl FnOnce(&Database) -> String) {
+    db.replication.lsn += 1;
+    if db.replication.followers.is_empty() && db.replication.journal.is_none() {
+        return;
+    }
+    let script = change(db);
+    if let Some(journal) = &mut db.replication.journal {
+        journal.push(script.clone());
+    }
+    let lsn = db.replication.lsn;
+    // A follower whose connection has gone away stops listening
+    db.replication.followers.retain(|f| {
+        f.changes
+            .send(Change {
+                lsn,
+                script: script.clone(),
+            })
+            .is_ok()
+    });
+}
+
+// ---------------------------------------------------------------------------
+// Follower
+// ---------------------------------------------------------------------------
+
+/// Make `db` a read-only copy of the leader at `addr`, kept up to date by a
+/// background thread that logs in as `user` and asks to REPLICATE. Whenever
+/// the stream breaks it reconnects and starts again from a fresh snapshot.
+/// With `tls`, the leader must present a certificate it trusts.
+pub fn follow(
+    addr: String,
+    user: String,
+    password: String,
+    tls: Option<Arc<ClientConfig>>,
+    db: Arc<Mutex<Database>>,
+) {
+    db.lock().unwrap().replication.leader = Some(LeaderStatus {
+        addr: addr.clone(),
+        error: Some("not connected yet".to_string()),
+        applied: 0,
+        leader_lsn: 0,
+        last_contact: None,
+    });
+    thread::Builder::new()
+        .name("rustydb-follower".to_string())
+        .spawn(move || {
+            loop {
+                let e = match stream_from(&addr, &user, &password, tls.as_ref(), &db) {
+                    Ok(()) => "the leader closed the connection".to_string(),
+                    Err(e) => e,
+                };
+                warning!("Replication from {}: {}", addr, e);
+                if let Some(leader) = &mut db.lock().unwrap().replication.leader {
+                    leader.error = Some(e);
+                }
+                thread::sleep(RETRY_INTERVAL);
+            }
+        })
+        .expect("Failed to start the replication thread");
+}
+
+/// One replication session: log in, load the snapshot, then apply changes
+/// until the connection fails.
+fn stream_from(
+    addr: &str,
+    user: &str,
+    password: &str,
+    tls: Option<&Arc<ClientConfig>>,
+    db: &Arc<Mutex<Database>>,
+) -> Result<(), String> {
+    let mut reader = BufReader::new(connect(addr, tls)?);
+
+    read_line(&mut reader)?; // the login prompt
+    send(&mut reader, &format!("LOGIN {} {}", user, password))?;
+    let reply = read_line(&mut reader)?;
+    if !reply.starts_with("OK") {
+        return Err(reply);
+    }
+    read_line(&mut reader)?; // the greeting
+    send(&mut reader, "REPLICATE")?;
+
+    loop {
+        let line = read_line(&mut reader)?;
+        let fields: Vec<&str> = line.split(' ').collect();
+        let number = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+        match (fields[0], number(1), number(2)) {
+            ("SNAPSHOT", Some(lsn), Some(len)) => {
+                let script = read_payload(&mut reader, len)?;
+                // Build the copy aside, so readers never see it half-loaded
+                let mut fresh = Database::new();
+                dump::restore(&mut fresh, &script).map_err(|e| format!("snapshot: {}", e))?;
+                let mut db = db.lock().unwrap();
+                db.replace_data(fresh);
+                if let Some(leader) = &mut db.replication.leader {
+                    leader.error = None;
+                    leader.applied = lsn;
+                    leader.leader_lsn = lsn;
+                    leader.last_contact = Some(Instant::now());
+                }
+                info!("Following {} from change {}", addr, lsn);
+            }
+            ("CHANGE", Some(lsn), Some(len)) => {
+                let script = read_payload(&mut reader, len)?;
+                {
+                    let mut db = db.lock().unwrap();
+                    dump::restore(&mut db, &script)
+                        .map_err(|e| format!("change {}: {}", lsn, e))?;
+                    if let Some(leader) = &mut db.replication.leader {
+                        leader.applied = lsn;
+                        leader.leader_lsn = lsn;
+                        leader.last_contact = Some(Instant::now());
+                    }
+                }
+                send(&mut reader, &format!("ACK {}", lsn))?;
+            }
+            ("HEARTBEAT", Some(lsn), None) => {
+                if let Some(leader) = &mut db.lock().unwrap().replication.leader {
+                    leader.leader_lsn = lsn;
+                    leader.last_contact = Some(Instant::now());
+                }
+            }
+            // Most likely an ERROR, e.g. for a user that isn't a superuser
+            _ => return Err(line),
+        }
+    }
+}
+
+/// Open a connection to the leader, over TLS if there is a config for it.
+/// The certificate must name the host part of `addr`.
+fn connect(addr: &str, tls: Option<&Arc<ClientConfig>>) -> Result<Box<dyn LeaderStream>, String> {
+    let stream = TcpStream::connect(addr).map_err(|e| format!("could not connect: {}", e))?;
+    stream
+        .set_read_timeout(Some(LEADER_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let Some(config) = tls else {
+        return Ok(Box::new(stream));
+    };
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    let name = ServerName::try_from(host.to_string())
+        .map_err(|_| format!("invalid server name: {}", host))?;
+    let conn = ClientConnection::new(Arc::clone(config), name).map_err(|e| e.to_string())?;
+    Ok(Box::new(StreamOwned::new(conn, stream)))
+}
+
+fn send(reader: &mut BufReader<Box<dyn LeaderStream>>, line: &str) -> Result<(), String> {
+    let stream = reader.get_mut();
+    stream
+        .write_all(format!("{}\n", line).as_bytes())
+        .and_then(|()| stream.flush())
+        .map_err(|e| format!("could not send to the leader: {}", e))
+}
+
+fn read_line(reader: &mut BufReader<Box<dyn LeaderStream>>) -> Result<String, String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Err("the leader closed the connection".to_string()),
+        Ok(_) => Ok(line.trim_end_matches(['\r', '\n']).to_string()),
+        Err(e) => Err(read_error(e)),
+    }
+}
+
+/// Read a `len`-byte script and the newline that follows it.
+fn read_payload(reader: &mut BufReader<Box<dyn LeaderStream>>, len: u64) -> Result<String, String> {
+    let mut script = String::new();
+    reader
+        .by_ref()
+        .take(len)
+        .read_to_string(&mut script)
+        .map_err(read_error)?;
+    if script.len() as u64 != len {
+        return Err("the leader closed the connection".to_string());
+    }
+    read_line(reader)?;
+    Ok(script)
+}
+
+fn read_error(e: std::io::Error) -> String {
+    match e.kind() {
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => format!(
+            "no word from the leader for {} seconds",
+            LEADER_TIMEOUT.as_secs()
+        ),
+        _ => e.to_string(),
+    }
+}
@@ -1,185 +1,1308 @@
 use std::{
-    io::{BufRead, BufReader, Write},
+    cell::Cell,
+    collections::VecDeque,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
     net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
     thread,
+    time::{Duration, Instant},
 };
 
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
 use crate::{
-    parser::Statement,
-    storage::{Database, Row},
+    auth, dump,
+    engine::{self, Outcome, Settings},
+    exec::{Query, QueryLimits},
+    lexer,
+    log::{debug, error, info, warning},
+    metrics::Metrics,
+    parser::{CopyEndpoint, Statement},
+    pool::ThreadPool,
+    replication,
+    storage::{Database, Value},
 };
 
+/// Failed LOGIN attempts allowed before the connection is closed.
+const MAX_LOGIN_ATTEMPTS: usize = 3;
+
+/// How often blocked accepts and reads wake up to check for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a TLS client has to finish its handshake, and a response to
+/// get out when the session has to read before it can write.
+const IO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Result rows sent per flush.
+const BATCH_ROWS: usize = 256;
+
+/// Tunables for how many clients are served and for how long.
+pub struct Limits {
+    /// Worker threads, i.e. clients served at the same time.
+    pub workers: usize,
+    /// Open connections allowed, including those waiting for a worker.
+    pub max_connections: usize,
+    /// Disconnect clients that send nothing for this long.
+    pub idle_timeout: Option<Duration>,
+    /// What each query may do; a session can change the timeout with SET.
+    pub query: QueryLimits,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            workers: 8,
+            max_connections: 64,
+            idle_timeout: Some(Duration::from_secs(300)),
+            query: QueryLimits {
+                timeout: Some(Duration::from_secs(300)),
+                max_rows: None,
+                max_memory: Some(1024 * 1024 * 1024),
+            },
+        }
+    }
+}
+
 pub struct Server {
     listener: TcpListener,
     db: Arc<Mutex<Database>>,
+    tls: Option<Arc<ServerConfig>>,
+    limits: Limits,
+    shutdown: Arc<AtomicBool>,
+    metrics: Arc<Metrics>,
 }
 
 impl Server {
     /// Bind to the given address (e.g. "127.0.0.1:7878") and return a Server.
     pub fn bind(addr: &str, db: Database) -> std::io::Result<Self> {
         let listener = TcpListener::bind(addr)?;
-        println!("RustyDB listening on {}", addr);
+        info!("RustyDB listening on {}", addr);
         Ok(Self {
             listener,
+            metrics: Arc::clone(&db.metrics),
             db: Arc::new(Mutex::new(db)),
+            tls: None,
+            limits: Limits::default(),
+            shutdown: Arc::new(AtomicBool::new(false)),
         })
     }
 
-    /// Accept connections in a loop. Each client gets its own thread.
+    /// Require TLS on every connection, using the given server configuration.
+    pub fn with_tls(mut self, config: Arc<ServerConfig>) -> Self {
+        info!("TLS enabled");
+        self.tls = Some(config);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Log each statement that takes at least `threshold`, from receiving
+    /// it to sending the last of its result; `None` logs none.
+    pub fn with_slow_query_log(self, threshold: Option<Duration>) -> Self {
+        self.metrics.set_slow_query_threshold(threshold);
+        self
+    }
+
+    /// A flag that, once set, makes `run` stop accepting clients, let every
+    /// connection finish its current statement, and return.
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shutdown)
+    }
+
+    /// Run a SQL script with superuser rights, e.g. to set up the schema at
+    /// startup. Stops at the first failing statement; returns how many ran.
+    pub fn run_script(&self, script: &str) -> Result<usize, String> {
+        let statements = lexer::split_statements(script);
+        for (i, text) in statements.iter().enumerate() {
+            let fail = |e: &str| format!("statement {}: {}", i + 1, e);
+            let statement = Statement::parse(text).map_err(|e| fail(&e))?;
+            if let Statement::CopyFrom {
+                source: CopyEndpoint::Client,
+                ..
+            }
+            | Statement::Restore {
+                source: CopyEndpoint::Client,
+            } = statement
+            {
+                return Err(fail("FROM STDIN needs a client connection"));
+            }
+            if let Statement::Source { .. } = statement {
+                return Err(fail("SOURCE needs a client connection"));
+            }
+            let mut settings = Settings::new(self.limits.query);
+            let response = respond(execute(statement, &self.db, &mut settings));
+            if let Some(e) = response.strip_prefix("ERROR: ") {
+                return Err(fail(e));
+            }
+        }
+        Ok(statements.len())
+    }
+
+    /// The shared database, e.g. for saving it once `run` returns.
+    pub fn database(&self) -> Arc<Mutex<Database>> {
+        Arc::clone(&self.db)
+    }
+
+    /// Accept connections until shutdown, handing each to the worker pool.
     pub fn run(self) {
-        for stream in self.listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let db = Arc::clone(&self.db);
-                    let peer = stream
-                        .peer_addr()
-                        .map(|a| a.to_string())
-                        .unwrap_or_else(|_| "unknown".to_string());
-                    println!("[+] Client connected: {}", peer);
-                    thread::spawn(move || {
-                        handle_client(stream, db, &peer);
-                        println!("[-] Client disconnected: {}", peer);
-                    });
+        let pool = ThreadPool::new(self.limits.workers);
+
+        // Poll rather than block, so a shutdown request is noticed promptly
+        self.listener
+            .set_nonblocking(true)
+            .expect("Failed to make the listener non-blocking");
+
+        while !self.shutdown.load(Ordering::SeqCst) {
+            let (stream, addr) = match self.listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Accept error: {}", e);
+                    continue;
                 }
-                Err(e) => eprintln!("Accept error: {}", e),
+            };
+            let peer = addr.to_string();
+
+            if self.metrics.connections_active.load(Ordering::SeqCst)
+                >= self.limits.max_connections as u64
+            {
+                warning!("[!] Rejected {}: too many connections", peer);
+                Metrics::add(&self.metrics.connections_rejected, 1);
+                self.reject(stream);
+                continue;
+            }
+            info!("[+] Client connected: {}", peer);
+            Metrics::add(&self.metrics.connections, 1);
+
+            // Reads time out regularly so idle clients and shutdown are noticed
+            if let Err(e) = stream
+                .set_nonblocking(false)
+                .and_then(|_| stream.set_read_timeout(Some(POLL_INTERVAL)))
+            {
+                error!("[{}] Socket setup failed: {}", peer, e);
+                continue;
             }
+
+            let stream: Box<dyn ClientStream> = match &self.tls {
+                None => Box::new(stream),
+                // The handshake is finished by the worker, before the greeting
+                Some(config) => match ServerConnection::new(Arc::clone(config)) {
+                    Ok(conn) => Box::new(StreamOwned::new(conn, stream)),
+                    Err(e) => {
+                        error!("[{}] TLS setup failed: {}", peer, e);
+                        continue;
+                    }
+                },
+            };
+
+            let db = Arc::clone(&self.db);
+            let shutdown = Arc::clone(&self.shutdown);
+            let idle_timeout = self.limits.idle_timeout;
+            let query_limits = self.limits.query;
+            let slot = ConnectionSlot::claim(&self.metrics);
+            pool.execute(move || {
+                let _slot = slot;
+                let mut conn = Connection::new(stream, idle_timeout, shutdown);
+                if !conn.handshake() {
+                    warning!("[{}] TLS handshake failed or timed out", peer);
+                    return;
+                }
+                handle_client(conn, db, &peer, query_limits);
+                info!("[-] Client disconnected: {}", peer);
+            });
+        }
+
+        info!(
+            "Shutting down: waiting for {} open connection(s) to finish...",
+            self.metrics.connections_active.load(Ordering::SeqCst)
+        );
+        drop(pool); // joins every worker
+        info!("Shutdown complete.");
+    }
+
+    /// Turn away a client over the connection limit. Plain connections are
+    /// told why; TLS ones are just closed, as a message would need a full
+    /// handshake first.
+    fn reject(&self, mut stream: TcpStream) {
+        if self.tls.is_none() {
+            let _ = stream.set_nonblocking(false);
+            let _ = stream.set_write_timeout(Some(POLL_INTERVAL));
+            let _ = writeln!(
+                stream,
+                "ERROR: Too many connections (limit is {}). Try again later.",
+                self.limits.max_connections
+            );
         }
     }
 }
 
-/// Handle one client connection for its entire lifetime.
-fn handle_client(stream: TcpStream, db: Arc<Mutex<Database>>, peer: &str) {
-    // Clone the stream so we can have separate read/write ends.
-    let write_stream = match stream.try_clone() {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("[{}] Failed to clone stream: {}", peer, e);
-            return;
+/// Counts one open connection for as long as it is alive.
+struct ConnectionSlot(Arc<Metrics>);
+
+impl ConnectionSlot {
+    fn claim(metrics: &Arc<Metrics>) -> Self {
+        metrics.connections_active.fetch_add(1, Ordering::SeqCst);
+        Self(Arc::clone(metrics))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.connections_active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Anything a client can be served over: a plain socket or a TLS session.
+trait ClientStream: Read + Write + Send {
+    /// The underlying socket, e.g. to change its blocking mode.
+    fn socket(&self) -> &TcpStream;
+
+    /// Take the handshake a step further, if the protocol has one. True
+    /// once it is over.
+    fn handshake(&mut self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Say goodbye at the protocol level before the socket is closed.
+    fn close(&mut self) {}
+}
+
+impl ClientStream for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl ClientStream for StreamOwned<ServerConnection, TcpStream> {
+    fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn handshake(&mut self) -> io::Result<bool> {
+        if self.conn.is_handshaking() {
+            self.conn.complete_io(&mut self.sock)?;
         }
-    };
+        Ok(!self.conn.is_handshaking())
+    }
 
-    let mut writer = std::io::BufWriter::new(write_stream);
-    let reader = BufReader::new(stream);
+    fn close(&mut self) {
+        self.conn.send_close_notify();
+        let _ = self.conn.complete_io(&mut self.sock);
+    }
+}
 
-    // Greet the client
-    let _ = writeln!(writer, "RustyDB ready. Type SQL or 'quit' to exit.");
-    let _ = writer.flush();
+/// The outcome of one attempt to read a line.
+enum Poll {
+    Line(String),
+    /// Nothing complete yet; any partial line is kept for next time.
+    Pending,
+    Closed,
+}
 
-    for line in reader.lines() {
-        let line = match line {
-            Ok(l) => l,
-            Err(_) => break, // client disconnected
-        };
+/// A client connection with line-oriented reads. Writes are buffered until
+/// `flush`, so each response goes out in one piece (and, under TLS, in as
+/// few records as possible).
+struct Connection {
+    reader: BufReader<Box<dyn ClientStream>>,
+    pending: Vec<u8>,
+    /// The start of a line whose end hasn't arrived yet.
+    partial: Vec<u8>,
+    /// Lines that arrived while a query was streaming, to be read next.
+    queued: VecDeque<String>,
+    closed: bool,
+    idle_timeout: Option<Duration>,
+    shutdown: Arc<AtomicBool>,
+}
 
+impl Connection {
+    fn new(
+        stream: Box<dyn ClientStream>,
+        idle_timeout: Option<Duration>,
+        shutdown: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            pending: Vec::new(),
+            partial: Vec::new(),
+            queued: VecDeque::new(),
+            closed: false,
+            idle_timeout,
+            shutdown,
+        }
+    }
+
+    /// Finish a TLS handshake before anything is written, so the first
+    /// message isn't lost to a read timeout part-way through it. False if
+    /// the client doesn't complete it in time or the server is shutting
+    /// down.
+    fn handshake(&mut self) -> bool {
+        let deadline = Instant::now() + IO_TIMEOUT;
+        while !self.shutdown.load(Ordering::SeqCst) {
+            match self.reader.get_mut().handshake() {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(e) if timed_out(&e) && Instant::now() < deadline => {}
+                Err(_) => return false,
+            }
+        }
+        false
+    }
+
+    /// The next line without its terminator, or `None` once the connection
+    /// is over: the client left, the TLS session failed, the client stayed
+    /// silent past the idle timeout, or the server is shutting down.
+    fn read_line(&mut self) -> Option<String> {
+        if let Some(line) = self.queued.pop_front() {
+            return Some(line);
+        }
+        let mut last_activity = Instant::now();
+        while !self.closed {
+            if self.shutdown.load(Ordering::SeqCst) {
+                self.notify("ERROR: Server is shutting down.");
+                return None;
+            }
+            if let Some(limit) = self.idle_timeout
+                && last_activity.elapsed() >= limit
+            {
+                self.notify(&format!(
+                    "ERROR: Idle for more than {} seconds, closing connection.",
+                    limit.as_secs()
+                ));
+                return None;
+            }
+
+            let before = self.partial.len();
+            match self.poll_line() {
+                Poll::Line(line) => return Some(line),
+                Poll::Pending if self.partial.len() > before => last_activity = Instant::now(),
+                Poll::Pending => {}
+                Poll::Closed => self.closed = true,
+            }
+        }
+        None
+    }
+
+    /// Read until a line is complete or the socket has nothing more for now.
+    fn poll_line(&mut self) -> Poll {
+        match self.reader.read_until(b'\n', &mut self.partial) {
+            Ok(0) if self.partial.is_empty() => Poll::Closed,
+            // Either a full line or whatever preceded EOF
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&self.partial).into_owned();
+                self.partial.clear();
+                let len = line.trim_end_matches(['\r', '\n']).len();
+                Poll::Line(line[..len].to_string())
+            }
+            Err(e) if timed_out(&e) => Poll::Pending,
+            Err(_) => Poll::Closed,
+        }
+    }
+
+    /// Check, without waiting, whether the client wants the running query
+    /// stopped: it sent CANCEL or went away. Other lines that have arrived
+    /// are kept for `read_line`.
+    fn cancel_requested(&mut self) -> bool {
+        if self
+            .reader
+            .get_ref()
+            .socket()
+            .set_nonblocking(true)
+            .is_err()
+        {
+            return false;
+        }
+        let mut cancel = false;
+        loop {
+            match self.poll_line() {
+                Poll::Line(line) if line.trim().eq_ignore_ascii_case("cancel") => cancel = true,
+                Poll::Line(line) => self.queued.push_back(line),
+                Poll::Pending => break,
+                Poll::Closed => {
+                    self.closed = true;
+                    cancel = true;
+                    break;
+                }
+            }
+        }
+        let _ = self.reader.get_ref().socket().set_nonblocking(false);
+        cancel
+    }
+
+    /// Send a final message before the connection is dropped.
+    fn notify(&mut self, message: &str) {
+        let _ = writeln!(self, "{}", message);
+        let _ = self.flush();
+    }
+
+    /// One attempt at writing out `pending`, keeping whatever didn't go.
+    fn send_pending(&mut self) -> io::Result<()> {
+        let stream = self.reader.get_mut();
+        while !self.pending.is_empty() {
+            let n = stream.write(&self.pending)?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.pending.drain(..n);
+        }
+        stream.flush()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            let _ = self.flush();
+        }
+        self.reader.get_mut().close();
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Send what is buffered. A TLS session may have to read before it can
+    /// write, and such reads time out; those are retried for a while. On
+    /// failure the rest of the buffer is dropped, as the client is gone.
+    fn flush(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + IO_TIMEOUT;
+        loop {
+            match self.send_pending() {
+                Err(e) if timed_out(&e) && Instant::now() < deadline => {}
+                Err(e) => {
+                    self.pending.clear();
+                    return Err(e);
+                }
+                Ok(()) => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Whether a failed read or write only ran into the poll timeout.
+fn timed_out(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+/// Handle one client connection for its entire lifetime.
+fn handle_client(
+    mut conn: Connection,
+    db: Arc<Mutex<Database>>,
+    peer: &str,
+    query_limits: QueryLimits,
+) {
+    // Nothing else is available until the client logs in
+    let Some(user) = login(&mut conn, &db, peer) else {
+        return;
+    };
+
+    // Greet the client
+    let _ = writeln!(
+        conn,
+        "RustyDB ready. Type SQL ending in ';', or 'quit' to exit."
+    );
+    if conn.flush().is_err() {
+        return;
+    }
+
+    let session = Session {
+        db: &db,
+        metrics: Arc::clone(&db.lock().unwrap().metrics),
+        user: &user,
+        peer,
+        settings: Cell::new(Settings::new(query_limits)),
+    };
+    while let Some(line) = conn.read_line() {
         let trimmed = line.trim().to_string();
         if trimmed.is_empty() {
             continue;
         }
 
         if trimmed.to_lowercase() == "quit" || trimmed.to_lowercase() == "exit" {
-            let _ = writeln!(writer, "BYE");
-            let _ = writer.flush();
+            let _ = writeln!(conn, "BYE");
+            let _ = conn.flush();
             break;
         }
+        // A CANCEL that arrives after its query already finished
+        if trimmed.eq_ignore_ascii_case("cancel") {
+            let _ = writeln!(conn, "OK: No query is running.");
+            let _ = conn.flush();
+            continue;
+        }
 
-        let response = execute_statement(&trimmed, &db);
-        let _ = writeln!(writer, "{}", response);
-        let _ = writer.flush();
+        // Turn this connection over to a follower for good
+        if trimmed.eq_ignore_ascii_case("replicate") {
+            replicate(&mut conn, &db, &user, peer);
+            return;
+        }
+
+        // `\i file` is psql's spelling of SOURCE 'file'
+        let mut request = match trimmed.strip_prefix("\\i ") {
+            Some(path) => format!("SOURCE {}", lexer::quote_literal(path.trim())),
+            None => line,
+        };
+        // A request runs once it ends in `;`, as in psql: until then a
+        // line may only be the start of a statement, like `DELETE FROM t`
+        // before its WHERE
+        while !lexer::is_terminated(&request) {
+            let Some(more) = conn.read_line() else {
+                return;
+            };
+            request.push('\n');
+            request.push_str(&more);
+        }
+
+        // Several statements may share a request; each gets its own result
+        for text in lexer::split_statements(&request) {
+            if !session.run(&mut conn, &text, 0) {
+                return; // client disconnected mid-statement
+            }
+        }
     }
 }
 
-/// Parse and execute a SQL statement, returning the response as a string.
-fn execute_statement(input: &str, db: &Arc<Mutex<Database>>) -> String {
-    match Statement::parse(input) {
-        Err(e) => format!("ERROR: {}", e),
-        Ok(statement) => match statement {
-            Statement::Insert {
-                table,
-                columns: _,
-                values,
-            } => {
-                let mut db = db.lock().unwrap();
-                match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                    db.insert_into_table(&table, Row::new(values.clone()));
-                })) {
-                    Ok(_) => format!("OK: Inserted 1 row into '{}'.", table),
-                    Err(_) => format!(
-                        "ERROR: Insert into '{}' failed (wrong number of values or table not found).",
-                        table
-                    ),
-                }
+/// SOURCE scripts may run other scripts, but not without end.
+const MAX_SOURCE_DEPTH: usize = 16;
+
+/// A logged-in client, for running its statements.
+struct Session<'a> {
+    db: &'a Arc<Mutex<Database>>,
+    metrics: Arc<Metrics>,
+    user: &'a str,
+    peer: &'a str,
+    settings: Cell<Settings>,
+}
+
+impl Session<'_> {
+    /// Parse, authorize and run one statement, writing its result to the
+    /// connection. `depth` counts the SOURCE scripts it is nested in.
+    /// Returns false if the client went away part-way through.
+    fn run(&self, conn: &mut Connection, text: &str, depth: usize) -> bool {
+        let started = Instant::now();
+        let db = self.db;
+        let statement = Statement::parse(text).and_then(|s| {
+            let db = db.lock().unwrap();
+            auth::authorize(&db.users, self.user, &s)?;
+            if let Some(leader) = db.replication.leader()
+                && s.is_write()
+            {
+                return Err(format!(
+                    "This server is a read-only follower of {}; send {} there.",
+                    leader,
+                    s.kind()
+                ));
             }
+            Ok(s)
+        });
+        // Only the kind: the text may hold a password (CREATE USER)
+        if let Ok(s) = &statement {
+            debug!("[{}] {}: {}", self.peer, self.user, s.kind());
+        }
+        let (kind, secret) = match &statement {
+            Ok(s) => (s.kind(), matches!(s, Statement::CreateUser { .. })),
+            Err(_) => ("INVALID", false),
+        };
 
-            Statement::Select {
+        let response = match statement {
+            Err(e) => format!("ERROR: {}", e),
+            // The rest of a script is not the data STDIN would expect
+            Ok(
+                Statement::CopyFrom {
+                    source: CopyEndpoint::Client,
+                    ..
+                }
+                | Statement::Restore {
+                    source: CopyEndpoint::Client,
+                },
+            ) if depth > 0 => "ERROR: FROM STDIN can't be used in a SOURCE script.".to_string(),
+            // COPY ... FROM STDIN: the CSV data follows on the connection
+            Ok(Statement::CopyFrom {
                 table,
                 columns,
-                condition,
-            } => {
-                let db = db.lock().unwrap();
-
-                // Get table metadata
-                let table_meta = match db.get_table(&table) {
-                    Some(t) => t,
-                    None => return format!("ERROR: Table '{}' not found.", table),
-                };
-
-                let all_cols = &table_meta.columns;
-
-                // Resolve column indices to display
-                let col_indices: Vec<usize> = if columns.len() == 1 && columns[0] == "*" {
-                    (0..all_cols.len()).collect()
-                } else {
-                    columns
-                        .iter()
-                        .filter_map(|name| all_cols.iter().position(|c| c == name))
-                        .collect()
-                };
-
-                if col_indices.is_empty() {
-                    return "ERROR: No valid columns selected.".to_string();
+                source: CopyEndpoint::Client,
+                options,
+            }) => match receive_data(conn, "COPY", "CSV data") {
+                Some(data) => respond(engine::copy_from(
+                    &mut db.lock().unwrap(),
+                    &table,
+                    columns.as_deref(),
+                    &data,
+                    &options,
+                )),
+                None => return false,
+            },
+            // RESTORE FROM STDIN: likewise for a dump script
+            Ok(Statement::Restore {
+                source: CopyEndpoint::Client,
+            }) => match receive_data(conn, "RESTORE", "the SQL script") {
+                Some(script) => respond(engine::restore(&mut db.lock().unwrap(), &script)),
+                None => return false,
+            },
+            // SOURCE: run each statement in the file as if the client sent it
+            Ok(Statement::Source { path }) => match self.source(conn, &path, depth) {
+                Some(response) => response,
+                None => return false,
+            },
+            Ok(statement) => {
+                let mut settings = self.settings.get();
+                let result = execute(statement, db, &mut settings);
+                self.settings.set(settings);
+                match result {
+                    // Stream rows as they are produced, outside the lock
+                    Ok(Outcome::Query(query)) => write_result(conn, query, |conn| {
+                        conn.flush().is_err() || conn.cancel_requested()
+                    }),
+                    result => respond(result),
                 }
+            }
+        };
+        let _ = writeln!(conn, "{}", response);
+        let sent = conn.flush().is_ok();
 
-                // Build header
-                let header: Vec<&str> = col_indices.iter().map(|&i| all_cols[i].as_str()).collect();
-                let header_str = header.join(" | ");
-                let separator = "-".repeat(header_str.len());
-
-                // Fetch rows
-                let rows: Vec<Vec<String>> = match &condition {
-                    None => db
-                        .select_all(&table)
-                        .iter()
-                        .map(|r| r.get_inner_vec().clone())
-                        .collect(),
-                    Some(cond) => db
-                        .select_where(&table, &cond.column, &cond.value)
-                        .into_iter()
-                        .map(|r| r.get_inner_vec().clone())
-                        .collect(),
-                };
-
-                let mut output = format!("{}\n{}", header_str, separator);
-
-                if rows.is_empty() {
-                    output.push_str("\n(no rows)");
-                } else {
-                    for row in &rows {
-                        let display: Vec<&str> =
-                            col_indices.iter().map(|&i| row[i].as_str()).collect();
-                        output.push('\n');
-                        output.push_str(&display.join(" | "));
-                    }
-                    let count = rows.len();
-                    output.push_str(&format!(
-                        "\n({} row{})",
-                        count,
-                        if count == 1 { "" } else { "s" }
-                    ));
+        let elapsed = started.elapsed();
+        if self
+            .metrics
+            .record(kind, elapsed, response.starts_with("ERROR"))
+        {
+            // As above, CREATE USER's text stays out of the log
+            let text = if secret {
+                kind.to_string()
+            } else {
+                text.split_whitespace().collect::<Vec<_>>().join(" ")
+            };
+            warning!(
+                "[{}] Slow query by '{}' ({} ms): {}",
+                self.peer,
+                self.user,
+                elapsed.as_millis(),
+                text
+            );
+        }
+        sent
+    }
+
+    /// Run the script at `path`, writing each statement's result, and
+    /// return the closing summary; `None` if the client went away.
+    fn source(&self, conn: &mut Connection, path: &str, depth: usize) -> Option<String> {
+        if depth >= MAX_SOURCE_DEPTH {
+            return Some(format!(
+                "ERROR: SOURCE '{}' nests too deeply; does a script run itself?",
+                path
+            ));
+        }
+        let script = match fs::read_to_string(path) {
+            Ok(script) => script,
+            Err(e) => return Some(format!("ERROR: Could not read '{}': {}", path, e)),
+        };
+        let statements = lexer::split_statements(&script);
+        for text in &statements {
+            if !self.run(conn, text, depth + 1) {
+                return None;
+            }
+        }
+        Some(format!(
+            "OK: Ran {} statement{} from '{}'.",
+            statements.len(),
+            if statements.len() == 1 { "" } else { "s" },
+            path
+        ))
+    }
+}
+
+/// Stream this server's changes to a follower: first a snapshot of every
+/// table and view, then each change as it commits, with a heartbeat when
+/// there is nothing to send. Each is a header line (`SNAPSHOT lsn bytes`,
+/// `CHANGE lsn bytes` or `HEARTBEAT lsn`) followed by that many bytes of
+/// SQL. The follower answers `ACK lsn` once a change is applied.
+fn replicate(conn: &mut Connection, db: &Arc<Mutex<Database>>, user: &str, peer: &str) {
+    let subscribed = {
+        let mut db = db.lock().unwrap();
+        if !db.users.get(user).is_some_and(|u| u.superuser) {
+            Err("ERROR: Permission denied: only a superuser may REPLICATE".to_string())
+        } else if let Some(leader) = db.replication.leader() {
+            Err(format!(
+                "ERROR: This server is itself a follower; replicate from {} instead.",
+                leader
+            ))
+        } else {
+            let snapshot = dump::dump(&db);
+            let (id, lsn, changes) = db.replication.subscribe(peer);
+            Ok((id, lsn, changes, snapshot))
+        }
+    };
+    let (id, mut lsn, changes, snapshot) = match subscribed {
+        Ok(subscribed) => subscribed,
+        Err(e) => return conn.notify(&e),
+    };
+    info!("[{}] Follower connected at change {}", peer, lsn);
+
+    let _ = write!(conn, "SNAPSHOT {} {}\n{}\n", lsn, snapshot.len(), snapshot);
+    while conn.flush().is_ok() && !conn.shutdown.load(Ordering::SeqCst) {
+        match changes.recv_timeout(replication::HEARTBEAT_INTERVAL) {
+            Ok(change) => {
+                lsn = change.lsn;
+                let _ = write!(
+                    conn,
+                    "CHANGE {} {}\n{}\n",
+                    change.lsn,
+                    change.script.len(),
+                    change.script
+                );
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let _ = writeln!(conn, "HEARTBEAT {}", lsn);
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        // Collect acknowledgements; a CANCEL or hang-up ends the stream
+        if conn.cancel_requested() {
+            break;
+        }
+        let acked = conn
+            .queued
+            .drain(..)
+            .filter_map(|line| line.strip_prefix("ACK ")?.trim().parse::<u64>().ok())
+            .max();
+        if let Some(acked) = acked {
+            db.lock().unwrap().replication.ack(id, acked);
+        }
+    }
+    db.lock().unwrap().replication.unsubscribe(id);
+    info!("[{}] Follower disconnected", peer);
+}
+
+/// Run the login handshake: the client must send `LOGIN <user> <password>`
+/// before anything else. Returns the authenticated user name, or `None` if
+/// the client quit, disconnected or used up its attempts.
+fn login(conn: &mut Connection, db: &Arc<Mutex<Database>>, peer: &str) -> Option<String> {
+    let _ = writeln!(
+        conn,
+        "RustyDB: Authentication required. Send: LOGIN <user> <password>"
+    );
+    conn.flush().ok()?;
+
+    for _ in 0..MAX_LOGIN_ATTEMPTS {
+        let line = conn.read_line()?;
+        let trimmed = line.trim();
+        if trimmed.eq_ignore_ascii_case("quit") || trimmed.eq_ignore_ascii_case("exit") {
+            let _ = writeln!(conn, "BYE");
+            let _ = conn.flush();
+            return None;
+        }
+
+        let Some((user, password)) = parse_login(trimmed) else {
+            let _ = writeln!(conn, "ERROR: Expected: LOGIN <user> <password>");
+            let _ = conn.flush();
+            continue;
+        };
+
+        // Hashing is slow, so only hold the lock long enough to fetch the hash
+        let (stored, metrics) = {
+            let db = db.lock().unwrap();
+            (db.users.password_hash(user), Arc::clone(&db.metrics))
+        };
+        let ok = match stored {
+            Some(hash) => auth::verify_password(password, &hash),
+            None => {
+                auth::verify_unknown_user(password);
+                false
+            }
+        };
+        if ok {
+            info!("[{}] Logged in as '{}'", peer, user);
+            let _ = writeln!(conn, "OK: Logged in as '{}'.", user);
+            let _ = conn.flush();
+            return Some(user.to_string());
+        }
+        warning!("[{}] Failed login as '{}'", peer, user);
+        Metrics::add(&metrics.failed_logins, 1);
+        let _ = writeln!(conn, "ERROR: Invalid user name or password.");
+        let _ = conn.flush();
+    }
+
+    let _ = writeln!(conn, "ERROR: Too many failed login attempts.");
+    let _ = conn.flush();
+    None
+}
+
+/// Split `LOGIN <user> <password>` into its parts. The password is the rest
+/// of the line and may be wrapped in single quotes.
+fn parse_login(line: &str) -> Option<(&str, &str)> {
+    let (keyword, rest) = line.split_once(char::is_whitespace)?;
+    if !keyword.eq_ignore_ascii_case("login") {
+        return None;
+    }
+    let (user, password) = rest.trim_start().split_once(char::is_whitespace)?;
+    let password = password.trim();
+    let password = password
+        .strip_prefix('\'')
+        .and_then(|p| p.strip_suffix('\''))
+        .unwrap_or(password);
+    Some((user, password))
+}
+
+/// Prompt the client for inline data and read lines until the `\.`
+/// terminator. Returns `None` if the client disconnects first.
+fn receive_data(conn: &mut Connection, command: &str, what: &str) -> Option<String> {
+    let _ = writeln!(
+        conn,
+        "{}: Send {}, ending with a line containing only \\.",
+        command, what
+    );
+    let _ = conn.flush();
+    let mut data = String::new();
+    loop {
+        let line = conn.read_line()?;
+        if line.trim_end() == "\\." {
+            return Some(data);
+        }
+        data.push_str(&line);
+        data.push('\n');
+    }
+}
+
+/// Execute a parsed statement. The lock is held only while it runs, not
+/// while a query's rows are read.
+fn execute(
+    statement: Statement,
+    db: &Arc<Mutex<Database>>,
+    settings: &mut Settings,
+) -> Result<Outcome, String> {
+    match statement {
+        // Hash before locking; it is deliberately slow
+        Statement::CreateUser {
+            name,
+            password,
+            superuser,
+        } => {
+            let hash = auth::hash_password(&password);
+            engine::create_user(&mut db.lock().unwrap(), &name, hash, superuser)
+        }
+        statement => engine::execute(&mut db.lock().unwrap(), statement, settings),
+    }
+}
+
+/// The response to send for a statement that has finished.
+fn respond(result: Result<Outcome, String>) -> String {
+    match result {
+        Ok(Outcome::Done { message, .. }) => message,
+        Ok(Outcome::Query(query)) => {
+            let mut out = Vec::new();
+            let summary = write_result(&mut out, query, |_| false);
+            format!("{}{}", String::from_utf8_lossy(&out), summary)
+        }
+        Err(e) => format!("ERROR: {}", e),
+    }
+}
+
+/// Write a query's header and rows to `out` as they are produced, and
+/// return the closing line: the row count, or the error that stopped it.
+/// The rows are produced on a thread of their own, so `check` can run
+/// every `BATCH_ROWS` rows and whenever none has come for `POLL_INTERVAL`,
+/// e.g. to flush; it cancels the query by returning true.
+fn write_result<W: Write>(
+    out: &mut W,
+    query: Query,
+    mut check: impl FnMut(&mut W) -> bool,
+) -> String {
+    let Query {
+        headers,
+        rows,
+        cancel,
+    } = query;
+    let header = headers.join(" | ");
+    let _ = writeln!(out, "{}\n{}", header, "-".repeat(header.len()));
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(BATCH_ROWS);
+        scope.spawn(move || {
+            for row in rows {
+                if sender.send(row).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut count = 0;
+        let summary = loop {
+            let row = match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(row)) => row,
+                Ok(Err(e)) => break format!("ERROR: {}", e),
+                Err(RecvTimeoutError::Timeout) if check(out) => {
+                    break format!("ERROR: Query cancelled after {} rows.", count);
+                }
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    break match count {
+                        0 => "(no rows)".to_string(),
+                        1 => "(1 row)".to_string(),
+                        n => format!("({} rows)", n),
+                    };
+                }
+            };
+            let display: Vec<String> = row.iter().map(Value::to_string).collect();
+            let _ = writeln!(out, "{}", display.join(" | "));
+            count += 1;
+            if count % BATCH_ROWS == 0 && check(out) {
+                break format!("ERROR: Query cancelled after {} rows.", count);
+            }
+        };
+        // A query stopped early stops producing at its next check
+        drop(receiver);
+        cancel.store(true, Ordering::Relaxed);
+        summary
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use rustls::{
+        ClientConfig, ClientConnection, RootCertStore,
+        pki_types::{CertificateDer, pem::PemObject},
+    };
+
+    use super::*;
+    use crate::tls;
+
+    /// A server on a free port whose `admin` has the password `secret`,
+    /// with `setup` already run.
+    fn server(setup: &str) -> Server {
+        let mut db = Database::new();
+        db.users
+            .create_user("admin", auth::hash_password("secret"), true)
+            .unwrap();
+        let server = Server::bind("127.0.0.1:0", db).unwrap();
+        server.run_script(setup).unwrap();
+        server
+    }
+
+    /// The test certificate (for `localhost`) and its key.
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/tls")
+            .join(name)
+    }
+
+    /// A server run on a thread of its own until dropped.
+    struct Running {
+        addr: String,
+        shutdown: Arc<AtomicBool>,
+        thread: Option<thread::JoinHandle<()>>,
+    }
+
+    impl Running {
+        fn start(setup: &str) -> Running {
+            Running::serve(server(setup))
+        }
+
+        fn serve(server: Server) -> Running {
+            let addr = server.listener.local_addr().unwrap().to_string();
+            let shutdown = server.shutdown_handle();
+            let thread = Some(thread::spawn(move || server.run()));
+            Running {
+                addr,
+                shutdown,
+                thread,
+            }
+        }
+
+        /// A client logged in as `admin`.
+        fn admin(&self) -> Client {
+            let mut client = Client::connect(&self.addr);
+            assert_eq!(client.login("admin", "secret"), "OK: Logged in as 'admin'.");
+            client
+        }
+    }
+
+    impl Drop for Running {
+        fn drop(&mut self) {
+            self.shutdown.store(true, Ordering::SeqCst);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    /// The other end of a connection, speaking the line protocol.
+    struct Client<S: Read + Write = TcpStream> {
+        reader: BufReader<S>,
+    }
+
+    impl Client {
+        fn connect(addr: &str) -> Client {
+            let stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            Client::over(stream)
+        }
+    }
+
+    impl<S: Read + Write> Client<S> {
+        /// Start talking over `stream`, which must time out its reads.
+        fn over(stream: S) -> Client<S> {
+            let mut client = Client {
+                reader: BufReader::new(stream),
+            };
+            assert!(
+                client
+                    .line()
+                    .starts_with("RustyDB: Authentication required")
+            );
+            client
+        }
+
+        /// Log in and return the server's answer, reading past the
+        /// greeting that follows a success.
+        fn login(&mut self, user: &str, password: &str) -> String {
+            self.send(&format!("LOGIN {} {}", user, password));
+            let answer = self.line();
+            if answer.starts_with("OK") {
+                assert!(self.line().starts_with("RustyDB ready."));
+            }
+            answer
+        }
+
+        fn send(&mut self, text: &str) {
+            let stream = self.reader.get_mut();
+            stream.write_all(text.as_bytes()).unwrap();
+            stream.write_all(b"\n").unwrap();
+            stream.flush().unwrap();
+        }
+
+        fn line(&mut self) -> String {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            line.trim_end().to_string()
+        }
+
+        /// The lines of the response to one statement, up to and including
+        /// the one that ends it.
+        fn response(&mut self) -> Vec<String> {
+            let mut lines = Vec::new();
+            loop {
+                let line = self.line();
+                let last = ["OK", "ERROR", "BYE", "("]
+                    .iter()
+                    .any(|end| line.starts_with(end))
+                    || line.is_empty();
+                lines.push(line);
+                if last {
+                    return lines;
                 }
+            }
+        }
+
+        /// Send one statement and return the last line of its response.
+        fn run(&mut self, statement: &str) -> String {
+            self.send(statement);
+            self.response().pop().unwrap()
+        }
+    }
+
+    const STUDENTS: &str = "
+        CREATE TABLE students (id INTEGER PRIMARY KEY, name TEXT);
+        INSERT INTO students VALUES (1, 'Ada');
+        INSERT INTO students VALUES (2, 'Grace');
+        INSERT INTO students VALUES (3, 'Edsger');";
+
+    // -----------------------------------------------------------------------
+    // Requests
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn waits_for_the_end_of_a_statement_split_over_lines() {
+        let server = Running::start(STUDENTS);
+        let mut client = server.admin();
+        client.send("DELETE FROM students");
+        client.send("WHERE id = 1;");
+        assert_eq!(client.response(), ["OK: Deleted 1 row from 'students'."]);
+        assert_eq!(client.run("SELECT * FROM students;"), "(2 rows)");
+        client.send("SELECT id");
+        client.send("FROM students;");
+        assert_eq!(
+            client.response(),
+            ["id", "--", "2", "3", "(2 rows)"].map(String::from)
+        );
+    }
+
+    #[test]
+    fn runs_each_statement_of_a_request() {
+        let server = Running::start(STUDENTS);
+        let mut client = server.admin();
+        client.send("DELETE FROM students WHERE id = 1; DELETE FROM nowhere; SELECT name");
+        client.send("FROM students WHERE id = 3;");
+        assert_eq!(client.response(), ["OK: Deleted 1 row from 'students'."]);
+        assert!(client.response()[0].starts_with("ERROR: "));
+        assert_eq!(
+            client.response(),
+            ["name", "----", "Edsger", "(1 row)"].map(String::from)
+        );
+        client.send("quit");
+        assert_eq!(client.line(), "BYE");
+    }
+
+    // -----------------------------------------------------------------------
+    // Login
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn closes_the_connection_after_three_failed_logins() {
+        let server = Running::start("");
+        let mut client = Client::connect(&server.addr);
+        assert_eq!(
+            client.login("admin", "wrong"),
+            "ERROR: Invalid user name or password."
+        );
+        assert_eq!(
+            client.login("nobody", "secret"),
+            "ERROR: Invalid user name or password."
+        );
+        client.send("LOGIN admin");
+        assert_eq!(client.line(), "ERROR: Expected: LOGIN <user> <password>");
+        assert_eq!(client.line(), "ERROR: Too many failed login attempts.");
+        assert_eq!(client.line(), "");
+    }
+
+    #[test]
+    fn lets_a_client_quit_before_logging_in() {
+        let server = Running::start("");
+        let mut client = Client::connect(&server.addr);
+        client.send("quit");
+        assert_eq!(client.line(), "BYE");
+        assert_eq!(client.line(), "");
+    }
+
+    #[test]
+    fn lets_a_user_do_only_what_they_were_granted() {
+        let server = Running::start(&format!(
+            "{}
+             CREATE USER bob WITH PASSWORD 'pw';
+             GRANT SELECT ON students TO bob;",
+            STUDENTS
+        ));
+        let mut client = Client::connect(&server.addr);
+        assert_eq!(client.login("bob", "pw"), "OK: Logged in as 'bob'.");
+        assert_eq!(client.run("SELECT * FROM students;"), "(3 rows)");
+        assert_eq!(
+            client.run("DELETE FROM students;"),
+            "ERROR: Permission denied: user 'bob' lacks DELETE on table 'students'"
+        );
+        assert_eq!(
+            client.run("CREATE TABLE t (a INTEGER);"),
+            "ERROR: Permission denied: only a superuser may run CREATE TABLE"
+        );
+        client.send("REPLICATE");
+        assert_eq!(
+            client.line(),
+            "ERROR: Permission denied: only a superuser may REPLICATE"
+        );
+    }
+
+    #[test]
+    fn answers_a_cancel_with_nothing_to_cancel() {
+        let server = Running::start("");
+        let mut client = server.admin();
+        client.send("CANCEL");
+        assert_eq!(client.line(), "OK: No query is running.");
+        assert_eq!(
+            client.run("CREATE TABLE t (a INTEGER);"),
+            "OK: Created table 't'."
+        );
+    }
 
-                output
+    // -----------------------------------------------------------------------
+    // TLS
+    // -----------------------------------------------------------------------
+
+    #[test]
+    fn greets_a_tls_client_that_is_slow_to_start_its_handshake() {
+        let cert = fixture("cert.pem");
+        let config = tls::server_config(&cert, &fixture("key.pem")).unwrap();
+        let server = Running::serve(server("").with_tls(config));
+
+        let tcp = TcpStream::connect(&server.addr).unwrap();
+        tcp.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        // Longer than a read on the server waits
+        thread::sleep(POLL_INTERVAL * 3);
+
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(&cert).unwrap() {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let name = "localhost".try_into().unwrap();
+        let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+        let mut client = Client::over(StreamOwned::new(conn, tcp));
+        assert_eq!(client.login("admin", "secret"), "OK: Logged in as 'admin'.");
+        assert_eq!(
+            client.run("CREATE TABLE t (a INTEGER);"),
+            "OK: Created table 't'."
+        );
+    }
+
+    // -----------------------------------------------------------------------
+    // Replication
+    // -----------------------------------------------------------------------
+
+    /// Run `statement` on `client` until it answers `expected`, for a
+    /// follower to catch up.
+    fn eventually(client: &mut Client, statement: &str, expected: &str) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let answer = client.run(statement);
+            if answer == expected {
+                return;
             }
-        },
+            assert!(Instant::now() < deadline, "{}: {}", statement, answer);
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn a_follower_copies_the_leader_and_refuses_writes() {
+        let leader = Running::start(STUDENTS);
+        let follower = server("");
+        replication::follow(
+            leader.addr.clone(),
+            "admin".to_string(),
+            "secret".to_string(),
+            None,
+            Arc::clone(&follower.db),
+        );
+        let follower = Running::serve(follower);
+        let mut reader = follower.admin();
+        eventually(&mut reader, "SELECT * FROM students;", "(3 rows)");
+
+        let mut writer = leader.admin();
+        writer.run("DELETE FROM students WHERE id = 1;");
+        writer.run("CREATE TABLE t (a INTEGER);");
+        eventually(&mut reader, "SELECT * FROM students;", "(2 rows)");
+        eventually(&mut reader, "SELECT * FROM t;", "(no rows)");
+
+        assert_eq!(
+            reader.run("DELETE FROM students;"),
+            format!(
+                "ERROR: This server is a read-only follower of {}; send DELETE there.",
+                leader.addr
+            )
+        );
     }
 }
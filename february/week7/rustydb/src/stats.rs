@@ -0,0 +1,125 @@
+use crate::storage::{Table, Value};
+
+/// ANALYZE looks at no more than this many rows of a table for its
+/// distinct-value estimates and histograms. Row and NULL counts, minimum
+/// and maximum come from every row.
+const SAMPLE_ROWS: usize = 30_000;
+
+/// Buckets in each column's histogram.
+const HISTOGRAM_BUCKETS: usize = 10;
+
+/// Fraction of rows assumed to match `column = value` when the table has
+/// never been analyzed.
+pub const DEFAULT_SELECTIVITY: f64 = 0.1;
+
+/// What ANALYZE found out about a table. The planner uses it to estimate
+/// how many rows a filter or join produces; it is not updated by writes, so
+/// it describes the table as it was then.
+#[derive(Clone)]
+pub struct TableStats {
+    pub rows: usize,
+    /// One per column, in schema order.
+    pub columns: Vec<ColumnStats>,
+}
+
+#[derive(Clone)]
+pub struct ColumnStats {
+    pub nulls: usize,
+    /// Estimated number of distinct non-NULL values.
+    pub distinct: f64,
+    /// `None` when every value is NULL.
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    /// Equi-depth histogram: `HISTOGRAM_BUCKETS + 1` bounds splitting the
+    /// sorted non-NULL values into buckets of about the same number of rows.
+    /// A value that appears as several bounds fills the buckets between.
+    pub histogram: Vec<Value>,
+}
+
+/// Gather statistics for `table`.
+pub fn analyze(table: &Table) -> TableStats {
+    let rows = table.select_all();
+    // Every n-th row, so the sample spans the whole table
+    let step = rows.len().div_ceil(SAMPLE_ROWS).max(1);
+
+    let columns = (0..table.columns.len())
+        .map(|c| {
+            let values = || rows.iter().map(move |row| &row.get_inner_vec()[c]);
+            let nulls = values().filter(|v| v.is_null()).count();
+            let non_null = || values().filter(|v| !v.is_null());
+            let min = non_null().min_by(|a, b| a.compare(b)).cloned();
+            let max = non_null().max_by(|a, b| a.compare(b)).cloned();
+
+            let mut sample: Vec<&Value> = values().step_by(step).filter(|v| !v.is_null()).collect();
+            sample.sort_by(|a, b| a.compare(b));
+            let histogram = if sample.is_empty() {
+                Vec::new()
+            } else {
+                (0..=HISTOGRAM_BUCKETS)
+                    .map(|i| sample[i * (sample.len() - 1) / HISTOGRAM_BUCKETS].clone())
+                    .collect()
+            };
+
+            ColumnStats {
+                nulls,
+                distinct: estimate_distinct(&sample, rows.len() - nulls),
+                min,
+                max,
+                histogram,
+            }
+        })
+        .collect();
+
+    TableStats {
+        rows: rows.len(),
+        columns,
+    }
+}
+
+impl TableStats {
+    /// Estimated fraction of rows whose column at `column` equals `value`,
+    /// which must already have the column's type.
+    pub fn equality_selectivity(&self, column: usize, value: &Value) -> f64 {
+        let stats = &self.columns[column];
+        let (Some(min), Some(max)) = (&stats.min, &stats.max) else {
+            return 0.0; // nothing but NULLs
+        };
+        if value.is_null() || value.compare(min).is_lt() || value.compare(max).is_gt() {
+            return 0.0;
+        }
+        let non_null = (self.rows - stats.nulls) as f64 / self.rows as f64;
+        let uniform = non_null / stats.distinct.max(1.0);
+        let bounds = stats
+            .histogram
+            .iter()
+            .filter(|b| b.compare(value).is_eq())
+            .count();
+        let frequent = bounds.saturating_sub(1) as f64 / HISTOGRAM_BUCKETS as f64 * non_null;
+        uniform.max(frequent)
+    }
+}
+
+/// Haas and Stokes' Duj1 estimator: `n·d / (n − f1 + f1·n/N)` for a sample
+/// of `n` out of `N` values holding `d` distinct ones, `f1` of them seen
+/// just once. A sample of everything is exact.
+fn estimate_distinct(sorted: &[&Value], total: usize) -> f64 {
+    let n = sorted.len();
+    let (mut distinct, mut singletons) = (0, 0);
+    let mut i = 0;
+    while i < n {
+        let run = sorted[i..]
+            .iter()
+            .take_while(|v| v.compare(sorted[i]).is_eq())
+            .count();
+        distinct += 1;
+        if run == 1 {
+            singletons += 1;
+        }
+        i += run;
+    }
+    if n == total {
+        return distinct as f64;
+    }
+    let (n, d, f1, total) = (n as f64, distinct as f64, singletons as f64, total as f64);
+    (n * d / (n - f1 + f1 * n / total)).clamp(d, total)
+}
@@ -1,100 +1,1947 @@
-use std::collections::HashMap;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+};
 
-pub struct Row(Vec<String>);
+use crate::{
+    auth::Users,
+    datetime::{self, Interval},
+    eval::{self, Field},
+    exec,
+    metrics::Metrics,
+    parser::{ConflictAction, Expr, InsertValue, OnConflict, Select},
+    replication::Replication,
+    stats::TableStats,
+    trigger,
+};
 
-impl Row {
-    pub fn new(data: Vec<String>) -> Self {
-        Self(data)
+/// A single value produced while evaluating an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    /// Days since 1970-01-01.
+    Date(i32),
+    /// Microseconds since midnight.
+    Time(i64),
+    /// Microseconds since 1970-01-01 00:00, in no particular time zone.
+    Timestamp(i64),
+    Interval(Interval),
+}
+
+impl Value {
+    /// Interpret the value as a number, parsing text if necessary.
+    /// Returns `None` for NULL, dates and times, and text that is not
+    /// numeric.
+    pub fn to_number(&self) -> Option<Value> {
+        match self {
+            Value::Integer(_) | Value::Real(_) => Some(self.clone()),
+            Value::Text(s) => {
+                let s = s.trim();
+                if let Ok(i) = s.parse::<i64>() {
+                    Some(Value::Integer(i))
+                } else if s.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+                    s.parse::<f64>().ok().map(Value::Real)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// The type of the value; `None` for NULL.
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            Value::Null => None,
+            Value::Integer(_) => Some(DataType::Integer),
+            Value::Real(_) => Some(DataType::Real),
+            Value::Text(_) => Some(DataType::Text),
+            Value::Date(_) => Some(DataType::Date),
+            Value::Time(_) => Some(DataType::Time),
+            Value::Timestamp(_) => Some(DataType::Timestamp),
+            Value::Interval(_) => Some(DataType::Interval),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Order values for sorting: NULL first, then numbers by value, then
+    /// text, then dates and timestamps (a date as its midnight), times and
+    /// intervals. 0.0 and -0.0 compare equal.
+    pub fn compare(&self, other: &Value) -> Ordering {
+        let number = |v: &Value| match v {
+            Value::Integer(i) => Some(*i as f64),
+            Value::Real(r) => Some(*r),
+            _ => None,
+        };
+        let rank = |v: &Value| match v {
+            Value::Date(_) | Value::Timestamp(_) => 1,
+            Value::Time(_) => 2,
+            Value::Interval(_) => 3,
+            _ => 0,
+        };
+        match (self, other) {
+            (Value::Integer(x), Value::Integer(y)) => x.cmp(y),
+            (Value::Text(x), Value::Text(y)) => x.cmp(y),
+            (Value::Date(x), Value::Date(y)) => x.cmp(y),
+            (Value::Time(x), Value::Time(y)) | (Value::Timestamp(x), Value::Timestamp(y)) => {
+                x.cmp(y)
+            }
+            (Value::Date(d), Value::Timestamp(t)) => datetime::midnight(*d).cmp(t),
+            (Value::Timestamp(t), Value::Date(d)) => t.cmp(&datetime::midnight(*d)),
+            (Value::Interval(x), Value::Interval(y)) => x.compare(y),
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Less,
+            (_, Value::Null) => Ordering::Greater,
+            _ => match (number(self), number(other)) {
+                (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or_else(|| x.total_cmp(&y)),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => rank(self).cmp(&rank(other)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "NULL"),
+            Value::Integer(i) => write!(f, "{}", i),
+            // Keep a trailing ".0" so reals stay distinguishable from integers
+            Value::Real(r) if r.is_finite() && r.fract() == 0.0 => write!(f, "{:.1}", r),
+            Value::Real(r) => write!(f, "{}", r),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::Date(d) => write!(f, "{}", datetime::format_date(*d)),
+            Value::Time(t) => write!(f, "{}", datetime::format_time(*t)),
+            Value::Timestamp(t) => write!(f, "{}", datetime::format_timestamp(*t)),
+            Value::Interval(i) => write!(f, "{}", i),
+        }
+    }
+}
+
+/// The declared type of a table column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataType {
+    Integer,
+    Real,
+    Text,
+    Date,
+    Time,
+    Timestamp,
+    Interval,
+}
+
+impl DataType {
+    /// Look up a type by its SQL name (case-insensitive), including aliases.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "integer" | "int" | "bigint" => Some(DataType::Integer),
+            "real" | "float" | "double" | "numeric" => Some(DataType::Real),
+            "text" | "varchar" | "string" => Some(DataType::Text),
+            "date" => Some(DataType::Date),
+            "time" => Some(DataType::Time),
+            "timestamp" | "datetime" => Some(DataType::Timestamp),
+            "interval" => Some(DataType::Interval),
+            _ => None,
+        }
     }
 
-    pub fn get_inner_vec(&self) -> &Vec<String> {
+    pub fn is_numeric(self) -> bool {
+        matches!(self, DataType::Integer | DataType::Real)
+    }
+
+    /// Convert `value` into this type for storage. Unlike `CAST`, this is
+    /// strict: text must parse exactly and reals are never truncated.
+    pub fn coerce(self, value: Value) -> Result<Value, String> {
+        let fail = |v: &Value| format!("Cannot store '{}' as {}", v, self);
+        match (self, value) {
+            (_, Value::Null) => Ok(Value::Null),
+            (DataType::Text, v) => Ok(Value::Text(v.to_string())),
+            (DataType::Integer, Value::Integer(i)) => Ok(Value::Integer(i)),
+            (DataType::Integer, Value::Real(r)) if r.fract() == 0.0 && r.abs() < 9.2e18 => {
+                Ok(Value::Integer(r as i64))
+            }
+            (DataType::Integer, Value::Text(s)) => s
+                .trim()
+                .parse::<i64>()
+                .map(Value::Integer)
+                .map_err(|_| fail(&Value::Text(s))),
+            (DataType::Real, Value::Integer(i)) => Ok(Value::Real(i as f64)),
+            (DataType::Real, Value::Real(r)) => Ok(Value::Real(r)),
+            (DataType::Real, Value::Text(s)) => match Value::Text(s.clone()).to_number() {
+                Some(Value::Integer(i)) => Ok(Value::Real(i as f64)),
+                Some(Value::Real(r)) => Ok(Value::Real(r)),
+                _ => Err(fail(&Value::Text(s))),
+            },
+            (t, v) if datetime::is_temporal(t) => {
+                datetime::convert(t, &v, true).ok_or_else(|| fail(&v))
+            }
+            (_, v) => Err(fail(&v)),
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::Integer => write!(f, "INTEGER"),
+            DataType::Real => write!(f, "REAL"),
+            DataType::Text => write!(f, "TEXT"),
+            DataType::Date => write!(f, "DATE"),
+            DataType::Time => write!(f, "TIME"),
+            DataType::Timestamp => write!(f, "TIMESTAMP"),
+            DataType::Interval => write!(f, "INTERVAL"),
+        }
+    }
+}
+
+/// A named, typed column in a table schema.
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub data_type: DataType,
+    /// PRIMARY KEY or UNIQUE: no two rows may hold the same value.
+    pub key: Option<KeyKind>,
+    /// AUTOINCREMENT (or SERIAL): a row inserted with NULL here is numbered
+    /// by the table's counter instead.
+    pub auto_increment: bool,
+    pub references: Option<ForeignKey>,
+}
+
+impl Column {
+    pub fn new(name: &str, data_type: DataType) -> Self {
+        Self {
+            name: name.to_string(),
+            data_type,
+            key: None,
+            auto_increment: false,
+            references: None,
+        }
+    }
+}
+
+/// `REFERENCES table (column)`: each non-NULL value must be held by a row
+/// of the other table, in a key column of the same type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+    pub table: String,
+    pub column: String,
+    pub on_delete: OnDelete,
+}
+
+/// The name errors give the foreign key of `column` in `table`, the way
+/// PostgreSQL names it.
+pub fn foreign_key_name(table: &str, column: &str) -> String {
+    format!("{}_{}_fkey", table, column)
+}
+
+/// What deleting a row does to the rows referencing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnDelete {
+    /// Refuse to delete it.
+    Restrict,
+    /// Delete them too.
+    Cascade,
+    /// Set their reference to NULL.
+    SetNull,
+}
+
+impl fmt::Display for OnDelete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OnDelete::Restrict => write!(f, "RESTRICT"),
+            OnDelete::Cascade => write!(f, "CASCADE"),
+            OnDelete::SetNull => write!(f, "SET NULL"),
+        }
+    }
+}
+
+/// The constraints that make a column a key. A primary key can't be NULL;
+/// a unique column may hold any number of NULLs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyKind {
+    Primary,
+    Unique,
+}
+
+impl fmt::Display for KeyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyKind::Primary => write!(f, "PRIMARY KEY"),
+            KeyKind::Unique => write!(f, "UNIQUE"),
+        }
+    }
+}
+
+/// `CHECK (expr)`: every row must leave `expr` true or NULL.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub expr: Expr,
+}
+
+/// `CREATE TRIGGER`: statements run for each row an INSERT, UPDATE or
+/// DELETE of the table changes, with `NEW.col` and `OLD.col` standing for
+/// the row's values after and before.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub timing: TriggerTiming,
+    pub event: TriggerEvent,
+    /// The text of each statement, as written between BEGIN and END.
+    pub body: Vec<String>,
+}
+
+/// Whether a trigger runs before the row changes or after.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerTiming {
+    Before,
+    After,
+}
+
+impl fmt::Display for TriggerTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerTiming::Before => write!(f, "BEFORE"),
+            TriggerTiming::After => write!(f, "AFTER"),
+        }
+    }
+}
+
+/// The kind of change a trigger runs for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TriggerEvent {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl fmt::Display for TriggerEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TriggerEvent::Insert => write!(f, "INSERT"),
+            TriggerEvent::Update => write!(f, "UPDATE"),
+            TriggerEvent::Delete => write!(f, "DELETE"),
+        }
+    }
+}
+
+/// How deep triggers may set off other triggers before the statement is
+/// given up on, so that ones setting each other off can't run forever.
+const MAX_TRIGGER_DEPTH: usize = 16;
+
+#[derive(Clone)]
+pub struct Row(Vec<Value>);
+
+impl Row {
+    pub fn get_inner_vec(&self) -> &Vec<Value> {
         &self.0
     }
 }
 
+/// A value as an index or hash join sees it. Reals with an integral value
+/// hash like the integer, so `1` and `1.0` match, and a date like its
+/// midnight; NULL has no key and never matches anything.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Integer(i64),
+    Real(u64),
+    Text(String),
+    Time(i64),
+    Timestamp(i64),
+    Interval(i128),
+}
+
+impl Key {
+    pub fn from_value(value: &Value) -> Option<Key> {
+        match value {
+            Value::Null => None,
+            Value::Integer(i) => Some(Key::Integer(*i)),
+            Value::Real(r) if r.fract() == 0.0 && r.abs() < 9.2e18 => Some(Key::Integer(*r as i64)),
+            Value::Real(r) => Some(Key::Real(r.to_bits())),
+            Value::Text(s) => Some(Key::Text(s.clone())),
+            Value::Date(d) => Some(Key::Timestamp(datetime::midnight(*d))),
+            Value::Time(t) => Some(Key::Time(*t)),
+            Value::Timestamp(t) => Some(Key::Timestamp(*t)),
+            Value::Interval(i) => Some(Key::Interval(i.key())),
+        }
+    }
+}
+
+/// A counter made by CREATE SEQUENCE, read with `nextval('name')`.
+#[derive(Debug, Clone)]
+pub struct Sequence {
+    pub start: i64,
+    pub increment: i64,
+    /// What `nextval` returns next.
+    pub next: i64,
+}
+
+impl Sequence {
+    /// Step on and return the value the sequence was at. The last value
+    /// before it would step past the range of an INTEGER is never handed
+    /// out, so there is always a next one to remember.
+    pub fn advance(&mut self, name: &str) -> Result<i64, String> {
+        let value = self.next;
+        self.next = value
+            .checked_add(self.increment)
+            .ok_or_else(|| format!("Sequence '{}' has run out of values", name))?;
+        Ok(value)
+    }
+}
+
+/// What an INSERT did with its row.
+pub enum Inserted {
+    /// Added it as `row`.
+    Added { row: Row },
+    /// Found a duplicate holding `old` and updated that to `new` instead
+    /// (ON CONFLICT DO UPDATE).
+    Updated { old: Row, new: Row },
+    /// Found a duplicate and left it be (ON CONFLICT DO NOTHING).
+    Skipped,
+}
+
+/// A hash index on one column: where each non-NULL value appears.
+#[derive(Clone)]
+pub struct Index {
+    pub name: String,
+    /// Position of the indexed column in the table.
+    pub column: usize,
+    /// Made along with the table for a PRIMARY KEY or UNIQUE column, to
+    /// find duplicates; it goes when the table does.
+    pub unique: bool,
+    /// Positions of the rows holding each value, in insertion order. Shared
+    /// with queries like the rows are.
+    entries: Arc<HashMap<Key, Vec<usize>>>,
+}
+
+impl Index {
+    /// Positions of the rows whose indexed column is `key`.
+    pub fn lookup(&self, key: &Key) -> &[usize] {
+        self.entries.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// How many distinct values the column holds.
+    pub fn distinct(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The entries as of now, unaffected by later writes.
+    pub fn snapshot(&self) -> Arc<HashMap<Key, Vec<usize>>> {
+        Arc::clone(&self.entries)
+    }
+
+    fn add(&mut self, position: usize, row: &Row) {
+        if let Some(key) = Key::from_value(&row.0[self.column]) {
+            let positions = Arc::make_mut(&mut self.entries).entry(key).or_default();
+            let at = positions.partition_point(|&p| p < position);
+            positions.insert(at, position);
+        }
+    }
+
+    fn remove(&mut self, position: usize, row: &Row) {
+        if let Some(key) = Key::from_value(&row.0[self.column]) {
+            let entries = Arc::make_mut(&mut self.entries);
+            if let Some(positions) = entries.get_mut(&key) {
+                positions.retain(|&p| p != position);
+                if positions.is_empty() {
+                    entries.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Table {
-    pub columns: Vec<String>,
-    rows: Vec<Row>,
+    pub columns: Vec<Column>,
+    /// Shared with any query still reading an older version; writers copy
+    /// on write rather than wait for readers.
+    rows: Arc<Vec<Row>>,
+    indexes: Vec<Index>,
+    /// The number the AUTOINCREMENT column, if any, gives the next row
+    /// inserted without one: always past every number it holds, so none is
+    /// handed out twice.
+    next_id: i64,
+    /// What the last ANALYZE found; not kept up to date by writes.
+    pub stats: Option<TableStats>,
+    pub checks: Vec<Check>,
+    /// In order of name, the order they run in, as in PostgreSQL.
+    pub triggers: Vec<Trigger>,
 }
 
 pub struct Database {
     tables: HashMap<String, Table>,
+    /// Stored queries, expanded by the executor wherever they are named.
+    views: HashMap<String, Select>,
+    sequences: HashMap<String, Sequence>,
+    /// Accounts allowed to connect, and what each may touch.
+    pub users: Users,
+    /// Committed changes and the followers streaming them.
+    pub replication: Replication,
+    /// What has been done with the database, for SHOW METRICS.
+    pub metrics: Arc<Metrics>,
+    /// Whether foreign keys are checked. Restoring a script turns this off,
+    /// as a dump may hold a row before the one it references; ON DELETE
+    /// actions still happen.
+    pub references_checked: bool,
+    /// How many triggers are running, one inside another.
+    trigger_depth: usize,
+    /// Whether a statement is under way, so that the rows and sequences it
+    /// started from are already kept to undo its triggers' changes with.
+    in_statement: bool,
 }
 
 impl Table {
-    pub fn insert(&mut self, row: Row) {
-        if self.columns.len() == row.0.len() {
-            self.rows.push(row);
-        } else {
-            panic!(
+    /// The column names, in schema order.
+    pub fn column_names(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    /// Check the arity of `values` and coerce each one to its column's type.
+    pub fn build_row(&self, values: Vec<Value>) -> Result<Row, String> {
+        if self.columns.len() != values.len() {
+            return Err(format!(
                 "Incorrect number of values: expected {}, got {}",
                 self.columns.len(),
-                row.0.len()
-            );
+                values.len()
+            ));
         }
+        let coerced = self
+            .columns
+            .iter()
+            .zip(values)
+            .map(|(col, v)| {
+                col.data_type
+                    .coerce(v)
+                    .map_err(|e| format!("{} (column '{}')", e, col.name))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Row(coerced))
     }
 
-    pub fn select_all(&self) -> &Vec<Row> {
-        &self.rows
+    /// The columns as fields of a row, under the table name `binding`.
+    pub fn fields(&self, binding: &str) -> Vec<Field> {
+        self.columns
+            .iter()
+            .map(|c| Field {
+                table: binding.to_string(),
+                name: c.name.clone(),
+                data_type: Some(c.data_type),
+            })
+            .collect()
     }
 
-    /// Return rows where `column_name = value` (string comparison).
-    /// Returns an empty vec if the column doesn't exist.
-    pub fn select_where(&self, column_name: &str, value: &str) -> Vec<&Row> {
-        let col_idx = self.columns.iter().position(|c| c == column_name);
-        match col_idx {
-            None => vec![],
-            Some(idx) => self
-                .rows
+    /// Map column names to their positions in the schema.
+    pub fn column_positions(&self, names: &[String]) -> Result<Vec<usize>, String> {
+        let mut positions: Vec<usize> = Vec::with_capacity(names.len());
+        for name in names {
+            let pos = self
+                .columns
                 .iter()
-                .filter(|row| row.0.get(idx).map(|v| v == value).unwrap_or(false))
+                .position(|c| &c.name == name)
+                .ok_or_else(|| format!("Column '{}' does not exist", name))?;
+            if positions.contains(&pos) {
+                return Err(format!("Column '{}' specified more than once", name));
+            }
+            positions.push(pos);
+        }
+        Ok(positions)
+    }
+
+    /// Append rows that were already checked with `build_row`, numbering
+    /// any without a value for the AUTOINCREMENT column. Either all of them
+    /// go in or, if one would break a key, none do.
+    pub fn insert_rows(&mut self, rows: Vec<Row>) -> Result<(), String> {
+        let (rows, next_id) = self.number_rows(rows)?;
+        self.check_keys(&rows, &HashSet::new())?;
+
+        self.next_id = next_id;
+        let first = self.rows.len();
+        for index in &mut self.indexes {
+            for (i, row) in rows.iter().enumerate() {
+                index.add(first + i, row);
+            }
+        }
+        Arc::make_mut(&mut self.rows).extend(rows);
+        Ok(())
+    }
+
+    /// `rows` numbered the way `insert_rows` numbers them, and what
+    /// `next_id` must become once they're in.
+    fn number_rows(&self, mut rows: Vec<Row>) -> Result<(Vec<Row>, i64), String> {
+        let mut next_id = self.next_id;
+        for row in &mut rows {
+            next_id = self.number_row(row, next_id)?;
+        }
+        Ok((rows, next_id))
+    }
+
+    /// Give `row` the number `next_id` if it has none in the AUTOINCREMENT
+    /// column, returning what `next_id` must become once it's in.
+    fn number_row(&self, row: &mut Row, next_id: i64) -> Result<i64, String> {
+        let Some(auto) = self.columns.iter().position(|c| c.auto_increment) else {
+            return Ok(next_id);
+        };
+        let id = match row.0[auto] {
+            Value::Integer(id) => id,
+            _ => {
+                row.0[auto] = Value::Integer(next_id);
+                next_id
+            }
+        };
+        self.past_id(auto, id, next_id)
+    }
+
+    /// What `next_id` must become for the AUTOINCREMENT column at `auto` to
+    /// hold `id`.
+    fn past_id(&self, auto: usize, id: i64, next_id: i64) -> Result<i64, String> {
+        if id < next_id {
+            return Ok(next_id);
+        }
+        id.checked_add(1).ok_or_else(|| {
+            format!(
+                "Column '{}' has run out of numbers to give new rows",
+                self.columns[auto].name
+            )
+        })
+    }
+
+    /// Replace the row at each position with the row paired with it,
+    /// checked with `build_row`. Either all of them change or, if one
+    /// would break a key, none do.
+    pub fn update_rows(&mut self, changes: Vec<(usize, Row)>) -> Result<(), String> {
+        let mut next_id = self.next_id;
+        if let Some(auto) = self.columns.iter().position(|c| c.auto_increment) {
+            for (_, row) in &changes {
+                if let Value::Integer(id) = row.0[auto] {
+                    next_id = self.past_id(auto, id, next_id)?;
+                }
+            }
+        }
+        let replacing: HashSet<usize> = changes.iter().map(|(p, _)| *p).collect();
+        let rows: Vec<Row> = changes.iter().map(|(_, row)| row.clone()).collect();
+        self.check_keys(&rows, &replacing)?;
+
+        self.next_id = next_id;
+        for index in &mut self.indexes {
+            for (position, row) in &changes {
+                index.remove(*position, &self.rows[*position]);
+                index.add(*position, row);
+            }
+        }
+        let stored = Arc::make_mut(&mut self.rows);
+        for (position, row) in changes {
+            stored[position] = row;
+        }
+        Ok(())
+    }
+
+    /// Remove the rows at `positions`. Those after them move up, so every
+    /// index is built again.
+    fn delete_rows(&mut self, positions: &HashSet<usize>) {
+        let mut position = 0;
+        Arc::make_mut(&mut self.rows).retain(|_| {
+            position += 1;
+            !positions.contains(&(position - 1))
+        });
+        for index in &mut self.indexes {
+            index.entries = Arc::new(HashMap::new());
+            for (i, row) in self.rows.iter().enumerate() {
+                index.add(i, row);
+            }
+        }
+    }
+
+    /// Positions of the rows holding `key` in the column at `column`.
+    fn positions_with(&self, column: usize, key: &Key) -> Vec<usize> {
+        match self.index_on(column) {
+            Some(index) => index.lookup(key).to_vec(),
+            None => (0..self.rows.len())
+                .filter(|&p| Key::from_value(&self.rows[p].0[column]).as_ref() == Some(key))
                 .collect(),
         }
     }
+
+    /// Which column is the AUTOINCREMENT one, and the number it gives the
+    /// next row inserted without one.
+    pub fn auto_increment(&self) -> Option<(usize, i64)> {
+        let column = self.columns.iter().position(|c| c.auto_increment)?;
+        Some((column, self.next_id))
+    }
+
+    /// Make `next` the number the AUTOINCREMENT column `column` gives the
+    /// next row, or 1 if `None`. Numbers rows already hold make inserts
+    /// fail as duplicates until it moves past them.
+    pub fn restart_auto_increment(
+        &mut self,
+        column: &str,
+        next: Option<i64>,
+    ) -> Result<(), String> {
+        match self.columns.iter().find(|c| c.name == column) {
+            Some(c) if c.auto_increment => {
+                self.next_id = next.unwrap_or(1);
+                Ok(())
+            }
+            Some(_) => Err(format!(
+                "Column '{}' is not an AUTOINCREMENT column",
+                column
+            )),
+            None => Err(format!("Column '{}' does not exist", column)),
+        }
+    }
+
+    /// The row `row` would duplicate a key of: in the column at `target`, or
+    /// in any key column.
+    pub fn find_duplicate(&self, row: &Row, target: Option<usize>) -> Option<usize> {
+        self.indexes
+            .iter()
+            .filter(|i| i.unique && target.is_none_or(|t| t == i.column))
+            .find_map(|index| {
+                let key = Key::from_value(&row.0[index.column])?;
+                index.lookup(&key).first().copied()
+            })
+    }
+
+    /// Check that adding `rows`, in place of the rows at `replacing`, leaves
+    /// every key column without NULLs (for a primary key) or duplicates.
+    fn check_keys(&self, rows: &[Row], replacing: &HashSet<usize>) -> Result<(), String> {
+        for index in self.indexes.iter().filter(|i| i.unique) {
+            let column = &self.columns[index.column];
+            let mut added = HashSet::new();
+            for row in rows {
+                let value = &row.0[index.column];
+                let Some(key) = Key::from_value(value) else {
+                    if column.key == Some(KeyKind::Primary) {
+                        return Err(format!(
+                            "Column '{}' is the primary key and can't be NULL",
+                            column.name
+                        ));
+                    }
+                    continue;
+                };
+                let taken = index.lookup(&key).iter().any(|p| !replacing.contains(p));
+                if taken || !added.insert(key) {
+                    return Err(format!(
+                        "Duplicate key: a row with {} = {} already exists",
+                        column.name, value
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn indexes(&self) -> &[Index] {
+        &self.indexes
+    }
+
+    /// An index on the column at `position`, if there is one.
+    pub fn index_on(&self, position: usize) -> Option<&Index> {
+        self.indexes.iter().find(|i| i.column == position)
+    }
+
+    pub fn select_all(&self) -> &Vec<Row> {
+        &self.rows
+    }
+
+    /// The rows as of now, unaffected by later writes.
+    pub fn snapshot(&self) -> Arc<Vec<Row>> {
+        Arc::clone(&self.rows)
+    }
 }
 
 impl Database {
     pub fn new() -> Self {
         Self {
             tables: HashMap::new(),
+            views: HashMap::new(),
+            sequences: HashMap::new(),
+            users: Users::default(),
+            replication: Replication::default(),
+            metrics: Arc::new(Metrics::new()),
+            references_checked: true,
+            trigger_depth: 0,
+            in_statement: false,
+        }
+    }
+
+    /// A copy of the tables and views, with no accounts, for a transaction
+    /// to change on its own. It keeps a journal of the changes made to it.
+    pub fn fork(&self) -> Database {
+        Database {
+            tables: self.tables.clone(),
+            views: self.views.clone(),
+            sequences: self.sequences.clone(),
+            users: Users::default(),
+            replication: Replication::journaled(),
+            metrics: Arc::clone(&self.metrics),
+            references_checked: true,
+            trigger_depth: 0,
+            in_statement: false,
         }
     }
 
-    pub fn create_table(&mut self, table_name: &str, columns: Vec<String>) {
+    /// Take `other`'s tables, views and sequences in place of ours, keeping
+    /// our accounts. A follower loads its leader's snapshot this way.
+    pub fn replace_data(&mut self, other: Database) {
+        self.tables = other.tables;
+        self.views = other.views;
+        self.sequences = other.sequences;
+    }
+
+    pub fn create_table(
+        &mut self,
+        table_name: &str,
+        columns: Vec<Column>,
+        checks: Vec<Check>,
+    ) -> Result<(), String> {
+        if self.tables.contains_key(table_name) {
+            return Err(format!("Table '{}' already exists", table_name));
+        }
+        if self.views.contains_key(table_name) {
+            return Err(format!("A view named '{}' already exists", table_name));
+        }
+        if self.references_checked {
+            for column in &columns {
+                if let Some(key) = &column.references {
+                    self.check_foreign_key(table_name, &columns, column, key)?;
+                }
+            }
+        }
+        // Each key column gets an index, named the way PostgreSQL does
+        let mut indexes = Vec::new();
+        for (position, column) in columns.iter().enumerate() {
+            let name = match column.key {
+                Some(KeyKind::Primary) => format!("{}_pkey", table_name),
+                Some(KeyKind::Unique) => format!("{}_{}_key", table_name, column.name),
+                None => continue,
+            };
+            if self.index_table(&name).is_some() {
+                return Err(format!(
+                    "Index '{}' already exists; it is needed for the {} of column '{}'",
+                    name,
+                    column.key.expect("a key column"),
+                    column.name
+                ));
+            }
+            indexes.push(Index {
+                name,
+                column: position,
+                unique: true,
+                entries: Arc::new(HashMap::new()),
+            });
+        }
         let table = Table {
             columns,
-            rows: Vec::new(),
+            rows: Arc::new(Vec::new()),
+            indexes,
+            next_id: 1,
+            stats: None,
+            checks,
+            triggers: Vec::new(),
         };
+        let fields = table.fields(table_name);
+        for check in &table.checks {
+            if check.expr.has_window() {
+                return Err("Window functions aren't allowed in CHECK".to_string());
+            }
+            // A row that passed could fail later, or on a follower
+            if check.expr.calls("NOW") {
+                return Err("NOW() isn't allowed in CHECK".to_string());
+            }
+            eval::check_columns(&check.expr, &fields).map_err(|e| {
+                format!(
+                    "Check constraint '{}' is invalid: {}",
+                    check.name,
+                    trim_period(e)
+                )
+            })?;
+        }
         self.tables.insert(table_name.to_owned(), table);
+        Ok(())
     }
 
-    pub fn insert_into_table(&mut self, table_name: &str, row: Row) {
-        let table = self
+    /// Check that the column `key` references, from `column` of a new table
+    /// with `columns`, exists and can be referenced.
+    fn check_foreign_key(
+        &self,
+        table_name: &str,
+        columns: &[Column],
+        column: &Column,
+        key: &ForeignKey,
+    ) -> Result<(), String> {
+        let others = match self.tables.get(&key.table) {
+            _ if key.table == table_name => columns,
+            Some(table) => &table.columns,
+            None => {
+                return Err(format!(
+                    "Table '{}', referenced by column '{}', not found",
+                    key.table, column.name
+                ));
+            }
+        };
+        let Some(other) = others.iter().find(|c| c.name == key.column) else {
+            return Err(format!(
+                "Column '{}.{}', referenced by column '{}', does not exist",
+                key.table, key.column, column.name
+            ));
+        };
+        if other.key.is_none() {
+            return Err(format!(
+                "Column '{}.{}', referenced by column '{}', must be a PRIMARY KEY or UNIQUE column",
+                key.table, key.column, column.name
+            ));
+        }
+        if other.data_type != column.data_type {
+            return Err(format!(
+                "Column '{}' is {} but '{}.{}', which it references, is {}",
+                column.name, column.data_type, key.table, key.column, other.data_type
+            ));
+        }
+        Ok(())
+    }
+
+    /// Remove a table and all of its rows, along with the grants on it.
+    /// With `if_exists`, dropping a missing table is not an error. A table
+    /// other tables reference can't be dropped before them.
+    pub fn drop_table(&mut self, table_name: &str, if_exists: bool) -> Result<(), String> {
+        self.remove_table(table_name, if_exists)?;
+        self.users.forget_table(table_name);
+        Ok(())
+    }
+
+    /// As [`Database::drop_table`], but keeping the grants, for a script
+    /// that creates the table again.
+    pub fn remove_table(&mut self, table_name: &str, if_exists: bool) -> Result<(), String> {
+        if self.references_checked
+            && let Some((referencing, column, _)) = self
+                .foreign_keys_to(table_name)
+                .into_iter()
+                .find(|(referencing, _, _)| referencing != table_name)
+        {
+            return Err(format!(
+                "Table '{}' is referenced by foreign key '{}' of '{}'; drop that table first",
+                table_name,
+                foreign_key_name(
+                    &referencing,
+                    &self.tables[&referencing].columns[column].name
+                ),
+                referencing
+            ));
+        }
+        match self.tables.remove(table_name) {
+            Some(_) => Ok(()),
+            None if self.views.contains_key(table_name) => Err(format!(
+                "'{}' is a view; use DROP VIEW to remove it",
+                table_name
+            )),
+            None if if_exists => Ok(()),
+            None => Err(format!("Table '{}' not found", table_name)),
+        }
+    }
+
+    /// Store a view. The query is not checked here; a view whose tables
+    /// have since gone away only fails when it is used.
+    pub fn create_view(&mut self, name: &str, query: Select) -> Result<(), String> {
+        if self.views.contains_key(name) {
+            return Err(format!("View '{}' already exists", name));
+        }
+        if self.tables.contains_key(name) {
+            return Err(format!("A table named '{}' already exists", name));
+        }
+        self.views.insert(name.to_owned(), query);
+        Ok(())
+    }
+
+    /// Remove a view, along with the grants on it.
+    pub fn drop_view(&mut self, name: &str, if_exists: bool) -> Result<(), String> {
+        self.remove_view(name, if_exists)?;
+        self.users.forget_table(name);
+        Ok(())
+    }
+
+    /// As [`Database::drop_view`], but keeping the grants.
+    pub fn remove_view(&mut self, name: &str, if_exists: bool) -> Result<(), String> {
+        match self.views.remove(name) {
+            Some(_) => Ok(()),
+            None if self.tables.contains_key(name) => Err(format!(
+                "'{}' is a table; use DROP TABLE to remove it",
+                name
+            )),
+            None if if_exists => Ok(()),
+            None => Err(format!("View '{}' not found", name)),
+        }
+    }
+
+    /// Index `column` of `table`. Index names are unique across the
+    /// database, and a column has at most one index.
+    pub fn create_index(&mut self, name: &str, table: &str, column: &str) -> Result<(), String> {
+        if self.index_table(name).is_some() {
+            return Err(format!("Index '{}' already exists", name));
+        }
+        let table_meta = self
             .tables
+            .get_mut(table)
+            .ok_or_else(|| format!("Table '{}' not found", table))?;
+        let position = table_meta
+            .columns
+            .iter()
+            .position(|c| c.name == column)
+            .ok_or_else(|| format!("Column '{}' not found in table '{}'", column, table))?;
+        if let Some(existing) = table_meta.index_on(position) {
+            return Err(format!(
+                "Column '{}' of '{}' is already indexed by '{}'",
+                column, table, existing.name
+            ));
+        }
+        let mut index = Index {
+            name: name.to_string(),
+            column: position,
+            unique: false,
+            entries: Arc::new(HashMap::new()),
+        };
+        for (i, row) in table_meta.rows.iter().enumerate() {
+            index.add(i, row);
+        }
+        table_meta.indexes.push(index);
+        Ok(())
+    }
+
+    pub fn drop_index(&mut self, name: &str, if_exists: bool) -> Result<(), String> {
+        match self.index_table(name) {
+            Some(table) => {
+                let table_meta = self.tables.get_mut(&table).expect("index_table found it");
+                let position = table_meta.indexes.iter().position(|i| i.name == name);
+                let index = &table_meta.indexes[position.expect("index_table found it")];
+                if index.unique {
+                    return Err(format!(
+                        "Index '{}' enforces the {} of '{}'; it goes when the table does",
+                        name,
+                        table_meta.columns[index.column]
+                            .key
+                            .expect("a unique index's column is a key"),
+                        table
+                    ));
+                }
+                table_meta.indexes.retain(|i| i.name != name);
+                Ok(())
+            }
+            None if if_exists => Ok(()),
+            None => Err(format!("Index '{}' not found", name)),
+        }
+    }
+
+    /// The table the index `name` belongs to.
+    fn index_table(&self, name: &str) -> Option<String> {
+        self.tables
+            .iter()
+            .find(|(_, t)| t.indexes.iter().any(|i| i.name == name))
+            .map(|(table, _)| table.clone())
+    }
+
+    /// Add a sequence, whose first `nextval` returns `start`.
+    pub fn create_sequence(
+        &mut self,
+        name: &str,
+        start: i64,
+        increment: i64,
+    ) -> Result<(), String> {
+        if self.sequences.contains_key(name) {
+            return Err(format!("Sequence '{}' already exists", name));
+        }
+        if increment == 0 {
+            return Err("A sequence's INCREMENT can't be 0".to_string());
+        }
+        self.sequences.insert(
+            name.to_owned(),
+            Sequence {
+                start,
+                increment,
+                next: start,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn drop_sequence(&mut self, name: &str, if_exists: bool) -> Result<(), String> {
+        match self.sequences.remove(name) {
+            Some(_) => Ok(()),
+            None if if_exists => Ok(()),
+            None => Err(format!("Sequence '{}' not found", name)),
+        }
+    }
+
+    /// Make the sequence's next value `next`, or its start again.
+    pub fn restart_sequence(&mut self, name: &str, next: Option<i64>) -> Result<(), String> {
+        let sequence = self
+            .sequences
+            .get_mut(name)
+            .ok_or_else(|| format!("Sequence '{}' not found", name))?;
+        sequence.next = next.unwrap_or(sequence.start);
+        Ok(())
+    }
+
+    /// As [`Table::restart_auto_increment`], for the table `table_name`.
+    pub fn restart_auto_increment(
+        &mut self,
+        table_name: &str,
+        column: &str,
+        next: Option<i64>,
+    ) -> Result<(), String> {
+        self.tables
             .get_mut(table_name)
-            .unwrap_or_else(|| panic!("Table '{}' not found", table_name));
-        table.insert(row);
+            .ok_or_else(|| format!("Table '{}' not found", table_name))?
+            .restart_auto_increment(column, next)
     }
 
-    pub fn get_table(&self, table_name: &str) -> Option<&Table> {
-        self.tables.get(table_name)
+    /// Add a trigger to `table_name`, once every `NEW.col` and `OLD.col` in
+    /// its body is known to name a column of a row the trigger has. The
+    /// tables the body changes are only looked for when it runs.
+    pub fn create_trigger(&mut self, table_name: &str, trigger: Trigger) -> Result<(), String> {
+        if self.trigger_table(&trigger.name).is_some() {
+            return Err(format!("Trigger '{}' already exists", trigger.name));
+        }
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| format!("Table '{}' not found", table_name))?;
+        for text in &trigger.body {
+            trigger::parse(text, |side, column| {
+                match (side, trigger.event) {
+                    (trigger::Side::New, TriggerEvent::Delete) => {
+                        return Err("a DELETE trigger has no NEW row".to_string());
+                    }
+                    (trigger::Side::Old, TriggerEvent::Insert) => {
+                        return Err("an INSERT trigger has no OLD row".to_string());
+                    }
+                    _ => {}
+                }
+                table.column_positions(&[column.to_string()])?;
+                Ok("NULL".to_string())
+            })
+            .map_err(|e| format!("In the body of trigger '{}': {}", trigger.name, e))?;
+        }
+        let triggers = &mut self.table_mut(table_name).triggers;
+        let at = triggers.partition_point(|t| t.name < trigger.name);
+        triggers.insert(at, trigger);
+        Ok(())
+    }
+
+    pub fn drop_trigger(&mut self, name: &str, if_exists: bool) -> Result<(), String> {
+        match self.trigger_table(name) {
+            Some(table_name) => {
+                self.table_mut(&table_name)
+                    .triggers
+                    .retain(|t| t.name != name);
+                Ok(())
+            }
+            None if if_exists => Ok(()),
+            None => Err(format!("Trigger '{}' not found", name)),
+        }
+    }
+
+    /// The table the trigger `name` belongs to.
+    fn trigger_table(&self, name: &str) -> Option<String> {
+        self.tables
+            .iter()
+            .find(|(_, table)| table.triggers.iter().any(|t| t.name == name))
+            .map(|(table_name, _)| table_name.clone())
+    }
+
+    pub fn get_sequence(&self, name: &str) -> Option<&Sequence> {
+        self.sequences.get(name)
+    }
+
+    /// Names of all sequences, sorted.
+    pub fn sequence_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.sequences.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn get_view(&self, name: &str) -> Option<&Select> {
+        self.views.get(name)
+    }
+
+    /// Names of all views, sorted.
+    pub fn view_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.views.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Names of all tables, sorted so output built from them is stable.
+    pub fn table_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tables.keys().cloned().collect();
+        names.sort();
+        names
     }
 
-    /// Select all rows from a table.
-    pub fn select_all(&self, table_name: &str) -> &Vec<Row> {
+    /// Insert one row as INSERT gives it: `values` for `columns`, or for
+    /// every column in order if there's no list, and DEFAULT for the rest.
+    /// With `on_conflict`, a row that would duplicate a key updates the one
+    /// already there, or is dropped, instead. Returns what happened and the
+    /// sequences `nextval` advanced, which only move if a row is added or
+    /// changed.
+    pub fn insert_into_table(
+        &mut self,
+        table_name: &str,
+        columns: Option<&[String]>,
+        values: Vec<InsertValue>,
+        on_conflict: Option<&OnConflict>,
+    ) -> Result<(Inserted, Vec<String>), String> {
         let table = self
             .tables
             .get(table_name)
-            .unwrap_or_else(|| panic!("Table '{}' not found", table_name));
-        table.select_all()
+            .ok_or_else(|| format!("Table '{}' not found", table_name))?;
+        let positions = match columns {
+            Some(names) => table.column_positions(names)?,
+            None if values.len() != table.columns.len() => {
+                return Err(format!(
+                    "Incorrect number of values: expected {}, got {}",
+                    table.columns.len(),
+                    values.len()
+                ));
+            }
+            None => (0..values.len()).collect(),
+        };
+        let target = match on_conflict.and_then(|c| c.target.as_ref()) {
+            Some(name) => {
+                let position = table.column_positions(std::slice::from_ref(name))?[0];
+                if table.columns[position].key.is_none() {
+                    return Err(format!(
+                        "ON CONFLICT column '{}' is not a PRIMARY KEY or UNIQUE column",
+                        name
+                    ));
+                }
+                Some(position)
+            }
+            None => None,
+        };
+        let assignments = match on_conflict.map(|c| &c.action) {
+            Some(ConflictAction::Update(assignments)) => {
+                let names: Vec<String> = assignments.iter().map(|(c, _)| c.clone()).collect();
+                let positions = table.column_positions(&names)?;
+                let fields = conflict_fields(table_name, table);
+                let mut exprs = Vec::with_capacity(assignments.len());
+                for (position, (_, expr)) in positions.into_iter().zip(assignments) {
+                    if expr.has_window() {
+                        return Err("Window functions aren't allowed in ON CONFLICT".to_string());
+                    }
+                    let mut expr = expr.clone();
+                    expr.qualify(table_name);
+                    eval::check_columns(&expr, &fields).map_err(trim_period)?;
+                    exprs.push((position, expr));
+                }
+                Some((fields, exprs))
+            }
+            _ => None,
+        };
+
+        let mut row = vec![Value::Null; table.columns.len()];
+        let mut advanced: Vec<(String, Sequence)> = Vec::new();
+        let now = datetime::now();
+        for (position, value) in positions.into_iter().zip(values) {
+            row[position] = match value {
+                InsertValue::Value(value) => value,
+                InsertValue::Default => Value::Null,
+                InsertValue::Now => now.clone(),
+                InsertValue::NextVal(name) => {
+                    let i = match advanced.iter().position(|(n, _)| *n == name) {
+                        Some(i) => i,
+                        None => {
+                            let sequence = self
+                                .sequences
+                                .get(&name)
+                                .ok_or_else(|| format!("Sequence '{}' not found", name))?;
+                            advanced.push((name.clone(), sequence.clone()));
+                            advanced.len() - 1
+                        }
+                    };
+                    Value::Integer(advanced[i].1.advance(&name)?)
+                }
+            };
+        }
+        let mut row = table.build_row(row)?;
+
+        // A row that may not go in still uses up its number, as in SQLite
+        // and PostgreSQL
+        let claimed = match on_conflict {
+            Some(_) => table.number_row(&mut row, table.next_id)?,
+            None => table.next_id,
+        };
+        // The row must pass its CHECK constraints even if it doesn't go in,
+        // as in SQLite and PostgreSQL
+        if on_conflict.is_some() {
+            self.check_constraints(table_name, std::slice::from_ref(&row))?;
+        }
+        let inserted = match on_conflict.and_then(|_| table.find_duplicate(&row, target)) {
+            None => {
+                let mut rows = self.insert_rows(table_name, vec![row])?;
+                Inserted::Added {
+                    row: rows.pop().expect("one row inserted"),
+                }
+            }
+            Some(_) if assignments.is_none() => {
+                self.table_mut(table_name).next_id = claimed;
+                return Ok((Inserted::Skipped, Vec::new()));
+            }
+            Some(position) => {
+                let (fields, exprs) = assignments.expect("checked above");
+                let old = table.rows[position].clone();
+                let both: Vec<Value> = old.0.iter().chain(&row.0).cloned().collect();
+                let mut new = old.0.clone();
+                for (column, expr) in &exprs {
+                    new[*column] = eval::eval(expr, &fields, &both).map_err(trim_period)?;
+                }
+                let new = table.build_row(new)?;
+                self.update_rows(table_name, vec![(position, new.clone())])?;
+                let table = self.table_mut(table_name);
+                table.next_id = table.next_id.max(claimed);
+                Inserted::Updated { old, new }
+            }
+        };
+        let mut names = Vec::with_capacity(advanced.len());
+        for (name, sequence) in advanced {
+            self.sequences.insert(name.clone(), sequence);
+            names.push(name);
+        }
+        Ok((inserted, names))
     }
 
-    /// Select rows matching `column = value`.
-    pub fn select_where<'a>(&'a self, table_name: &str, column: &str, value: &str) -> Vec<&'a Row> {
+    /// UPDATE: set the columns `assignments` name in the rows `condition`
+    /// holds for, or in every row. Returns how many rows changed.
+    pub fn update_where(
+        &mut self,
+        table_name: &str,
+        assignments: &[(String, Expr)],
+        condition: Option<&Expr>,
+    ) -> Result<usize, String> {
         let table = self
             .tables
             .get(table_name)
-            .unwrap_or_else(|| panic!("Table '{}' not found", table_name));
-        table.select_where(column, value)
+            .ok_or_else(|| format!("Table '{}' not found", table_name))?;
+        let names: Vec<String> = assignments.iter().map(|(c, _)| c.clone()).collect();
+        let columns = table.column_positions(&names)?;
+        let fields = table.fields(table_name);
+        for (_, expr) in assignments {
+            if expr.has_window() {
+                return Err("Window functions aren't allowed in UPDATE".to_string());
+            }
+            eval::check_columns(expr, &fields).map_err(trim_period)?;
+        }
+        let positions = exec::matching_rows(table_name, table, condition).map_err(trim_period)?;
+        let mut changes = Vec::with_capacity(positions.len());
+        for position in positions {
+            let old = &table.rows[position].0;
+            let mut new = old.clone();
+            for (&column, (_, expr)) in columns.iter().zip(assignments) {
+                new[column] = eval::eval(expr, &fields, old).map_err(trim_period)?;
+            }
+            changes.push((position, table.build_row(new)?));
+        }
+        let count = changes.len();
+        self.update_rows(table_name, changes)?;
+        Ok(count)
+    }
+
+    /// DELETE: remove the rows `condition` holds for, or every row, along
+    /// with what ON DELETE does to the rows referencing them. Returns how
+    /// many rows of the table went.
+    pub fn delete_where(
+        &mut self,
+        table_name: &str,
+        condition: Option<&Expr>,
+    ) -> Result<usize, String> {
+        let table = self
+            .tables
+            .get(table_name)
+            .ok_or_else(|| format!("Table '{}' not found", table_name))?;
+        let positions = exec::matching_rows(table_name, table, condition).map_err(trim_period)?;
+        self.delete_rows(table_name, positions)
+    }
+
+    /// Append rows already checked with `build_row` to a table, once their
+    /// CHECK constraints and foreign keys are known to hold, running the
+    /// table's INSERT triggers. Either all of them go in or none do.
+    /// Returns them as they went in, numbered.
+    pub fn insert_rows(&mut self, table_name: &str, rows: Vec<Row>) -> Result<Vec<Row>, String> {
+        self.statement(|db| {
+            let triggered = db.tables[table_name]
+                .triggers
+                .iter()
+                .any(|t| t.event == TriggerEvent::Insert);
+            if !triggered {
+                return db.add_rows(table_name, rows);
+            }
+            // One at a time, as if each had an INSERT of its own, which is
+            // how followers get them
+            let mut added = Vec::with_capacity(rows.len());
+            for row in rows {
+                added.extend(db.add_rows(table_name, vec![row])?);
+            }
+            Ok(added)
+        })
     }
+
+    fn add_rows(&mut self, table_name: &str, rows: Vec<Row>) -> Result<Vec<Row>, String> {
+        let (rows, next_id) = self.tables[table_name].number_rows(rows)?;
+        let before = self.triggers(table_name, TriggerTiming::Before, TriggerEvent::Insert);
+        if !before.is_empty() {
+            // Rows the triggers add are numbered after these
+            self.table_mut(table_name).next_id = next_id;
+            self.fire(table_name, &before, &[], &rows)?;
+        }
+        self.check_constraints(table_name, &rows)?;
+        self.check_references(table_name, &rows, &HashSet::new())?;
+        self.table_mut(table_name).insert_rows(rows.clone())?;
+        let after = self.triggers(table_name, TriggerTiming::After, TriggerEvent::Insert);
+        self.fire(table_name, &after, &[], &rows)?;
+        Ok(rows)
+    }
+
+    /// As [`Table::update_rows`], once the new rows' CHECK constraints and
+    /// foreign keys are known to hold and no row elsewhere references a key
+    /// value that goes away, running the table's UPDATE triggers.
+    pub fn update_rows(
+        &mut self,
+        table_name: &str,
+        changes: Vec<(usize, Row)>,
+    ) -> Result<(), String> {
+        self.statement(|db| {
+            let before = db.triggers(table_name, TriggerTiming::Before, TriggerEvent::Update);
+            let after = db.triggers(table_name, TriggerTiming::After, TriggerEvent::Update);
+            if before.is_empty() && after.is_empty() {
+                return db.change_rows(table_name, changes);
+            }
+            let positions: Vec<usize> = changes.iter().map(|(p, _)| *p).collect();
+            let old = db.rows_at(table_name, &positions);
+            let new: Vec<Row> = changes.iter().map(|(_, row)| row.clone()).collect();
+            db.fire(table_name, &before, &old, &new)?;
+            db.check_unchanged(table_name, &positions, &old)?;
+            db.change_rows(table_name, changes)?;
+            db.fire(table_name, &after, &old, &new)
+        })
+    }
+
+    fn change_rows(&mut self, table_name: &str, changes: Vec<(usize, Row)>) -> Result<(), String> {
+        let rows: Vec<Row> = changes.iter().map(|(_, row)| row.clone()).collect();
+        let replacing: HashSet<usize> = changes.iter().map(|(p, _)| *p).collect();
+        self.check_constraints(table_name, &rows)?;
+        self.check_references(table_name, &rows, &replacing)?;
+        if self.references_checked {
+            let table = &self.tables[table_name];
+            let mut columns: Vec<usize> = self
+                .foreign_keys_to(table_name)
+                .iter()
+                .filter_map(|(_, _, key)| table.columns.iter().position(|c| c.name == key.column))
+                .collect();
+            columns.sort();
+            columns.dedup();
+            for column in columns {
+                let kept: HashSet<Key> = rows
+                    .iter()
+                    .filter_map(|row| Key::from_value(&row.0[column]))
+                    .collect();
+                for (position, _) in &changes {
+                    let old = &table.rows[*position].0[column];
+                    let Some(key) = Key::from_value(old).filter(|k| !kept.contains(k)) else {
+                        continue;
+                    };
+                    if let Some((name, referencing)) =
+                        self.referenced(table_name, column, &key, &rows, &replacing)
+                    {
+                        return Err(format!(
+                            "Foreign key '{}' is violated: '{}' still has rows with {} = {}",
+                            name, referencing, table.columns[column].name, old
+                        ));
+                    }
+                }
+            }
+        }
+        self.table_mut(table_name).update_rows(changes)
+    }
+
+    /// Delete the rows at `positions` of a table, along with the rows that
+    /// reference them ON DELETE CASCADE, and set references ON DELETE SET
+    /// NULL to NULL. Returns how many rows of the table itself went. Each
+    /// row runs its own table's triggers: DELETE ones for the rows that go,
+    /// UPDATE ones for those whose references are set to NULL.
+    pub fn delete_rows(
+        &mut self,
+        table_name: &str,
+        positions: Vec<usize>,
+    ) -> Result<usize, String> {
+        self.statement(|db| {
+            let before = db.triggers(table_name, TriggerTiming::Before, TriggerEvent::Delete);
+            let after = db.triggers(table_name, TriggerTiming::After, TriggerEvent::Delete);
+            if before.is_empty() && after.is_empty() {
+                return db.remove_rows(table_name, positions);
+            }
+            let old = db.rows_at(table_name, &positions);
+            db.fire(table_name, &before, &old, &[])?;
+            db.check_unchanged(table_name, &positions, &old)?;
+            let count = db.remove_rows(table_name, positions)?;
+            db.fire(table_name, &after, &old, &[])?;
+            Ok(count)
+        })
+    }
+
+    fn remove_rows(&mut self, table_name: &str, positions: Vec<usize>) -> Result<usize, String> {
+        let count = positions.len();
+        let mut asked = positions.clone();
+        asked.sort();
+        let removal = self.plan_removal(table_name, positions)?;
+
+        // The rows ON DELETE reaches run their tables' triggers as if a
+        // statement of their own had reached them; the table's own rows
+        // have had theirs run already
+        let triggered = |db: &Self, name: &str, event: TriggerEvent| {
+            db.tables[name].triggers.iter().any(|t| t.event == event)
+        };
+        let mut cascaded = Vec::new();
+        for (name, positions) in &removal.deleted {
+            if !triggered(self, name, TriggerEvent::Delete) {
+                continue;
+            }
+            let mut positions: Vec<usize> = positions
+                .iter()
+                .copied()
+                .filter(|p| name != table_name || asked.binary_search(p).is_err())
+                .collect();
+            if positions.is_empty() {
+                continue;
+            }
+            positions.sort();
+            cascaded.push(Cascaded {
+                old: self.rows_at(name, &positions),
+                table: name.clone(),
+                event: TriggerEvent::Delete,
+                positions,
+                new: Vec::new(),
+            });
+        }
+        for (name, rows) in &removal.changes {
+            if !triggered(self, name, TriggerEvent::Update) {
+                continue;
+            }
+            let positions: Vec<usize> = rows.iter().map(|(p, _)| *p).collect();
+            cascaded.push(Cascaded {
+                old: self.rows_at(name, &positions),
+                table: name.clone(),
+                event: TriggerEvent::Update,
+                positions,
+                new: rows.iter().map(|(_, row)| row.clone()).collect(),
+            });
+        }
+        if cascaded.is_empty() {
+            self.apply_removal(removal)?;
+            return Ok(count);
+        }
+        // In a stable order, as followers run them too
+        cascaded.sort_by_key(|c| (c.table.clone(), c.event == TriggerEvent::Update));
+
+        let old = self.rows_at(table_name, &asked);
+        for c in &cascaded {
+            let before = self.triggers(&c.table, TriggerTiming::Before, c.event);
+            self.fire(&c.table, &before, &c.old, &c.new)?;
+        }
+        self.check_unchanged(table_name, &asked, &old)?;
+        for c in &cascaded {
+            self.check_unchanged(&c.table, &c.positions, &c.old)?;
+        }
+        self.apply_removal(removal)?;
+        for c in &cascaded {
+            let after = self.triggers(&c.table, TriggerTiming::After, c.event);
+            self.fire(&c.table, &after, &c.old, &c.new)?;
+        }
+        Ok(count)
+    }
+
+    /// Work out what deleting the rows at `positions` of a table does
+    /// through ON DELETE, checking that all of it may be done.
+    fn plan_removal(&self, table_name: &str, positions: Vec<usize>) -> Result<Removal, String> {
+        let mut deleted: HashMap<String, HashSet<usize>> = HashMap::new();
+        deleted.insert(table_name.to_string(), positions.iter().copied().collect());
+        // (table, row, column) to set to NULL, and the references that
+        // forbid deleting the row they reference
+        let mut nulled: Vec<(String, usize, usize)> = Vec::new();
+        let mut restricted: Vec<(String, String, usize, Value)> = Vec::new();
+        let mut work: Vec<(String, Vec<usize>)> = vec![(table_name.to_string(), positions)];
+        while let Some((name, positions)) = work.pop() {
+            let table = &self.tables[&name];
+            for (referencing, column, key) in self.foreign_keys_to(&name) {
+                let Some(target) = table.columns.iter().position(|c| c.name == key.column) else {
+                    continue;
+                };
+                let other = &self.tables[&referencing];
+                let mut cascaded = Vec::new();
+                for &position in &positions {
+                    let value = &table.rows[position].0[target];
+                    let Some(k) = Key::from_value(value) else {
+                        continue;
+                    };
+                    for p in other.positions_with(column, &k) {
+                        if deleted.get(&referencing).is_some_and(|d| d.contains(&p)) {
+                            continue;
+                        }
+                        match key.on_delete {
+                            OnDelete::Restrict => restricted.push((
+                                referencing.clone(),
+                                foreign_key_name(&referencing, &other.columns[column].name),
+                                p,
+                                value.clone(),
+                            )),
+                            OnDelete::SetNull => nulled.push((referencing.clone(), p, column)),
+                            OnDelete::Cascade => cascaded.push(p),
+                        }
+                    }
+                }
+                if !cascaded.is_empty() {
+                    let set = deleted.entry(referencing.clone()).or_default();
+                    cascaded.retain(|p| set.insert(*p));
+                    work.push((referencing, cascaded));
+                }
+            }
+        }
+        if self.references_checked {
+            // A row that is going anyway may keep its reference
+            for (referencing, name, position, value) in &restricted {
+                if !deleted
+                    .get(referencing)
+                    .is_some_and(|d| d.contains(position))
+                {
+                    return Err(format!(
+                        "Foreign key '{}' is violated: '{}' still has rows referencing {}",
+                        name, referencing, value
+                    ));
+                }
+            }
+        }
+
+        // References set to NULL, in rows that stay
+        let mut changes: HashMap<String, Vec<(usize, Row)>> = HashMap::new();
+        for (name, position, column) in nulled {
+            if deleted.get(&name).is_some_and(|d| d.contains(&position)) {
+                continue;
+            }
+            let entry = changes.entry(name.clone()).or_default();
+            match entry.iter_mut().find(|(p, _)| *p == position) {
+                Some((_, row)) => row.0[column] = Value::Null,
+                None => {
+                    let mut row = self.tables[&name].rows[position].clone();
+                    row.0[column] = Value::Null;
+                    entry.push((position, row));
+                }
+            }
+        }
+        // Check every change before making any
+        for (name, rows) in &changes {
+            let table = &self.tables[name];
+            let replacing: HashSet<usize> = rows.iter().map(|(p, _)| *p).collect();
+            let rows: Vec<Row> = rows.iter().map(|(_, row)| row.clone()).collect();
+            table.check_keys(&rows, &replacing)?;
+            self.check_constraints(name, &rows)?;
+        }
+        Ok(Removal { deleted, changes })
+    }
+
+    fn apply_removal(&mut self, removal: Removal) -> Result<(), String> {
+        for (name, rows) in removal.changes {
+            self.table_mut(&name).update_rows(rows)?;
+        }
+        for (name, positions) in removal.deleted {
+            self.table_mut(&name).delete_rows(&positions);
+        }
+        Ok(())
+    }
+
+    /// Check that no row of `rows` makes a CHECK constraint of the table
+    /// false.
+    fn check_constraints(&self, table_name: &str, rows: &[Row]) -> Result<(), String> {
+        let table = &self.tables[table_name];
+        if table.checks.is_empty() {
+            return Ok(());
+        }
+        let fields = table.fields(table_name);
+        for row in rows {
+            for check in &table.checks {
+                let value = eval::eval(&check.expr, &fields, &row.0).map_err(trim_period)?;
+                if eval::truth("CHECK", &value).map_err(trim_period)? == Some(false) {
+                    return Err(format!(
+                        "Check constraint '{}' ({}) is violated",
+                        check.name, check.expr
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run one statement's changes with `f`. If it fails once triggers have
+    /// made changes of their own, those are undone too; anything else it
+    /// does is checked before any of it is done.
+    fn statement<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        if self.in_statement || self.tables.values().all(|t| t.triggers.is_empty()) {
+            return f(self);
+        }
+        let tables = self.tables.clone();
+        let sequences = self.sequences.clone();
+        self.in_statement = true;
+        let result = f(self);
+        self.in_statement = false;
+        if result.is_err() {
+            self.tables = tables;
+            self.sequences = sequences;
+        }
+        result
+    }
+
+    /// The triggers of `table_name` that run at `timing` for `event`.
+    fn triggers(
+        &self,
+        table_name: &str,
+        timing: TriggerTiming,
+        event: TriggerEvent,
+    ) -> Vec<Trigger> {
+        self.tables[table_name]
+            .triggers
+            .iter()
+            .filter(|t| t.timing == timing && t.event == event)
+            .cloned()
+            .collect()
+    }
+
+    /// Run `triggers` for each row of `table_name` changing from `old` to
+    /// `new`; for an INSERT there are no old rows, for a DELETE no new ones.
+    fn fire(
+        &mut self,
+        table_name: &str,
+        triggers: &[Trigger],
+        old: &[Row],
+        new: &[Row],
+    ) -> Result<(), String> {
+        let Some(first) = triggers.first() else {
+            return Ok(());
+        };
+        if self.trigger_depth == MAX_TRIGGER_DEPTH {
+            return Err(format!(
+                "Trigger '{}' would run {} triggers deep; triggers may be setting each other off",
+                first.name,
+                MAX_TRIGGER_DEPTH + 1
+            ));
+        }
+        let columns = self.tables[table_name].column_names();
+        self.trigger_depth += 1;
+        let mut result = Ok(());
+        'rows: for i in 0..old.len().max(new.len()) {
+            for trigger in triggers {
+                for text in &trigger.body {
+                    // A trigger the body set off names itself instead
+                    result =
+                        trigger::run(self, text, &columns, old.get(i), new.get(i)).map_err(|e| {
+                            if e.starts_with("Trigger '") {
+                                return e;
+                            }
+                            format!("Trigger '{}' failed: {}", trigger.name, trim_period(e))
+                        });
+                    if result.is_err() {
+                        break 'rows;
+                    }
+                }
+            }
+        }
+        self.trigger_depth -= 1;
+        result
+    }
+
+    fn rows_at(&self, table_name: &str, positions: &[usize]) -> Vec<Row> {
+        let rows = &self.tables[table_name].rows;
+        positions.iter().map(|&p| rows[p].clone()).collect()
+    }
+
+    /// Check that BEFORE triggers left the rows at `positions` as `rows`,
+    /// which the change about to be made was worked out from.
+    fn check_unchanged(
+        &self,
+        table_name: &str,
+        positions: &[usize],
+        rows: &[Row],
+    ) -> Result<(), String> {
+        let stored = &self.tables[table_name].rows;
+        let unchanged = positions
+            .iter()
+            .zip(rows)
+            .all(|(&p, row)| stored.get(p).is_some_and(|r| r.0 == row.0));
+        if unchanged {
+            Ok(())
+        } else {
+            Err(format!(
+                "A BEFORE trigger changed rows of '{}' that the statement was changing",
+                table_name
+            ))
+        }
+    }
+
+    /// Check that each non-NULL value `rows` hold in a foreign key column
+    /// of the table is one the table it references holds. A table that
+    /// references itself is taken as it will be with `rows` in place of
+    /// the rows at `replacing`.
+    fn check_references(
+        &self,
+        table_name: &str,
+        rows: &[Row],
+        replacing: &HashSet<usize>,
+    ) -> Result<(), String> {
+        if !self.references_checked {
+            return Ok(());
+        }
+        let table = &self.tables[table_name];
+        for (column, c) in table.columns.iter().enumerate() {
+            let Some(key) = &c.references else {
+                continue;
+            };
+            // A restore may have dropped the table
+            let target = self.tables.get(&key.table).and_then(|other| {
+                let target = other.columns.iter().position(|c| c.name == key.column)?;
+                Some((other, target))
+            });
+            for row in rows {
+                let value = &row.0[column];
+                let Some(k) = Key::from_value(value) else {
+                    continue;
+                };
+                let found = target.is_some_and(|(other, target)| {
+                    if key.table != table_name {
+                        return !other.positions_with(target, &k).is_empty();
+                    }
+                    other
+                        .positions_with(target, &k)
+                        .iter()
+                        .any(|p| !replacing.contains(p))
+                        || rows
+                            .iter()
+                            .any(|r| Key::from_value(&r.0[target]).as_ref() == Some(&k))
+                });
+                if !found {
+                    return Err(format!(
+                        "Foreign key '{}' is violated: '{}' has no row with {} = {}",
+                        foreign_key_name(table_name, &c.name),
+                        key.table,
+                        key.column,
+                        value
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The first foreign key found referencing `key` in the column at
+    /// `column` of `table_name`, once `rows` replace the rows at
+    /// `replacing`: its name and table.
+    fn referenced(
+        &self,
+        table_name: &str,
+        column: usize,
+        key: &Key,
+        rows: &[Row],
+        replacing: &HashSet<usize>,
+    ) -> Option<(String, String)> {
+        let table = &self.tables[table_name];
+        for (referencing, c, foreign_key) in self.foreign_keys_to(table_name) {
+            if foreign_key.column != table.columns[column].name {
+                continue;
+            }
+            let other = &self.tables[&referencing];
+            let found = if referencing == table_name {
+                other
+                    .positions_with(c, key)
+                    .iter()
+                    .any(|p| !replacing.contains(p))
+                    || rows
+                        .iter()
+                        .any(|r| Key::from_value(&r.0[c]).as_ref() == Some(key))
+            } else {
+                !other.positions_with(c, key).is_empty()
+            };
+            if found {
+                let name = foreign_key_name(&referencing, &other.columns[c].name);
+                return Some((name, referencing));
+            }
+        }
+        None
+    }
+
+    /// Every foreign key referencing a column of `table_name`: the table
+    /// and column it belongs to.
+    fn foreign_keys_to(&self, table_name: &str) -> Vec<(String, usize, ForeignKey)> {
+        let mut keys = Vec::new();
+        for name in self.table_names() {
+            for (column, c) in self.tables[&name].columns.iter().enumerate() {
+                if let Some(key) = c.references.as_ref().filter(|k| k.table == table_name) {
+                    keys.push((name.clone(), column, key.clone()));
+                }
+            }
+        }
+        keys
+    }
+
+    fn table_mut(&mut self, table_name: &str) -> &mut Table {
+        self.tables
+            .get_mut(table_name)
+            .expect("table checked above")
+    }
+
+    pub fn get_table(&self, table_name: &str) -> Option<&Table> {
+        self.tables.get(table_name)
+    }
+
+    pub fn get_table_mut(&mut self, table_name: &str) -> Option<&mut Table> {
+        self.tables.get_mut(table_name)
+    }
+}
+
+/// The columns ON CONFLICT DO UPDATE may read: the row already there's, then
+/// the one that wasn't added, as `excluded`.
+/// What deleting rows does, worked out before any of it is done.
+struct Removal {
+    /// Rows to delete by table: those asked for and those ON DELETE
+    /// CASCADE takes along.
+    deleted: HashMap<String, HashSet<usize>>,
+    /// Rows that stay, with the references ON DELETE SET NULL clears.
+    changes: HashMap<String, Vec<(usize, Row)>>,
+}
+
+/// Rows of one table that ON DELETE deletes or updates, as its triggers
+/// see them.
+struct Cascaded {
+    table: String,
+    event: TriggerEvent,
+    positions: Vec<usize>,
+    old: Vec<Row>,
+    new: Vec<Row>,
+}
+
+fn conflict_fields(table_name: &str, table: &Table) -> Vec<Field> {
+    let mut fields = table.fields(table_name);
+    fields.extend(table.fields("excluded"));
+    fields
+}
+
+/// An expression error without its full stop, to go inside another message.
+fn trim_period(e: String) -> String {
+    e.trim_end_matches('.').to_string()
 }
@@ -0,0 +1,74 @@
+use std::{io, path::Path, sync::Arc};
+
+use rustls::{
+    ClientConfig, RootCertStore, ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+
+/// Build a TLS server configuration from PEM files holding the certificate
+/// chain (leaf first) and its private key. Self-signed certificates work as
+/// long as clients are told to trust them.
+pub fn server_config(cert_path: &Path, key_path: &Path) -> io::Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| {
+            invalid(format!(
+                "Could not read certificates from '{}': {}",
+                cert_path.display(),
+                e
+            ))
+        })?;
+    if certs.is_empty() {
+        return Err(invalid(format!(
+            "No certificates found in '{}'",
+            cert_path.display()
+        )));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| {
+        invalid(format!(
+            "Could not read private key from '{}': {}",
+            key_path.display(),
+            e
+        ))
+    })?;
+
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| invalid(format!("Invalid certificate or key: {}", e)))?;
+    Ok(Arc::new(config))
+}
+
+/// Build a TLS client configuration trusting the certificates in the PEM
+/// file at `ca_path`: the server's own, if self-signed, or its CA's.
+pub fn client_config(ca_path: &Path) -> io::Result<Arc<ClientConfig>> {
+    let unreadable = |e: &dyn std::fmt::Display| {
+        invalid(format!(
+            "Could not read certificates from '{}': {}",
+            ca_path.display(),
+            e
+        ))
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca_path).map_err(|e| unreadable(&e))? {
+        roots
+            .add(cert.map_err(|e| unreadable(&e))?)
+            .map_err(|e| unreadable(&e))?;
+    }
+    if roots.is_empty() {
+        return Err(invalid(format!(
+            "No certificates found in '{}'",
+            ca_path.display()
+        )));
+    }
+
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,149 @@
+//! The statements in a trigger's body, and running them for a row.
+//!
+//! A body is kept as text, as written. Each time the trigger runs, every
+//! `NEW.col` and `OLD.col` in it is replaced by the literal value of the
+//! row it runs for before the statement is parsed, so that the statements
+//! are the plain INSERT, UPDATE and DELETE a follower could be sent.
+
+use crate::{
+    dump,
+    lexer::{self, Token, TokenKind},
+    parser::{ConflictAction, InsertValue, Statement},
+    storage::{Database, Row},
+};
+
+/// Which row of a change `NEW.col` or `OLD.col` reads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    New,
+    Old,
+}
+
+/// Parse one statement of a trigger's body, with each `NEW.col` and
+/// `OLD.col` replaced by the SQL `value` gives it. Only INSERT (without
+/// RETURNING), UPDATE and DELETE may appear in a body.
+pub fn parse(
+    text: &str,
+    mut value: impl FnMut(Side, &str) -> Result<String, String>,
+) -> Result<Statement, String> {
+    let tokens = lexer::tokenize(text)?;
+    let mut bound: Vec<Token> = Vec::with_capacity(tokens.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let side = match &tokens[i].kind {
+            TokenKind::Identifier(word) if word.eq_ignore_ascii_case("new") => Some(Side::New),
+            TokenKind::Identifier(word) if word.eq_ignore_ascii_case("old") => Some(Side::Old),
+            _ => None,
+        };
+        let column = match tokens.get(i + 1..i + 3) {
+            Some([dot, name]) if dot.kind == TokenKind::Symbol(".") => match &name.kind {
+                TokenKind::Identifier(name) | TokenKind::QuotedIdentifier(name) => Some(name),
+                _ => None,
+            },
+            _ => None,
+        };
+        match side.zip(column) {
+            Some((side, column)) => {
+                let text = value(side, column)?;
+                bound.extend(lexer::tokenize(&text)?);
+                i += 3;
+            }
+            None => {
+                bound.push(tokens[i].clone());
+                i += 1;
+            }
+        }
+    }
+    let statement = Statement::parse(&lexer::join_tokens(&bound))?;
+    if calls_now(&statement) {
+        // Followers run triggers for themselves, and would get another time
+        return Err("a trigger can't call NOW()".to_string());
+    }
+    match statement {
+        Statement::Insert {
+            returning: Some(_), ..
+        } => Err("a trigger has nowhere to send what RETURNING returns".to_string()),
+        Statement::Insert { .. } | Statement::Update { .. } | Statement::Delete { .. } => {
+            Ok(statement)
+        }
+        _ => Err(format!(
+            "a trigger may only run INSERT, UPDATE and DELETE, not {}",
+            statement.kind()
+        )),
+    }
+}
+
+/// Whether an INSERT, UPDATE or DELETE calls NOW() anywhere.
+fn calls_now(statement: &Statement) -> bool {
+    let condition = match statement {
+        Statement::Update { condition, .. } | Statement::Delete { condition, .. } => {
+            condition.as_ref()
+        }
+        _ => None,
+    };
+    if condition.is_some_and(|c| c.calls("NOW")) {
+        return true;
+    }
+    let assignments = match statement {
+        Statement::Insert {
+            values,
+            on_conflict,
+            ..
+        } => {
+            if values.contains(&InsertValue::Now) {
+                return true;
+            }
+            match on_conflict.as_ref().map(|c| &c.action) {
+                Some(ConflictAction::Update(assignments)) => assignments.as_slice(),
+                _ => &[],
+            }
+        }
+        Statement::Update { assignments, .. } => assignments.as_slice(),
+        _ => &[],
+    };
+    assignments.iter().any(|(_, expr)| expr.calls("NOW"))
+}
+
+/// Run one statement of a trigger's body for a row of a table with
+/// `columns` changing from `old` to `new`.
+pub fn run(
+    db: &mut Database,
+    text: &str,
+    columns: &[String],
+    old: Option<&Row>,
+    new: Option<&Row>,
+) -> Result<(), String> {
+    let statement = parse(text, |side, column| {
+        let row = match side {
+            Side::New => new.ok_or("there is no NEW row here")?,
+            Side::Old => old.ok_or("there is no OLD row here")?,
+        };
+        let position = columns
+            .iter()
+            .position(|c| c == column)
+            .ok_or_else(|| format!("Column '{}' not found", column))?;
+        Ok(dump::sql_literal(&row.get_inner_vec()[position]))
+    })?;
+    match statement {
+        Statement::Insert {
+            table,
+            columns,
+            values,
+            on_conflict,
+            ..
+        } => db
+            .insert_into_table(&table, columns.as_deref(), values, on_conflict.as_ref())
+            .map(|_| ()),
+        Statement::Update {
+            table,
+            assignments,
+            condition,
+        } => db
+            .update_where(&table, &assignments, condition.as_ref())
+            .map(|_| ()),
+        Statement::Delete { table, condition } => {
+            db.delete_where(&table, condition.as_ref()).map(|_| ())
+        }
+        _ => unreachable!("parse only lets data changes through"),
+    }
+}
@@ -0,0 +1,333 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{
+    eval::{self, Field},
+    parser::{Expr, FrameBound, OrderBy},
+    storage::{DataType, Key, Value},
+};
+
+/// Functions that can be called with OVER: name, minimum and maximum
+/// argument count. `COUNT(*)` counts as one argument.
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("ROW_NUMBER", 0, 0),
+    ("RANK", 0, 0),
+    ("DENSE_RANK", 0, 0),
+    ("LAG", 1, 3),
+    ("LEAD", 1, 3),
+    ("COUNT", 1, 1),
+    ("SUM", 1, 1),
+    ("AVG", 1, 1),
+    ("MIN", 1, 1),
+    ("MAX", 1, 1),
+];
+
+/// Whether `name` is a window function.
+pub fn is_function(name: &str) -> bool {
+    FUNCTIONS.iter().any(|(n, _, _)| *n == name)
+}
+
+/// Check that `name` is a window function and accepts `argc` arguments.
+pub fn check_function(name: &str, argc: usize) -> Result<(), String> {
+    eval::check_arity(FUNCTIONS, name, argc)
+        .unwrap_or_else(|| Err(format!("{} is not a window function", name)))
+}
+
+/// The type window function `name` returns given the types of its
+/// arguments, where that can be told without running it.
+pub fn data_type(name: &str, args: &[Option<DataType>]) -> Option<DataType> {
+    match name {
+        "ROW_NUMBER" | "RANK" | "DENSE_RANK" | "COUNT" => Some(DataType::Integer),
+        "AVG" => Some(DataType::Real),
+        "SUM" => args[0].filter(|t| t.is_numeric()),
+        "MIN" | "MAX" => args[0],
+        // LAG and LEAD return their default where there's no row to read
+        _ => match args.get(2) {
+            Some(default) if *default != args[0] => None,
+            _ => args[0],
+        },
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Evaluation
+// ---------------------------------------------------------------------------
+
+/// The value of `call`, an [`Expr::Window`], for each of `rows` (whose
+/// values line up with `fields`), in the same order.
+///
+/// The rows are split into partitions by the PARTITION BY values, NULLs
+/// counting as equal, and each partition is sorted by ORDER BY, keeping
+/// the input order between rows that sort equal ("peers"). Ranks and LAG
+/// and LEAD are counted within the partition; aggregates are taken over
+/// each row's frame.
+pub fn compute(call: &Expr, fields: &[Field], rows: &[Vec<Value>]) -> Result<Vec<Value>, String> {
+    let Expr::Window { name, args, over } = call else {
+        unreachable!("not a window function call");
+    };
+    let eval_all = |exprs: &mut dyn Iterator<Item = &Expr>, row: &[Value]| {
+        exprs
+            .map(|e| eval::eval(e, fields, row))
+            .collect::<Result<Vec<_>, _>>()
+    };
+
+    let mut partitions: Vec<Vec<usize>> = Vec::new();
+    let mut partition_of: HashMap<Vec<Option<Key>>, usize> = HashMap::new();
+    let mut sort_keys = Vec::with_capacity(rows.len());
+    let mut arg_values = Vec::with_capacity(rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        let key = eval_all(&mut over.partition_by.iter(), row)?
+            .iter()
+            .map(Key::from_value)
+            .collect();
+        let next = partitions.len();
+        let p = *partition_of.entry(key).or_insert(next);
+        if p == next {
+            partitions.push(Vec::new());
+        }
+        partitions[p].push(i);
+        sort_keys.push(eval_all(&mut over.order_by.iter().map(|o| &o.expr), row)?);
+        arg_values.push(eval_all(&mut args.iter(), row)?);
+    }
+
+    let mut results = vec![Value::Null; rows.len()];
+    for mut partition in partitions {
+        partition.sort_by(|&a, &b| compare_keys(&over.order_by, &sort_keys[a], &sort_keys[b]));
+        let peers = |a: usize, b: usize| {
+            compare_keys(
+                &over.order_by,
+                &sort_keys[partition[a]],
+                &sort_keys[partition[b]],
+            )
+            .is_eq()
+        };
+        let n = partition.len();
+        let values: Vec<Value> = match name.as_str() {
+            "ROW_NUMBER" => (1..=n as i64).map(Value::Integer).collect(),
+            "RANK" | "DENSE_RANK" => {
+                let mut values = Vec::with_capacity(n);
+                let (mut rank, mut dense_rank) = (1, 1);
+                for p in 0..n {
+                    if p > 0 && !peers(p - 1, p) {
+                        rank = p as i64 + 1;
+                        dense_rank += 1;
+                    }
+                    values.push(Value::Integer(if name == "RANK" {
+                        rank
+                    } else {
+                        dense_rank
+                    }));
+                }
+                values
+            }
+            "LAG" | "LEAD" => (0..n)
+                .map(|p| {
+                    let args = &arg_values[partition[p]];
+                    let offset = match args.get(1) {
+                        Some(v) => match v.to_number() {
+                            Some(Value::Integer(i)) if i >= 0 => i as usize,
+                            _ => {
+                                return Err(format!(
+                                    "{}: the offset must be a non-negative integer, got '{}'",
+                                    name, v
+                                ));
+                            }
+                        },
+                        None => 1,
+                    };
+                    let target = if name == "LAG" {
+                        p.checked_sub(offset)
+                    } else {
+                        p.checked_add(offset).filter(|&t| t < n)
+                    };
+                    Ok(match target {
+                        Some(t) => arg_values[partition[t]][0].clone(),
+                        None => args.get(2).cloned().unwrap_or(Value::Null),
+                    })
+                })
+                .collect::<Result<_, String>>()?,
+            _ => {
+                // Where each row's peers end
+                let mut peer_end = vec![n; n];
+                for p in (0..n.saturating_sub(1)).rev() {
+                    peer_end[p] = if peers(p, p + 1) {
+                        peer_end[p + 1]
+                    } else {
+                        p + 1
+                    };
+                }
+                // Each row's frame, as a range of positions in the partition
+                let frames: Vec<(usize, usize)> = (0..n)
+                    .map(|p| match &over.frame {
+                        Some((start, end)) => {
+                            let start = match *start {
+                                FrameBound::UnboundedPreceding => 0,
+                                FrameBound::Preceding(k) => p.saturating_sub(k),
+                                FrameBound::CurrentRow => p,
+                                FrameBound::Following(k) => p.saturating_add(k).min(n),
+                                FrameBound::UnboundedFollowing => n,
+                            };
+                            let end = match *end {
+                                FrameBound::UnboundedPreceding => 0,
+                                FrameBound::Preceding(k) => (p + 1).saturating_sub(k),
+                                FrameBound::CurrentRow => p + 1,
+                                FrameBound::Following(k) => {
+                                    p.saturating_add(k).saturating_add(1).min(n)
+                                }
+                                FrameBound::UnboundedFollowing => n,
+                            };
+                            (start, end.max(start))
+                        }
+                        None if over.order_by.is_empty() => (0, n),
+                        None => (0, peer_end[p]),
+                    })
+                    .collect();
+                aggregate(name, &partition, &arg_values, &frames)?
+            }
+        };
+        for (p, value) in values.into_iter().enumerate() {
+            results[partition[p]] = value;
+        }
+    }
+    Ok(results)
+}
+
+/// Compare two rows' ORDER BY values. NULLs sort first, or last with DESC.
+fn compare_keys(order_by: &[OrderBy], a: &[Value], b: &[Value]) -> Ordering {
+    for (o, (a, b)) in order_by.iter().zip(a.iter().zip(b)) {
+        let ordering = a.compare(b);
+        let ordering = if o.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        if ordering.is_ne() {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// Aggregate `name` over each frame of a sorted partition. Frames that all
+/// start at the first row, or all end at the last, are added up as they
+/// grow; others are each added up from scratch.
+fn aggregate(
+    name: &str,
+    partition: &[usize],
+    arg_values: &[Vec<Value>],
+    frames: &[(usize, usize)],
+) -> Result<Vec<Value>, String> {
+    let n = partition.len();
+    let arg = |p: usize| arg_values[partition[p]].first();
+    let mut values = vec![Value::Null; n];
+    if frames.iter().all(|&(start, _)| start == 0) {
+        let mut acc = Accumulator::new(name);
+        let mut added = 0;
+        for (p, &(_, end)) in frames.iter().enumerate() {
+            for q in added..end {
+                acc.add(arg(q))?;
+            }
+            added = added.max(end);
+            values[p] = acc.value();
+        }
+    } else if frames.iter().all(|&(_, end)| end == n) {
+        let mut acc = Accumulator::new(name);
+        let mut added = n;
+        for (p, &(start, _)) in frames.iter().enumerate().rev() {
+            for q in (start..added).rev() {
+                acc.add(arg(q))?;
+            }
+            added = added.min(start);
+            values[p] = acc.value();
+        }
+    } else {
+        for (p, &(start, end)) in frames.iter().enumerate() {
+            let mut acc = Accumulator::new(name);
+            for q in start..end {
+                acc.add(arg(q))?;
+            }
+            values[p] = acc.value();
+        }
+    }
+    Ok(values)
+}
+
+/// A running COUNT, SUM, AVG, MIN or MAX. NULLs are skipped, except by
+/// `COUNT(*)`, whose rows come without an argument.
+struct Accumulator<'a> {
+    name: &'a str,
+    count: i64,
+    /// Integers are added up exactly until a real comes along.
+    sum: Option<Value>,
+    extreme: Option<Value>,
+}
+
+impl<'a> Accumulator<'a> {
+    fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            count: 0,
+            sum: None,
+            extreme: None,
+        }
+    }
+
+    fn add(&mut self, arg: Option<&Value>) -> Result<(), String> {
+        let Some(v) = arg else {
+            self.count += 1;
+            return Ok(());
+        };
+        if v.is_null() {
+            return Ok(());
+        }
+        self.count += 1;
+        match self.name {
+            "SUM" | "AVG" => {
+                let Some(n) = v.to_number() else {
+                    return Err(format!("{}: expected a number, got '{}'", self.name, v));
+                };
+                self.sum = Some(match (self.sum.take(), n) {
+                    (None, n) => n,
+                    (Some(Value::Integer(a)), Value::Integer(b)) => match a.checked_add(b) {
+                        Some(sum) => Value::Integer(sum),
+                        // AVG has no need for an exact sum
+                        None if self.name == "AVG" => Value::Real(a as f64 + b as f64),
+                        None => return Err("Integer overflow".to_string()),
+                    },
+                    (Some(a), b) => Value::Real(as_f64(&a) + as_f64(&b)),
+                });
+            }
+            "MIN" | "MAX" => {
+                let replace = match &self.extreme {
+                    None => true,
+                    Some(e) if self.name == "MIN" => v.compare(e).is_lt(),
+                    Some(e) => v.compare(e).is_gt(),
+                };
+                if replace {
+                    self.extreme = Some(v.clone());
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn value(&self) -> Value {
+        match self.name {
+            "COUNT" => Value::Integer(self.count),
+            "SUM" => self.sum.clone().unwrap_or(Value::Null),
+            "AVG" => match &self.sum {
+                Some(sum) => Value::Real(as_f64(sum) / self.count as f64),
+                None => Value::Null,
+            },
+            _ => self.extreme.clone().unwrap_or(Value::Null),
+        }
+    }
+}
+
+fn as_f64(v: &Value) -> f64 {
+    match v {
+        Value::Integer(i) => *i as f64,
+        Value::Real(r) => *r,
+        _ => 0.0,
+    }
+}
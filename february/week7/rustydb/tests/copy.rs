@@ -0,0 +1,152 @@
+//! COPY to and from CSV files.
+
+use std::fs;
+use std::path::PathBuf;
+
+use rustydb::{Connection, Database, Value};
+
+fn connect() -> Connection {
+    let mut conn = Database::new().connect();
+    conn.execute_batch(
+        "CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT, score REAL);
+         CREATE TABLE u (id INTEGER PRIMARY KEY, name TEXT, score REAL);",
+    )
+    .unwrap();
+    conn
+}
+
+/// A file of its own for each test, removed when it is done with.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(test: &str) -> Self {
+        let name = format!("rustydb-copy-{}-{}.csv", std::process::id(), test);
+        Scratch(std::env::temp_dir().join(name))
+    }
+
+    fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    fn write(&self, data: &str) {
+        fs::write(&self.0, data).unwrap();
+    }
+
+    fn read(&self) -> String {
+        fs::read_to_string(&self.0).unwrap()
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Every row of `table`, written out.
+fn rows(conn: &mut Connection, table: &str) -> Vec<Vec<String>> {
+    conn.query(&format!("SELECT * FROM {} ORDER BY id", table))
+        .unwrap()
+        .into_iter()
+        .map(|row| {
+            (0..3)
+                .map(|i| row.get::<Value>(i).unwrap().to_string())
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn reads_back_what_it_wrote() {
+    let file = Scratch::new("round-trip");
+    let mut conn = connect();
+    conn.execute_batch(
+        "INSERT INTO t VALUES (1, 'plain', 1.5);
+         INSERT INTO t VALUES (2, 'a, b', NULL);
+         INSERT INTO t VALUES (3, 'say \"hi\"', 2);
+         INSERT INTO t VALUES (4, '', -0.25);",
+    )
+    .unwrap();
+    conn.execute("INSERT INTO t VALUES (5, 'two\nlines', 0)")
+        .unwrap();
+
+    conn.execute(&format!("COPY t TO '{}' WITH CSV HEADER", file.path()))
+        .unwrap();
+    let copied = conn
+        .execute(&format!("COPY u FROM '{}' WITH CSV HEADER", file.path()))
+        .unwrap();
+    assert_eq!(copied, 5);
+    assert_eq!(rows(&mut conn, "u"), rows(&mut conn, "t"));
+}
+
+#[test]
+fn quotes_fields_that_would_be_misread() {
+    let file = Scratch::new("quoting");
+    let mut conn = connect();
+    conn.execute_batch(
+        "INSERT INTO t VALUES (1, 'a, b', NULL);
+         INSERT INTO t VALUES (2, 'say \"hi\"', 1);
+         INSERT INTO t VALUES (3, '', 2);",
+    )
+    .unwrap();
+    conn.execute(&format!("COPY t (id, name, score) TO '{}'", file.path()))
+        .unwrap();
+    assert_eq!(
+        file.read(),
+        "1,\"a, b\",\n2,\"say \"\"hi\"\"\",1.0\n3,\"\",2.0\n"
+    );
+}
+
+#[test]
+fn takes_a_delimiter_null_marker_and_column_list() {
+    let file = Scratch::new("options");
+    file.write("name;id\nAda;1\nNULL;2\n\n\"x;y\";3\n");
+    let mut conn = connect();
+    conn.execute(&format!(
+        "COPY t (name, id) FROM '{}' WITH (FORMAT csv, HEADER true, DELIMITER ';', NULL 'NULL')",
+        file.path()
+    ))
+    .unwrap();
+    assert_eq!(
+        rows(&mut conn, "t"),
+        [
+            ["1", "Ada", "NULL"],
+            ["2", "NULL", "NULL"],
+            ["3", "x;y", "NULL"],
+        ]
+    );
+}
+
+#[test]
+fn reports_the_line_of_a_bad_record_and_copies_nothing() {
+    let file = Scratch::new("errors");
+    let mut conn = connect();
+
+    file.write("1,ok,1\n2,\"spans\nlines\",2\n3,short\n");
+    let err = conn
+        .execute(&format!("COPY t FROM '{}'", file.path()))
+        .unwrap_err();
+    assert_eq!(err, "COPY t failed: line 4: expected 3 fields, got 2.");
+
+    file.write("1,ok,1\n2,\"open,2\n");
+    let err = conn
+        .execute(&format!("COPY t FROM '{}'", file.path()))
+        .unwrap_err();
+    assert_eq!(err, "COPY t failed: line 2: unterminated quoted field.");
+
+    file.write("1,ok,1\n2,\"x\"y,2\n");
+    let err = conn
+        .execute(&format!("COPY t FROM '{}'", file.path()))
+        .unwrap_err();
+    assert_eq!(
+        err,
+        "COPY t failed: line 2: unexpected 'y' after closing quote."
+    );
+
+    file.write("1,ok,1\n1,again,2\n");
+    assert!(
+        conn.execute(&format!("COPY t FROM '{}'", file.path()))
+            .is_err()
+    );
+    assert!(rows(&mut conn, "t").is_empty());
+}
@@ -0,0 +1,48 @@
+//! Dates and times as queries see them. The arithmetic itself is tested
+//! next to it, in `datetime.rs`.
+
+use rustydb::{Connection, Database, Value};
+
+/// A table of `n` rows, to evaluate expressions over.
+fn rows(n: usize) -> Connection {
+    let mut conn = Database::new().connect();
+    conn.execute("CREATE TABLE t (a INTEGER)").unwrap();
+    for a in 0..n {
+        conn.execute(&format!("INSERT INTO t VALUES ({})", a))
+            .unwrap();
+    }
+    conn
+}
+
+/// The first column of every row, written out.
+fn column(conn: &mut Connection, sql: &str) -> Vec<String> {
+    conn.query(sql)
+        .unwrap()
+        .into_iter()
+        .map(|row| row.get::<Value>(0).unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn now_is_the_same_throughout_a_select() {
+    let mut conn = rows(200);
+    let spans = column(&mut conn, "SELECT NOW() - NOW() FROM t");
+    assert!(spans.iter().all(|s| s == "00:00:00"), "{:?}", spans);
+
+    let mut times = column(
+        &mut conn,
+        "SELECT NOW() FROM t UNION ALL SELECT NOW() FROM t ORDER BY 1",
+    );
+    times.dedup();
+    assert_eq!(times.len(), 1, "{:?}", times);
+}
+
+#[test]
+fn reads_timestamps_with_offsets_as_utc() {
+    let mut conn = rows(1);
+    let times = column(
+        &mut conn,
+        "SELECT TIMESTAMP '2024-01-31T13:45:00+02:00' FROM t",
+    );
+    assert_eq!(times, ["2024-01-31 11:45:00"]);
+}
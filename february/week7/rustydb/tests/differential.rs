@@ -0,0 +1,1404 @@
+//! Randomised tests of the parser and executor.
+//!
+//! `matches_sqlite` makes up a few tables, some with CHECK constraints and
+//! triggers, fills them with random rows, sometimes adds indexes,
+//! statistics and views, then runs random queries
+//! against rustydb (through the library API) and against an in-memory
+//! SQLite database given the same statements. The two must agree on every
+//! result, compared as multisets of rows since neither promises an order,
+//! and on whether each statement fails. The generator sticks to SQL both
+//! read the same way; `Gen` lists what it leaves out and why.
+//!
+//! `parser_never_panics` throws token soup and mangled queries at the
+//! parser: anything is fine except a panic.
+//!
+//! Every case is reproducible from its seed. Run more of them, or others:
+//!
+//! ```text
+//! RUSTYDB_FUZZ_CASES=5000 RUSTYDB_FUZZ_SEED=42 cargo test --test differential
+//! ```
+
+use std::{cmp::Ordering, env, fmt::Write};
+
+use rusqlite::types::ValueRef;
+use rustydb::{Database, Value, quote_literal};
+
+/// Cases run by each test unless RUSTYDB_FUZZ_CASES says otherwise.
+const DEFAULT_CASES: u64 = 200;
+
+/// Queries run against each generated database.
+const QUERIES_PER_CASE: usize = 25;
+
+/// Mismatches shown in full when the test fails.
+const MAX_REPORTED: usize = 5;
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    match env::var(name) {
+        Ok(v) => v
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number, not '{}'", name, v)),
+        Err(_) => default,
+    }
+}
+
+/// The seeds to run, one per case.
+fn seeds() -> impl Iterator<Item = u64> {
+    let first = env_u64("RUSTYDB_FUZZ_SEED", 1);
+    let cases = env_u64("RUSTYDB_FUZZ_CASES", DEFAULT_CASES);
+    first..first + cases
+}
+
+// ---------------------------------------------------------------------------
+// Differential test
+// ---------------------------------------------------------------------------
+
+#[test]
+fn matches_sqlite() {
+    let mut mismatches = Vec::new();
+    let mut total = 0;
+    for seed in seeds() {
+        let found = run_case(seed);
+        total += found.len();
+        let room = MAX_REPORTED.saturating_sub(mismatches.len());
+        mismatches.extend(found.into_iter().take(room));
+    }
+    if total > 0 {
+        panic!(
+            "{} statement(s) disagreed with SQLite; the first {}:\n\n{}",
+            total,
+            mismatches.len(),
+            mismatches.join("\n\n")
+        );
+    }
+}
+
+/// Build one random database in both engines and compare their answers.
+/// Returns a report for each statement they disagreed on.
+fn run_case(seed: u64) -> Vec<String> {
+    let mut generator = Gen::new(seed);
+    let setup = generator.setup();
+    let db = Database::new();
+    let mut ours = db.connect();
+    let theirs = rusqlite::Connection::open_in_memory().expect("open SQLite");
+    theirs
+        .execute_batch("PRAGMA foreign_keys = ON")
+        .expect("enable foreign keys");
+
+    let mut mismatches = Vec::new();
+    let mut report = |statement: &str, ours: String, theirs: String| {
+        mismatches.push(format!(
+            "seed {}, after:\n{}\n\n{}\n  rustydb: {}\n  sqlite:  {}",
+            seed,
+            setup.join(";\n"),
+            statement,
+            ours,
+            theirs
+        ));
+    };
+
+    for statement in &setup {
+        let a = ours.execute(statement).map(|_| ());
+        let b = theirs.execute_batch(statement).map_err(|e| e.to_string());
+        if a.is_ok() != b.is_ok() {
+            report(statement, format!("{:?}", a), format!("{:?}", b));
+            return mismatches; // the databases differ from here on
+        }
+    }
+
+    // A dump restored elsewhere must answer the same way
+    let restored = Database::restore(&db.dump());
+    let mut restored = match restored {
+        Ok(restored) => restored.connect(),
+        Err(e) => {
+            report("(dump and restore)", e, "ok".to_string());
+            return mismatches;
+        }
+    };
+
+    for _ in 0..QUERIES_PER_CASE {
+        let mut query = generator.query();
+        // Sorted by every column, the rows can only come in one order
+        let width = ours.query(&query).map_or(0, |rows| rows.columns().len());
+        let order_by = generator.order_by(width);
+        if let Some(order_by) = &order_by {
+            query = format!("{} ORDER BY {}", query, order_by);
+        }
+        let arrange = |rows| {
+            if order_by.is_some() {
+                rounded(rows)
+            } else {
+                canonical(rows)
+            }
+        };
+        let a = ours.query(&query).map(|rows| {
+            rows.iter()
+                .map(|row| row.values().to_vec())
+                .collect::<Vec<_>>()
+        });
+        let b = sqlite_query(&theirs, &query);
+        match (a, b) {
+            (Ok(a), Ok(b)) => {
+                let (a, b) = (arrange(a), arrange(b));
+                if a != b {
+                    report(&query, show(&a), show(&b));
+                }
+            }
+            (Err(_), Err(_)) => {}
+            (a, b) => report(
+                &query,
+                a.map_or_else(|e| format!("error: {}", e), |r| show(&canonical(r))),
+                b.map_or_else(|e| format!("error: {}", e), |r| show(&canonical(r))),
+            ),
+        }
+
+        let again = restored
+            .query(&query)
+            .map(|rows| canonical(rows.iter().map(|row| row.values().to_vec()).collect()));
+        let first = ours
+            .query(&query)
+            .map(|rows| canonical(rows.iter().map(|row| row.values().to_vec()).collect()));
+        if again != first {
+            report(
+                &format!("{} (on the restored dump)", query),
+                format!("{:?}", again),
+                format!("{:?} before the dump", first),
+            );
+        }
+    }
+    mismatches
+}
+
+fn sqlite_query(conn: &rusqlite::Connection, sql: &str) -> Result<Vec<Vec<Value>>, String> {
+    let mut statement = conn.prepare(sql).map_err(|e| e.to_string())?;
+    let columns = statement.column_count();
+    let mut rows = statement.query([]).map_err(|e| e.to_string())?;
+    let mut result = Vec::new();
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let values = (0..columns)
+            .map(|i| match row.get_ref(i).expect("column in range") {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(i) => Value::Integer(i),
+                ValueRef::Real(r) => Value::Real(r),
+                ValueRef::Text(t) => Value::Text(String::from_utf8_lossy(t).into_owned()),
+                ValueRef::Blob(_) => panic!("the generator never makes blobs"),
+            })
+            .collect();
+        result.push(values);
+    }
+    Ok(result)
+}
+
+/// Rows in a fixed order, with reals rounded off so that both engines'
+/// floating-point arithmetic compares equal.
+fn canonical(rows: Vec<Vec<Value>>) -> Vec<Vec<Value>> {
+    let mut rows = rounded(rows);
+    rows.sort_by(|a, b| {
+        a.iter()
+            .zip(b)
+            .map(|(x, y)| compare(x, y))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    rows
+}
+
+/// Rows in the order they came, with reals rounded off as for
+/// `canonical`.
+fn rounded(rows: Vec<Vec<Value>>) -> Vec<Vec<Value>> {
+    rows.into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|v| match v {
+                    // Adding zero also turns -0.0 into 0.0
+                    Value::Real(r) => Value::Real((r * 1e9).round() / 1e9 + 0.0),
+                    // Dates are text to SQLite
+                    Value::Date(_) => Value::Text(v.to_string()),
+                    v => v,
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn compare(a: &Value, b: &Value) -> Ordering {
+    let rank = |v: &Value| match v {
+        Value::Null => 0,
+        Value::Integer(_) => 1,
+        Value::Real(_) => 2,
+        _ => 3,
+    };
+    match (a, b) {
+        (Value::Integer(x), Value::Integer(y)) => x.cmp(y),
+        (Value::Real(x), Value::Real(y)) => x.total_cmp(y),
+        (Value::Text(x), Value::Text(y)) => x.cmp(y),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn show(rows: &[Vec<Value>]) -> String {
+    let mut out = format!("{} row(s)", rows.len());
+    for row in rows.iter().take(20) {
+        let _ = write!(out, "\n    {:?}", row);
+    }
+    if rows.len() > 20 {
+        out.push_str("\n    ...");
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Parser robustness
+// ---------------------------------------------------------------------------
+
+/// Words and symbols the garbage is made of: enough real SQL that some of
+/// it gets deep into the parser.
+const TOKENS: &[&str] = &[
+    "SELECT",
+    "FROM",
+    "WHERE",
+    "JOIN",
+    "INNER",
+    "ON",
+    "AND",
+    "AS",
+    "DISTINCT",
+    "WITH",
+    "RECURSIVE",
+    "OVER",
+    "PARTITION",
+    "BY",
+    "ORDER",
+    "ROWS",
+    "PRECEDING",
+    "ROW_NUMBER",
+    "UNION",
+    "ALL",
+    "INTERSECT",
+    "EXCEPT",
+    "INSERT",
+    "INTO",
+    "VALUES",
+    "DEFAULT",
+    "RETURNING",
+    "CONFLICT",
+    "DO",
+    "NOTHING",
+    "UPDATE",
+    "excluded",
+    "DELETE",
+    "CHECK",
+    "CONSTRAINT",
+    "TRIGGER",
+    "BEFORE",
+    "AFTER",
+    "FOR",
+    "EACH",
+    "ROW",
+    "BEGIN",
+    "END",
+    "NEW",
+    "OLD",
+    "OR",
+    "NOT",
+    "IS",
+    "REFERENCES",
+    "CASCADE",
+    "RESTRICT",
+    "ALTER",
+    "RESTART",
+    "PRIMARY",
+    "KEY",
+    "UNIQUE",
+    "AUTOINCREMENT",
+    "SEQUENCE",
+    "nextval",
+    "CREATE",
+    "TABLE",
+    "VIEW",
+    "INDEX",
+    "DROP",
+    "IF",
+    "EXISTS",
+    "CAST",
+    "NULL",
+    "INTEGER",
+    "REAL",
+    "TEXT",
+    "DATE",
+    "TIMESTAMP",
+    "INTERVAL",
+    "'2024-01-31'",
+    "'1 day'",
+    "EXTRACT",
+    "NOW",
+    "DATE_TRUNC",
+    "ANALYZE",
+    "EXPLAIN",
+    "SHOW",
+    "SET",
+    "COPY",
+    "DUMP",
+    "RESTORE",
+    "GRANT",
+    "REVOKE",
+    "UPPER",
+    "SUBSTR",
+    "COALESCE",
+    "ROUND",
+    "t0",
+    "t1",
+    "c0",
+    "c1",
+    "a0",
+    "\"q\"",
+    "*",
+    ",",
+    "(",
+    ")",
+    "=",
+    "<",
+    ">=",
+    "<>",
+    "+",
+    "-",
+    "/",
+    "%",
+    "||",
+    ".",
+    ";",
+    "'",
+    "''",
+    "'x'",
+    "'unterminated",
+    "\"",
+    "0",
+    "-1",
+    "2.5",
+    "1e3",
+    "99999999999999999999",
+    "--",
+    "/*",
+    "*/",
+    "\n",
+    "\\.",
+    "é",
+    "\u{0}",
+];
+
+#[test]
+fn parser_never_panics() {
+    for seed in seeds() {
+        let mut rng = Rng::new(seed);
+        let mut generator = Gen::new(seed);
+        let conn = Database::new();
+        let mut conn = conn.connect();
+        for statement in generator.setup() {
+            let _ = conn.execute(&statement);
+        }
+
+        for _ in 0..QUERIES_PER_CASE {
+            let text = if rng.chance(50) {
+                (0..rng.below(12) + 1)
+                    .map(|_| *rng.pick(TOKENS))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            } else {
+                mangle(&mut rng, &generator.query())
+            };
+            // Errors are expected; the test fails only if this panics
+            let _ = conn.execute(&text);
+        }
+    }
+}
+
+/// A valid query with a few of its tokens dropped, repeated or swapped.
+fn mangle(rng: &mut Rng, query: &str) -> String {
+    let mut words: Vec<&str> = query.split(' ').collect();
+    for _ in 0..rng.below(3) + 1 {
+        let i = rng.below(words.len());
+        match rng.below(4) {
+            0 => {
+                words.remove(i);
+            }
+            1 => words.insert(i, words[i]),
+            2 => {
+                let j = rng.below(words.len());
+                words.swap(i, j);
+            }
+            _ => words.insert(i, *rng.pick(TOKENS)),
+        }
+        if words.is_empty() {
+            break;
+        }
+    }
+    words.join(" ")
+}
+
+// ---------------------------------------------------------------------------
+// Generator
+// ---------------------------------------------------------------------------
+
+/// SplitMix64: tiny, and the same sequence everywhere for a given seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// A column type. SQLite keeps a DATE as the text it was given, which
+/// orders as the dates do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Integer,
+    Real,
+    Text,
+    Date,
+}
+
+const TYPES: &[Type] = &[Type::Integer, Type::Real, Type::Text, Type::Date];
+
+impl Type {
+    fn sql(self) -> &'static str {
+        match self {
+            Type::Integer => "INTEGER",
+            Type::Real => "REAL",
+            Type::Text => "TEXT",
+            Type::Date => "DATE",
+        }
+    }
+
+    /// Whether both engines compare values of the two types the same way:
+    /// any number with any other, and otherwise only the same type.
+    fn comparable(self, other: Type) -> bool {
+        let numeric = |t| matches!(t, Type::Integer | Type::Real);
+        self == other || numeric(self) && numeric(other)
+    }
+}
+
+#[derive(Clone)]
+struct Column {
+    name: String,
+    ty: Type,
+    /// A table column rather than a view's. SQLite only converts a literal
+    /// compared with a column that has a declared type.
+    declared: bool,
+}
+
+#[derive(Clone)]
+struct Relation {
+    name: String,
+    columns: Vec<Column>,
+}
+
+/// Texts values are drawn from; a small pool so that joins and WHERE
+/// conditions find matches. ASCII only: SQLite's UPPER and LOWER leave
+/// anything else alone.
+const TEXTS: &[&str] = &["", "a", "b", "ab", "Ab", "abc", "x y", "10", "B"];
+
+/// Dates values are drawn from, around the ends of months and years.
+const DATES: &[&str] = &[
+    "2023-12-31",
+    "2024-01-01",
+    "2024-01-31",
+    "2024-02-29",
+    "2024-03-01",
+    "1999-12-31",
+];
+
+/// Makes up schemas, data and queries.
+///
+/// What it avoids, because the two engines differ there on purpose:
+/// - values that don't fit their column's type, which SQLite stores as is
+///   and rustydb refuses, and real literals for INTEGER columns;
+/// - division or remainder by zero (an error here, NULL in SQLite), and
+///   `%` on reals, which SQLite truncates to integers first;
+/// - integers big enough to overflow;
+/// - comparing a view column with a literal of another type, which SQLite
+///   does without converting either side;
+/// - joining text columns to numeric ones;
+/// - UPDATEs of key columns, which SQLite checks row by row rather than
+///   once every row has changed;
+/// - comparing values of different types, which SQLite orders rather than
+///   converts;
+/// - text where a date is expected, other than stored or compared with a
+///   date: rustydb keeps it as text, where to SQLite it is the date;
+/// - BEFORE triggers, which SQLite runs for an INSERT's row even when ON
+///   CONFLICT then drops it or updates another;
+/// - triggers on tables with a foreign key, which SQLite runs for the rows
+///   ON DELETE changes, and triggers changing such tables, whose foreign
+///   keys SQLite only checks once the outermost statement is done;
+/// - UPDATE and DELETE triggers on tables numbering their rows, which
+///   SQLite goes through in the order of their numbers rather than the
+///   order they went in.
+struct Gen {
+    rng: Rng,
+    relations: Vec<Relation>,
+    /// The PRIMARY KEY and UNIQUE columns of the tables: table, column
+    /// and type.
+    keys: Vec<(String, String, Type)>,
+    /// The tables with a foreign key.
+    referencing: Vec<String>,
+}
+
+/// The relations of a query being built, by alias.
+type Scope = Vec<(String, Relation)>;
+
+impl Gen {
+    fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed.wrapping_mul(0x2545_f491_4f6c_dd1d)),
+            relations: Vec::new(),
+            keys: Vec::new(),
+            referencing: Vec::new(),
+        }
+    }
+
+    /// Statements creating and filling a database.
+    fn setup(&mut self) -> Vec<String> {
+        let mut statements = Vec::new();
+        for t in 0..self.rng.below(3) + 1 {
+            let name = format!("t{}", t);
+            // Some tables number their rows themselves
+            let numbered = self.rng.chance(25);
+            let mut columns: Vec<Column> = (0..self.rng.below(4) + 1)
+                .map(|c| Column {
+                    name: format!("c{}", c),
+                    ty: *self.rng.pick(TYPES),
+                    declared: true,
+                })
+                .collect();
+            // A UNIQUE column has its index already
+            let mut unique: Vec<bool> = columns.iter().map(|_| self.rng.chance(15)).collect();
+            let mut definitions: Vec<String> = columns
+                .iter()
+                .zip(&unique)
+                .map(|(c, &unique)| {
+                    let key = if unique { " UNIQUE" } else { "" };
+                    format!("{} {}{}", c.name, c.ty.sql(), key)
+                })
+                .collect();
+            // Some reference a key of a table made before them
+            if !self.keys.is_empty() && self.rng.chance(35) {
+                let (table, key, ty) = self.rng.pick(&self.keys).clone();
+                let column = format!("c{}", columns.len());
+                let action = self.rng.pick(&[
+                    "",
+                    " ON DELETE CASCADE",
+                    " ON DELETE SET NULL",
+                    " ON DELETE NO ACTION",
+                ]);
+                definitions.push(format!(
+                    "{} {} REFERENCES {} ({}){}",
+                    column,
+                    ty.sql(),
+                    table,
+                    key,
+                    action
+                ));
+                columns.push(Column {
+                    name: column,
+                    ty,
+                    declared: true,
+                });
+                unique.push(false);
+                self.referencing.push(name.clone());
+            }
+            // Some constrain their rows, which then may not go in
+            if self.rng.chance(25) {
+                let scope = vec![(
+                    name.clone(),
+                    Relation {
+                        name: name.clone(),
+                        columns: columns.clone(),
+                    },
+                )];
+                let check = self.boolean(&scope, false, 1);
+                definitions.push(format!("CHECK ({})", check));
+            }
+            if numbered {
+                definitions.insert(0, "id INTEGER PRIMARY KEY AUTOINCREMENT".to_string());
+            }
+            statements.push(format!(
+                "CREATE TABLE {} ({})",
+                name,
+                definitions.join(", ")
+            ));
+            // and some change a table made before them whenever their own
+            // rows change
+            if !self.referencing.contains(&name) && self.rng.chance(30) {
+                let mut own = columns.clone();
+                if numbered {
+                    own.insert(
+                        0,
+                        Column {
+                            name: "id".to_string(),
+                            ty: Type::Integer,
+                            declared: true,
+                        },
+                    );
+                }
+                if let Some(trigger) = self.trigger(&name, &own, numbered) {
+                    statements.push(trigger);
+                }
+            }
+
+            // Indexes made before the rows are kept up to date as they
+            // arrive; those made after are built from them
+            let early = self.rng.chance(50);
+            let mut indexes = Vec::new();
+            for (column, &unique) in columns.iter().zip(&unique) {
+                if !unique && self.rng.chance(30) {
+                    indexes.push(format!(
+                        "CREATE INDEX {}_{} ON {} ({})",
+                        name, column.name, name, column.name
+                    ));
+                }
+            }
+            if early {
+                statements.append(&mut indexes);
+            }
+            let mut keys: Vec<&str> = columns
+                .iter()
+                .zip(&unique)
+                .filter(|(_, unique)| **unique)
+                .map(|(c, _)| c.name.as_str())
+                .collect();
+            if numbered {
+                keys.push("id");
+            }
+            for key in &keys {
+                let ty = columns
+                    .iter()
+                    .find(|c| c.name == *key)
+                    .map_or(Type::Integer, |c| c.ty);
+                self.keys.push((name.clone(), key.to_string(), ty));
+            }
+            for _ in 0..self.rng.below(25) {
+                let mut values: Vec<String> = columns.iter().map(|c| self.value(c.ty)).collect();
+                let mut insert = if numbered && self.rng.chance(70) {
+                    let names: Vec<&str> = columns.iter().map(|c| c.name.as_str()).collect();
+                    format!(
+                        "INSERT INTO {} ({}) VALUES ({})",
+                        name,
+                        names.join(", "),
+                        values.join(", ")
+                    )
+                } else {
+                    if numbered {
+                        values.insert(0, self.value(Type::Integer));
+                    }
+                    format!("INSERT INTO {} VALUES ({})", name, values.join(", "))
+                };
+                if !keys.is_empty() && self.rng.chance(30) {
+                    insert.push_str(&self.on_conflict(&keys, &columns));
+                }
+                statements.push(insert);
+            }
+            if numbered {
+                columns.insert(
+                    0,
+                    Column {
+                        name: "id".to_string(),
+                        ty: Type::Integer,
+                        declared: true,
+                    },
+                );
+            }
+            statements.append(&mut indexes);
+            self.relations.push(Relation { name, columns });
+        }
+
+        for _ in 0..self.rng.below(4) {
+            statements.push(self.change());
+        }
+
+        if self.rng.chance(50) {
+            statements.push("ANALYZE".to_string());
+        }
+
+        for v in 0..self.rng.below(3) {
+            if !self.rng.chance(50) {
+                continue;
+            }
+            let (query, columns) = self.view_query();
+            statements.push(format!("CREATE VIEW v{} AS {}", v, query));
+            self.relations.push(Relation {
+                name: format!("v{}", v),
+                columns,
+            });
+        }
+        statements
+    }
+
+    /// A query over one relation to define a view or WITH query with, and
+    /// the columns it returns.
+    fn view_query(&mut self) -> (String, Vec<Column>) {
+        let table = self.rng.pick(&self.relations[..]).clone();
+        let scope = vec![(table.name.clone(), table.clone())];
+        let mut columns = Vec::new();
+        let mut items = Vec::new();
+        for c in 0..self.rng.below(3) + 1 {
+            let ty = *self.rng.pick(TYPES);
+            items.push(format!("{} AS c{}", self.expr(&scope, false, ty, 2), c));
+            columns.push(Column {
+                name: format!("c{}", c),
+                ty,
+                declared: false,
+            });
+        }
+        let filter = self.condition(&scope, false);
+        let query = format!("SELECT {} FROM {}{}", items.join(", "), table.name, filter);
+        (query, columns)
+    }
+
+    /// `WITH w AS (...) `, sometimes recursive, and what it defines.
+    fn with(&mut self) -> (String, Relation) {
+        if self.rng.chance(50) {
+            return self.recursive_with();
+        }
+        let (query, columns) = self.view_query();
+        let relation = Relation {
+            name: "w".to_string(),
+            columns,
+        };
+        (format!("WITH w AS ({}) ", query), relation)
+    }
+
+    /// A recursive WITH query that follows one column of a table to
+    /// another, from a starting set of values. It only ever produces values
+    /// already in the tables, so with UNION it always ends.
+    fn recursive_with(&mut self) -> (String, Relation) {
+        let tables: Vec<Relation> = self
+            .relations
+            .iter()
+            .filter(|r| r.columns[0].declared)
+            .cloned()
+            .collect();
+        let start = self.rng.pick(&tables).clone();
+        let column = self.rng.pick(&start.columns).clone();
+        // (table, column joined to the last round's values, column returned)
+        let mut steps = Vec::new();
+        for table in &tables {
+            for joined in &table.columns {
+                for returned in &table.columns {
+                    if joined.ty.comparable(column.ty) && returned.ty == column.ty {
+                        steps.push((&table.name, &joined.name, &returned.name));
+                    }
+                }
+            }
+        }
+        let (table, joined, returned) = *self.rng.pick(&steps);
+        let scope = vec![(start.name.clone(), start.clone())];
+        let filter = self.condition(&scope, false);
+        let with = format!(
+            "WITH RECURSIVE w (x0) AS (SELECT {} FROM {}{} UNION SELECT s.{} FROM {} AS s JOIN w ON s.{} = w.x0) ",
+            column.name, start.name, filter, returned, table, joined
+        );
+        let relation = Relation {
+            name: "w".to_string(),
+            columns: vec![Column {
+                name: "x0".to_string(),
+                ty: column.ty,
+                declared: false,
+            }],
+        };
+        (with, relation)
+    }
+
+    /// A random query over the tables and views made by `setup`, sometimes
+    /// with a WITH query of its own to read as well.
+    fn query(&mut self) -> String {
+        if !self.rng.chance(15) {
+            return self.compound();
+        }
+        let (with, relation) = self.with();
+        self.relations.push(relation);
+        let query = self.compound();
+        self.relations.pop();
+        format!("{}{}", with, query)
+    }
+
+    /// `ORDER BY` terms for all `columns` of a result, by number and each
+    /// either way, in any order; none at times.
+    fn order_by(&mut self, columns: usize) -> Option<String> {
+        if columns == 0 || !self.rng.chance(30) {
+            return None;
+        }
+        let mut numbers: Vec<usize> = (1..=columns).collect();
+        for i in (1..numbers.len()).rev() {
+            numbers.swap(i, self.rng.below(i + 1));
+        }
+        let terms: Vec<String> = numbers
+            .into_iter()
+            .map(|n| {
+                let direction = if self.rng.chance(50) { " DESC" } else { "" };
+                format!("{}{}", n, direction)
+            })
+            .collect();
+        Some(terms.join(", "))
+    }
+
+    /// One SELECT, or a few combined with UNION, INTERSECT or EXCEPT.
+    fn compound(&mut self) -> String {
+        let distinct = self.rng.chance(15);
+        if !self.rng.chance(25) {
+            return self.select(None, distinct);
+        }
+        // Every block returns the same column types, so that values equal
+        // to each other are also the same value, whichever one an engine
+        // keeps
+        let types: Vec<Type> = (0..self.rng.below(3) + 1)
+            .map(|_| *self.rng.pick(TYPES))
+            .collect();
+        let mut query = self.select(Some(&types), distinct);
+        for _ in 0..self.rng.below(2) + 1 {
+            let op = *self
+                .rng
+                .pick(&["UNION", "UNION ALL", "INTERSECT", "EXCEPT"]);
+            let distinct = self.rng.chance(15);
+            let block = self.select(Some(&types), distinct);
+            let _ = write!(query, " {} {}", op, block);
+        }
+        query
+    }
+
+    /// One SELECT, returning columns of `types` if given.
+    fn select(&mut self, types: Option<&[Type]>, distinct: bool) -> String {
+        let first = self.rng.pick(&self.relations[..]).clone();
+        let mut scope: Scope = vec![("a0".to_string(), first)];
+        let mut from = format!("{} AS a0", scope[0].1.name);
+
+        for j in 1..=2 {
+            if !self.rng.chance(40) {
+                break;
+            }
+            let relation = self.rng.pick(&self.relations[..]).clone();
+            let alias = format!("a{}", j);
+            // Each ON pair compares the new relation with an earlier one
+            let mut pairs = Vec::new();
+            for column in &relation.columns {
+                for (earlier, other) in &scope {
+                    for theirs in &other.columns {
+                        if column.ty.comparable(theirs.ty) {
+                            pairs.push((
+                                format!("{}.{}", alias, column.name),
+                                format!("{}.{}", earlier, theirs.name),
+                            ));
+                        }
+                    }
+                }
+            }
+            if pairs.is_empty() {
+                break;
+            }
+            let on: Vec<String> = (0..self.rng.below(2) + 1)
+                .map(|_| {
+                    let (mine, theirs) = self.rng.pick(&pairs).clone();
+                    if self.rng.chance(50) {
+                        format!("{} = {}", mine, theirs)
+                    } else {
+                        format!("{} = {}", theirs, mine)
+                    }
+                })
+                .collect();
+            let _ = write!(
+                from,
+                " JOIN {} AS {} ON {}",
+                relation.name,
+                alias,
+                on.join(" AND ")
+            );
+            scope.push((alias, relation));
+        }
+
+        let qualified = scope.len() > 1 || self.rng.chance(50);
+        if !qualified {
+            from = scope[0].1.name.clone();
+        }
+
+        let projection = match types {
+            None if self.rng.chance(25) => "*".to_string(),
+            _ => {
+                let types = types.map_or_else(
+                    || {
+                        (0..self.rng.below(4) + 1)
+                            .map(|_| *self.rng.pick(TYPES))
+                            .collect()
+                    },
+                    <[Type]>::to_vec,
+                );
+                types
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, ty)| {
+                        let item = if self.rng.chance(15) {
+                            self.window(&scope, qualified, ty)
+                        } else {
+                            self.expr(&scope, qualified, ty, 3)
+                        };
+                        format!("{} AS x{}", item, i)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+        let condition = self.condition(&scope, qualified);
+        format!(
+            "SELECT {}{} FROM {}{}",
+            if distinct { "DISTINCT " } else { "" },
+            projection,
+            from,
+            condition
+        )
+    }
+
+    /// A window function call returning `ty`. Where the result depends on
+    /// the order of rows that sort equal, as ROW_NUMBER's and any ROWS
+    /// frame's do, every column is added to ORDER BY to settle it.
+    fn window(&mut self, scope: &Scope, qualified: bool, ty: Type) -> String {
+        let any_type = |g: &mut Self| *g.rng.pick(TYPES);
+        let arg = |g: &mut Self, ty| g.expr(scope, qualified, ty, 1);
+        let (call, aggregate) = match (ty, self.rng.below(7)) {
+            (Type::Integer, 0) => (
+                self.rng
+                    .pick(&["ROW_NUMBER()", "RANK()", "DENSE_RANK()"])
+                    .to_string(),
+                false,
+            ),
+            (Type::Integer, 1) => ("COUNT(*)".to_string(), true),
+            (Type::Integer, 2) => {
+                let ty = any_type(self);
+                (format!("COUNT({})", arg(self, ty)), true)
+            }
+            (Type::Real, 0) => {
+                let ty = *self.rng.pick(&[Type::Integer, Type::Real]);
+                (format!("AVG({})", arg(self, ty)), true)
+            }
+            (Type::Integer, 3) | (Type::Real, 1 | 2) => (format!("SUM({})", arg(self, ty)), true),
+            (_, 3 | 4) => {
+                let name = self.rng.pick(&["MIN", "MAX"]);
+                (format!("{}({})", name, arg(self, ty)), true)
+            }
+            _ => {
+                let name = self.rng.pick(&["LAG", "LEAD"]);
+                let call = match self.rng.below(3) {
+                    0 => format!("{}({})", name, arg(self, ty)),
+                    1 => format!("{}({}, {})", name, arg(self, ty), self.rng.below(4)),
+                    _ => format!(
+                        "{}({}, {}, {})",
+                        name,
+                        arg(self, ty),
+                        self.rng.below(4),
+                        match ty {
+                            Type::Date => "NULL".to_string(),
+                            _ => self.literal(ty),
+                        }
+                    ),
+                };
+                (call, false)
+            }
+        };
+
+        let mut clauses = Vec::new();
+        if self.rng.chance(50) {
+            let exprs: Vec<String> = (0..self.rng.below(2) + 1)
+                .map(|_| {
+                    let ty = any_type(self);
+                    arg(self, ty)
+                })
+                .collect();
+            clauses.push(format!("PARTITION BY {}", exprs.join(", ")));
+        }
+        let mut order_by: Vec<String> = Vec::new();
+        if !aggregate || self.rng.chance(60) {
+            for _ in 0..self.rng.below(2) + 1 {
+                let ty = any_type(self);
+                let direction = self.rng.pick(&["", " ASC", " DESC"]);
+                order_by.push(format!("{}{}", arg(self, ty), direction));
+            }
+        }
+        let frame = if aggregate && self.rng.chance(40) {
+            let preceding = |g: &mut Self| format!("{} PRECEDING", g.rng.below(3));
+            let following = |g: &mut Self| format!("{} FOLLOWING", g.rng.below(3));
+            let start = match self.rng.below(4) {
+                0 => "UNBOUNDED PRECEDING".to_string(),
+                1 => preceding(self),
+                2 => "CURRENT ROW".to_string(),
+                _ => following(self),
+            };
+            Some(if !start.ends_with("FOLLOWING") && self.rng.chance(25) {
+                format!("ROWS {}", start)
+            } else {
+                let end = match self.rng.below(3) {
+                    _ if start.ends_with("FOLLOWING") => "UNBOUNDED FOLLOWING".to_string(),
+                    0 => "CURRENT ROW".to_string(),
+                    1 => following(self),
+                    _ => "UNBOUNDED FOLLOWING".to_string(),
+                };
+                format!("ROWS BETWEEN {} AND {}", start, end)
+            })
+        } else {
+            None
+        };
+        if !aggregate || frame.is_some() {
+            for (alias, relation) in scope {
+                for column in &relation.columns {
+                    order_by.push(if qualified {
+                        format!("{}.{}", alias, column.name)
+                    } else {
+                        column.name.clone()
+                    });
+                }
+            }
+        }
+        if !order_by.is_empty() {
+            clauses.push(format!("ORDER BY {}", order_by.join(", ")));
+        }
+        clauses.extend(frame);
+        format!("{} OVER ({})", call, clauses.join(" "))
+    }
+
+    /// ` WHERE column = literal`, a condition of any other form, both ANDed
+    /// together, or nothing.
+    fn condition(&mut self, scope: &Scope, qualified: bool) -> String {
+        if !self.rng.chance(60) {
+            return String::new();
+        }
+        match self.rng.below(4) {
+            0 => format!(" WHERE {}", self.boolean(scope, qualified, 1)),
+            1 => {
+                let boolean = self.boolean(scope, qualified, 1);
+                format!("{} AND {}", self.equality(scope, qualified), boolean)
+            }
+            _ => self.equality(scope, qualified),
+        }
+    }
+
+    /// ` WHERE column = literal`, which rustydb may look up in an index.
+    fn equality(&mut self, scope: &Scope, qualified: bool) -> String {
+        let (alias, relation) = self.rng.pick(scope);
+        let column = self.rng.pick(&relation.columns);
+        let name = if qualified {
+            format!("{}.{}", alias, column.name)
+        } else {
+            column.name.clone()
+        };
+        let literal = if self.rng.chance(5) {
+            "NULL".to_string()
+        } else if column.declared && self.rng.chance(30) {
+            // Another type, which both convert to the column's
+            match column.ty {
+                Type::Integer => format!("'{}'", self.rng.below(10)),
+                Type::Real => self.rng.below(10).to_string(),
+                Type::Text => self.rng.below(12).to_string(),
+                Type::Date => self.literal(column.ty),
+            }
+        } else {
+            self.literal(column.ty)
+        };
+        format!(" WHERE {} = {}", name, literal)
+    }
+
+    /// An ON CONFLICT clause for a table with key columns `keys`, updating
+    /// some of `columns`.
+    fn on_conflict(&mut self, keys: &[&str], columns: &[Column]) -> String {
+        let target = self.rng.pick(keys);
+        if self.rng.chance(40) {
+            return if self.rng.chance(25) {
+                " ON CONFLICT DO NOTHING".to_string()
+            } else {
+                format!(" ON CONFLICT ({}) DO NOTHING", target)
+            };
+        }
+        let mut assignments = Vec::new();
+        for c in columns {
+            if self.rng.chance(60) {
+                assignments.push(match self.rng.below(3) {
+                    0 => format!("{} = excluded.{}", c.name, c.name),
+                    1 => format!("{} = {}", c.name, self.value(c.ty)),
+                    _ => format!("{} = {}", c.name, c.name),
+                });
+            }
+        }
+        if assignments.is_empty() {
+            return format!(" ON CONFLICT ({}) DO NOTHING", target);
+        }
+        format!(
+            " ON CONFLICT ({}) DO UPDATE SET {}",
+            target,
+            assignments.join(", ")
+        )
+    }
+
+    /// An UPDATE or DELETE of one of the tables.
+    fn change(&mut self) -> String {
+        let table = self.rng.pick(&self.relations[..]).clone();
+        let scope = vec![(table.name.clone(), table.clone())];
+        let condition = self.condition(&scope, false);
+        let columns: Vec<Column> = table
+            .columns
+            .iter()
+            .filter(|c| {
+                !self
+                    .keys
+                    .iter()
+                    .any(|(t, key, _)| *t == table.name && *key == c.name)
+            })
+            .cloned()
+            .collect();
+        if columns.is_empty() || self.rng.chance(40) {
+            return format!("DELETE FROM {}{}", table.name, condition);
+        }
+        let mut assignments = Vec::new();
+        for c in &columns {
+            if assignments.is_empty() || self.rng.chance(40) {
+                let value = if self.rng.chance(50) {
+                    self.value(c.ty)
+                } else {
+                    self.expr(&scope, false, c.ty, 2)
+                };
+                assignments.push(format!("{} = {}", c.name, value));
+            }
+        }
+        format!(
+            "UPDATE {} SET {}{}",
+            table.name,
+            assignments.join(", "),
+            condition
+        )
+    }
+
+    /// An AFTER trigger on `table`, whose columns are `columns`, changing
+    /// one of the tables made before it; none if there is none to change.
+    /// One on a `numbered` table only runs for INSERTs.
+    fn trigger(&mut self, table: &str, columns: &[Column], numbered: bool) -> Option<String> {
+        let targets: Vec<Relation> = self
+            .relations
+            .iter()
+            .filter(|r| !self.referencing.contains(&r.name))
+            .cloned()
+            .collect();
+        if targets.is_empty() {
+            return None;
+        }
+        let event = match numbered {
+            true => "INSERT",
+            false => *self.rng.pick(&["INSERT", "UPDATE", "DELETE"]),
+        };
+        let rows: &[&str] = match event {
+            "INSERT" => &["NEW"],
+            "UPDATE" => &["NEW", "OLD"],
+            _ => &["OLD"],
+        };
+        let mut body = Vec::new();
+        for _ in 0..self.rng.below(2) + 1 {
+            let target = self.rng.pick(&targets).clone();
+            let changeable: Vec<Column> = target
+                .columns
+                .iter()
+                .filter(|c| {
+                    !self
+                        .keys
+                        .iter()
+                        .any(|(t, key, _)| *t == target.name && *key == c.name)
+                })
+                .cloned()
+                .collect();
+            let filter = if self.rng.chance(70) {
+                let column = self.rng.pick(&target.columns).clone();
+                let value = self.row_value(columns, rows, column.ty);
+                format!(" WHERE {} = {}", column.name, value)
+            } else {
+                String::new()
+            };
+            body.push(match self.rng.below(3) {
+                0 => {
+                    // The numbering is left to the table
+                    let own: Vec<&Column> =
+                        target.columns.iter().filter(|c| c.name != "id").collect();
+                    let names: Vec<&str> = own.iter().map(|c| c.name.as_str()).collect();
+                    let values: Vec<String> = own
+                        .iter()
+                        .map(|c| self.row_value(columns, rows, c.ty))
+                        .collect();
+                    format!(
+                        "INSERT INTO {} ({}) VALUES ({})",
+                        target.name,
+                        names.join(", "),
+                        values.join(", ")
+                    )
+                }
+                1 if !changeable.is_empty() => {
+                    let column = self.rng.pick(&changeable).clone();
+                    let value = self.row_value(columns, rows, column.ty);
+                    format!(
+                        "UPDATE {} SET {} = {}{}",
+                        target.name, column.name, value, filter
+                    )
+                }
+                _ => format!("DELETE FROM {}{}", target.name, filter),
+            });
+        }
+        Some(format!(
+            "CREATE TRIGGER {}_{} AFTER {} ON {} FOR EACH ROW BEGIN {}; END",
+            table,
+            event.to_lowercase(),
+            event,
+            table,
+            body.join("; ")
+        ))
+    }
+
+    /// A value of type `ty` for a trigger's body to use: a column of the
+    /// row it runs for, one of `rows` (NEW or OLD), or a literal.
+    fn row_value(&mut self, columns: &[Column], rows: &[&str], ty: Type) -> String {
+        let own: Vec<&Column> = columns.iter().filter(|c| c.ty == ty).collect();
+        if own.is_empty() || self.rng.chance(30) {
+            return self.value(ty);
+        }
+        format!("{}.{}", self.rng.pick(rows), self.rng.pick(&own).name)
+    }
+
+    /// A value to store in a column of type `ty`; sometimes NULL.
+    fn value(&mut self, ty: Type) -> String {
+        if self.rng.chance(10) {
+            return "NULL".to_string();
+        }
+        self.literal(ty)
+    }
+
+    fn literal(&mut self, ty: Type) -> String {
+        match ty {
+            Type::Integer => (self.rng.below(16) as i64 - 3).to_string(),
+            Type::Real => format!("{:.1}", (self.rng.below(31) as f64 - 6.0) / 2.0),
+            Type::Text => quote_literal(self.rng.pick::<&str>(TEXTS)),
+            Type::Date => quote_literal(self.rng.pick::<&str>(DATES)),
+        }
+    }
+
+    /// An expression of type `ty` over the columns in `scope`.
+    fn expr(&mut self, scope: &Scope, qualified: bool, ty: Type, depth: usize) -> String {
+        let columns: Vec<String> = scope
+            .iter()
+            .flat_map(|(alias, relation)| {
+                relation
+                    .columns
+                    .iter()
+                    .filter(|c| c.ty == ty)
+                    .map(move |c| {
+                        if qualified {
+                            format!("{}.{}", alias, c.name)
+                        } else {
+                            c.name.clone()
+                        }
+                    })
+            })
+            .collect();
+        if depth == 0 || self.rng.chance(35) {
+            return match ty {
+                Type::Date if columns.is_empty() => "NULL".to_string(),
+                Type::Date => self.rng.pick(&columns).clone(),
+                _ if columns.is_empty() || self.rng.chance(25) => self.literal(ty),
+                _ => self.rng.pick(&columns).clone(),
+            };
+        }
+
+        let sub = |g: &mut Self, ty| g.expr(scope, qualified, ty, depth - 1);
+        match ty {
+            Type::Integer => match self.rng.below(11) {
+                0 => format!("({} + {})", sub(self, ty), sub(self, ty)),
+                1 => format!("({} - {})", sub(self, ty), sub(self, ty)),
+                2 => format!("({} * {})", sub(self, ty), sub(self, ty)),
+                3 => format!("({} / {})", sub(self, ty), self.rng.below(4) + 1),
+                4 => format!("({} % {})", sub(self, ty), self.rng.below(4) + 1),
+                5 => format!("(- {})", sub(self, ty)),
+                6 => format!("ABS({})", sub(self, ty)),
+                7 => format!("LENGTH({})", sub(self, Type::Text)),
+                8 => format!("CAST({} AS INTEGER)", sub(self, Type::Real)),
+                9 => format!("COALESCE({}, {})", sub(self, ty), sub(self, ty)),
+                _ => self.boolean(scope, qualified, depth - 1),
+            },
+            Type::Real => match self.rng.below(9) {
+                0 => format!("({} + {})", sub(self, ty), sub(self, Type::Integer)),
+                1 => format!("({} - {})", sub(self, ty), sub(self, ty)),
+                2 => format!("({} * {})", sub(self, ty), sub(self, ty)),
+                3 => format!("({} / 2.0)", sub(self, ty)),
+                4 => format!("ROUND({})", sub(self, ty)),
+                5 => format!("ROUND({}, 1)", sub(self, ty)),
+                6 => format!("CAST({} AS REAL)", sub(self, Type::Integer)),
+                7 => format!("ABS({})", sub(self, ty)),
+                _ => format!("(- {})", sub(self, ty)),
+            },
+            Type::Text => match self.rng.below(9) {
+                0 => format!("UPPER({})", sub(self, ty)),
+                1 => format!("LOWER({})", sub(self, ty)),
+                2 => format!("SUBSTR({}, {})", sub(self, ty), self.rng.below(3) + 1),
+                3 => format!(
+                    "SUBSTR({}, {}, {})",
+                    sub(self, ty),
+                    self.rng.below(3) + 1,
+                    self.rng.below(3)
+                ),
+                4 => format!("({} || {})", sub(self, ty), sub(self, ty)),
+                5 => format!("({} || {})", sub(self, ty), sub(self, Type::Integer)),
+                6 => format!("CAST({} AS TEXT)", sub(self, Type::Integer)),
+                7 => format!("CAST({} AS TEXT)", sub(self, Type::Date)),
+                _ => format!("COALESCE({}, 'z')", sub(self, ty)),
+            },
+            Type::Date => format!("COALESCE({}, {})", sub(self, ty), sub(self, ty)),
+        }
+    }
+
+    /// A condition, 1, 0 or NULL: a comparison of two expressions of one
+    /// type, or AND, OR, NOT or IS NULL of others.
+    fn boolean(&mut self, scope: &Scope, qualified: bool, depth: usize) -> String {
+        let ty = *self.rng.pick(TYPES);
+        let sub = |g: &mut Self, ty| g.expr(scope, qualified, ty, depth);
+        match self.rng.below(6) {
+            0 | 1 => {
+                let op = self.rng.pick(&["=", "<>", "!=", "<", "<=", ">", ">="]);
+                let right = match ty {
+                    Type::Date if self.rng.chance(50) => self.literal(ty),
+                    _ => sub(self, ty),
+                };
+                format!("({} {} {})", sub(self, ty), op, right)
+            }
+            2 => format!(
+                "({} AND {})",
+                sub(self, Type::Integer),
+                sub(self, Type::Integer)
+            ),
+            3 => format!(
+                "({} OR {})",
+                sub(self, Type::Integer),
+                sub(self, Type::Integer)
+            ),
+            4 => format!("(NOT {})", sub(self, Type::Integer)),
+            _ if self.rng.chance(50) => format!("({} IS NULL)", sub(self, ty)),
+            _ => format!("({} IS NOT NULL)", sub(self, ty)),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Dumps replayed, mostly as ordinary scripts through `execute_batch`, since
+//! `Database::restore` doesn't check foreign keys.
+
+use rustydb::Database;
+
+/// Tables that sort before the ones they reference, and a table whose rows
+/// reference later rows of their own.
+const SCHEMA: &str = "
+    CREATE TABLE p (id INTEGER PRIMARY KEY, name TEXT);
+    CREATE TABLE c (id INTEGER PRIMARY KEY, pid INTEGER REFERENCES p (id) ON DELETE CASCADE);
+    CREATE TABLE b (id INTEGER PRIMARY KEY, cid INTEGER REFERENCES c (id));
+    CREATE TABLE staff (id INTEGER PRIMARY KEY, boss INTEGER REFERENCES staff (id));
+    INSERT INTO p VALUES (1, 'one');
+    INSERT INTO p VALUES (2, 'two');
+    INSERT INTO c VALUES (10, 1);
+    INSERT INTO c VALUES (20, 2);
+    INSERT INTO b VALUES (100, 20);
+    INSERT INTO staff VALUES (1, NULL);
+    INSERT INTO staff VALUES (2, 2);
+    INSERT INTO staff VALUES (3, 1);
+    UPDATE staff SET boss = 3 WHERE id = 1;";
+
+fn database(script: &str) -> Database {
+    let db = Database::new();
+    db.connect().execute_batch(script).unwrap();
+    db
+}
+
+#[test]
+fn replays_into_an_empty_database() {
+    let db = database(SCHEMA);
+    let script = db.dump();
+
+    let copy = Database::new();
+    copy.connect().execute_batch(&script).unwrap();
+    assert_eq!(copy.dump(), script);
+}
+
+#[test]
+fn replays_over_the_database_it_came_from() {
+    let db = database(SCHEMA);
+    let script = db.dump();
+    db.connect().execute_batch(&script).unwrap();
+    assert_eq!(db.dump(), script);
+}
+
+#[test]
+fn keeps_the_foreign_keys_working() {
+    let copy = Database::new();
+    copy.connect()
+        .execute_batch(&database(SCHEMA).dump())
+        .unwrap();
+    let mut conn = copy.connect();
+
+    let err = conn.execute("INSERT INTO c VALUES (30, 3)").unwrap_err();
+    assert!(
+        err.contains("Foreign key 'c_pid_fkey' is violated"),
+        "{}",
+        err
+    );
+    conn.execute("DELETE FROM p WHERE id = 1").unwrap();
+    let left: Vec<i64> = conn
+        .query("SELECT id FROM c")
+        .unwrap()
+        .into_iter()
+        .map(|row| row.get(0).unwrap())
+        .collect();
+    assert_eq!(left, [20]);
+}
+
+#[test]
+fn restore_reads_the_same_script() {
+    let db = database(SCHEMA);
+    let script = db.dump();
+    assert_eq!(Database::restore(&script).unwrap().dump(), script);
+}
+
+/// Every kind of object a dump writes, and values of every type.
+const EVERYTHING: &str = "
+    CREATE SEQUENCE ids START WITH 5 INCREMENT BY 5;
+    CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, code TEXT UNIQUE, score REAL,
+        day DATE, at TIME, stamp TIMESTAMP, span INTERVAL,
+        CONSTRAINT positive CHECK (score > 0));
+    CREATE TABLE log (id INTEGER, code TEXT);
+    CREATE INDEX t_day ON t (day);
+    CREATE VIEW best AS SELECT code, score FROM t WHERE score > 1 ORDER BY score DESC;
+    CREATE TRIGGER t_logged AFTER INSERT ON t FOR EACH ROW
+        BEGIN INSERT INTO log VALUES (NEW.id, NEW.code); END;
+    INSERT INTO t VALUES (DEFAULT, 'it''s', 1.5, DATE '2024-02-29', TIME '23:59:59',
+        TIMESTAMP '2024-01-31 13:45:00', INTERVAL '1 day 02:00:00');
+    INSERT INTO t VALUES (DEFAULT, 'a, \"b\"', 0.25, NULL, NULL, NULL, NULL);
+    INSERT INTO t VALUES (DEFAULT, NULL, 3, NULL, NULL, NULL, NULL);
+    SELECT nextval('ids') FROM t;";
+
+#[test]
+fn keeps_every_kind_of_object() {
+    let db = database(EVERYTHING);
+    let script = db.dump();
+    let copy = Database::restore(&script).unwrap();
+    assert_eq!(copy.dump(), script);
+
+    let mut conn = copy.connect();
+    let best: Vec<String> = conn
+        .query("SELECT code FROM best")
+        .unwrap()
+        .into_iter()
+        .map(|row| row.get::<Option<String>>(0).unwrap().unwrap_or_default())
+        .collect();
+    assert_eq!(best, ["", "it's"]);
+
+    // The sequence, AUTOINCREMENT, trigger and check carry on from where
+    // they were
+    conn.execute("INSERT INTO t (code, score) VALUES ('next', 2)")
+        .unwrap();
+    let row = conn
+        .query("SELECT id, nextval('ids') FROM t WHERE code = 'next'")
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap();
+    assert_eq!(row.get::<i64>(0).unwrap(), 4);
+    assert_eq!(row.get::<i64>(1).unwrap(), 20);
+    assert_eq!(conn.query("SELECT * FROM log").unwrap().len(), 4);
+    let err = conn
+        .execute("INSERT INTO t (code, score) VALUES ('bad', -1)")
+        .unwrap_err();
+    assert!(err.contains("Check constraint 'positive'"), "{}", err);
+}
@@ -0,0 +1,163 @@
+//! Foreign keys: what they let in, and what ON DELETE does to the rows
+//! that reference a deleted one.
+
+use rustydb::{Connection, Database};
+
+/// Authors, their books (which go with them), reviews of the books (which
+/// keep a deleted author from going), and editors whose author is cleared.
+const SCHEMA: &str = "
+    CREATE TABLE author (id INTEGER PRIMARY KEY, name TEXT UNIQUE);
+    CREATE TABLE book (id INTEGER PRIMARY KEY, aid INTEGER REFERENCES author (id) ON DELETE CASCADE);
+    CREATE TABLE chapter (id INTEGER PRIMARY KEY, bid INTEGER REFERENCES book (id) ON DELETE CASCADE);
+    CREATE TABLE review (id INTEGER PRIMARY KEY, bid INTEGER REFERENCES book (id));
+    CREATE TABLE editor (id INTEGER PRIMARY KEY, aname TEXT REFERENCES author (name) ON DELETE SET NULL);
+    INSERT INTO author VALUES (1, 'Ada');
+    INSERT INTO author VALUES (2, 'Grace');
+    INSERT INTO book VALUES (10, 1);
+    INSERT INTO book VALUES (20, 2);
+    INSERT INTO chapter VALUES (100, 10);
+    INSERT INTO chapter VALUES (101, 10);
+    INSERT INTO chapter VALUES (200, 20);
+    INSERT INTO review VALUES (1000, 20);
+    INSERT INTO editor VALUES (7, 'Ada');";
+
+fn connect() -> Connection {
+    let mut conn = Database::new().connect();
+    conn.execute_batch(SCHEMA).unwrap();
+    conn
+}
+
+/// The ids in `table`, in order.
+fn ids(conn: &mut Connection, table: &str) -> Vec<i64> {
+    conn.query(&format!("SELECT id FROM {} ORDER BY id", table))
+        .unwrap()
+        .into_iter()
+        .map(|row| row.get(0).unwrap())
+        .collect()
+}
+
+#[test]
+fn refuse_a_reference_to_a_missing_row() {
+    let mut conn = connect();
+    let err = conn.execute("INSERT INTO book VALUES (30, 3)").unwrap_err();
+    assert!(
+        err.contains("Foreign key 'book_aid_fkey' is violated: 'author' has no row with id = 3"),
+        "{}",
+        err
+    );
+    let err = conn
+        .execute("UPDATE chapter SET bid = 30 WHERE id = 100")
+        .unwrap_err();
+    assert!(err.contains("'book' has no row with id = 30"), "{}", err);
+
+    conn.execute("INSERT INTO book VALUES (30, NULL)").unwrap();
+    assert_eq!(ids(&mut conn, "book"), [10, 20, 30]);
+}
+
+#[test]
+fn cascade_through_every_level() {
+    let mut conn = connect();
+    conn.execute("DELETE FROM author WHERE id = 1").unwrap();
+    assert_eq!(ids(&mut conn, "author"), [2]);
+    assert_eq!(ids(&mut conn, "book"), [20]);
+    assert_eq!(ids(&mut conn, "chapter"), [200]);
+}
+
+#[test]
+fn set_a_reference_to_null() {
+    let mut conn = connect();
+    conn.execute("DELETE FROM author WHERE name = 'Ada'")
+        .unwrap();
+    let aname: Option<String> = conn
+        .query("SELECT aname FROM editor")
+        .unwrap()
+        .into_iter()
+        .next()
+        .unwrap()
+        .get(0)
+        .unwrap();
+    assert_eq!(aname, None);
+}
+
+#[test]
+fn restrict_a_delete_that_would_leave_a_reference_behind() {
+    let mut conn = connect();
+    let err = conn.execute("DELETE FROM author WHERE id = 2").unwrap_err();
+    assert!(
+        err.contains(
+            "Foreign key 'review_bid_fkey' is violated: 'review' still has rows referencing 20"
+        ),
+        "{}",
+        err
+    );
+    assert_eq!(ids(&mut conn, "author"), [1, 2]);
+    assert_eq!(ids(&mut conn, "book"), [10, 20]);
+    assert_eq!(ids(&mut conn, "chapter"), [100, 101, 200]);
+
+    let err = conn
+        .execute("UPDATE book SET id = 21 WHERE id = 20")
+        .unwrap_err();
+    assert!(
+        err.contains("'chapter' still has rows with id = 20"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn let_a_table_reference_itself() {
+    let mut conn = Database::new().connect();
+    conn.execute_batch(
+        "CREATE TABLE staff (id INTEGER PRIMARY KEY, boss INTEGER REFERENCES staff (id) ON DELETE CASCADE);
+         INSERT INTO staff VALUES (1, NULL);
+         INSERT INTO staff VALUES (2, 1);
+         INSERT INTO staff VALUES (3, 2);
+         INSERT INTO staff VALUES (4, NULL);",
+    )
+    .unwrap();
+    conn.execute("DELETE FROM staff WHERE id = 1").unwrap();
+    assert_eq!(ids(&mut conn, "staff"), [4]);
+    conn.execute("DROP TABLE staff").unwrap();
+}
+
+#[test]
+fn keep_a_referenced_table_from_being_dropped() {
+    let mut conn = connect();
+    let err = conn.execute("DROP TABLE author").unwrap_err();
+    assert!(
+        err.contains(
+            "Table 'author' is referenced by foreign key 'book_aid_fkey' of 'book'; drop that table first"
+        ),
+        "{}",
+        err
+    );
+    conn.execute_batch(
+        "DROP TABLE editor;
+         DROP TABLE review;
+         DROP TABLE chapter;
+         DROP TABLE book;
+         DROP TABLE author;",
+    )
+    .unwrap();
+}
+
+#[test]
+fn must_reference_a_key() {
+    let mut conn = connect();
+    let err = conn
+        .execute("CREATE TABLE note (bid INTEGER REFERENCES chapter (bid))")
+        .unwrap_err();
+    assert!(
+        err.contains("must be a PRIMARY KEY or UNIQUE column"),
+        "{}",
+        err
+    );
+    let err = conn
+        .execute("CREATE TABLE note (bid INTEGER REFERENCES nowhere (id))")
+        .unwrap_err();
+    assert!(
+        err.contains("Table 'nowhere', referenced by column 'bid', not found"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,52 @@
+//! What INSERT takes as a value.
+
+use rustydb::{Connection, Database, Value};
+
+fn connect() -> Connection {
+    let mut conn = Database::new().connect();
+    conn.execute_batch(
+        "CREATE SEQUENCE ids START WITH 10;
+         CREATE TABLE t (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, n INTEGER, at TIMESTAMP);",
+    )
+    .unwrap();
+    conn
+}
+
+#[test]
+fn rejects_a_bare_word() {
+    let mut conn = connect();
+    let err = conn
+        .execute("INSERT INTO t VALUES (1, Ada, 2, NULL)")
+        .unwrap_err();
+    assert!(
+        err.contains("expected a value, found identifier 'Ada'"),
+        "{}",
+        err
+    );
+    assert_eq!(conn.query("SELECT * FROM t").unwrap().len(), 0);
+}
+
+#[test]
+fn takes_literals_and_the_special_values() {
+    let mut conn = connect();
+    conn.execute_batch(
+        "INSERT INTO t VALUES (DEFAULT, 'Ada', -2, NOW());
+         INSERT INTO t VALUES (nextval('ids'), NULL, 3, TIMESTAMP '2024-01-31 13:45');",
+    )
+    .unwrap();
+    let rows: Vec<_> = conn
+        .query("SELECT id, name, n, at FROM t")
+        .unwrap()
+        .into_iter()
+        .collect();
+    assert_eq!(rows[0].get::<i64>(0).unwrap(), 1);
+    assert_eq!(rows[0].get::<String>(1).unwrap(), "Ada");
+    assert_eq!(rows[0].get::<i64>(2).unwrap(), -2);
+    assert!(matches!(rows[0].get(3).unwrap(), Value::Timestamp(_)));
+    assert_eq!(rows[1].get::<i64>(0).unwrap(), 10);
+    assert_eq!(rows[1].get::<Option<String>>(1).unwrap(), None);
+    assert_eq!(
+        rows[1].get::<Value>(3).unwrap().to_string(),
+        "2024-01-31 13:45:00"
+    );
+}
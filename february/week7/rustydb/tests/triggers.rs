@@ -0,0 +1,152 @@
+//! Triggers, including those set off by what a statement does to other
+//! tables through ON DELETE.
+
+use rustydb::{Connection, Database};
+
+/// A parent table, a child deleted along with its parent, another whose
+/// reference is cleared instead, and an audit table the children's
+/// triggers write to.
+const SCHEMA: &str = "
+    CREATE TABLE parent (id INTEGER PRIMARY KEY);
+    CREATE TABLE child (id INTEGER PRIMARY KEY, pid INTEGER REFERENCES parent (id) ON DELETE CASCADE);
+    CREATE TABLE pet (id INTEGER PRIMARY KEY, pid INTEGER REFERENCES parent (id) ON DELETE SET NULL);
+    CREATE TABLE audit (event TEXT, id INTEGER, pid INTEGER);
+    CREATE TRIGGER child_gone AFTER DELETE ON child FOR EACH ROW
+        BEGIN INSERT INTO audit VALUES ('delete', OLD.id, OLD.pid); END;
+    CREATE TRIGGER pet_orphaned AFTER UPDATE ON pet FOR EACH ROW
+        BEGIN INSERT INTO audit VALUES ('update', NEW.id, NEW.pid); END;
+    INSERT INTO parent VALUES (1);
+    INSERT INTO parent VALUES (2);
+    INSERT INTO child VALUES (10, 1);
+    INSERT INTO child VALUES (11, 1);
+    INSERT INTO child VALUES (20, 2);
+    INSERT INTO pet VALUES (30, 1);";
+
+fn connect(script: &str) -> Connection {
+    let mut conn = Database::new().connect();
+    conn.execute_batch(script).unwrap();
+    conn
+}
+
+/// The audit trail, oldest first.
+fn audit(conn: &mut Connection) -> Vec<(String, i64, Option<i64>)> {
+    conn.query("SELECT event, id, pid FROM audit")
+        .unwrap()
+        .into_iter()
+        .map(|row| {
+            (
+                row.get(0).unwrap(),
+                row.get(1).unwrap(),
+                row.get(2).unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn run_for_rows_deleted_or_updated_through_on_delete() {
+    let mut conn = connect(SCHEMA);
+    conn.execute("DELETE FROM parent WHERE id = 1").unwrap();
+    assert_eq!(
+        audit(&mut conn),
+        [
+            ("delete".to_string(), 10, Some(1)),
+            ("delete".to_string(), 11, Some(1)),
+            ("update".to_string(), 30, None),
+        ]
+    );
+}
+
+#[test]
+fn a_failing_trigger_on_a_cascaded_row_undoes_the_delete() {
+    let mut conn = connect(SCHEMA);
+    conn.execute_batch(
+        "CREATE TRIGGER child_kept BEFORE DELETE ON child FOR EACH ROW
+             BEGIN INSERT INTO parent VALUES (2); END;",
+    )
+    .unwrap();
+    let err = conn.execute("DELETE FROM parent WHERE id = 2").unwrap_err();
+    assert!(err.contains("Trigger 'child_kept' failed"), "{}", err);
+    assert_eq!(conn.query("SELECT * FROM parent").unwrap().len(), 2);
+    assert_eq!(conn.query("SELECT * FROM child").unwrap().len(), 3);
+    assert!(audit(&mut conn).is_empty());
+}
+
+#[test]
+fn see_the_new_row_of_an_insert_and_both_rows_of_an_update() {
+    let mut conn = connect(
+        "CREATE TABLE audit (event TEXT, id INTEGER, pid INTEGER);
+         CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER);
+         CREATE TRIGGER t_added AFTER INSERT ON t FOR EACH ROW
+             BEGIN INSERT INTO audit VALUES ('insert', NEW.id, NEW.n); END;
+         CREATE TRIGGER t_changed AFTER UPDATE ON t FOR EACH ROW
+             BEGIN INSERT INTO audit VALUES ('old', OLD.id, OLD.n);
+                   INSERT INTO audit VALUES ('new', NEW.id, NEW.n); END;
+         INSERT INTO t VALUES (1, 5);
+         INSERT INTO t VALUES (2, NULL);
+         UPDATE t SET n = 6 WHERE id = 1;",
+    );
+    assert_eq!(
+        audit(&mut conn),
+        [
+            ("insert".to_string(), 1, Some(5)),
+            ("insert".to_string(), 2, None),
+            ("old".to_string(), 1, Some(5)),
+            ("new".to_string(), 1, Some(6)),
+        ]
+    );
+}
+
+#[test]
+fn a_failing_before_trigger_stops_the_statement() {
+    let mut conn = connect(
+        "CREATE TABLE audit (event TEXT, id INTEGER, pid INTEGER);
+         CREATE TABLE t (id INTEGER PRIMARY KEY);
+         CREATE TRIGGER t_checked BEFORE INSERT ON t FOR EACH ROW
+             BEGIN INSERT INTO audit VALUES ('insert', NEW.id, NULL);
+                   INSERT INTO nowhere VALUES (1); END;",
+    );
+    let err = conn.execute("INSERT INTO t VALUES (1)").unwrap_err();
+    assert!(err.contains("Trigger 't_checked' failed"), "{}", err);
+    assert_eq!(conn.query("SELECT * FROM t").unwrap().len(), 0);
+    assert!(audit(&mut conn).is_empty());
+
+    conn.execute("DROP TRIGGER t_checked").unwrap();
+    conn.execute("INSERT INTO t VALUES (1)").unwrap();
+}
+
+#[test]
+fn setting_each_other_off_is_given_up_on() {
+    let mut conn = connect(
+        "CREATE TABLE ping (n INTEGER);
+         CREATE TABLE pong (n INTEGER);
+         CREATE TRIGGER pinged AFTER INSERT ON ping FOR EACH ROW
+             BEGIN INSERT INTO pong VALUES (NEW.n); END;
+         CREATE TRIGGER ponged AFTER INSERT ON pong FOR EACH ROW
+             BEGIN INSERT INTO ping VALUES (NEW.n); END;",
+    );
+    let err = conn.execute("INSERT INTO ping VALUES (0)").unwrap_err();
+    assert!(
+        err.contains("would run 17 triggers deep; triggers may be setting each other off"),
+        "{}",
+        err
+    );
+    assert_eq!(conn.query("SELECT * FROM ping").unwrap().len(), 0);
+    assert_eq!(conn.query("SELECT * FROM pong").unwrap().len(), 0);
+}
+
+#[test]
+fn names_are_unique() {
+    let mut conn = connect(SCHEMA);
+    let err = conn
+        .execute(
+            "CREATE TRIGGER child_gone AFTER INSERT ON parent
+                 BEGIN INSERT INTO audit VALUES ('insert', NEW.id, NULL); END",
+        )
+        .unwrap_err();
+    assert!(
+        err.contains("Trigger 'child_gone' already exists"),
+        "{}",
+        err
+    );
+}